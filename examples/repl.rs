@@ -191,7 +191,7 @@ fn event_loop<H: Host>(
             }
             if paused {
                 decoder.pause();
-                manager.pause();
+                manager.pause()?;
             }
 
             if reset {
@@ -1,18 +1,30 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use dasp::sample::Sample as DaspSample;
 use symphonia::core::audio::conv::ConvertibleSample;
 use symphonia::core::audio::sample::Sample;
+use tracing::{info, warn};
 
-use crate::DEFAULT_SAMPLE_RATE;
 use crate::decoder::{
     Decoder, DecoderError, DecoderResult, DecoderSettings, ResampledDecoder, ResamplerSettings,
     Source,
 };
+use crate::mixer::{Mixer, duration_frames};
 use crate::output::{
-    AudioOutput, AudioOutputError, DecalSample, Host, OutputBuilder, RequestedOutputConfig,
-    SupportedStreamConfig, WriteBlockingError,
+    AudioOutput, AudioOutputError, Crossfeed, CrossfeedSettings, DecalSample, Device, DeviceId,
+    DeviceSelection, DeviceSubscription, Host, MirrorOutput, MirrorTarget, OfflineHost,
+    OutputBuilder, RequestedOutputConfig, SupportedStreamConfig, WriteBlockingError,
 };
+use crate::recorder::{OutputTap, RecorderError, TapFormat, TapSettings, TapSink};
+use crate::{ChannelCount, DEFAULT_SAMPLE_RATE, SampleRate};
+
+#[cfg(test)]
+#[path = "./audio_manager_test.rs"]
+mod audio_manager_test;
 
 #[derive(thiserror::Error, Debug)]
 pub enum WriteOutputError {
@@ -20,6 +32,20 @@ pub enum WriteOutputError {
     DecoderError(#[from] DecoderError),
     #[error(transparent)]
     WriteBlockingError(#[from] WriteBlockingError),
+    #[error(transparent)]
+    FlushError(#[from] FlushError),
+    #[error(transparent)]
+    AudioOutputError(#[from] AudioOutputError),
+    #[error("Output device couldn't be recovered after {0} attempts")]
+    RecoveryFailed(u32),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FlushError {
+    #[error(transparent)]
+    WriteBlockingError(#[from] WriteBlockingError),
+    #[error(transparent)]
+    AudioOutputError(#[from] AudioOutputError),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -30,6 +56,107 @@ pub enum ResetError {
     WriteBlockingError(#[from] WriteBlockingError),
     #[error(transparent)]
     DecoderError(#[from] DecoderError),
+    #[error(transparent)]
+    FlushError(#[from] FlushError),
+    #[error("Error filling the output buffer: {0:?}")]
+    PrefillError(rb::RbError),
+}
+
+#[derive(Clone, Debug)]
+pub struct RecoverySettings {
+    /// Rebuild the output automatically when its device is lost. When disabled, the application
+    /// has to call [`AudioManager::reset`] from `on_configuration_changed` itself.
+    pub enabled: bool,
+    /// Number of consecutive stalled writes before the device is treated as lost.
+    pub max_stalls: u32,
+    /// Delay before retrying after the first failed attempt. Doubles after every failure.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Give up after this many attempts. `None` retries until the device comes back.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RecoverySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_stalls: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecoveryEvent {
+    /// The output stopped. Writes won't play anything until it's recovered.
+    DeviceLost,
+    /// A device higher in the [`DeviceSelection`] became available, or the current one went
+    /// away, so the output is moving to `device`.
+    Switching { device: DeviceId },
+    AttemptFailed {
+        attempt: u32,
+        retry_in: Duration,
+        error: ResetError,
+    },
+    Recovered {
+        attempts: u32,
+        config: SupportedStreamConfig,
+    },
+    /// `max_attempts` was reached. The next write starts over.
+    GaveUp { attempts: u32 },
+}
+
+type RecoveryHandler = Box<dyn FnMut(RecoveryEvent) + Send>;
+
+struct Recovery<T> {
+    attempt: u32,
+    next_attempt: Instant,
+    // Audio that was written to the lost output but never played
+    buffered: Vec<T>,
+}
+
+/// How writes reach the output. Nothing consumes an offline output's buffer, so it's rendered
+/// instead of waited on.
+trait OutputWriter<T, H: Host> {
+    fn write_blocking(output: &AudioOutput<T, H>, samples: &[T]) -> Result<(), WriteBlockingError>;
+    fn drain(output: &AudioOutput<T, H>);
+    /// Waits before the next recovery attempt.
+    fn wait(duration: Duration);
+}
+
+struct RealTime;
+
+impl<T: DecalSample + Default + 'static, H: Host> OutputWriter<T, H> for RealTime {
+    fn write_blocking(output: &AudioOutput<T, H>, samples: &[T]) -> Result<(), WriteBlockingError> {
+        output.write_blocking(samples)
+    }
+
+    fn drain(output: &AudioOutput<T, H>) {
+        output.drain();
+    }
+
+    fn wait(duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+struct Offline;
+
+impl<T: DecalSample + Default + 'static> OutputWriter<T, OfflineHost> for Offline {
+    fn write_blocking(
+        output: &AudioOutput<T, OfflineHost>,
+        samples: &[T],
+    ) -> Result<(), WriteBlockingError> {
+        output.render_blocking(samples)
+    }
+
+    fn drain(output: &AudioOutput<T, OfflineHost>) {
+        output.render_remaining();
+    }
+
+    fn wait(_duration: Duration) {}
 }
 
 pub struct AudioManager<T: Sample + DaspSample, H: Host> {
@@ -38,8 +165,35 @@ pub struct AudioManager<T: Sample + DaspSample, H: Host> {
     output: AudioOutput<T, H>,
     resampled: ResampledDecoder<T>,
     device_name: Option<String>,
+    device_selection: DeviceSelection,
+    devices_changed: Arc<AtomicBool>,
+    _device_subscription: DeviceSubscription,
     resampler_settings: ResamplerSettings,
     volume: T::Float,
+    tap: Option<OutputTap<T>>,
+    recovery_settings: RecoverySettings,
+    on_recovery: Option<RecoveryHandler>,
+    recovery: Option<Recovery<T>>,
+    stalls: u32,
+    paused: bool,
+    // The pinned device is missing, so the output is paused until it's back
+    device_unavailable: bool,
+    switch_crossfade: Duration,
+    // The previous output, playing out its last few milliseconds after a device switch
+    fading_out: Option<AudioOutput<T, H>>,
+    mirror: Option<MirrorOutput<T, H>>,
+    mix_buf: Vec<f32>,
+    mix_samples: Vec<T>,
+    // How much of `mix_samples` has been written, less than all of it after a stall
+    mix_written: usize,
+    crossfeed: Option<Crossfeed>,
+    crossfeed_buf: Vec<T>,
+    // Set while `crossfeed_buf` holds the current frame after a stall, so the retry doesn't run it
+    // through the filter again
+    frame_crossfed: bool,
+    // Audio moved from the previous device that didn't fit in the new one's buffer. It's written
+    // before the next frame.
+    pending: Vec<T>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -76,17 +230,47 @@ where
             resampler_settings.clone(),
         );
 
+        let devices_changed = Arc::new(AtomicBool::new(false));
+        let device_subscription = output_builder.watch_devices({
+            let devices_changed = devices_changed.clone();
+            move |_| devices_changed.store(true, Ordering::SeqCst)
+        });
+
         Ok(Self {
             output_config,
             output_builder,
             output,
             resampled,
             device_name: None,
+            device_selection: DeviceSelection::FollowDefault,
+            devices_changed,
+            _device_subscription: device_subscription,
             resampler_settings,
             volume: 1.0.to_sample(),
+            tap: None,
+            recovery_settings: RecoverySettings::default(),
+            on_recovery: None,
+            recovery: None,
+            stalls: 0,
+            paused: false,
+            device_unavailable: false,
+            switch_crossfade: Duration::from_millis(50),
+            fading_out: None,
+            mirror: None,
+            mix_buf: Vec::new(),
+            mix_samples: Vec::new(),
+            mix_written: 0,
+            crossfeed: None,
+            crossfeed_buf: Vec::new(),
+            frame_crossfed: false,
+            pending: Vec::new(),
         })
     }
 
+    pub fn output_config(&self) -> &SupportedStreamConfig {
+        &self.output_config
+    }
+
     pub fn resampler_settings(&self) -> &ResamplerSettings {
         &self.resampler_settings
     }
@@ -95,14 +279,172 @@ where
         self.resampler_settings = settings;
     }
 
+    pub fn recovery_settings(&self) -> &RecoverySettings {
+        &self.recovery_settings
+    }
+
+    pub fn set_recovery_settings(&mut self, settings: RecoverySettings) {
+        self.recovery_settings = settings;
+    }
+
+    /// Called from [`write`](Self::write) as the output is lost and recovered.
+    pub fn set_on_recovery(&mut self, on_recovery: impl FnMut(RecoveryEvent) + Send + 'static) {
+        self.on_recovery = Some(Box::new(on_recovery));
+    }
+
+    /// Whether the output was lost and hasn't been rebuilt yet.
+    pub fn is_recovering(&self) -> bool {
+        self.recovery.is_some()
+    }
+
+    /// Whether the pinned device is unavailable. The output stays paused until it's back.
+    pub fn is_waiting_for_device(&self) -> bool {
+        self.device_unavailable
+    }
+
+    /// Pins the output to a device, or follows the system default if `None`. Takes effect on
+    /// the next reset.
     pub fn set_device(&mut self, device: Option<String>) {
+        self.device_selection = match &device {
+            Some(device) => DeviceSelection::Pinned(device.clone()),
+            None => DeviceSelection::FollowDefault,
+        };
         self.device_name = device;
     }
 
+    pub fn device_selection(&self) -> &DeviceSelection {
+        &self.device_selection
+    }
+
+    /// Changes how the output device is chosen. The output moves to the selected device on the
+    /// next write, and again whenever devices are added or removed or the default changes.
+    pub fn set_device_selection(&mut self, selection: DeviceSelection) {
+        self.device_selection = selection;
+        self.devices_changed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn switch_crossfade(&self) -> Duration {
+        self.switch_crossfade
+    }
+
+    /// How long both devices play at once when switching devices during playback.
+    pub fn set_switch_crossfade(&mut self, crossfade: Duration) {
+        self.switch_crossfade = crossfade;
+    }
+
+    /// Moves playback to another device without stopping it. Audio that was buffered for the
+    /// old device is moved to the new one, resampled if the new device runs at a different
+    /// rate, and the two devices are crossfaded.
+    ///
+    /// Falls back to [`reset`](Self::reset) if the new device doesn't support the current
+    /// channel count.
+    pub fn switch_device(
+        &mut self,
+        selection: DeviceSelection,
+        decoder: &mut Decoder<T>,
+    ) -> Result<(), ResetError> {
+        self.device_selection = selection;
+        self.hot_swap(Some(decoder))
+    }
+
+    /// Plays the same audio on other devices as well as the main output. An empty list stops
+    /// mirroring.
+    pub fn set_mirror_targets(&mut self, targets: Vec<MirrorTarget>) {
+        if targets.is_empty() {
+            self.mirror = None;
+            return;
+        }
+        let mut mirror = MirrorOutput::new(
+            self.output_builder.clone(),
+            self.output_config.clone(),
+            targets,
+        );
+        if self.output.is_started() && !self.paused {
+            mirror.start();
+        }
+        self.mirror = Some(mirror);
+    }
+
+    pub fn mirror(&self) -> Option<&MirrorOutput<T, H>> {
+        self.mirror.as_ref()
+    }
+
+    pub fn mirror_mut(&mut self) -> Option<&mut MirrorOutput<T, H>> {
+        self.mirror.as_mut()
+    }
+
+    fn start_output(&mut self) -> Result<(), AudioOutputError> {
+        self.output.start()?;
+        if let Some(mirror) = &mut self.mirror {
+            mirror.start();
+        }
+        Ok(())
+    }
+
+    fn resolve_device(&self) -> Result<Option<String>, AudioOutputError> {
+        Ok(match &self.device_selection {
+            DeviceSelection::FollowDefault => None,
+            DeviceSelection::Pinned(device) => Some(device.clone()),
+            DeviceSelection::Preferred(preferences) => self
+                .output_builder
+                .find_preferred_device(preferences)?
+                .map(|id| id.0),
+        })
+    }
+
     pub fn set_volume(&mut self, volume: T::Float) {
         self.volume = volume;
     }
 
+    /// Starts copying every sample written to the output to the sink, replacing any existing
+    /// tap.
+    pub fn start_tap(
+        &mut self,
+        sink: TapSink<T>,
+        settings: TapSettings,
+    ) -> Result<(), RecorderError> {
+        self.stop_tap()?;
+        self.tap = Some(OutputTap::new(
+            sink,
+            tap_format(&self.output_config),
+            settings,
+        )?);
+        Ok(())
+    }
+
+    /// Stops the tap after the queued samples have been written.
+    pub fn stop_tap(&mut self) -> Result<(), RecorderError> {
+        match self.tap.take() {
+            Some(tap) => tap.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn tap(&self) -> Option<&OutputTap<T>> {
+        self.tap.as_ref()
+    }
+
+    /// Enables headphone crossfeed, or disables it with `None`. It's only applied when the output
+    /// is stereo.
+    pub fn set_crossfeed(&mut self, settings: Option<CrossfeedSettings>) {
+        match settings {
+            Some(settings) => match &mut self.crossfeed {
+                Some(crossfeed) => crossfeed.set_settings(settings),
+                None => {
+                    self.crossfeed = Some(Crossfeed::new(settings, self.output_config.sample_rate));
+                }
+            },
+            None => {
+                self.crossfeed = None;
+                self.frame_crossfed = false;
+            }
+        }
+    }
+
+    pub fn crossfeed(&self) -> Option<&Crossfeed> {
+        self.crossfeed.as_ref()
+    }
+
     pub fn init_decoder(
         &mut self,
         source: Box<dyn Source>,
@@ -119,13 +461,28 @@ where
     }
 
     fn rebuild_output(&mut self) -> Result<(), AudioOutputError> {
+        if let Some(tap) = &mut self.tap {
+            tap.set_format(tap_format(&self.output_config));
+        }
+        if let Some(mirror) = &mut self.mirror {
+            mirror.reconfigure(self.output_config.clone());
+        }
+        self.update_crossfeed_rate();
         self.output = self
             .output_builder
             .new_output(self.device_name.clone(), self.output_config.clone())?;
+        self.paused = false;
+        self.device_unavailable = false;
+        self.stalls = 0;
+        self.recovery = None;
+        self.fading_out = None;
+        self.pending.clear();
+        self.mix_written = self.mix_samples.len();
         Ok(())
     }
 
     pub fn reset_output(&mut self) -> Result<(), ResetError> {
+        self.device_name = self.resolve_device()?;
         let new_output_config = self.output_builder.find_closest_config(
             self.device_name.as_deref(),
             RequestedOutputConfig {
@@ -140,6 +497,8 @@ where
     }
 
     pub fn reset(&mut self, decoder: &mut Decoder<T>, mode: ResetMode) -> Result<(), ResetError> {
+        self.frame_crossfed = false;
+        self.device_name = self.resolve_device()?;
         let new_output_config = self.output_builder.find_closest_config(
             self.device_name.as_deref(),
             RequestedOutputConfig {
@@ -162,7 +521,7 @@ where
         // No changes needed, just make sure the output is running
         if !output_config_changed && !in_sample_rate_changed {
             self.resampled.initialize(decoder)?;
-            self.output.start()?;
+            self.start_output()?;
             return Ok(());
         }
 
@@ -183,46 +542,730 @@ where
 
         // Pre-fill output buffer before starting the stream
         while self.resampled.current(decoder).len() <= self.output.buffer_space_available() {
-            self.output.write(self.resampled.current(decoder)).unwrap();
+            let samples = apply_crossfeed(
+                &mut self.crossfeed,
+                self.output_config.channels,
+                &mut self.crossfeed_buf,
+                self.resampled.current(decoder),
+            );
+            self.output
+                .write(samples)
+                .map_err(ResetError::PrefillError)?;
+            push_tap(&self.tap, samples);
+            if let Some(mirror) = &mut self.mirror {
+                mirror.write(samples, self.output.buffer_size());
+            }
             if self.resampled.decode_next_frame(decoder)? == DecoderResult::Finished {
                 break;
             }
         }
 
-        self.output.start()?;
+        self.start_output()?;
         Ok(())
     }
 
-    pub fn pause(&mut self) {
-        self.output.pause();
+    pub fn pause(&mut self) -> Result<(), AudioOutputError> {
+        self.paused = true;
+        if let Some(mirror) = &mut self.mirror {
+            mirror.pause();
+        }
+        self.output.pause()
     }
 
-    pub fn flush(&mut self) -> Result<(), WriteBlockingError> {
-        let res = self.flush_output();
+    pub fn flush(&mut self) -> Result<(), FlushError> {
+        match self.offline() {
+            Some(manager) => manager.flush_with::<Offline>(),
+            None => self.flush_with::<RealTime>(),
+        }
+    }
+
+    fn flush_with<W: OutputWriter<T, H>>(&mut self) -> Result<(), FlushError> {
+        self.fading_out = None;
+        let res = self.flush_output::<W>();
         if res.is_ok() {
-            thread::sleep(self.output.settings().buffer_duration);
+            W::drain(&self.output);
         }
-        self.output.stop();
-        res
+        if let Some(mirror) = &mut self.mirror {
+            mirror.stop();
+        }
+        let stopped = self.output.stop();
+        // The flush error is more useful, so don't let a stop error hide it
+        if let Err(e) = res {
+            if let Err(stop_error) = stopped {
+                warn!("Error stopping output after a failed flush: {stop_error:?}");
+            }
+            return Err(e.into());
+        }
+        Ok(stopped?)
     }
 
+    /// Writes the current frame and decodes the next one. If recovery is enabled and the output
+    /// was lost, this rebuilds it instead and returns [`DecoderResult::Unfinished`] without
+    /// advancing the decoder until it succeeds.
     pub fn write(&mut self, decoder: &mut Decoder<T>) -> Result<DecoderResult, WriteOutputError> {
-        self.output
-            .write_blocking(self.resampled.current(decoder))?;
+        match self.offline() {
+            Some(manager) => manager.write_with::<Offline>(decoder),
+            None => self.write_with::<RealTime>(decoder),
+        }
+    }
+
+    fn write_with<W: OutputWriter<T, H>>(
+        &mut self,
+        decoder: &mut Decoder<T>,
+    ) -> Result<DecoderResult, WriteOutputError> {
+        if !self.prepare_write::<W>(Some(&mut *decoder))? || self.write_pending::<W>()? {
+            return Ok(DecoderResult::Unfinished);
+        }
+
+        let samples = if self.frame_crossfed {
+            &self.crossfeed_buf[..]
+        } else {
+            apply_crossfeed(
+                &mut self.crossfeed,
+                self.output_config.channels,
+                &mut self.crossfeed_buf,
+                self.resampled.current(decoder),
+            )
+        };
+        let result = W::write_blocking(&self.output, samples);
+        if result.is_ok() {
+            push_tap(&self.tap, samples);
+            if let Some(mirror) = &mut self.mirror {
+                mirror.write(samples, self.output.buffer_size());
+            }
+        }
+        // The same frame is written again after a stall
+        self.frame_crossfed = result.is_err()
+            && self.crossfeed.is_some()
+            && self.output_config.channels == ChannelCount(2);
+        if !self.finish_write(result)? {
+            return Ok(DecoderResult::Unfinished);
+        }
         let decoder_result = self.resampled.decode_next_frame(decoder)?;
         Ok(decoder_result)
     }
 
+    /// Follows device changes and recovers a lost output before a write. Returns `false` if the
+    /// output isn't ready to be written to yet.
+    fn prepare_write<W: OutputWriter<T, H>>(
+        &mut self,
+        mut decoder: Option<&mut Decoder<T>>,
+    ) -> Result<bool, WriteOutputError> {
+        if self
+            .fading_out
+            .as_ref()
+            .is_some_and(|output| output.buffer_size() == 0)
+        {
+            self.fading_out = None;
+        }
+        if !self.paused
+            && self.recovery.is_none()
+            && self.devices_changed.swap(false, Ordering::SeqCst)
+        {
+            self.follow_device_changes(decoder.as_deref_mut());
+        }
+        if self.recovery.is_some()
+            || (self.recovery_settings.enabled && self.output.is_device_lost())
+        {
+            return self.recover::<W>(decoder);
+        }
+        Ok(true)
+    }
+
+    /// Writes audio moved from the previous device before anything new. Returns `false` if
+    /// there wasn't any.
+    fn write_pending<W: OutputWriter<T, H>>(&mut self) -> Result<bool, WriteOutputError> {
+        if self.pending.is_empty() {
+            return Ok(false);
+        }
+        // Already tapped and mirrored before the device switch
+        let result = W::write_blocking(&self.output, &self.pending);
+        if self.finish_write(result)? {
+            self.pending.clear();
+        }
+        Ok(true)
+    }
+
+    /// Counts stalls towards recovery. Returns `false` if recovery started instead.
+    fn finish_write(
+        &mut self,
+        result: Result<(), WriteBlockingError>,
+    ) -> Result<bool, WriteOutputError> {
+        match result {
+            Ok(()) => {
+                self.stalls = 0;
+                Ok(true)
+            }
+            Err(WriteBlockingError::OutputStalled)
+                if self.recovery_settings.enabled && !self.paused && !self.device_unavailable =>
+            {
+                self.stalls += 1;
+                if self.stalls < self.recovery_settings.max_stalls {
+                    return Err(WriteBlockingError::OutputStalled.into());
+                }
+                warn!("Output stalled repeatedly, recovering");
+                self.begin_recovery(RecoveryEvent::DeviceLost);
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub fn write_all(&mut self, decoder: &mut Decoder<T>) -> Result<(), WriteOutputError> {
+        match self.offline() {
+            Some(manager) => manager.write_all_with::<Offline>(decoder),
+            None => self.write_all_with::<RealTime>(decoder),
+        }
+    }
+
+    // Offline hosts are rendered instead of waited on, so the public methods switch to the
+    // `Offline` writer for them
+    fn offline(&mut self) -> Option<&mut AudioManager<T, OfflineHost>> {
+        (self as &mut dyn Any).downcast_mut()
+    }
+
+    fn write_all_with<W: OutputWriter<T, H>>(
+        &mut self,
+        decoder: &mut Decoder<T>,
+    ) -> Result<(), WriteOutputError> {
         loop {
-            if self.write(decoder)? == DecoderResult::Finished {
-                self.flush()?;
+            if self.write_with::<W>(decoder)? == DecoderResult::Finished {
+                self.flush_with::<W>()?;
                 return Ok(());
             }
         }
     }
 
-    fn flush_output(&mut self) -> Result<(), WriteBlockingError> {
-        self.output.write_blocking(self.resampled.flush())
+    /// Mixes the next `frames` frames and writes them, switching the mixer to the output's format
+    /// first if needed. Starts the output once its buffer is full, unless it's paused. Follows
+    /// device changes and recovers the output like [`write`](Self::write).
+    ///
+    /// If the write stalls, the next call writes the same block again instead of mixing a new
+    /// one.
+    pub fn write_mixer(
+        &mut self,
+        mixer: &mut Mixer,
+        frames: usize,
+    ) -> Result<(), WriteOutputError> {
+        match self.offline() {
+            Some(manager) => manager.write_mixer_with::<Offline>(mixer, frames),
+            None => self.write_mixer_with::<RealTime>(mixer, frames),
+        }
+    }
+
+    fn write_mixer_with<W: OutputWriter<T, H>>(
+        &mut self,
+        mixer: &mut Mixer,
+        frames: usize,
+    ) -> Result<(), WriteOutputError> {
+        if !self.prepare_write::<W>(None)? || self.write_pending::<W>()? {
+            return Ok(());
+        }
+
+        if self.mix_written == self.mix_samples.len() {
+            if mixer.sample_rate() != self.output_config.sample_rate
+                || mixer.channels() != self.output_config.channels
+            {
+                mixer.set_format(self.output_config.sample_rate, self.output_config.channels);
+            }
+
+            self.mix_buf
+                .resize(frames * self.output_config.channels.0 as usize, 0.0);
+            mixer.mix(&mut self.mix_buf);
+            if let Some(crossfeed) = &mut self.crossfeed
+                && self.output_config.channels == ChannelCount(2)
+            {
+                crossfeed.process(&mut self.mix_buf);
+            }
+            self.mix_samples.clear();
+            self.mix_samples.extend(
+                self.mix_buf
+                    .iter()
+                    .map(|&sample| from_f64::<T>(sample as f64)),
+            );
+            self.mix_written = 0;
+        }
+
+        let samples = std::mem::take(&mut self.mix_samples);
+        let res = self.write_mixed::<W>(&samples);
+        self.mix_samples = samples;
+        res
+    }
+
+    fn write_mixed<W: OutputWriter<T, H>>(
+        &mut self,
+        samples: &[T],
+    ) -> Result<(), WriteOutputError> {
+        if !self.output.is_started() && !self.paused {
+            self.mix_written += self.output.write(&samples[self.mix_written..]).unwrap_or(0);
+            if self.output.is_buffer_full() {
+                self.start_output()?;
+            }
+        }
+        let result = W::write_blocking(&self.output, &samples[self.mix_written..]);
+        if !self.finish_write(result)? {
+            return Ok(());
+        }
+        self.mix_written = samples.len();
+        push_tap(&self.tap, samples);
+        if let Some(mirror) = &mut self.mirror {
+            mirror.write(samples, self.output.buffer_size());
+        }
+        Ok(())
+    }
+
+    /// Moves the output if the selected device isn't the one that's playing.
+    fn follow_device_changes(&mut self, decoder: Option<&mut Decoder<T>>) {
+        let target = self
+            .resolve_device()
+            .and_then(|device| self.output_builder.find_device(device.as_deref()))
+            .and_then(|device| Ok(device.id()?));
+        let target = match target {
+            Ok(target) => target,
+            Err(e) => {
+                if let DeviceSelection::Pinned(device) = &self.device_selection {
+                    if !self.device_unavailable {
+                        warn!("Pinned output device {device:?} is unavailable, pausing: {e:?}");
+                        self.device_unavailable = true;
+                        if let Err(e) = self.output.pause() {
+                            warn!("Error pausing output: {e:?}");
+                        }
+                        if let Some(mirror) = &mut self.mirror {
+                            mirror.pause();
+                        }
+                    }
+                } else {
+                    warn!("Unable to find the selected output device: {e:?}");
+                }
+                return;
+            }
+        };
+        if !self.device_unavailable && self.output.device().id().ok().as_ref() == Some(&target) {
+            return;
+        }
+        info!("Switching output device to {target:?}");
+        self.emit(RecoveryEvent::Switching { device: target });
+        match self.hot_swap(decoder) {
+            Ok(()) => {
+                if std::mem::take(&mut self.device_unavailable)
+                    && let Some(mirror) = &mut self.mirror
+                {
+                    mirror.start();
+                }
+            }
+            Err(e) => warn!("Unable to switch output device: {e:?}"),
+        }
+    }
+
+    /// Moves playback to the selected device. Without a decoder, only the buffered audio moves.
+    fn hot_swap(&mut self, mut decoder: Option<&mut Decoder<T>>) -> Result<(), ResetError> {
+        let device_name = self.resolve_device()?;
+        let old_config = self.output_config.clone();
+        let new_config = self.output_builder.find_closest_config(
+            device_name.as_deref(),
+            RequestedOutputConfig {
+                sample_rate: Some(old_config.sample_rate),
+                channels: Some(old_config.channels),
+                sample_format: Some(<T as DecalSample>::FORMAT),
+            },
+        )?;
+        if new_config.channels != old_config.channels {
+            info!("New device doesn't support the current channel count, resetting");
+            return match decoder {
+                Some(decoder) => self.reset(decoder, ResetMode::Force),
+                None => self.reset_output(),
+            };
+        }
+        let mut new_output = self
+            .output_builder
+            .new_output(device_name.clone(), new_config.clone())?;
+
+        self.fading_out = None;
+        if let Err(e) = self.output.stop() {
+            warn!("Error stopping previous output: {e:?}");
+        }
+        let mut buffered = self.output.take_buffered();
+        buffered.append(&mut self.pending);
+        let channels = old_config.channels.0 as usize;
+        let rate_changed = new_config.sample_rate != old_config.sample_rate;
+
+        // The current frame and whatever the resampler is still holding were resampled for the
+        // old rate, so they have to move with the buffer
+        let mut to_move = buffered.clone();
+        if rate_changed {
+            // A mixed block that stalled is still at the old rate too
+            let unwritten = &self.mix_samples[self.mix_written..];
+            to_move.extend_from_slice(unwritten);
+            push_tap(&self.tap, unwritten);
+            self.mix_written = self.mix_samples.len();
+        }
+        if rate_changed && let Some(decoder) = decoder.as_deref_mut() {
+            let current = if self.frame_crossfed {
+                &self.crossfeed_buf[..]
+            } else {
+                apply_crossfeed(
+                    &mut self.crossfeed,
+                    old_config.channels,
+                    &mut self.crossfeed_buf,
+                    self.resampled.current(decoder),
+                )
+            };
+            to_move.extend_from_slice(current);
+            push_tap(&self.tap, current);
+            let drained = apply_crossfeed(
+                &mut self.crossfeed,
+                old_config.channels,
+                &mut self.crossfeed_buf,
+                self.resampled.drain(),
+            );
+            to_move.extend_from_slice(drained);
+            push_tap(&self.tap, drained);
+            self.frame_crossfed = false;
+        }
+        let mut moved = resample_linear(
+            &to_move,
+            channels,
+            old_config.sample_rate,
+            new_config.sample_rate,
+        );
+        // Nothing's playing on the old device to fade out
+        let crossfade = if self.paused || self.device_unavailable {
+            Duration::ZERO
+        } else {
+            self.switch_crossfade
+        };
+        let fade_in_frames = duration_frames(crossfade, new_config.sample_rate);
+        apply_ramp(&mut moved, channels, fade_in_frames, 0.0, 1.0);
+
+        let written = new_output.write(&moved).unwrap_or(0);
+        if self.paused {
+            // Upsampling can move more than the new buffer holds
+            self.pending = moved.split_off(written);
+        } else {
+            if let Err(e) = new_output.start() {
+                // Put the old device back the way it was
+                let written = self.output.write(&buffered).unwrap_or(0);
+                self.pending = buffered.split_off(written);
+                self.output.start().ok();
+                return Err(e.into());
+            }
+            new_output.write_blocking(&moved[written..])?;
+        }
+
+        // Play the same audio on the old device while it fades out
+        let fade_out_frames =
+            duration_frames(crossfade, old_config.sample_rate).min(buffered.len() / channels);
+        let mut fading = false;
+        if fade_out_frames > 0 {
+            let mut tail = buffered[..fade_out_frames * channels].to_vec();
+            apply_ramp(&mut tail, channels, fade_out_frames, 1.0, 0.0);
+            self.output.write(&tail).ok();
+            fading = self.output.start().is_ok();
+        }
+        let old_output = std::mem::replace(&mut self.output, new_output);
+        if fading {
+            self.fading_out = Some(old_output);
+        }
+
+        self.device_name = device_name;
+        self.output_config = new_config;
+        self.stalls = 0;
+        if let Some(tap) = &mut self.tap {
+            tap.set_format(tap_format(&self.output_config));
+        }
+        if rate_changed {
+            self.update_crossfeed_rate();
+        }
+        if rate_changed && let Some(mirror) = &mut self.mirror {
+            mirror.reconfigure(self.output_config.clone());
+            if !self.paused {
+                mirror.start();
+            }
+        }
+        if rate_changed && let Some(decoder) = decoder {
+            // The current frame was already moved, so continue from the next one
+            if !self.resampled.is_resampling() {
+                decoder.next()?;
+            }
+            self.resampled = ResampledDecoder::new(
+                self.output_config.sample_rate,
+                self.output_config.channels,
+                self.resampler_settings.clone(),
+            );
+            self.resampled.initialize(decoder)?;
+        }
+        Ok(())
+    }
+
+    fn begin_recovery(&mut self, event: RecoveryEvent) {
+        if let Err(e) = self.output.stop() {
+            warn!("Error stopping lost output: {e:?}");
+        }
+        let mut buffered = self.output.take_buffered();
+        buffered.append(&mut self.pending);
+        self.recovery = Some(Recovery {
+            attempt: 0,
+            next_attempt: Instant::now(),
+            buffered,
+        });
+        self.stalls = 0;
+        self.emit(event);
+    }
+
+    /// Returns `true` once the output is running again.
+    fn recover<W: OutputWriter<T, H>>(
+        &mut self,
+        decoder: Option<&mut Decoder<T>>,
+    ) -> Result<bool, WriteOutputError> {
+        if self.recovery.is_none() {
+            warn!("Output device lost, recovering");
+            self.begin_recovery(RecoveryEvent::DeviceLost);
+        }
+        let mut recovery = self.recovery.take().expect("recovery started");
+
+        let now = Instant::now();
+        if now < recovery.next_attempt {
+            // Wait a little at a time so callers can still handle their own commands
+            W::wait((recovery.next_attempt - now).min(self.output.settings().buffer_duration));
+            self.recovery = Some(recovery);
+            return Ok(false);
+        }
+
+        recovery.attempt += 1;
+        match self.rebuild_lost_output(decoder, &mut recovery.buffered) {
+            Ok(()) => {
+                info!("Output recovered after {} attempts", recovery.attempt);
+                self.emit(RecoveryEvent::Recovered {
+                    attempts: recovery.attempt,
+                    config: self.output_config.clone(),
+                });
+                Ok(true)
+            }
+            Err(error) => {
+                let attempts = recovery.attempt;
+                if self
+                    .recovery_settings
+                    .max_attempts
+                    .is_some_and(|max_attempts| attempts >= max_attempts)
+                {
+                    warn!("Giving up on output recovery: {error:?}");
+                    self.emit(RecoveryEvent::GaveUp { attempts });
+                    return Err(WriteOutputError::RecoveryFailed(attempts));
+                }
+
+                let retry_in = self
+                    .recovery_settings
+                    .initial_backoff
+                    .saturating_mul(2u32.saturating_pow(attempts - 1))
+                    .min(self.recovery_settings.max_backoff);
+                warn!("Output recovery attempt {attempts} failed, retrying in {retry_in:?}");
+                recovery.next_attempt = now + retry_in;
+                self.recovery = Some(recovery);
+                self.emit(RecoveryEvent::AttemptFailed {
+                    attempt: attempts,
+                    retry_in,
+                    error,
+                });
+                Ok(false)
+            }
+        }
+    }
+
+    fn rebuild_lost_output(
+        &mut self,
+        decoder: Option<&mut Decoder<T>>,
+        buffered: &mut Vec<T>,
+    ) -> Result<(), ResetError> {
+        let old_config = self.output_config.clone();
+        self.device_name = self.resolve_device()?;
+        self.output_config = self.output_builder.find_closest_config(
+            self.device_name.as_deref(),
+            RequestedOutputConfig {
+                sample_rate: Some(old_config.sample_rate),
+                channels: Some(old_config.channels),
+                sample_format: Some(<T as DecalSample>::FORMAT),
+            },
+        )?;
+        self.rebuild_output()?;
+
+        if self.output_config.sample_rate == old_config.sample_rate
+            && self.output_config.channels == old_config.channels
+        {
+            // Pick up exactly where the old device stopped
+            let written = self.output.write(buffered).unwrap_or(0);
+            self.pending = buffered.split_off(written);
+        } else if let Some(decoder) = decoder {
+            // The buffered audio doesn't fit the new device, so decode it again instead
+            self.frame_crossfed = false;
+            self.resampled = ResampledDecoder::new(
+                self.output_config.sample_rate,
+                self.output_config.channels,
+                self.resampler_settings.clone(),
+            );
+            let buffered_frames = buffered.len() / old_config.channels.0.max(1) as usize;
+            if buffered_frames > 0 {
+                let buffered_duration = Duration::from_secs_f64(
+                    buffered_frames as f64 / old_config.sample_rate.0.max(1) as f64,
+                );
+                let position = decoder
+                    .current_position()
+                    .position
+                    .saturating_sub(buffered_duration);
+                if let Err(e) = decoder.seek(position) {
+                    warn!("Unable to rewind to the unplayed audio: {e:?}");
+                }
+                // Don't rewind again if a later step fails
+                buffered.clear();
+            }
+            self.resampled.initialize(decoder)?;
+        } else {
+            // Mixed audio can't be mixed again, so it's dropped
+            buffered.clear();
+        }
+        self.start_output()?;
+        Ok(())
+    }
+
+    fn emit(&mut self, event: RecoveryEvent) {
+        if let Some(on_recovery) = &mut self.on_recovery {
+            on_recovery(event);
+        }
+    }
+
+    fn flush_output<W: OutputWriter<T, H>>(&mut self) -> Result<(), WriteBlockingError> {
+        if !self.pending.is_empty() {
+            W::write_blocking(&self.output, &self.pending)?;
+            self.pending.clear();
+        }
+        self.frame_crossfed = false;
+        let samples = apply_crossfeed(
+            &mut self.crossfeed,
+            self.output_config.channels,
+            &mut self.crossfeed_buf,
+            self.resampled.flush(),
+        );
+        W::write_blocking(&self.output, samples)?;
+        push_tap(&self.tap, samples);
+        Ok(())
+    }
+
+    /// The crossfeed filters depend on the sample rate, so they're recomputed when it changes.
+    fn update_crossfeed_rate(&mut self) {
+        if let Some(crossfeed) = &mut self.crossfeed
+            && crossfeed.sample_rate() != self.output_config.sample_rate
+        {
+            crossfeed.set_sample_rate(self.output_config.sample_rate);
+        }
+    }
+}
+
+impl<T> AudioManager<T, OfflineHost>
+where
+    T: Sample + DecalSample + ConvertibleSample + rubato::Sample + Send,
+{
+    /// Plays the rest of the decoder through the whole pipeline and returns the output. Nothing
+    /// waits on real time, so the result is the same every time.
+    pub fn render_offline(&mut self, decoder: &mut Decoder<T>) -> Result<Vec<T>, WriteOutputError> {
+        self.write_all_with::<Offline>(decoder)?;
+        Ok(self.output.device().take_rendered())
+    }
+
+    /// Mixes the next `frames` frames and returns them once they're rendered, starting the output
+    /// even if its buffer isn't full yet.
+    pub fn render_mixer_offline(
+        &mut self,
+        mixer: &mut Mixer,
+        frames: usize,
+    ) -> Result<Vec<T>, WriteOutputError> {
+        self.write_mixer_with::<Offline>(mixer, frames)?;
+        if !self.output.is_started() && !self.paused {
+            self.start_output()?;
+        }
+        self.output.render_remaining();
+        Ok(self.output.device().take_rendered())
+    }
+}
+
+fn tap_format(config: &SupportedStreamConfig) -> TapFormat {
+    TapFormat {
+        channels: config.channels,
+        sample_rate: config.sample_rate,
+    }
+}
+
+fn to_f64<T: DaspSample>(sample: T) -> f64 {
+    sample.to_float_sample().to_sample::<f64>()
+}
+
+fn from_f64<T: DaspSample>(value: f64) -> T {
+    T::from_float_sample(<T::Float as DaspSample>::from_sample(value))
+}
+
+/// Linear interpolation is plenty for the fraction of a second that's moved between devices.
+fn resample_linear<T: DaspSample>(
+    samples: &[T],
+    channels: usize,
+    from: SampleRate,
+    to: SampleRate,
+) -> Vec<T> {
+    let in_frames = samples.len() / channels.max(1);
+    if from == to || in_frames == 0 {
+        return samples.to_vec();
+    }
+    let out_frames = (in_frames as u64 * to.0 as u64 / from.0.max(1) as u64) as usize;
+    let step = from.0 as f64 / to.0 as f64;
+    let mut resampled = Vec::with_capacity(out_frames * channels);
+    for frame in 0..out_frames {
+        let position = frame as f64 * step;
+        let index = (position as usize).min(in_frames - 1);
+        let next = (index + 1).min(in_frames - 1);
+        let fraction = position - index as f64;
+        for channel in 0..channels {
+            let current = to_f64(samples[index * channels + channel]);
+            let next = to_f64(samples[next * channels + channel]);
+            resampled.push(from_f64(current + (next - current) * fraction));
+        }
+    }
+    resampled
+}
+
+/// Ramps the gain of the first `frames` frames from `from` to `to`.
+fn apply_ramp<T: DaspSample>(
+    samples: &mut [T],
+    channels: usize,
+    frames: usize,
+    from: f64,
+    to: f64,
+) {
+    for (frame, samples) in samples.chunks_mut(channels.max(1)).take(frames).enumerate() {
+        let gain = from + (to - from) * frame as f64 / frames as f64;
+        for sample in samples {
+            *sample = from_f64(to_f64(*sample) * gain);
+        }
+    }
+}
+
+/// Returns the samples with crossfeed applied, or unchanged if it's disabled or the output isn't
+/// stereo.
+fn apply_crossfeed<'a, T: DaspSample>(
+    crossfeed: &mut Option<Crossfeed>,
+    channels: ChannelCount,
+    buf: &'a mut Vec<T>,
+    samples: &'a [T],
+) -> &'a [T] {
+    match crossfeed {
+        Some(crossfeed) if channels == ChannelCount(2) => {
+            buf.clear();
+            buf.extend_from_slice(samples);
+            crossfeed.process(buf);
+            buf
+        }
+        _ => samples,
+    }
+}
+
+fn push_tap<T: DecalSample>(tap: &Option<OutputTap<T>>, samples: &[T]) {
+    if let Some(tap) = tap {
+        tap.push(samples);
     }
 }
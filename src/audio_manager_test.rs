@@ -0,0 +1,663 @@
+use std::io::Cursor;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::audio_manager::{
+    RecoveryEvent, RecoverySettings, ResetError, ResetMode, WriteOutputError,
+};
+use crate::decoder::{DecoderSettings, ReadSeekSource, ResamplerSettings, Source};
+use crate::encoder::{Endianness, WavWriter};
+use crate::mixer::{BufferSource, Mixer, VoiceSettings};
+use crate::output::{
+    AudioOutputError, BuildStreamError, CrossfeedSettings, DeviceId, DeviceMatcher,
+    DeviceSelection, FileContainer, FileHost, FileOutputSettings, MirrorStatus, MirrorTarget,
+    MockDevice, MockHost, OfflineHost, OutputBuilder, OutputSettings, Pacing, SampleFormat,
+    StreamError, WriteBlockingError,
+};
+use crate::{AudioManager, ChannelCount, SampleRate};
+
+const SOURCE_FRAMES: usize = 44100;
+
+fn source() -> Box<dyn Source> {
+    source_at(SampleRate(44100))
+}
+
+fn source_at(sample_rate: SampleRate) -> Box<dyn Source> {
+    let mut writer = WavWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(2),
+        sample_rate,
+        SampleFormat::I16,
+    )
+    .unwrap();
+    writer.write_samples(&[0.5f32; SOURCE_FRAMES * 2]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+    let len = bytes.len() as u64;
+    Box::new(ReadSeekSource::new(
+        Cursor::new(bytes),
+        Some(len),
+        Some("wav".to_owned()),
+    ))
+}
+
+#[test]
+fn render_offline() {
+    let output_builder =
+        OutputBuilder::new(OfflineHost::default(), Default::default(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_volume(0.5);
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    let rendered = manager.render_offline(&mut decoder).unwrap();
+    assert_eq!(vec![0.25; SOURCE_FRAMES * 2], rendered);
+}
+
+#[test]
+fn render_offline_doesnt_wait_for_playback() {
+    let output_builder =
+        OutputBuilder::new(OfflineHost::default(), Default::default(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+
+    // Draining a real-time output sleeps for the whole 200ms buffer on each reset
+    let start = Instant::now();
+    for _ in 0..3 {
+        let mut decoder = manager
+            .init_decoder(source(), DecoderSettings::default())
+            .unwrap();
+        let rendered = manager.render_offline(&mut decoder).unwrap();
+        assert_eq!(SOURCE_FRAMES * 2, rendered.len());
+    }
+    assert!(start.elapsed() < Duration::from_millis(200));
+}
+
+#[test]
+fn crossfeed_follows_output_rate() {
+    let output_builder =
+        OutputBuilder::new(OfflineHost::default(), Default::default(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_crossfeed(Some(CrossfeedSettings::JAN_MEIER));
+    manager
+        .init_decoder(source_at(SampleRate(48000)), DecoderSettings::default())
+        .unwrap();
+    let crossfeed = manager.crossfeed().unwrap();
+    assert_eq!(SampleRate(48000), crossfeed.sample_rate());
+    assert_eq!(CrossfeedSettings::JAN_MEIER, crossfeed.settings());
+
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+    assert_eq!(
+        SampleRate(44100),
+        manager.crossfeed().unwrap().sample_rate()
+    );
+
+    // The same signal in both channels settles back to its original level
+    let rendered = manager.render_offline(&mut decoder).unwrap();
+    assert_eq!(SOURCE_FRAMES * 2, rendered.len());
+    assert_ne!(0.5, rendered[0]);
+    assert!((rendered[rendered.len() - 1] - 0.5).abs() < 1e-3);
+
+    manager.set_crossfeed(None);
+    assert!(manager.crossfeed().is_none());
+}
+
+fn mock_host() -> MockHost {
+    let speakers = MockHost::default().devices().remove(0);
+    let headphones = MockDevice::new(
+        "headphones".to_owned(),
+        speakers.default_config.clone(),
+        speakers.default_min_sample_rate,
+        speakers.default_max_sample_rate,
+        vec![],
+    );
+    MockHost::new(speakers, vec![headphones])
+}
+
+#[test]
+fn reset_after_device_lost() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let headphones_id = DeviceId("headphones".to_owned());
+    let (changed_tx, changed_rx) = mpsc::channel();
+    let output_builder = OutputBuilder::new(
+        host.clone(),
+        Default::default(),
+        move || changed_tx.send(()).unwrap(),
+        |_| {},
+    );
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+    assert!(speakers.is_playing());
+
+    host.set_default_device(Some(headphones_id.clone()));
+    speakers.send_error(StreamError::DeviceNotAvailable);
+    changed_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    manager.reset(&mut decoder, ResetMode::Force).unwrap();
+    assert!(!speakers.has_output_stream());
+    let headphones = host.device(&headphones_id).unwrap();
+    assert!(headphones.is_playing());
+    assert_eq!(vec![0.5; 1024], headphones.trigger_callback());
+}
+
+#[test]
+fn shares_the_builder_device_watcher() {
+    let host = mock_host();
+    let output_builder = OutputBuilder::new(host.clone(), Default::default(), || {}, |_| {});
+    let _manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    // Wait for the watcher to register
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(1, host.notification_count());
+}
+
+#[test]
+fn reset_reports_build_errors() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), Default::default(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    speakers.fail_next_build(BuildStreamError::DeviceBusy);
+    assert!(matches!(
+        manager.reset(&mut decoder, ResetMode::Force),
+        Err(ResetError::AudioOutputError(
+            AudioOutputError::OpenStreamError(BuildStreamError::DeviceBusy)
+        ))
+    ));
+
+    manager.reset(&mut decoder, ResetMode::Force).unwrap();
+    assert!(speakers.is_playing());
+}
+
+fn fast_settings() -> OutputSettings {
+    OutputSettings {
+        buffer_duration: Duration::from_millis(10),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn write_recovers_lost_device() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let headphones_id = DeviceId("headphones".to_owned());
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    let (event_tx, event_rx) = mpsc::channel();
+    manager.set_on_recovery(move |event| event_tx.send(event).unwrap());
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    host.set_default_device(Some(headphones_id.clone()));
+    speakers.send_error(StreamError::DeviceNotAvailable);
+    // Nothing consumes the new output, so the write stalls after recovering
+    assert!(matches!(
+        manager.write(&mut decoder),
+        Err(WriteOutputError::WriteBlockingError(
+            WriteBlockingError::OutputStalled
+        ))
+    ));
+
+    assert!(matches!(event_rx.try_recv(), Ok(RecoveryEvent::DeviceLost)));
+    assert!(matches!(
+        event_rx.try_recv(),
+        Ok(RecoveryEvent::Recovered { attempts: 1, .. })
+    ));
+    assert!(!manager.is_recovering());
+    assert!(!speakers.has_output_stream());
+    // Audio buffered for the lost device is played on the new one
+    let headphones = host.device(&headphones_id).unwrap();
+    assert_eq!(vec![0.5; 1024], headphones.trigger_callback());
+}
+
+#[test]
+fn write_recovers_stalled_output() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_recovery_settings(RecoverySettings {
+        max_stalls: 2,
+        ..Default::default()
+    });
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    assert!(manager.write(&mut decoder).is_err());
+    assert!(!manager.is_recovering());
+    manager.write(&mut decoder).unwrap();
+    assert!(manager.is_recovering());
+    assert!(!speakers.has_output_stream());
+
+    assert!(manager.write(&mut decoder).is_err());
+    assert!(!manager.is_recovering());
+    assert!(speakers.is_playing());
+}
+
+#[test]
+fn write_gives_up_recovery() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_recovery_settings(RecoverySettings {
+        initial_backoff: Duration::ZERO,
+        max_attempts: Some(2),
+        ..Default::default()
+    });
+    let (event_tx, event_rx) = mpsc::channel();
+    manager.set_on_recovery(move |event| event_tx.send(event).unwrap());
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    speakers.fail_next_build(BuildStreamError::DeviceNotAvailable);
+    speakers.fail_next_build(BuildStreamError::DeviceNotAvailable);
+    speakers.send_error(StreamError::DeviceNotAvailable);
+
+    manager.write(&mut decoder).unwrap();
+    assert!(manager.is_recovering());
+    assert!(matches!(
+        manager.write(&mut decoder),
+        Err(WriteOutputError::RecoveryFailed(2))
+    ));
+
+    let events: Vec<_> = event_rx.try_iter().collect();
+    assert!(matches!(
+        events[..],
+        [
+            RecoveryEvent::DeviceLost,
+            RecoveryEvent::AttemptFailed { attempt: 1, .. },
+            RecoveryEvent::GaveUp { attempts: 2 }
+        ]
+    ));
+}
+
+#[test]
+fn follows_device_preferences() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    // Nothing consumes the output, so don't treat stalls as a lost device
+    manager.set_recovery_settings(RecoverySettings {
+        max_stalls: u32::MAX,
+        ..Default::default()
+    });
+    let (event_tx, event_rx) = mpsc::channel();
+    manager.set_on_recovery(move |event| event_tx.send(event).unwrap());
+    manager.set_switch_crossfade(Duration::ZERO);
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+    manager.set_device_selection(DeviceSelection::Preferred(vec![
+        DeviceMatcher::NameContains("usb".to_owned()),
+        DeviceMatcher::Default,
+    ]));
+
+    let mut next_switch = || {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            manager.write(&mut decoder).ok();
+            if let Ok(RecoveryEvent::Switching { device }) = event_rx.try_recv() {
+                return device;
+            }
+        }
+        panic!("output didn't switch devices");
+    };
+
+    let usb_id = DeviceId("USB DAC".to_owned());
+    host.add_device(MockDevice::new(
+        usb_id.0.clone(),
+        speakers.default_config.clone(),
+        speakers.default_min_sample_rate,
+        speakers.default_max_sample_rate,
+        vec![],
+    ));
+    assert_eq!(usb_id, next_switch());
+    assert!(host.device(&usb_id).unwrap().is_playing());
+    assert!(!speakers.has_output_stream());
+
+    host.remove_device(&usb_id);
+    assert_eq!(speakers.id, next_switch());
+    assert!(speakers.is_playing());
+}
+
+#[test]
+fn pinned_device_pauses_while_unavailable() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_recovery_settings(RecoverySettings {
+        max_stalls: u32::MAX,
+        ..Default::default()
+    });
+    let (event_tx, event_rx) = mpsc::channel();
+    manager.set_on_recovery(move |event| event_tx.send(event).unwrap());
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+    assert!(speakers.is_playing());
+
+    manager.set_device_selection(DeviceSelection::Pinned("USB DAC".to_owned()));
+    manager.write(&mut decoder).ok();
+    assert!(manager.is_waiting_for_device());
+    assert!(!speakers.is_playing());
+    // Stalls while waiting don't count as a lost device
+    for _ in 0..5 {
+        manager.write(&mut decoder).ok();
+    }
+    assert!(!manager.is_recovering());
+
+    let usb_id = DeviceId("USB DAC".to_owned());
+    host.add_device(MockDevice::new(
+        usb_id.0.clone(),
+        speakers.default_config.clone(),
+        speakers.default_min_sample_rate,
+        speakers.default_max_sample_rate,
+        vec![],
+    ));
+    let start = Instant::now();
+    while manager.is_waiting_for_device() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "output didn't resume"
+        );
+        manager.write(&mut decoder).ok();
+    }
+    assert!(matches!(
+        event_rx.try_iter().last(),
+        Some(RecoveryEvent::Switching { device }) if device == usb_id
+    ));
+    assert!(host.device(&usb_id).unwrap().is_playing());
+    assert!(!speakers.has_output_stream());
+}
+
+#[test]
+fn switch_device_crossfades() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_recovery_settings(RecoverySettings {
+        max_stalls: u32::MAX,
+        ..Default::default()
+    });
+    // 100 frames at 44.1 kHz
+    manager.set_switch_crossfade(Duration::from_micros(2268));
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    manager
+        .switch_device(
+            DeviceSelection::Pinned("headphones".to_owned()),
+            &mut decoder,
+        )
+        .unwrap();
+
+    let headphones = host.device(&DeviceId("headphones".to_owned())).unwrap();
+    let fade_in = headphones.trigger_callback();
+    assert_eq!(0.0, fade_in[0]);
+    assert!((fade_in[100] - 0.25).abs() < 0.01);
+    assert_eq!(vec![0.5; 1024 - 200], fade_in[200..]);
+
+    // The old device plays the same audio while fading out, then stops
+    let fade_out = speakers.trigger_callback();
+    assert_eq!(0.5, fade_out[0]);
+    assert!((fade_out[100] - 0.25).abs() < 0.01);
+    assert_eq!(vec![0.0; 1024 - 200], fade_out[200..]);
+    manager.write(&mut decoder).ok();
+    assert!(!speakers.has_output_stream());
+}
+
+#[test]
+fn paused_switch_keeps_every_sample() {
+    let speakers = MockHost::default().devices().remove(0);
+    let mut config = speakers.default_config.clone();
+    config.sample_rate = SampleRate(96000);
+    let headphones = MockDevice::new(
+        "headphones".to_owned(),
+        config,
+        SampleRate(96000),
+        SampleRate(96000),
+        vec![],
+    );
+    let host = MockHost::new(speakers, vec![headphones]);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+    let frames_before =
+        (manager.output.buffer_size() + manager.resampled.current(&mut decoder).len()) / 2;
+
+    manager.pause().unwrap();
+    manager
+        .switch_device(
+            DeviceSelection::Pinned("headphones".to_owned()),
+            &mut decoder,
+        )
+        .unwrap();
+
+    // Upsampling moved more than the new buffer holds, so the rest waits for the next write
+    assert!(!manager.pending.is_empty());
+    assert!(manager.output.is_buffer_full());
+    let frames_after = (manager.output.buffer_size() + manager.pending.len()) / 2;
+    assert_eq!(frames_before * 96000 / 44100, frames_after);
+}
+
+#[test]
+fn mirrors_to_other_devices() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_mirror_targets(vec![MirrorTarget {
+        volume: 0.5,
+        ..MirrorTarget::new("headphones")
+    }]);
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    let headphones = host.device(&DeviceId("headphones".to_owned())).unwrap();
+    // Play everything that's buffered so the next write doesn't stall
+    speakers.set_buffer_len(44100);
+    assert_eq!(vec![0.5; 1024], speakers.trigger_callback()[..1024]);
+    assert_eq!(vec![0.25; 1024], headphones.trigger_callback());
+
+    // Losing the mirror doesn't affect the main output
+    headphones.send_error(StreamError::DeviceNotAvailable);
+    manager.write(&mut decoder).unwrap();
+    assert!(matches!(
+        manager.mirror().unwrap().status()[0].1,
+        MirrorStatus::Failed(_)
+    ));
+    assert!(speakers.is_playing());
+}
+
+#[test]
+fn write_mixer_starts_once_buffered() {
+    let host = MockHost::default();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), Default::default(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    let mut mixer = Mixer::new(SampleRate(48000), ChannelCount(1));
+    mixer.add(
+        BufferSource::new(vec![0.25; 44100 * 2], ChannelCount(2), SampleRate(44100)),
+        VoiceSettings::default(),
+    );
+    mixer.add(
+        BufferSource::new(vec![0.5; 44100], ChannelCount(1), SampleRate(44100)),
+        VoiceSettings {
+            gain: 0.5,
+            pan: -1.0,
+            ..Default::default()
+        },
+    );
+
+    manager.write_mixer(&mut mixer, 8000).unwrap();
+    assert_eq!(SampleRate(44100), mixer.sample_rate());
+    assert_eq!(ChannelCount(2), mixer.channels());
+    assert!(!speakers.is_playing());
+
+    // Filling the rest of the buffer starts the output
+    manager.write_mixer(&mut mixer, 820).unwrap();
+    assert!(speakers.is_playing());
+    speakers.set_buffer_len(4);
+    assert_eq!(vec![0.5, 0.25, 0.5, 0.25], speakers.trigger_callback());
+}
+
+#[test]
+fn render_mixer_offline() {
+    let output_builder =
+        OutputBuilder::new(OfflineHost::default(), Default::default(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(2));
+    mixer.add(
+        BufferSource::new(vec![0.25; 44100 * 2], ChannelCount(2), SampleRate(44100)),
+        VoiceSettings::default(),
+    );
+
+    // Less than the output's buffer still comes out in full, without waiting
+    let start = Instant::now();
+    assert_eq!(
+        vec![0.25; 1000 * 2],
+        manager.render_mixer_offline(&mut mixer, 1000).unwrap()
+    );
+    assert_eq!(
+        vec![0.25; 20000 * 2],
+        manager.render_mixer_offline(&mut mixer, 20000).unwrap()
+    );
+    assert!(start.elapsed() < Duration::from_millis(200));
+}
+
+#[test]
+fn write_mixer_retries_stalls_and_follows_devices() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_recovery_settings(RecoverySettings {
+        max_stalls: u32::MAX,
+        ..Default::default()
+    });
+    manager.set_switch_crossfade(Duration::ZERO);
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(2));
+    mixer.add(
+        BufferSource::new(vec![0.5; 44100 * 2], ChannelCount(2), SampleRate(44100)),
+        VoiceSettings::default(),
+    );
+    manager.write_mixer(&mut mixer, 8820).unwrap();
+    assert!(speakers.is_playing());
+
+    // Nothing consumes the output, so the next block stalls and is kept for the retry
+    assert!(matches!(
+        manager.write_mixer(&mut mixer, 1000),
+        Err(WriteOutputError::WriteBlockingError(
+            WriteBlockingError::OutputStalled
+        ))
+    ));
+    assert_eq!(0, manager.mix_written);
+    speakers.set_buffer_len(2000);
+    speakers.trigger_callback();
+    manager.write_mixer(&mut mixer, 1000).unwrap();
+    assert_eq!(2000, manager.mix_written);
+
+    manager.set_device_selection(DeviceSelection::Pinned("headphones".to_owned()));
+    manager.write_mixer(&mut mixer, 1000).ok();
+    let headphones = host.device(&DeviceId("headphones".to_owned())).unwrap();
+    assert!(headphones.is_playing());
+    assert!(!speakers.has_output_stream());
+}
+
+#[test]
+fn stalled_frames_are_crossfed_once() {
+    let host = mock_host();
+    let speakers = host.devices().remove(0);
+    let output_builder = OutputBuilder::new(host.clone(), fast_settings(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    manager.set_recovery_settings(RecoverySettings {
+        max_stalls: u32::MAX,
+        ..Default::default()
+    });
+    manager.set_crossfeed(Some(CrossfeedSettings::JAN_MEIER));
+    let mut decoder = manager
+        .init_decoder(source(), DecoderSettings::default())
+        .unwrap();
+
+    // Nothing consumes the output, so the frame is kept with the filter already applied
+    assert!(manager.write(&mut decoder).is_err());
+    assert!(manager.frame_crossfed);
+    let crossfed = manager.crossfeed_buf.clone();
+    assert!(manager.write(&mut decoder).is_err());
+    assert_eq!(crossfed, manager.crossfeed_buf);
+
+    speakers.set_buffer_len(44100);
+    speakers.trigger_callback();
+    manager.write(&mut decoder).unwrap();
+    assert!(!manager.frame_crossfed);
+    assert_eq!(crossfed, manager.crossfeed_buf);
+}
+
+#[test]
+fn unpaced_file_keeps_every_track() {
+    let path = std::env::temp_dir().join(format!("decal-{}-tracks.pcm", std::process::id()));
+    let host = FileHost::new(FileOutputSettings {
+        path: path.clone(),
+        container: FileContainer::Raw(Endianness::Little),
+        pacing: Pacing::Unpaced,
+        ..Default::default()
+    });
+    let output_builder = OutputBuilder::new(host, Default::default(), || {}, |_| {});
+    let mut manager =
+        AudioManager::<f32, _>::new(output_builder, ResamplerSettings::default()).unwrap();
+    for _ in 0..2 {
+        let mut decoder = manager
+            .init_decoder(source(), DecoderSettings::default())
+            .unwrap();
+        manager.write_all(&mut decoder).unwrap();
+    }
+    drop(manager);
+
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let samples: Vec<_> = data
+        .chunks(4)
+        .map(|s| f32::from_le_bytes(s.try_into().unwrap()))
+        .collect();
+    // Nothing is padded except the end of each track's last period
+    let padded_frames = SOURCE_FRAMES.div_ceil(512) * 512;
+    let mut track = vec![0.5; SOURCE_FRAMES * 2];
+    track.resize(padded_frames * 2, 0.0);
+    assert_eq!([track.clone(), track].concat(), samples);
+}
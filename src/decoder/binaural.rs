@@ -0,0 +1,443 @@
+use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2, PI};
+#[cfg(feature = "hrtf-sofa")]
+use std::path::Path;
+
+use dasp::sample::Sample as DaspSample;
+use thiserror::Error;
+
+use crate::SampleRate;
+
+// Spherical head model used for the built-in HRTF
+const HEAD_RADIUS: f32 = 0.0875;
+const SPEED_OF_SOUND: f32 = 343.0;
+const MIN_SHADOW: f32 = 0.1;
+const MIN_SHADOW_ANGLE: f32 = 150.0 * PI / 180.0;
+// Length of the built-in impulse responses
+const BUILTIN_HRIR_DURATION: f32 = 0.0025;
+// Longest impulse response that's convolved. The direct sound and the delay between the ears
+// fit well within it; longer responses cost too much to convolve sample by sample.
+const MAX_HRIR_DURATION: f32 = 0.003;
+// Level relative to the peak where an impulse response is considered to start
+const ONSET_THRESHOLD: f32 = 0.01;
+// Spacing of the directions the built-in and SOFA HRTFs are sampled at
+const AZIMUTH_STEP: usize = 5;
+// Leaves headroom for every speaker playing at once
+const BINAURAL_GAIN: f32 = 0.5;
+
+#[derive(Error, Debug)]
+pub enum HrtfError {
+    #[error("The HRTF has no impulse responses")]
+    Empty,
+    #[cfg(feature = "hrtf-sofa")]
+    #[error("Error reading SOFA file: {0}")]
+    Sofa(String),
+}
+
+/// Surround layouts that can be rendered binaurally. Channels are in the usual WAV order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeakerLayout {
+    /// FL, FR, FC, LFE, SL, SR
+    Surround51,
+    /// FL, FR, FC, LFE, BL, BR, SL, SR
+    Surround71,
+}
+
+impl SpeakerLayout {
+    pub fn from_channels(channels: usize) -> Option<Self> {
+        match channels {
+            6 => Some(Self::Surround51),
+            8 => Some(Self::Surround71),
+            _ => None,
+        }
+    }
+
+    pub fn channels(self) -> usize {
+        self.azimuths().len()
+    }
+
+    // Azimuth of each speaker in degrees, positive to the left. `None` for the LFE.
+    fn azimuths(self) -> &'static [Option<f32>] {
+        match self {
+            Self::Surround51 => &[
+                Some(30.0),
+                Some(-30.0),
+                Some(0.0),
+                None,
+                Some(110.0),
+                Some(-110.0),
+            ],
+            Self::Surround71 => &[
+                Some(30.0),
+                Some(-30.0),
+                Some(0.0),
+                None,
+                Some(150.0),
+                Some(-150.0),
+                Some(90.0),
+                Some(-90.0),
+            ],
+        }
+    }
+}
+
+/// A pair of head-related impulse responses for one direction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hrir {
+    /// Degrees, positive to the left.
+    pub azimuth: f32,
+    /// Degrees, positive upwards.
+    pub elevation: f32,
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+}
+
+/// A set of HRIRs, used to place virtual speakers around the listener's head.
+#[derive(Clone, Debug)]
+pub struct Hrtf {
+    sample_rate: SampleRate,
+    hrirs: Vec<Hrir>,
+}
+
+impl Hrtf {
+    pub fn new(sample_rate: SampleRate, hrirs: Vec<Hrir>) -> Result<Self, HrtfError> {
+        if hrirs.is_empty() {
+            return Err(HrtfError::Empty);
+        }
+        Ok(Self { sample_rate, hrirs })
+    }
+
+    /// An HRTF generated from a spherical head model. It doesn't sound as natural as a measured
+    /// set, but needs no data files.
+    pub fn builtin(sample_rate: SampleRate) -> Self {
+        let taps = ((BUILTIN_HRIR_DURATION * sample_rate.0 as f32).ceil() as usize).max(16);
+        let hrirs = (0..360)
+            .step_by(AZIMUTH_STEP)
+            .map(|azimuth| {
+                let azimuth = azimuth as f32 - 180.0;
+                // Angle between the source and each ear
+                let lateral = azimuth.to_radians().sin();
+                Hrir {
+                    azimuth,
+                    elevation: 0.0,
+                    left: head_model(lateral.acos(), sample_rate, taps),
+                    right: head_model((-lateral).acos(), sample_rate, taps),
+                }
+            })
+            .collect();
+        Self { sample_rate, hrirs }
+    }
+
+    /// Loads the HRIRs for the horizontal plane from a SOFA file, resampled to `sample_rate`.
+    /// Only the first 3ms after the earliest onset are used when rendering, see
+    /// [`BinauralRenderer`].
+    #[cfg(feature = "hrtf-sofa")]
+    pub fn from_sofa(path: impl AsRef<Path>, sample_rate: SampleRate) -> Result<Self, HrtfError> {
+        let sofa = sofar::reader::OpenOptions::new()
+            .sample_rate(sample_rate.0 as f32)
+            .open(path)
+            .map_err(|e| HrtfError::Sofa(e.to_string()))?;
+        let mut filter = sofar::reader::Filter::new(sofa.filter_len());
+        let hrirs = (0..360)
+            .step_by(AZIMUTH_STEP)
+            .map(|azimuth| {
+                let azimuth = azimuth as f32 - 180.0;
+                let (y, x) = azimuth.to_radians().sin_cos();
+                // SOFA's x axis points forwards and its y axis points left
+                sofa.filter(x, y, 0.0, &mut filter);
+                Hrir {
+                    azimuth,
+                    elevation: 0.0,
+                    left: filter.left.to_vec(),
+                    right: filter.right.to_vec(),
+                }
+            })
+            .collect();
+        Self::new(sample_rate, hrirs)
+    }
+
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    pub fn hrirs(&self) -> &[Hrir] {
+        &self.hrirs
+    }
+
+    /// The HRIR measured closest to the direction.
+    pub fn nearest(&self, azimuth: f32, elevation: f32) -> &Hrir {
+        let target = direction(azimuth, elevation);
+        self.hrirs
+            .iter()
+            .max_by(|a, b| {
+                let a = dot(direction(a.azimuth, a.elevation), target);
+                let b = dot(direction(b.azimuth, b.elevation), target);
+                a.total_cmp(&b)
+            })
+            .expect("HRTF isn't empty")
+    }
+}
+
+/// Renders surround audio to stereo for headphones by convolving each speaker with the HRIRs
+/// for its position. The LFE is sent to both ears unfiltered.
+///
+/// The HRIRs are convolved directly, so they're limited to 3ms. The delay before the earliest
+/// onset is removed first, keeping the difference between the ears but dropping any latency the
+/// measurements started with.
+pub struct BinauralRenderer {
+    channels: usize,
+    taps: usize,
+    // Reversed HRIRs for each channel, `None` for the LFE
+    filters: Vec<Option<(Vec<f32>, Vec<f32>)>>,
+    // The last `taps` samples of each channel, stored twice so they can be read as one slice
+    history: Vec<f32>,
+    position: usize,
+}
+
+impl BinauralRenderer {
+    /// The HRIRs are resampled if the HRTF doesn't match `sample_rate`.
+    pub fn new(hrtf: &Hrtf, layout: SpeakerLayout, sample_rate: SampleRate) -> Self {
+        let filters: Vec<_> = layout
+            .azimuths()
+            .iter()
+            .map(|azimuth| {
+                azimuth.map(|azimuth| {
+                    let hrir = hrtf.nearest(azimuth, 0.0);
+                    (
+                        resample(&hrir.left, hrtf.sample_rate, sample_rate),
+                        resample(&hrir.right, hrtf.sample_rate, sample_rate),
+                    )
+                })
+            })
+            .collect();
+        // Remove the delay shared by every response, then cut them to a length that's cheap
+        // to convolve
+        let peak = filters
+            .iter()
+            .flatten()
+            .flat_map(|(left, right)| left.iter().chain(right))
+            .fold(0.0f32, |peak, tap| peak.max(tap.abs()));
+        let onset = filters
+            .iter()
+            .flatten()
+            .flat_map(|(left, right)| [left, right])
+            .filter_map(|filter| {
+                filter
+                    .iter()
+                    .position(|tap| tap.abs() > peak * ONSET_THRESHOLD)
+            })
+            .min()
+            .unwrap_or(0);
+        let max_taps = ((MAX_HRIR_DURATION * sample_rate.0 as f32).ceil() as usize).max(16);
+        let taps = filters
+            .iter()
+            .flatten()
+            .map(|(left, right)| left.len().max(right.len()).saturating_sub(onset))
+            .max()
+            .unwrap_or(0)
+            .clamp(1, max_taps);
+        let filters = filters
+            .into_iter()
+            .map(|filter| {
+                filter
+                    .map(|(left, right)| (reverse(left, onset, taps), reverse(right, onset, taps)))
+            })
+            .collect();
+
+        Self {
+            channels: layout.channels(),
+            taps,
+            filters,
+            history: vec![0.0; layout.channels() * taps * 2],
+            position: 0,
+        }
+    }
+
+    /// Clears the filter history, e.g. after seeking.
+    pub fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.position = 0;
+    }
+
+    /// Renders interleaved surround frames from `input` to interleaved stereo in `output`.
+    pub fn process<T: DaspSample>(&mut self, input: &[T], output: &mut [T]) {
+        let taps = self.taps;
+        for (frame, out) in input
+            .chunks_exact(self.channels)
+            .zip(output.chunks_exact_mut(2))
+        {
+            let mut left = 0.0;
+            let mut right = 0.0;
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = sample.to_float_sample().to_sample::<f32>();
+                let Some((left_filter, right_filter)) = &self.filters[channel] else {
+                    left += sample * FRAC_1_SQRT_2;
+                    right += sample * FRAC_1_SQRT_2;
+                    continue;
+                };
+                let history = &mut self.history[channel * taps * 2..(channel + 1) * taps * 2];
+                history[self.position] = sample;
+                history[self.position + taps] = sample;
+                let window = &history[self.position + 1..self.position + 1 + taps];
+                left += window
+                    .iter()
+                    .zip(left_filter)
+                    .map(|(x, h)| x * h)
+                    .sum::<f32>();
+                right += window
+                    .iter()
+                    .zip(right_filter)
+                    .map(|(x, h)| x * h)
+                    .sum::<f32>();
+            }
+            self.position = (self.position + 1) % taps;
+            out[0] = from_f32(left * BINAURAL_GAIN);
+            out[1] = from_f32(right * BINAURAL_GAIN);
+        }
+    }
+}
+
+/// Mixes surround frames down to stereo or mono without any spatial processing.
+pub(crate) fn downmix<T: DaspSample>(
+    layout: SpeakerLayout,
+    input: &[T],
+    output: &mut [T],
+    output_channels: usize,
+) {
+    let azimuths = layout.azimuths();
+    let gains: Vec<_> = azimuths
+        .iter()
+        .map(|azimuth| match azimuth {
+            None => (0.0, 0.0),
+            Some(azimuth) if *azimuth == 0.0 => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            Some(azimuth) if azimuth.abs() < 45.0 => {
+                if *azimuth > 0.0 {
+                    (1.0, 0.0)
+                } else {
+                    (0.0, 1.0)
+                }
+            }
+            Some(azimuth) if *azimuth > 0.0 => (FRAC_1_SQRT_2, 0.0),
+            Some(_) => (0.0, FRAC_1_SQRT_2),
+        })
+        .collect();
+    // Keep a signal in every channel from clipping
+    let scale = 1.0 / gains.iter().map(|(left, _)| left).sum::<f32>();
+
+    for (frame, out) in input
+        .chunks_exact(azimuths.len())
+        .zip(output.chunks_exact_mut(output_channels.max(1)))
+    {
+        let (mut left, mut right) = (0.0, 0.0);
+        for (sample, (left_gain, right_gain)) in frame.iter().zip(&gains) {
+            let sample = sample.to_float_sample().to_sample::<f32>();
+            left += sample * left_gain;
+            right += sample * right_gain;
+        }
+        if output_channels == 1 {
+            out[0] = from_f32((left + right) * 0.5 * scale);
+        } else {
+            out[0] = from_f32(left * scale);
+            out[1] = from_f32(right * scale);
+        }
+    }
+}
+
+/// Impulse response of one ear for a source `angle` radians away from it, with the delay and
+/// head shadow of a rigid sphere.
+fn head_model(angle: f32, sample_rate: SampleRate, taps: usize) -> Vec<f32> {
+    let rate = sample_rate.0.max(1) as f32;
+    let time = HEAD_RADIUS / SPEED_OF_SOUND;
+    let delay = if angle < FRAC_PI_2 {
+        time - time * angle.cos()
+    } else {
+        time + time * (angle - FRAC_PI_2)
+    } * rate;
+
+    // One-pole, one-zero shelf that gets darker as the source moves behind the head
+    let shadow =
+        (1.0 + MIN_SHADOW / 2.0) + (1.0 - MIN_SHADOW / 2.0) * (angle / MIN_SHADOW_ANGLE * PI).cos();
+    let beta = 2.0 * SPEED_OF_SOUND / HEAD_RADIUS;
+    let k = 2.0 * rate;
+    let b0 = (beta + shadow * k) / (beta + k);
+    let b1 = (beta - shadow * k) / (beta + k);
+    let a1 = (beta - k) / (beta + k);
+
+    // Split the impulse between two samples for the fractional part of the delay
+    let whole = delay.floor() as usize;
+    let fraction = delay - delay.floor();
+    let input = |n: usize| {
+        if n == whole {
+            1.0 - fraction
+        } else if n == whole + 1 {
+            fraction
+        } else {
+            0.0
+        }
+    };
+    let mut output = Vec::with_capacity(taps);
+    let mut previous_in = 0.0;
+    let mut previous_out = 0.0;
+    for n in 0..taps {
+        let x = input(n);
+        let y = b0 * x + b1 * previous_in - a1 * previous_out;
+        output.push(y);
+        previous_in = x;
+        previous_out = y;
+    }
+    output
+}
+
+fn resample(hrir: &[f32], from: SampleRate, to: SampleRate) -> Vec<f32> {
+    if from == to || hrir.is_empty() {
+        return hrir.to_vec();
+    }
+    let ratio = from.0 as f32 / to.0 as f32;
+    let len = ((hrir.len() as f32 / ratio).ceil() as usize).max(1);
+    if ratio > 1.0 {
+        // Spread each tap over the two nearest output taps so none of the response is skipped
+        let mut output = vec![0.0; len + 1];
+        for (index, tap) in hrir.iter().enumerate() {
+            let position = index as f32 / ratio;
+            let whole = position as usize;
+            let fraction = position - whole as f32;
+            output[whole] += tap * (1.0 - fraction);
+            output[whole + 1] += tap * fraction;
+        }
+        return output;
+    }
+    (0..len)
+        .map(|n| {
+            let position = n as f32 * ratio;
+            let index = position as usize;
+            let fraction = position - index as f32;
+            let current = hrir.get(index).copied().unwrap_or(0.0);
+            let next = hrir.get(index + 1).copied().unwrap_or(0.0);
+            // Keep the same gain with more taps
+            (current + (next - current) * fraction) * ratio
+        })
+        .collect()
+}
+
+fn reverse(mut filter: Vec<f32>, onset: usize, taps: usize) -> Vec<f32> {
+    filter.drain(..onset.min(filter.len()));
+    filter.resize(taps, 0.0);
+    filter.reverse();
+    filter
+}
+
+fn direction(azimuth: f32, elevation: f32) -> [f32; 3] {
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    [
+        elevation.cos() * azimuth.cos(),
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn from_f32<T: DaspSample>(value: f32) -> T {
+    T::from_float_sample(<T::Float as DaspSample>::from_sample(value))
+}
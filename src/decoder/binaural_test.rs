@@ -0,0 +1,148 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use super::{BinauralRenderer, Hrir, Hrtf, HrtfError, SpeakerLayout, downmix};
+use crate::SampleRate;
+
+fn energy(samples: impl Iterator<Item = f32>) -> f32 {
+    samples.map(|sample| sample * sample).sum()
+}
+
+fn render(renderer: &mut BinauralRenderer, input: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    let mut output = vec![0.0; input.len() / 6 * 2];
+    renderer.process(input, &mut output);
+    let left = output.iter().step_by(2).copied().collect();
+    let right = output.iter().skip(1).step_by(2).copied().collect();
+    (left, right)
+}
+
+// Frames for a 5.1 source with a signal in one channel
+fn surround(channel: usize, signal: &[f32]) -> Vec<f32> {
+    let mut input = vec![0.0; signal.len() * 6];
+    for (frame, sample) in signal.iter().enumerate() {
+        input[frame * 6 + channel] = *sample;
+    }
+    input
+}
+
+#[test]
+fn builtin_hrtf_is_lateralized() {
+    let hrtf = Hrtf::builtin(SampleRate(44100));
+
+    let front = hrtf.nearest(0.0, 0.0);
+    assert_eq!(0.0, front.azimuth);
+    assert_eq!(front.left, front.right);
+
+    let left = hrtf.nearest(90.0, 0.0);
+    assert!(energy(left.left.iter().copied()) > energy(left.right.iter().copied()) * 2.0);
+    let right = hrtf.nearest(-90.0, 0.0);
+    for (a, b) in left.left.iter().zip(&right.right) {
+        assert!((a - b).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn renders_speakers_to_their_side() {
+    let hrtf = Hrtf::builtin(SampleRate(44100));
+    let mut renderer = BinauralRenderer::new(&hrtf, SpeakerLayout::Surround51, SampleRate(44100));
+    let mut impulse = vec![0.0; 256];
+    impulse[0] = 1.0;
+
+    // Front left
+    let (left, right) = render(&mut renderer, &surround(0, &impulse));
+    assert!(energy(left.into_iter()) > energy(right.into_iter()) * 2.0);
+
+    // Surround right
+    renderer.reset();
+    let (left, right) = render(&mut renderer, &surround(5, &impulse));
+    assert!(energy(right.into_iter()) > energy(left.into_iter()) * 2.0);
+
+    // The LFE isn't filtered
+    renderer.reset();
+    let (left, right) = render(&mut renderer, &surround(3, &impulse));
+    assert!((left[0] - FRAC_1_SQRT_2 * 0.5).abs() < 1e-6);
+    assert_eq!(left, right);
+}
+
+#[test]
+fn resampled_hrirs_keep_their_gain() {
+    let constant = vec![1.0; 1024];
+    for hrtf_rate in [44100, 48000, 96000] {
+        let hrtf = Hrtf::builtin(SampleRate(hrtf_rate));
+        let mut renderer =
+            BinauralRenderer::new(&hrtf, SpeakerLayout::Surround51, SampleRate(44100));
+        // A constant signal in the center comes out at the same level in both ears
+        let (left, right) = render(&mut renderer, &surround(2, &constant));
+        assert!(
+            (left[1023] - 0.5).abs() < 0.02,
+            "{hrtf_rate}: {}",
+            left[1023]
+        );
+        assert!(
+            (right[1023] - 0.5).abs() < 0.02,
+            "{hrtf_rate}: {}",
+            right[1023]
+        );
+    }
+}
+
+#[test]
+fn downmixes_without_hrtf() {
+    let input = [1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+    let scale = 1.0 / (1.0 + FRAC_1_SQRT_2 * 2.0);
+
+    let mut stereo = [0.0f32; 2];
+    downmix(SpeakerLayout::Surround51, &input, &mut stereo, 2);
+    assert!((stereo[0] - scale).abs() < 1e-6);
+    assert_eq!(0.0, stereo[1]);
+
+    let mut mono = [0.0f32; 1];
+    downmix(SpeakerLayout::Surround51, &input, &mut mono, 1);
+    assert!((mono[0] - scale * 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn empty_hrtf_is_rejected() {
+    assert!(matches!(
+        Hrtf::new(SampleRate(44100), vec![]),
+        Err(HrtfError::Empty)
+    ));
+}
+
+#[test]
+fn long_hrirs_are_trimmed() {
+    // 512 taps with a 200 sample delay before the response starts
+    let hrir = |azimuth: f32| {
+        let mut left = vec![0.0; 512];
+        let mut right = vec![0.0; 512];
+        left[200] = 1.0;
+        right[210] = 0.5;
+        left[511] = 0.5;
+        Hrir {
+            azimuth,
+            elevation: 0.0,
+            left,
+            right,
+        }
+    };
+    let hrtf = Hrtf::new(SampleRate(44100), vec![hrir(30.0), hrir(-30.0)]).unwrap();
+    let mut renderer = BinauralRenderer::new(&hrtf, SpeakerLayout::Surround51, SampleRate(44100));
+    assert_eq!(133, renderer.taps);
+
+    let mut impulse = vec![0.0; 256];
+    impulse[0] = 1.0;
+    let (left, right) = render(&mut renderer, &surround(0, &impulse));
+    // The shared delay is removed but the difference between the ears is kept
+    assert!((left[0] - 0.5).abs() < 1e-6);
+    assert!((right[10] - 0.25).abs() < 1e-6);
+    // The tail past the limit is dropped
+    assert!(left[1..].iter().all(|sample| *sample == 0.0));
+}
+
+#[cfg(feature = "hrtf-sofa")]
+#[test]
+fn missing_sofa_file_is_an_error() {
+    assert!(matches!(
+        Hrtf::from_sofa("does-not-exist.sofa", SampleRate(44100)),
+        Err(HrtfError::Sofa(_))
+    ));
+}
@@ -1,4 +1,4 @@
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use dasp::sample::Sample as DaspSample;
@@ -21,12 +21,18 @@ use tap::TapFallible;
 use thiserror::Error;
 use tracing::{error, info, warn};
 
+mod binaural;
+pub use binaural::*;
 mod resampler;
 pub use resampler::*;
 mod source;
 pub use source::*;
 mod fixed_buffer;
 
+#[cfg(test)]
+#[path = "./binaural_test.rs"]
+mod binaural_test;
+
 use crate::{ChannelCount, SampleRate};
 
 #[derive(Error, Debug)]
@@ -58,12 +64,14 @@ pub struct CurrentPosition {
 #[derive(Clone, Debug)]
 pub struct DecoderSettings {
     enable_gapless: bool,
+    hrtf: Option<Arc<Hrtf>>,
 }
 
 impl DecoderSettings {
     pub fn new() -> Self {
         Self {
             enable_gapless: true,
+            hrtf: None,
         }
     }
 
@@ -71,6 +79,13 @@ impl DecoderSettings {
         self.enable_gapless = enable_gapless;
         self
     }
+
+    /// Renders 5.1 and 7.1 sources binaurally with this HRTF when they're decoded to stereo,
+    /// instead of downmixing them.
+    pub fn hrtf(mut self, hrtf: Option<Arc<Hrtf>>) -> Self {
+        self.hrtf = hrtf;
+        self
+    }
 }
 
 impl Default for DecoderSettings {
@@ -163,6 +178,8 @@ pub struct Decoder<T: Sample + dasp::sample::Sample> {
     seek_required_ts: Option<Timestamp>,
     settings: DecoderSettings,
     frame_position: usize,
+    layout: Option<SpeakerLayout>,
+    binaural: Option<BinauralRenderer>,
 }
 
 fn create_decoder(
@@ -240,6 +257,8 @@ where
             num_frames,
             settings,
             frame_position: 0,
+            layout: None,
+            binaural: None,
         };
         decoder.initialize()?;
 
@@ -286,6 +305,11 @@ where
         self.sample_rate
     }
 
+    /// The number of channels the decoder outputs.
+    pub fn channels(&self) -> ChannelCount {
+        self.output_channels
+    }
+
     pub fn seek(&mut self, time: Duration) -> Result<SeekedTo, DecoderError> {
         let position = self.current_position();
         let seek_result = match self.reader_seek(time) {
@@ -349,6 +373,9 @@ where
             },
         );
         self.decoder.reset();
+        if let Some(binaural) = &mut self.binaural {
+            binaural.reset();
+        }
         if res.is_ok() {
             // Manually set the timestamp here in case it's queried before we decode the next packet
             self.timestamp = self.time_base.calc_timestamp(seek_time).unwrap();
@@ -379,14 +406,9 @@ where
                 }
                 self.buf_len -= index;
                 samples_skipped += index;
-                // Trim all the silent samples
-                let buf_no_silence: Vec<T> = self.buf[index..]
-                    .iter()
-                    .map(|b| (*b).mul_amp(self.volume))
-                    .collect();
-
-                // Put the segment without silence at the beginning
-                self.buf[..buf_no_silence.len()].copy_from_slice(&buf_no_silence);
+                // Trim all the silent samples and put the rest at the beginning. The volume was
+                // already applied when the packet was decoded.
+                self.buf.copy_within(index..index + self.buf_len, 0);
                 info!("Skipped {samples_skipped} silent samples");
                 break;
             } else {
@@ -441,10 +463,22 @@ where
             info!("Input channels = {channels}");
             info!("Input sample rate = {}", sample_rate.0);
 
-            if channels > 2 {
-                return Err(DecoderError::UnsupportedFormat(
-                    "Audio sources with more than 2 channels are not supported".to_owned(),
-                ));
+            if channels > 2 && channels != self.output_channels.0 as usize {
+                let layout = SpeakerLayout::from_channels(channels)
+                    .filter(|_| self.output_channels.0 <= 2)
+                    .ok_or_else(|| {
+                        DecoderError::UnsupportedFormat(format!(
+                            "Can't convert {channels} channels to {}",
+                            self.output_channels.0
+                        ))
+                    })?;
+                self.layout = Some(layout);
+                if self.output_channels.0 == 2
+                    && let Some(hrtf) = &self.settings.hrtf
+                {
+                    info!("Rendering {layout:?} binaurally");
+                    self.binaural = Some(BinauralRenderer::new(hrtf, layout, sample_rate));
+                }
             }
         }
 
@@ -472,6 +506,21 @@ where
                         .mul_amp(self.volume);
                 }
             }
+            (input, output) if input > 2 && input != output => {
+                let frames = samples_len / input as usize;
+                self.adjust_buffer_size(frames * output as usize);
+                let buf = &mut self.buf[..self.buf_len];
+                match (&mut self.binaural, self.layout) {
+                    (Some(binaural), _) => binaural.process(&self.sample_buf, buf),
+                    (None, Some(layout)) => {
+                        downmix(layout, &self.sample_buf, buf, output as usize);
+                    }
+                    (None, None) => unreachable!("layout is set for surround sources"),
+                }
+                for sample in buf.iter_mut() {
+                    *sample = (*sample).mul_amp(self.volume);
+                }
+            }
             _ => {
                 self.adjust_buffer_size(samples_len);
 
@@ -84,6 +84,36 @@ impl<T: Sample + DaspSample + ConvertibleSample + rubato::Sample> ResampleDecode
         self.in_buf.reset();
         &self.out_buf[..n_out * self.channels]
     }
+
+    fn drain(&mut self) -> &[T] {
+        let partial_len = self.in_buf.position() / self.channels;
+        let frames_in = self.resampler.input_frames_next();
+        let frames_out = self.resampler.output_frames_next();
+        // Everything that's been read but not output yet, without the padding after it
+        let remaining = partial_len * frames_out / frames_in + self.resampler.output_delay();
+
+        let (input_adapter, mut output_adapter) = create_adapters(
+            &self.in_buf,
+            &mut self.out_buf,
+            &self.resampler,
+            self.channels,
+        );
+        let (_, n_out) = self
+            .resampler
+            .process_into_buffer(
+                &input_adapter,
+                &mut output_adapter,
+                Some(&Indexing {
+                    input_offset: 0,
+                    output_offset: 0,
+                    partial_len: Some(partial_len),
+                    active_channels_mask: None,
+                }),
+            )
+            .expect("number of frames was not correctly calculated");
+        self.in_buf.reset();
+        &self.out_buf[..remaining.min(n_out) * self.channels]
+    }
 }
 
 fn create_adapters<'a, T>(
@@ -193,6 +223,12 @@ impl<T: Sample + DaspSample + ConvertibleSample + rubato::Sample> ResampledDecod
         self.in_sample_rate
     }
 
+    /// Whether samples are converted to the output rate, rather than passed through from the
+    /// decoder.
+    pub fn is_resampling(&self) -> bool {
+        matches!(self.decoder_inner, ResampledDecoderImpl::Resampled(_))
+    }
+
     pub fn out_sample_rate(&self) -> SampleRate {
         self.out_sample_rate
     }
@@ -211,6 +247,15 @@ impl<T: Sample + DaspSample + ConvertibleSample + rubato::Sample> ResampledDecod
         }
     }
 
+    /// Returns the input that's been read but not output yet, including what's still in the
+    /// resampler's delay line. Used before replacing the resampler so nothing is dropped.
+    pub fn drain(&mut self) -> &[T] {
+        match &mut self.decoder_inner {
+            ResampledDecoderImpl::Resampled(decoder) => decoder.drain(),
+            ResampledDecoderImpl::Native => &[],
+        }
+    }
+
     pub fn decode_next_frame<'a>(
         &'a mut self,
         decoder: &'a mut Decoder<T>,
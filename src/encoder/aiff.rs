@@ -0,0 +1,189 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use dasp::Sample;
+
+use super::{Endianness, PcmFormat};
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+// The only version of AIFF-C that was ever published
+const AIFC_VERSION: u32 = 0xA280_5140;
+const FORM_SIZE_OFFSET: u64 = 4;
+
+/// The format samples are stored in when written to an AIFF file. AIFF only supports signed
+/// integers of up to 32 bits, so other integer formats are converted to their closest
+/// equivalent. Float samples are stored in an AIFF-C file.
+pub fn aiff_sample_format(sample_format: SampleFormat) -> SampleFormat {
+    match sample_format {
+        SampleFormat::U8 => SampleFormat::I8,
+        SampleFormat::U16 => SampleFormat::I16,
+        SampleFormat::U24 => SampleFormat::I24,
+        SampleFormat::U32 | SampleFormat::I64 | SampleFormat::U64 => SampleFormat::I32,
+        format => format,
+    }
+}
+
+/// Writes samples to an AIFF file, or an AIFF-C file for float samples. The header is written up
+/// front and patched with the final sizes when the writer is finalized.
+///
+/// The writer is finalized when dropped, but errors can only be observed by calling
+/// [`finalize`](Self::finalize).
+pub struct AiffWriter<W: Write + Seek> {
+    writer: Option<W>,
+    format: PcmFormat,
+    channels: ChannelCount,
+    frames_offset: u64,
+    data_size_offset: u64,
+    data_len: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write + Seek> AiffWriter<W> {
+    pub fn new(
+        mut writer: W,
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        sample_format: SampleFormat,
+    ) -> io::Result<Self> {
+        if channels.0 == 0 || channels.0 > i16::MAX as u16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("AIFF doesn't support {} channels", channels.0),
+            ));
+        }
+        let format = PcmFormat::new(aiff_sample_format(sample_format), Endianness::Big);
+        let (header, frames_offset) = header(&format, channels, sample_rate);
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer: Some(writer),
+            format,
+            channels,
+            frames_offset,
+            // The SSND chunk size comes before the 8 byte offset and block size fields
+            data_size_offset: header.len() as u64 - 12,
+            data_len: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.format.sample_format
+    }
+
+    /// Number of bytes of sample data written so far.
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    pub fn write_samples<S: Sample>(&mut self, samples: &[S]) -> io::Result<()> {
+        let writer = self.writer.as_mut().expect("writer already finalized");
+        self.buf.clear();
+        self.format.encode(samples, &mut self.buf);
+        writer.write_all(&self.buf)?;
+        self.data_len += self.buf.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the final chunk sizes and returns the inner writer.
+    pub fn finalize(mut self) -> io::Result<W> {
+        self.write_sizes()?;
+        Ok(self.writer.take().expect("writer already finalized"))
+    }
+
+    fn write_sizes(&mut self) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        // Chunks must be aligned to an even number of bytes
+        if self.data_len % 2 == 1 {
+            writer.write_all(&[0])?;
+        }
+        let end = writer.stream_position()?;
+        let too_large = || io::Error::other("AIFF files can't be larger than 4 GiB");
+        let form_size = u32::try_from(end - 8).map_err(|_| too_large())?;
+        let data_size = u32::try_from(self.data_len + 8).map_err(|_| too_large())?;
+        let frame_size = (self.format.bytes_per_sample() * self.channels.0 as usize) as u64;
+        let frames = (self.data_len / frame_size) as u32;
+
+        writer.seek(SeekFrom::Start(FORM_SIZE_OFFSET))?;
+        writer.write_all(&form_size.to_be_bytes())?;
+        writer.seek(SeekFrom::Start(self.frames_offset))?;
+        writer.write_all(&frames.to_be_bytes())?;
+        writer.seek(SeekFrom::Start(self.data_size_offset))?;
+        writer.write_all(&data_size.to_be_bytes())?;
+        writer.seek(SeekFrom::Start(end))?;
+        writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for AiffWriter<W> {
+    fn drop(&mut self) {
+        self.write_sizes().ok();
+    }
+}
+
+/// Encodes the sample rate as an 80-bit IEEE 754 extended precision float.
+fn extended_sample_rate(sample_rate: SampleRate) -> [u8; 10] {
+    let mut bytes = [0; 10];
+    if sample_rate.0 == 0 {
+        return bytes;
+    }
+    let exponent = 31 - sample_rate.0.leading_zeros();
+    let mantissa = (sample_rate.0 as u64) << (63 - exponent);
+    bytes[..2].copy_from_slice(&(16383 + exponent as u16).to_be_bytes());
+    bytes[2..].copy_from_slice(&mantissa.to_be_bytes());
+    bytes
+}
+
+/// Returns the header and the offset of the frame count in the COMM chunk.
+fn header(format: &PcmFormat, channels: ChannelCount, sample_rate: SampleRate) -> (Vec<u8>, u64) {
+    let compression: Option<(&[u8; 4], &[u8])> = match format.sample_format {
+        SampleFormat::F32 => Some((b"fl32", b"32-bit floating point")),
+        SampleFormat::F64 => Some((b"fl64", b"64-bit floating point")),
+        _ => None,
+    };
+
+    let mut header = Vec::with_capacity(72);
+    header.extend_from_slice(b"FORM");
+    // Patched on finalize
+    header.extend_from_slice(&0u32.to_be_bytes());
+    header.extend_from_slice(if compression.is_some() {
+        b"AIFC"
+    } else {
+        b"AIFF"
+    });
+    if compression.is_some() {
+        header.extend_from_slice(b"FVER");
+        header.extend_from_slice(&4u32.to_be_bytes());
+        header.extend_from_slice(&AIFC_VERSION.to_be_bytes());
+    }
+
+    let mut comm = Vec::with_capacity(48);
+    comm.extend_from_slice(&channels.0.to_be_bytes());
+    let frames_offset = header.len() as u64 + 8 + comm.len() as u64;
+    // Patched on finalize
+    comm.extend_from_slice(&0u32.to_be_bytes());
+    comm.extend_from_slice(&(format.sample_format.bits_per_sample() as u16).to_be_bytes());
+    comm.extend_from_slice(&extended_sample_rate(sample_rate));
+    if let Some((compression_type, name)) = compression {
+        comm.extend_from_slice(compression_type);
+        // Pascal string padded to an even length
+        comm.push(name.len() as u8);
+        comm.extend_from_slice(name);
+        if (name.len() + 1) % 2 == 1 {
+            comm.push(0);
+        }
+    }
+    header.extend_from_slice(b"COMM");
+    header.extend_from_slice(&(comm.len() as u32).to_be_bytes());
+    header.extend_from_slice(&comm);
+
+    header.extend_from_slice(b"SSND");
+    // Patched on finalize
+    header.extend_from_slice(&0u32.to_be_bytes());
+    // Offset and block size
+    header.extend_from_slice(&0u32.to_be_bytes());
+    header.extend_from_slice(&0u32.to_be_bytes());
+    (header, frames_offset)
+}
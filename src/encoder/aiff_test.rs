@@ -0,0 +1,79 @@
+use std::io::Cursor;
+
+use super::AiffWriter;
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+#[test]
+fn aiff_pcm_header() {
+    let mut writer = AiffWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(2),
+        SampleRate(44100),
+        SampleFormat::I16,
+    )
+    .unwrap();
+    writer.write_samples(&[1i16, -1, 2, -2]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+
+    // FORM header, 26 byte COMM chunk, 16 byte SSND header and 8 data bytes
+    assert_eq!(12 + 26 + 16 + 8, bytes.len());
+    assert_eq!(b"FORM", &bytes[0..4]);
+    assert_eq!((bytes.len() as u32 - 8).to_be_bytes(), bytes[4..8]);
+    assert_eq!(b"AIFF", &bytes[8..12]);
+    assert_eq!(b"COMM", &bytes[12..16]);
+    assert_eq!(18u32.to_be_bytes(), bytes[16..20]);
+    // Stereo, 2 frames, 16 bits
+    assert_eq!(
+        [
+            &2u16.to_be_bytes()[..],
+            &2u32.to_be_bytes(),
+            &16u16.to_be_bytes()
+        ]
+        .concat(),
+        bytes[20..28]
+    );
+    // 44100 as an 80-bit float
+    assert_eq!([0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0], bytes[28..38]);
+    assert_eq!(b"SSND", &bytes[38..42]);
+    assert_eq!(16u32.to_be_bytes(), bytes[42..46]);
+    assert_eq!([0, 1, 255, 255, 0, 2, 255, 254], bytes[54..]);
+}
+
+#[test]
+fn aiff_float_uses_aifc() {
+    let mut writer = AiffWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(1),
+        SampleRate(48000),
+        SampleFormat::F32,
+    )
+    .unwrap();
+    writer.write_samples(&[0.5f32]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+
+    assert_eq!(b"AIFC", &bytes[8..12]);
+    assert_eq!(b"FVER", &bytes[12..16]);
+    assert_eq!(b"COMM", &bytes[24..28]);
+    assert_eq!(b"fl32", &bytes[50..54]);
+    assert_eq!(0.5f32.to_be_bytes(), bytes[bytes.len() - 4..]);
+}
+
+#[test]
+fn aiff_pads_odd_data() {
+    let mut writer = AiffWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(1),
+        SampleRate(8000),
+        SampleFormat::U8,
+    )
+    .unwrap();
+    // U8 is stored as I8
+    assert_eq!(SampleFormat::I8, writer.sample_format());
+    writer.write_samples(&[0i8, 127, -128]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+
+    assert_eq!(3u32.to_be_bytes(), bytes[22..26]);
+    assert_eq!(11u32.to_be_bytes(), bytes[42..46]);
+    assert_eq!([0, 127, 128, 0], bytes[54..]);
+}
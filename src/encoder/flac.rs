@@ -0,0 +1,432 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use dasp::Sample;
+
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+/// Number of frames encoded into each FLAC frame.
+pub const FLAC_BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+// The 4-bit Rice parameter reserves 0b1111 as an escape code
+const MAX_RICE_PARAMETER: u32 = 14;
+const STREAMINFO_OFFSET: u64 = 8;
+
+/// Whether samples of the format can be stored in a FLAC file without converting them to a
+/// different bit depth. Float and 32-bit formats aren't supported by most decoders.
+pub fn flac_supports(sample_format: SampleFormat) -> bool {
+    matches!(
+        sample_format,
+        SampleFormat::I8 | SampleFormat::I16 | SampleFormat::I24
+    )
+}
+
+/// Writes samples to a FLAC file using the fixed linear predictors. The stream info block is
+/// patched with the final sample count when the writer is finalized.
+///
+/// The writer is finalized when dropped, but errors can only be observed by calling
+/// [`finalize`](Self::finalize).
+pub struct FlacWriter<W: Write + Seek> {
+    writer: Option<W>,
+    channels: usize,
+    sample_rate: SampleRate,
+    bits_per_sample: u32,
+    sample_format: SampleFormat,
+    // Interleaved samples that haven't filled a block yet
+    pending: Vec<i32>,
+    frame_number: u64,
+    total_frames: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    data_len: u64,
+    bits: BitWriter,
+    channel_buf: Vec<i32>,
+    residual_buf: Vec<i32>,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub fn new(
+        mut writer: W,
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        sample_format: SampleFormat,
+    ) -> io::Result<Self> {
+        if !flac_supports(sample_format) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FLAC doesn't support {sample_format:?} samples"),
+            ));
+        }
+        if channels.0 == 0 || channels.0 > 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FLAC doesn't support {} channels", channels.0),
+            ));
+        }
+        if sample_rate.0 == 0 || sample_rate.0 >= 1 << 20 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FLAC doesn't support a sample rate of {}", sample_rate.0),
+            ));
+        }
+
+        let bits_per_sample = sample_format.bits_per_sample();
+        let mut header = BitWriter::default();
+        header.write_bytes(b"fLaC");
+        // Last metadata block, STREAMINFO, 34 bytes
+        header.write(1, 1);
+        header.write(0, 7);
+        header.write(34, 24);
+        header.write(FLAC_BLOCK_SIZE as u64, 16);
+        header.write(FLAC_BLOCK_SIZE as u64, 16);
+        // Frame sizes and sample count are patched on finalize
+        header.write(0, 24);
+        header.write(0, 24);
+        header.write(sample_rate.0 as u64, 20);
+        header.write(channels.0 as u64 - 1, 3);
+        header.write(bits_per_sample as u64 - 1, 5);
+        header.write(0, 36);
+        // An all-zero MD5 signature means it wasn't computed
+        header.write_bytes(&[0; 16]);
+        writer.write_all(&header.finish())?;
+
+        Ok(Self {
+            writer: Some(writer),
+            channels: channels.0 as usize,
+            sample_rate,
+            bits_per_sample,
+            sample_format,
+            pending: Vec::with_capacity(FLAC_BLOCK_SIZE * channels.0 as usize),
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+            data_len: 0,
+            bits: BitWriter::default(),
+            channel_buf: Vec::with_capacity(FLAC_BLOCK_SIZE),
+            residual_buf: Vec::with_capacity(FLAC_BLOCK_SIZE),
+        })
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    /// Number of bytes of encoded frames written so far. Samples that haven't filled a block
+    /// aren't counted until they're encoded.
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    pub fn write_samples<S: Sample>(&mut self, samples: &[S]) -> io::Result<()> {
+        let block_len = FLAC_BLOCK_SIZE * self.channels;
+        for sample in samples {
+            let sample = sample.to_float_sample().to_sample::<f64>();
+            self.pending.push(match self.sample_format {
+                SampleFormat::I8 => sample.to_sample::<i8>() as i32,
+                SampleFormat::I16 => sample.to_sample::<i16>() as i32,
+                _ => sample.to_sample::<i32>() >> 8,
+            });
+            if self.pending.len() == block_len {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes any remaining samples, writes the final stream info and returns the inner
+    /// writer.
+    pub fn finalize(mut self) -> io::Result<W> {
+        self.write_stream_info()?;
+        Ok(self.writer.take().expect("writer already finalized"))
+    }
+
+    fn write_stream_info(&mut self) -> io::Result<()> {
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        // Drop any partial frame so the block is a whole number of frames
+        let partial = self.pending.len() % self.channels;
+        self.pending.truncate(self.pending.len() - partial);
+        if !self.pending.is_empty() {
+            self.write_frame()?;
+        }
+
+        let writer = self.writer.as_mut().expect("writer already finalized");
+        let end = writer.stream_position()?;
+        let mut info = BitWriter::default();
+        if self.frame_number == 0 {
+            info.write(0, 48);
+        } else {
+            info.write(self.min_frame_size as u64, 24);
+            info.write(self.max_frame_size as u64, 24);
+        }
+        // The sample count shares its first byte with the bit depth, so the whole field is
+        // rewritten
+        info.write(self.sample_rate.0 as u64, 20);
+        info.write(self.channels as u64 - 1, 3);
+        info.write(self.bits_per_sample as u64 - 1, 5);
+        info.write(self.total_frames & ((1 << 36) - 1), 36);
+        writer.seek(SeekFrom::Start(STREAMINFO_OFFSET + 4))?;
+        writer.write_all(&info.finish())?;
+
+        writer.seek(SeekFrom::Start(end))?;
+        writer.flush()
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let block_size = self.pending.len() / self.channels;
+        let bits = &mut self.bits;
+
+        // Frame header: sync code, fixed block size, 16-bit block size and sample rate from
+        // the stream info
+        bits.write(0b11111111111110, 14);
+        bits.write(0, 1);
+        bits.write(0, 1);
+        bits.write(0b0111, 4);
+        bits.write(0b0000, 4);
+        bits.write(self.channels as u64 - 1, 4);
+        bits.write(
+            match self.bits_per_sample {
+                8 => 0b001,
+                16 => 0b100,
+                _ => 0b110,
+            },
+            3,
+        );
+        bits.write(0, 1);
+        bits.write_utf8(self.frame_number);
+        bits.write(block_size as u64 - 1, 16);
+        let crc = crc8(bits.bytes());
+        bits.write(crc as u64, 8);
+
+        for channel in 0..self.channels {
+            self.channel_buf.clear();
+            self.channel_buf.extend(
+                self.pending
+                    .iter()
+                    .skip(channel)
+                    .step_by(self.channels)
+                    .copied(),
+            );
+            write_subframe(
+                bits,
+                &self.channel_buf,
+                self.bits_per_sample,
+                &mut self.residual_buf,
+            );
+        }
+
+        bits.align();
+        let crc = crc16(bits.bytes());
+        bits.write(crc as u64, 16);
+        let frame = bits.finish();
+
+        self.writer
+            .as_mut()
+            .expect("writer already finalized")
+            .write_all(&frame)?;
+        self.min_frame_size = self.min_frame_size.min(frame.len() as u32);
+        self.max_frame_size = self.max_frame_size.max(frame.len() as u32);
+        self.data_len += frame.len() as u64;
+        self.total_frames += block_size as u64;
+        self.frame_number += 1;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for FlacWriter<W> {
+    fn drop(&mut self) {
+        self.write_stream_info().ok();
+    }
+}
+
+fn write_subframe(
+    bits: &mut BitWriter,
+    samples: &[i32],
+    bits_per_sample: u32,
+    residual: &mut Vec<i32>,
+) {
+    let first = samples[0];
+    if samples.iter().all(|s| *s == first) {
+        bits.write(0b0000000, 7);
+        bits.write(0, 1);
+        bits.write_signed(first, bits_per_sample);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * bits_per_sample as u64;
+    let mut best = None;
+    for order in 0..=MAX_FIXED_ORDER.min(samples.len() - 1) {
+        fixed_residual(samples, order, residual);
+        let (parameter, residual_bits) = rice_parameter(residual);
+        let size = order as u64 * bits_per_sample as u64 + 10 + residual_bits;
+        if best.is_none_or(|(_, _, best_size)| size < best_size) {
+            best = Some((order, parameter, size));
+        }
+    }
+
+    match best {
+        Some((order, parameter, size)) if size < verbatim_bits => {
+            bits.write(0b001000 | order as u64, 7);
+            bits.write(0, 1);
+            for sample in &samples[..order] {
+                bits.write_signed(*sample, bits_per_sample);
+            }
+            fixed_residual(samples, order, residual);
+            // Rice coding with 4-bit parameters and a single partition
+            bits.write(0b00, 2);
+            bits.write(0, 4);
+            bits.write(parameter as u64, 4);
+            for r in residual.iter() {
+                bits.write_rice(zigzag(*r), parameter);
+            }
+        }
+        _ => {
+            bits.write(0b0000001, 7);
+            bits.write(0, 1);
+            for sample in samples {
+                bits.write_signed(*sample, bits_per_sample);
+            }
+        }
+    }
+}
+
+fn fixed_residual(samples: &[i32], order: usize, residual: &mut Vec<i32>) {
+    residual.clear();
+    residual.extend((order..samples.len()).map(|i| {
+        let s = |offset: usize| samples[i - offset] as i64;
+        let predicted = match order {
+            0 => 0,
+            1 => s(1),
+            2 => 2 * s(1) - s(2),
+            3 => 3 * s(1) - 3 * s(2) + s(3),
+            _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+        };
+        (samples[i] as i64 - predicted) as i32
+    }));
+}
+
+/// Finds the Rice parameter that encodes the residual in the fewest bits.
+fn rice_parameter(residual: &[i32]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits = residual
+                .iter()
+                .map(|r| (zigzag(*r) >> k) as u64 + 1 + k as u64)
+                .sum::<u64>();
+            (k, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .expect("parameter range is not empty")
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        let mut remaining = bits;
+        while remaining > 0 {
+            let n = remaining.min(32);
+            remaining -= n;
+            let chunk = (value >> remaining) & ((1 << n) - 1);
+            self.acc = (self.acc << n) | chunk;
+            self.len += n;
+            while self.len >= 8 {
+                self.len -= 8;
+                self.bytes.push((self.acc >> self.len) as u8);
+            }
+        }
+    }
+
+    fn write_signed(&mut self, value: i32, bits: u32) {
+        self.write(value as u64 & ((1 << bits) - 1), bits);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write(*byte as u64, 8);
+        }
+    }
+
+    fn write_rice(&mut self, value: u32, parameter: u32) {
+        let mut quotient = value >> parameter;
+        while quotient >= 32 {
+            self.write(0, 32);
+            quotient -= 32;
+        }
+        self.write(1, quotient + 1);
+        self.write(value as u64 & ((1 << parameter) - 1), parameter);
+    }
+
+    // Frame numbers are stored with the same variable length encoding as UTF-8
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let bits = 64 - value.leading_zeros();
+        // Each continuation byte holds 6 bits and the first byte holds 7 - len bits
+        let len = (2..=7).find(|len| bits <= 5 * len + 1).unwrap_or(7);
+        let prefix = !0u8 << (8 - len);
+        self.write((prefix as u64) | (value >> (6 * (len - 1))), 8);
+        for i in (0..len - 1).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    fn align(&mut self) {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+    }
+
+    /// The completed bytes written so far.
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        self.align();
+        self.acc = 0;
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+pub(crate) fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+pub(crate) fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, byte| {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
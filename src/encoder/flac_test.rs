@@ -0,0 +1,138 @@
+use std::io::Cursor;
+
+use super::{FLAC_BLOCK_SIZE, FlacWriter, crc8, crc16};
+#[cfg(feature = "decoder-flac")]
+use crate::decoder::{Decoder, DecoderResult, DecoderSettings, ReadSeekSource};
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+fn stream_info(bytes: &[u8]) -> (u32, u16, u32, u64) {
+    let packed = u64::from_be_bytes(bytes[18..26].try_into().unwrap());
+    (
+        (packed >> 44) as u32,
+        ((packed >> 41) & 0x7) as u16 + 1,
+        ((packed >> 36) & 0x1F) as u32 + 1,
+        packed & ((1 << 36) - 1),
+    )
+}
+
+#[test]
+fn flac_stream_info() {
+    let mut writer = FlacWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(2),
+        SampleRate(48000),
+        SampleFormat::I24,
+    )
+    .unwrap();
+    let samples: Vec<f32> = (0..FLAC_BLOCK_SIZE * 2 + 100)
+        .map(|i| (i as f32 * 0.01).sin())
+        .collect();
+    writer.write_samples(&samples).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+
+    assert_eq!(b"fLaC", &bytes[0..4]);
+    // Last metadata block, STREAMINFO, 34 bytes
+    assert_eq!([0x80, 0, 0, 34], bytes[4..8]);
+    assert_eq!(
+        (48000, 2, 24, FLAC_BLOCK_SIZE as u64 + 50),
+        stream_info(&bytes)
+    );
+    // Compressed frames are smaller than the raw samples
+    assert!(bytes.len() < samples.len() * 3);
+}
+
+#[test]
+fn flac_frame_checksums() {
+    let mut writer = FlacWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(1),
+        SampleRate(8000),
+        SampleFormat::I16,
+    )
+    .unwrap();
+    writer.write_samples(&[0i16; 64]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+
+    let frame = &bytes[42..];
+    // Sync code, frame number 0 and a 64 sample block
+    assert_eq!([0xFF, 0xF8], frame[0..2]);
+    assert_eq!(0, frame[4]);
+    assert_eq!(63u16.to_be_bytes(), frame[5..7]);
+    assert_eq!(crc8(&frame[..7]), frame[7]);
+    // Silence is stored as a single constant sample
+    assert_eq!([0, 0, 0], frame[8..11]);
+    assert_eq!(
+        crc16(&frame[..frame.len() - 2]).to_be_bytes(),
+        frame[frame.len() - 2..]
+    );
+    assert_eq!((8000, 1, 16, 64), stream_info(&bytes));
+}
+
+#[test]
+fn flac_rejects_float() {
+    assert!(
+        FlacWriter::new(
+            Cursor::new(vec![]),
+            ChannelCount(2),
+            SampleRate(44100),
+            SampleFormat::F32,
+        )
+        .is_err()
+    );
+}
+
+#[cfg(feature = "decoder-flac")]
+fn decode(bytes: Vec<u8>, channels: ChannelCount) -> Vec<f32> {
+    let len = bytes.len() as u64;
+    let mut decoder = Decoder::<f32>::new(
+        Box::new(ReadSeekSource::new(
+            Cursor::new(bytes),
+            Some(len),
+            Some("flac".to_owned()),
+        )),
+        1.0,
+        channels,
+        DecoderSettings::default(),
+    )
+    .unwrap();
+    let mut samples = Vec::new();
+    loop {
+        samples.extend_from_slice(decoder.current(None));
+        if decoder.next().unwrap() == DecoderResult::Finished {
+            return samples;
+        }
+    }
+}
+
+#[cfg(feature = "decoder-flac")]
+#[test]
+fn flac_decodes_with_symphonia() {
+    for (sample_format, bits, channels) in [
+        (SampleFormat::I8, 8, ChannelCount(1)),
+        (SampleFormat::I16, 16, ChannelCount(2)),
+        (SampleFormat::I24, 24, ChannelCount(2)),
+    ] {
+        // Values the format can hold exactly, from full scale down to a quiet sine. The last
+        // block is shorter than the rest.
+        let scale = (1i32 << (bits - 1)) as f32;
+        let frames = FLAC_BLOCK_SIZE * 2 + 37;
+        let mut samples: Vec<f32> = (0..frames * channels.0 as usize)
+            .map(|i| ((i as f32 * 0.01).sin() * 0.5 * scale).round() / scale)
+            .collect();
+        samples[0] = -1.0;
+        samples[1] = (scale - 1.0) / scale;
+
+        let mut writer = FlacWriter::new(
+            Cursor::new(vec![]),
+            channels,
+            SampleRate(44100),
+            sample_format,
+        )
+        .unwrap();
+        writer.write_samples(&samples).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        assert_eq!(samples, decode(bytes, channels), "{bits} bit");
+    }
+}
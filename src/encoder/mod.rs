@@ -0,0 +1,20 @@
+mod aiff;
+pub use aiff::*;
+mod flac;
+pub use flac::*;
+mod pcm;
+pub use pcm::*;
+mod wav;
+pub use wav::*;
+
+#[cfg(test)]
+#[path = "./aiff_test.rs"]
+mod aiff_test;
+
+#[cfg(test)]
+#[path = "./flac_test.rs"]
+mod flac_test;
+
+#[cfg(test)]
+#[path = "./wav_test.rs"]
+mod wav_test;
@@ -0,0 +1,91 @@
+use dasp::Sample;
+
+use crate::output::SampleFormat;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") {
+            Self::Big
+        } else {
+            Self::Little
+        }
+    }
+}
+
+/// Describes how samples are laid out when they're written to a byte stream.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct PcmFormat {
+    pub sample_format: SampleFormat,
+    pub endianness: Endianness,
+}
+
+macro_rules! push_bytes {
+    ($out:expr, $endianness:expr, $value:expr) => {
+        match $endianness {
+            Endianness::Little => $out.extend_from_slice(&$value.to_le_bytes()),
+            Endianness::Big => $out.extend_from_slice(&$value.to_be_bytes()),
+        }
+    };
+}
+
+impl PcmFormat {
+    pub fn new(sample_format: SampleFormat, endianness: Endianness) -> Self {
+        Self {
+            sample_format,
+            endianness,
+        }
+    }
+
+    /// Number of bytes used by a single encoded sample. Unlike in-memory samples, 24-bit formats
+    /// are packed into 3 bytes.
+    pub fn bytes_per_sample(&self) -> usize {
+        match self.sample_format {
+            SampleFormat::I24 | SampleFormat::U24 => 3,
+            format => format.sample_size(),
+        }
+    }
+
+    /// Converts the samples to this format and appends them to `out`.
+    pub fn encode<S: Sample>(&self, samples: &[S], out: &mut Vec<u8>) {
+        out.reserve(samples.len() * self.bytes_per_sample());
+        for sample in samples {
+            self.encode_sample(sample.to_float_sample().to_sample::<f64>(), out);
+        }
+    }
+
+    fn encode_sample(&self, sample: f64, out: &mut Vec<u8>) {
+        match self.sample_format {
+            SampleFormat::I8 => push_bytes!(out, self.endianness, sample.to_sample::<i8>()),
+            SampleFormat::I16 => push_bytes!(out, self.endianness, sample.to_sample::<i16>()),
+            SampleFormat::I24 => {
+                let value = sample.to_sample::<i32>() >> 8;
+                self.push_packed_24(value.to_le_bytes(), out);
+            }
+            SampleFormat::I32 => push_bytes!(out, self.endianness, sample.to_sample::<i32>()),
+            SampleFormat::I64 => push_bytes!(out, self.endianness, sample.to_sample::<i64>()),
+            SampleFormat::U8 => out.push(sample.to_sample::<u8>()),
+            SampleFormat::U16 => push_bytes!(out, self.endianness, sample.to_sample::<u16>()),
+            SampleFormat::U24 => {
+                let value = sample.to_sample::<u32>() >> 8;
+                self.push_packed_24(value.to_le_bytes(), out);
+            }
+            SampleFormat::U32 => push_bytes!(out, self.endianness, sample.to_sample::<u32>()),
+            SampleFormat::U64 => push_bytes!(out, self.endianness, sample.to_sample::<u64>()),
+            SampleFormat::F32 => push_bytes!(out, self.endianness, sample.to_sample::<f32>()),
+            SampleFormat::F64 => push_bytes!(out, self.endianness, sample),
+        }
+    }
+
+    fn push_packed_24(&self, le_bytes: [u8; 4], out: &mut Vec<u8>) {
+        match self.endianness {
+            Endianness::Little => out.extend_from_slice(&le_bytes[..3]),
+            Endianness::Big => out.extend([le_bytes[2], le_bytes[1], le_bytes[0]]),
+        }
+    }
+}
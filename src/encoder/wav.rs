@@ -0,0 +1,213 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use dasp::Sample;
+
+use super::{Endianness, PcmFormat};
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// The GUID tail shared by the KSDATAFORMAT_SUBTYPE_* formats
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+// Large enough to be replaced by a ds64 chunk if the file grows past 4 GiB
+const JUNK_CHUNK_SIZE: u32 = 28;
+const RIFF_SIZE_OFFSET: u64 = 4;
+const JUNK_CHUNK_OFFSET: u64 = 12;
+
+/// The format samples are stored in when written to a WAV file. WAV only supports unsigned
+/// 8-bit samples and signed samples for everything wider, so other formats are converted to
+/// their closest equivalent without losing precision.
+pub fn wav_sample_format(sample_format: SampleFormat) -> SampleFormat {
+    match sample_format {
+        SampleFormat::I8 => SampleFormat::U8,
+        SampleFormat::U16 => SampleFormat::I16,
+        SampleFormat::U24 => SampleFormat::I24,
+        SampleFormat::U32 => SampleFormat::I32,
+        SampleFormat::U64 => SampleFormat::I64,
+        format => format,
+    }
+}
+
+fn channel_mask(channels: ChannelCount) -> u32 {
+    match channels.0 {
+        1 => 0x4,
+        2 => 0x3,
+        4 => 0x33,
+        6 => 0x3F,
+        8 => 0x63F,
+        _ => 0,
+    }
+}
+
+/// Writes samples to a WAV file. The header is written up front and patched with the final
+/// sizes when the writer is finalized. Files larger than 4 GiB are upgraded to RF64.
+///
+/// The writer is finalized when dropped, but errors can only be observed by calling
+/// [`finalize`](Self::finalize).
+pub struct WavWriter<W: Write + Seek> {
+    writer: Option<W>,
+    format: PcmFormat,
+    channels: ChannelCount,
+    data_size_offset: u64,
+    data_len: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(
+        mut writer: W,
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        sample_format: SampleFormat,
+    ) -> io::Result<Self> {
+        let format = PcmFormat::new(wav_sample_format(sample_format), Endianness::Little);
+        let header = header(&format, channels, sample_rate);
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer: Some(writer),
+            format,
+            channels,
+            data_size_offset: header.len() as u64 - 4,
+            data_len: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.format.sample_format
+    }
+
+    /// Number of bytes of sample data written so far.
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    pub fn write_samples<S: Sample>(&mut self, samples: &[S]) -> io::Result<()> {
+        let writer = self.writer.as_mut().expect("writer already finalized");
+        self.buf.clear();
+        self.format.encode(samples, &mut self.buf);
+        writer.write_all(&self.buf)?;
+        self.data_len += self.buf.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the final chunk sizes and returns the inner writer.
+    pub fn finalize(mut self) -> io::Result<W> {
+        self.write_sizes()?;
+        Ok(self.writer.take().expect("writer already finalized"))
+    }
+
+    /// Writes the chunk sizes so far, leaving the file valid while more samples are written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_sizes()?;
+        if self.data_len % 2 == 1 {
+            // Later samples replace the padding byte
+            let writer = self.writer.as_mut().expect("writer already finalized");
+            writer.seek(SeekFrom::Current(-1))?;
+        }
+        Ok(())
+    }
+
+    fn write_sizes(&mut self) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        // Chunks must be aligned to an even number of bytes
+        let padding = self.data_len % 2;
+        if padding == 1 {
+            writer.write_all(&[0])?;
+        }
+        let end = writer.stream_position()?;
+        let riff_size = end - 8;
+
+        if let (Ok(riff_size), Ok(data_len)) =
+            (u32::try_from(riff_size), u32::try_from(self.data_len))
+        {
+            writer.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+            writer.write_all(&riff_size.to_le_bytes())?;
+            writer.seek(SeekFrom::Start(self.data_size_offset))?;
+            writer.write_all(&data_len.to_le_bytes())?;
+        } else {
+            let frame_size = (self.format.bytes_per_sample() * self.channels.0 as usize) as u64;
+            let mut ds64 = Vec::with_capacity(JUNK_CHUNK_SIZE as usize + 8);
+            ds64.extend_from_slice(b"ds64");
+            ds64.extend_from_slice(&JUNK_CHUNK_SIZE.to_le_bytes());
+            ds64.extend_from_slice(&riff_size.to_le_bytes());
+            ds64.extend_from_slice(&self.data_len.to_le_bytes());
+            ds64.extend_from_slice(&(self.data_len / frame_size.max(1)).to_le_bytes());
+            // No table entries
+            ds64.extend_from_slice(&0u32.to_le_bytes());
+
+            writer.seek(SeekFrom::Start(0))?;
+            writer.write_all(b"RF64")?;
+            writer.write_all(&u32::MAX.to_le_bytes())?;
+            writer.seek(SeekFrom::Start(JUNK_CHUNK_OFFSET))?;
+            writer.write_all(&ds64)?;
+            writer.seek(SeekFrom::Start(self.data_size_offset))?;
+            writer.write_all(&u32::MAX.to_le_bytes())?;
+        }
+        writer.seek(SeekFrom::Start(end))?;
+        writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        self.write_sizes().ok();
+    }
+}
+
+fn header(format: &PcmFormat, channels: ChannelCount, sample_rate: SampleRate) -> Vec<u8> {
+    let bytes_per_sample = format.bytes_per_sample() as u16;
+    let bits_per_sample = format.sample_format.bits_per_sample() as u16;
+    let block_align = bytes_per_sample * channels.0;
+    let is_float = format.sample_format.is_float();
+    let extensible = channels.0 > 2 || bits_per_sample > 16 || is_float;
+    let format_tag = if is_float {
+        WAVE_FORMAT_IEEE_FLOAT
+    } else {
+        WAVE_FORMAT_PCM
+    };
+
+    let mut header = Vec::with_capacity(80);
+    header.extend_from_slice(b"RIFF");
+    // Patched on finalize
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"JUNK");
+    header.extend_from_slice(&JUNK_CHUNK_SIZE.to_le_bytes());
+    header.extend_from_slice(&[0; JUNK_CHUNK_SIZE as usize]);
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&(if extensible { 40u32 } else { 16u32 }).to_le_bytes());
+    header.extend_from_slice(
+        &(if extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            format_tag
+        })
+        .to_le_bytes(),
+    );
+    header.extend_from_slice(&channels.0.to_le_bytes());
+    header.extend_from_slice(&sample_rate.0.to_le_bytes());
+    header.extend_from_slice(&(sample_rate.0 * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&(bytes_per_sample * 8).to_le_bytes());
+    if extensible {
+        header.extend_from_slice(&22u16.to_le_bytes());
+        header.extend_from_slice(&bits_per_sample.to_le_bytes());
+        header.extend_from_slice(&channel_mask(channels).to_le_bytes());
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+    }
+
+    header.extend_from_slice(b"data");
+    // Patched on finalize
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header
+}
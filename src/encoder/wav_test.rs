@@ -0,0 +1,83 @@
+use std::io::Cursor;
+
+use super::{Endianness, PcmFormat, WavWriter};
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+#[test]
+fn encode_packed_24_bit() {
+    let mut little = vec![];
+    PcmFormat::new(SampleFormat::I24, Endianness::Little).encode(&[0.5f32, -1.0], &mut little);
+    assert_eq!(vec![0x00, 0x00, 0x40, 0x00, 0x00, 0x80], little);
+
+    let mut big = vec![];
+    PcmFormat::new(SampleFormat::I24, Endianness::Big).encode(&[0.5f32, -1.0], &mut big);
+    assert_eq!(vec![0x40, 0x00, 0x00, 0x80, 0x00, 0x00], big);
+}
+
+#[test]
+fn encode_same_format_is_lossless() {
+    let samples = [i16::MIN, -1, 0, 1, i16::MAX];
+    let mut encoded = vec![];
+    PcmFormat::new(SampleFormat::I16, Endianness::Big).encode(&samples, &mut encoded);
+    let decoded: Vec<_> = encoded
+        .chunks(2)
+        .map(|c| i16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    assert_eq!(samples.to_vec(), decoded);
+}
+
+#[test]
+fn wav_pcm_header() {
+    let mut writer = WavWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(2),
+        SampleRate(44100),
+        SampleFormat::I16,
+    )
+    .unwrap();
+    writer.write_samples(&[1i16, -1, 2, -2]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+
+    // RIFF header, 36 byte JUNK chunk, 24 byte fmt chunk, 8 byte data header and 8 data bytes
+    assert_eq!(12 + 36 + 24 + 8 + 8, bytes.len());
+    assert_eq!(b"RIFF", &bytes[0..4]);
+    assert_eq!((bytes.len() as u32 - 8).to_le_bytes(), bytes[4..8]);
+    assert_eq!(b"WAVE", &bytes[8..12]);
+    assert_eq!(b"fmt ", &bytes[48..52]);
+    // PCM, stereo, 44.1kHz, 176400 bytes per second, 4 byte frames, 16 bits
+    assert_eq!(
+        [
+            &1u16.to_le_bytes()[..],
+            &2u16.to_le_bytes(),
+            &44100u32.to_le_bytes(),
+            &176400u32.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            &16u16.to_le_bytes()
+        ]
+        .concat(),
+        bytes[56..72]
+    );
+    assert_eq!(b"data", &bytes[72..76]);
+    assert_eq!(8u32.to_le_bytes(), bytes[76..80]);
+    assert_eq!([1, 0, 255, 255, 2, 0, 254, 255], bytes[80..]);
+}
+
+#[test]
+fn wav_pads_odd_data() {
+    let mut writer = WavWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(1),
+        SampleRate(8000),
+        SampleFormat::I8,
+    )
+    .unwrap();
+    // I8 is stored as U8
+    assert_eq!(SampleFormat::U8, writer.sample_format());
+    writer.write_samples(&[0i8, 127, -128]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+
+    assert_eq!(3u32.to_le_bytes(), bytes[76..80]);
+    assert_eq!([128, 255, 0, 0], bytes[80..]);
+    assert_eq!((bytes.len() as u32 - 8).to_le_bytes(), bytes[4..8]);
+}
@@ -0,0 +1,138 @@
+use std::io::Cursor;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+
+use super::{Dither, ExportError, ExportSettings, export};
+use crate::decoder::{ReadSeekSource, Source};
+use crate::encoder::WavWriter;
+use crate::output::SampleFormat;
+use crate::recorder::RecordingContainer;
+use crate::{ChannelCount, SampleRate};
+
+const SOURCE_FRAMES: usize = 4410;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("decal-{}-{name}", std::process::id()))
+}
+
+// 100ms of stereo audio at 44.1kHz
+fn source() -> Box<dyn Source> {
+    let mut writer = WavWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(2),
+        SampleRate(44100),
+        SampleFormat::I16,
+    )
+    .unwrap();
+    writer.write_samples(&[0.5f32; SOURCE_FRAMES * 2]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+    let len = bytes.len() as u64;
+    Box::new(ReadSeekSource::new(
+        Cursor::new(bytes),
+        Some(len),
+        Some("wav".to_owned()),
+    ))
+}
+
+fn read_and_remove(path: &PathBuf) -> Vec<u8> {
+    let bytes = std::fs::read(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    bytes
+}
+
+#[test]
+fn export_wav_with_gain() {
+    let path = temp_path("export.wav");
+    let mut updates = 0;
+    let progress = export(
+        source(),
+        &ExportSettings {
+            path: path.clone(),
+            channels: ChannelCount(1),
+            gain_db: -6.0206,
+            dither: Dither::None,
+            ..Default::default()
+        },
+        |_| {
+            updates += 1;
+            ControlFlow::Continue(())
+        },
+    )
+    .unwrap();
+
+    assert!(updates > 0);
+    assert_eq!(SOURCE_FRAMES as u64, progress.frames);
+    assert_eq!(SampleRate(44100), progress.sample_rate);
+    let bytes = read_and_remove(&path);
+    assert_eq!((SOURCE_FRAMES as u32 * 2).to_le_bytes(), bytes[76..80]);
+    let first = i16::from_le_bytes([bytes[80], bytes[81]]);
+    assert!((first - 8192).abs() <= 1, "{first}");
+}
+
+#[test]
+fn export_resampled_aiff() {
+    let path = temp_path("export.aiff");
+    let progress = export(
+        source(),
+        &ExportSettings {
+            path: path.clone(),
+            container: RecordingContainer::Aiff,
+            sample_format: SampleFormat::I24,
+            sample_rate: Some(SampleRate(22050)),
+            ..Default::default()
+        },
+        |_| ControlFlow::Continue(()),
+    )
+    .unwrap();
+
+    let bytes = read_and_remove(&path);
+    assert_eq!(b"AIFF", &bytes[8..12]);
+    // Stereo, 24 bits, 22.05kHz
+    assert_eq!(2u16.to_be_bytes(), bytes[20..22]);
+    assert_eq!(
+        progress.frames as u32,
+        u32::from_be_bytes(bytes[22..26].try_into().unwrap())
+    );
+    assert_eq!(24u16.to_be_bytes(), bytes[26..28]);
+    assert_eq!([0x40, 0x0D, 0xAC, 0x44], bytes[28..32]);
+    // The resampler's delay means the length isn't exact
+    let expected = SOURCE_FRAMES as i64 / 2;
+    assert!(
+        (progress.frames as i64 - expected).abs() < 1024,
+        "{}",
+        progress.frames
+    );
+}
+
+#[test]
+fn export_cancelled() {
+    let path = temp_path("cancelled.flac");
+    let result = export(
+        source(),
+        &ExportSettings {
+            path: path.clone(),
+            container: RecordingContainer::Flac,
+            ..Default::default()
+        },
+        |_| ControlFlow::Break(()),
+    );
+
+    assert!(matches!(result, Err(ExportError::Cancelled)));
+    assert!(!path.exists());
+}
+
+#[test]
+fn export_rejects_surround() {
+    let result = export(
+        source(),
+        &ExportSettings {
+            channels: ChannelCount(6),
+            ..Default::default()
+        },
+        |_| ControlFlow::Continue(()),
+    );
+    assert!(matches!(
+        result,
+        Err(ExportError::UnsupportedChannels(ChannelCount(6)))
+    ));
+}
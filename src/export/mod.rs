@@ -0,0 +1,256 @@
+use std::io;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::decoder::{
+    Decoder, DecoderError, DecoderResult, DecoderSettings, ResampledDecoder, ResamplerSettings,
+    Source,
+};
+use crate::output::SampleFormat;
+use crate::recorder::{
+    RecorderError, RecordingContainer, RecordingWriter, check_format, db_to_amplitude,
+};
+use crate::{ChannelCount, SampleRate};
+
+#[cfg(test)]
+#[path = "./export_test.rs"]
+mod export_test;
+
+/// Noise added to the audio before it's reduced to the file's bit depth, which turns
+/// quantization distortion into a constant noise floor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Dither {
+    None,
+    /// Uniform noise of one least significant bit.
+    Rectangular,
+    /// Triangular noise of two least significant bits, which also keeps the noise floor
+    /// independent of the signal.
+    #[default]
+    Triangular,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExportSettings {
+    pub path: PathBuf,
+    pub container: RecordingContainer,
+    /// The format samples are stored in, which also sets the bit depth.
+    pub sample_format: SampleFormat,
+    /// The rate of the exported file. Defaults to the source's sample rate.
+    pub sample_rate: Option<SampleRate>,
+    /// Only mono and stereo are supported. Mono sources are copied to both channels and stereo
+    /// sources are mixed down.
+    pub channels: ChannelCount,
+    pub gain_db: f32,
+    /// Ignored when exporting float samples.
+    pub dither: Dither,
+    pub decoder_settings: DecoderSettings,
+    pub resampler_settings: ResamplerSettings,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("export.wav"),
+            container: RecordingContainer::Wav,
+            sample_format: SampleFormat::I16,
+            sample_rate: None,
+            channels: ChannelCount(2),
+            gain_db: 0.0,
+            dither: Dither::Triangular,
+            decoder_settings: DecoderSettings::default(),
+            resampler_settings: ResamplerSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExportProgress {
+    /// Number of frames written so far.
+    pub frames: u64,
+    pub sample_rate: SampleRate,
+    /// Length of the source, if the format reports it.
+    pub duration: Option<Duration>,
+}
+
+impl ExportProgress {
+    pub fn position(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.sample_rate.0.max(1) as f64)
+    }
+
+    /// How much of the source has been exported, from 0 to 1.
+    pub fn fraction(&self) -> Option<f32> {
+        let duration = self.duration?;
+        if duration.is_zero() {
+            return Some(1.0);
+        }
+        Some((self.position().as_secs_f64() / duration.as_secs_f64()).min(1.0) as f32)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("Exports can only have 1 or 2 channels, got {0:?}")]
+    UnsupportedChannels(ChannelCount),
+    #[error("Export was cancelled")]
+    Cancelled,
+    #[error("Error writing export: {0}")]
+    IoError(#[from] io::Error),
+    #[error(transparent)]
+    RecorderError(#[from] RecorderError),
+    #[error(transparent)]
+    DecoderError(#[from] DecoderError),
+}
+
+/// Decodes the source and writes it to a file as fast as it can be decoded. No output device
+/// is involved, so this can run on any thread.
+///
+/// `on_progress` is called after each chunk is written. Returning [`ControlFlow::Break`] cancels
+/// the export. The partially written file is removed if the export is cancelled or fails.
+pub fn export(
+    source: Box<dyn Source>,
+    settings: &ExportSettings,
+    mut on_progress: impl FnMut(&ExportProgress) -> ControlFlow<()>,
+) -> Result<ExportProgress, ExportError> {
+    if !(1..=2).contains(&settings.channels.0) {
+        return Err(ExportError::UnsupportedChannels(settings.channels));
+    }
+    check_format(settings.container, settings.sample_format)?;
+
+    let mut decoder = Decoder::<f32>::new(
+        source,
+        db_to_amplitude(settings.gain_db),
+        settings.channels,
+        settings.decoder_settings.clone(),
+    )?;
+    let sample_rate = settings.sample_rate.unwrap_or(decoder.sample_rate());
+    let mut resampled = ResampledDecoder::new(
+        sample_rate,
+        settings.channels,
+        settings.resampler_settings.clone(),
+    );
+    resampled.initialize(&mut decoder)?;
+
+    info!("Exporting to {:?}", settings.path);
+    let writer = RecordingWriter::create(
+        &settings.path,
+        settings.container,
+        settings.channels,
+        sample_rate,
+        settings.sample_format,
+    )?;
+    let mut export = ExportWriter {
+        dither: DitherState::new(settings.dither, writer.sample_format()),
+        writer,
+        channels: settings.channels.0 as usize,
+        buf: Vec::new(),
+        progress: ExportProgress {
+            frames: 0,
+            sample_rate,
+            duration: decoder.duration(),
+        },
+    };
+
+    match export.run(&mut decoder, &mut resampled, &mut on_progress) {
+        Ok(()) => {
+            let progress = export.progress;
+            export.writer.finalize()?;
+            Ok(progress)
+        }
+        Err(e) => {
+            drop(export);
+            if let Err(remove_error) = std::fs::remove_file(&settings.path) {
+                warn!("Error removing incomplete export: {remove_error:?}");
+            }
+            Err(e)
+        }
+    }
+}
+
+struct ExportWriter {
+    writer: RecordingWriter,
+    dither: DitherState,
+    channels: usize,
+    buf: Vec<f64>,
+    progress: ExportProgress,
+}
+
+impl ExportWriter {
+    fn run(
+        &mut self,
+        decoder: &mut Decoder<f32>,
+        resampled: &mut ResampledDecoder<f32>,
+        on_progress: &mut impl FnMut(&ExportProgress) -> ControlFlow<()>,
+    ) -> Result<(), ExportError> {
+        loop {
+            self.write(resampled.current(decoder), on_progress)?;
+            if resampled.decode_next_frame(decoder)? == DecoderResult::Finished {
+                break;
+            }
+        }
+        self.write(resampled.flush(), on_progress)
+    }
+
+    fn write(
+        &mut self,
+        samples: &[f32],
+        on_progress: &mut impl FnMut(&ExportProgress) -> ControlFlow<()>,
+    ) -> Result<(), ExportError> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        self.buf.clear();
+        self.buf.extend(
+            samples
+                .iter()
+                .map(|sample| *sample as f64 + self.dither.next()),
+        );
+        self.writer.write_samples(&self.buf)?;
+        self.progress.frames += (samples.len() / self.channels) as u64;
+        if on_progress(&self.progress).is_break() {
+            info!("Export cancelled");
+            return Err(ExportError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+struct DitherState {
+    dither: Dither,
+    lsb: f64,
+    rng: u64,
+}
+
+impl DitherState {
+    fn new(dither: Dither, sample_format: SampleFormat) -> Self {
+        let dither = if sample_format.is_float() {
+            Dither::None
+        } else {
+            dither
+        };
+        Self {
+            dither,
+            lsb: 2f64.powi(1 - sample_format.bits_per_sample() as i32),
+            rng: 0x853C_49E6_748F_EA9B,
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        match self.dither {
+            Dither::None => 0.0,
+            Dither::Rectangular => (self.random() - 0.5) * self.lsb,
+            Dither::Triangular => (self.random() - self.random()) * self.lsb,
+        }
+    }
+
+    /// Xorshift, returning a value between 0 and 1.
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -3,7 +3,15 @@ mod audio_manager;
 #[cfg(feature = "decoder")]
 pub mod decoder;
 #[cfg(feature = "output")]
+pub mod encoder;
+#[cfg(all(feature = "decoder", feature = "output"))]
+pub mod export;
+#[cfg(all(feature = "decoder", feature = "output"))]
+pub mod mixer;
+#[cfg(feature = "output")]
 pub mod output;
+#[cfg(feature = "output")]
+pub mod recorder;
 #[cfg(all(feature = "decoder", feature = "output"))]
 pub use audio_manager::*;
 #[cfg(feature = "decoder")]
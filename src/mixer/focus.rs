@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+/// Orders voices for ducking. While a voice is playing, voices with a lower priority are ducked
+/// or paused according to the [`FocusSettings`] for its priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Priority(pub u8);
+
+impl Priority {
+    pub const MEDIA: Self = Self(0);
+    pub const NOTIFICATION: Self = Self(128);
+    pub const VOICE_PROMPT: Self = Self(192);
+}
+
+/// How voices of one priority affect lower-priority voices while they play.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FocusSettings {
+    /// Linear gain lower-priority voices are ducked to. 0.25 is about -12 dB.
+    pub duck_gain: f32,
+    /// How long it takes to duck.
+    pub attack: Duration,
+    /// How long it takes to come back once nothing is ducking the voice.
+    pub release: Duration,
+    /// Pause lower-priority voices instead of ducking them. They fade out over `attack` and
+    /// resume from where they were, fading back in over `release`.
+    pub exclusive: bool,
+}
+
+impl Default for FocusSettings {
+    fn default() -> Self {
+        Self {
+            duck_gain: 0.25,
+            attack: Duration::from_millis(100),
+            release: Duration::from_millis(500),
+            exclusive: false,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use super::{BufferSource, FocusSettings, Mixer, Priority, VoiceSettings};
+use crate::decoder::{Decoder, DecoderSettings, ReadSeekSource, ResamplerSettings};
+use crate::encoder::WavWriter;
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+fn mono(samples: Vec<f32>, sample_rate: u32) -> BufferSource {
+    BufferSource::new(samples, ChannelCount(1), SampleRate(sample_rate))
+}
+
+fn assert_close(expected: &[f32], actual: &[f32]) {
+    assert_eq!(expected.len(), actual.len());
+    for (expected, actual) in expected.iter().zip(actual) {
+        assert!(
+            (expected - actual).abs() < 1e-4,
+            "expected {expected:?}, got {actual:?}"
+        );
+    }
+}
+
+#[test]
+fn mixes_gain_and_pan() {
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(2));
+    mixer.add(
+        mono(vec![0.5; 64], 44100),
+        VoiceSettings {
+            gain: 0.5,
+            pan: -1.0,
+            ..Default::default()
+        },
+    );
+    mixer.add(
+        BufferSource::new([0.1, 0.2].repeat(64), ChannelCount(2), SampleRate(44100)),
+        VoiceSettings::default(),
+    );
+
+    let mut out = vec![0.0; 8];
+    mixer.mix(&mut out);
+    assert_close(&[0.35, 0.2].repeat(4), &out);
+
+    mixer.set_master_gain(2.0);
+    mixer.mix(&mut out);
+    assert_close(&[0.7, 0.4].repeat(4), &out);
+}
+
+#[test]
+fn downmixes_surround_sources() {
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(2));
+    // Front left, front right, center, LFE, surround left, surround right
+    let center = mixer.add(
+        BufferSource::new(
+            [0.0, 0.0, 1.0, 0.0, 0.0, 0.0].repeat(4),
+            ChannelCount(6),
+            SampleRate(44100),
+        ),
+        VoiceSettings::default(),
+    );
+    let mut out = vec![0.0; 8];
+    mixer.mix(&mut out);
+    assert!(out[0] > 0.0);
+    assert_close(&out[..2].repeat(4), &out);
+    assert_eq!(out[0], out[1]);
+    mixer.stop(center, Duration::ZERO);
+
+    mixer.add(
+        BufferSource::new(
+            [0.0, 0.0, 0.0, 0.0, 1.0, 0.0].repeat(4),
+            ChannelCount(6),
+            SampleRate(44100),
+        ),
+        VoiceSettings::default(),
+    );
+    mixer.mix(&mut out);
+    assert!(out[0] > 0.0);
+    assert_eq!(0.0, out[1]);
+}
+
+#[test]
+fn removes_finished_voices() {
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(1));
+    let short = mixer.add(mono(vec![0.5; 4], 44100), VoiceSettings::default());
+    let long = mixer.add(mono(vec![0.25; 64], 44100), VoiceSettings::default());
+
+    let mut out = vec![0.0; 8];
+    mixer.mix(&mut out);
+    assert_close(&[0.75, 0.75, 0.75, 0.75, 0.25, 0.25, 0.25, 0.25], &out);
+    assert_eq!(vec![short], mixer.take_finished());
+    assert!(!mixer.contains(short));
+    assert!(mixer.contains(long));
+    assert!(mixer.take_finished().is_empty());
+}
+
+#[test]
+fn resamples_sources() {
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(1));
+    let id = mixer.add(mono(vec![0.5; 100], 22050), VoiceSettings::default());
+
+    let mut out = vec![0.0; 150];
+    mixer.mix(&mut out);
+    assert_close(&[0.5; 150], &out);
+    assert!(mixer.contains(id));
+
+    let mut out = vec![0.0; 100];
+    mixer.mix(&mut out);
+    assert_close(&[0.5; 50], &out[..50]);
+    assert_close(&[0.0; 50], &out[50..]);
+    assert!(mixer.is_empty());
+}
+
+#[test]
+fn fades_pauses_and_stops() {
+    let mut mixer = Mixer::new(SampleRate(1000), ChannelCount(1));
+    let id = mixer.add(
+        mono(vec![1.0; 1000], 1000),
+        VoiceSettings {
+            fade_in: Duration::from_millis(4),
+            ..Default::default()
+        },
+    );
+
+    let mut out = vec![0.0; 6];
+    mixer.mix(&mut out);
+    assert_close(&[0.0, 0.25, 0.5, 0.75, 1.0, 1.0], &out);
+
+    assert!(mixer.pause(id));
+    mixer.mix(&mut out);
+    assert_close(&[0.0; 6], &out);
+    assert!(mixer.is_paused(id));
+
+    assert!(mixer.resume(id));
+    assert!(mixer.stop(id, Duration::from_millis(2)));
+    mixer.mix(&mut out);
+    assert_close(&[1.0, 0.5, 0.0, 0.0, 0.0, 0.0], &out);
+    assert_eq!(vec![id], mixer.take_finished());
+    assert!(!mixer.stop(id, Duration::ZERO));
+}
+
+#[test]
+fn stops_paused_voices() {
+    let mut mixer = Mixer::new(SampleRate(1000), ChannelCount(1));
+    mixer.set_focus(
+        Priority::VOICE_PROMPT,
+        FocusSettings {
+            attack: Duration::ZERO,
+            exclusive: true,
+            ..Default::default()
+        },
+    );
+    let paused = mixer.add(
+        mono(vec![1.0; 1000], 1000),
+        VoiceSettings {
+            paused: true,
+            ..Default::default()
+        },
+    );
+    let music = mixer.add(mono(vec![1.0; 1000], 1000), VoiceSettings::default());
+    let prompt = mixer.add(
+        mono(vec![0.5; 1000], 1000),
+        VoiceSettings {
+            priority: Priority::VOICE_PROMPT,
+            ..Default::default()
+        },
+    );
+
+    let mut out = vec![0.0; 4];
+    mixer.mix(&mut out);
+    assert!(mixer.is_focus_paused(music));
+
+    assert!(mixer.stop(paused, Duration::from_millis(100)));
+    assert!(mixer.stop(music, Duration::from_millis(100)));
+    mixer.mix(&mut out);
+    assert_close(&[0.5; 4], &out);
+    let mut finished = mixer.take_finished();
+    finished.sort();
+    assert_eq!(vec![paused, music], finished);
+    assert_eq!(vec![prompt], mixer.voices().collect::<Vec<_>>());
+}
+
+#[test]
+fn ducks_lower_priorities() {
+    let mut mixer = Mixer::new(SampleRate(1000), ChannelCount(1));
+    mixer.set_focus(
+        Priority::NOTIFICATION,
+        FocusSettings {
+            duck_gain: 0.5,
+            attack: Duration::from_millis(2),
+            release: Duration::from_millis(4),
+            exclusive: false,
+        },
+    );
+    let music = mixer.add(mono(vec![1.0; 1000], 1000), VoiceSettings::default());
+    mixer.add(
+        mono(vec![0.0; 4], 1000),
+        VoiceSettings {
+            priority: Priority::NOTIFICATION,
+            ..Default::default()
+        },
+    );
+
+    let mut out = vec![0.0; 8];
+    mixer.mix(&mut out);
+    assert_close(&[1.0, 0.75, 0.5, 0.5, 0.5, 0.5, 0.5, 0.5], &out);
+
+    // Comes back once the notification ends
+    mixer.mix(&mut out);
+    assert_close(&[0.5, 0.625, 0.75, 0.875, 1.0, 1.0, 1.0, 1.0], &out);
+    assert!(mixer.contains(music));
+}
+
+#[test]
+fn exclusive_focus_pauses_lower_priorities() {
+    let mut mixer = Mixer::new(SampleRate(1000), ChannelCount(1));
+    mixer.set_focus(
+        Priority::VOICE_PROMPT,
+        FocusSettings {
+            attack: Duration::from_millis(2),
+            release: Duration::from_millis(2),
+            exclusive: true,
+            ..Default::default()
+        },
+    );
+    let music = mixer.add(mono(vec![1.0; 10], 1000), VoiceSettings::default());
+    mixer.add(
+        mono(vec![0.0; 6], 1000),
+        VoiceSettings {
+            priority: Priority::VOICE_PROMPT,
+            ..Default::default()
+        },
+    );
+
+    let mut out = vec![0.0; 4];
+    mixer.mix(&mut out);
+    assert_close(&[1.0, 0.5, 0.0, 0.0], &out);
+    assert!(mixer.is_focus_paused(music));
+
+    mixer.mix(&mut out);
+    assert_close(&[0.0; 4], &out);
+
+    // Resumes from where it was paused
+    mixer.mix(&mut out);
+    assert_close(&[0.0, 0.5, 1.0, 1.0], &out);
+    assert!(!mixer.is_focus_paused(music));
+    mixer.mix(&mut out);
+    assert_close(&[1.0, 1.0, 0.0, 0.0], &out);
+    assert!(mixer.is_empty());
+}
+
+#[test]
+fn plays_decoders() {
+    let mut writer = WavWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(2),
+        SampleRate(44100),
+        SampleFormat::I16,
+    )
+    .unwrap();
+    writer.write_samples(&[0.5f32; 1000 * 2]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+    let len = bytes.len() as u64;
+    let decoder = Decoder::<f32>::new(
+        Box::new(ReadSeekSource::new(
+            Cursor::new(bytes),
+            Some(len),
+            Some("wav".to_owned()),
+        )),
+        1.0,
+        ChannelCount(2),
+        DecoderSettings::default(),
+    )
+    .unwrap();
+
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(2));
+    mixer
+        .add_decoder(
+            decoder,
+            ResamplerSettings::default(),
+            VoiceSettings::default(),
+        )
+        .unwrap();
+
+    let mut out = vec![0.0; 2000 * 2];
+    mixer.mix(&mut out);
+    assert_close(&[0.5; 1000 * 2], &out[..1000 * 2]);
+    assert_close(&[0.0; 1000 * 2], &out[1000 * 2..]);
+    assert!(mixer.is_empty());
+}
@@ -0,0 +1,374 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::decoder::{Decoder, DecoderError, ResamplerSettings};
+use crate::{ChannelCount, SampleRate};
+
+mod focus;
+pub use focus::*;
+mod sound_bank;
+pub use sound_bank::*;
+mod source;
+pub use source::*;
+mod spatial;
+pub use spatial::*;
+mod voice;
+use voice::Voice;
+
+#[cfg(test)]
+#[path = "./mixer_test.rs"]
+mod mixer_test;
+#[cfg(test)]
+#[path = "./sound_bank_test.rs"]
+mod sound_bank_test;
+#[cfg(test)]
+#[path = "./spatial_test.rs"]
+mod spatial_test;
+
+/// Identifies a voice in a [`Mixer`]. IDs aren't reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VoiceId(u64);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoiceSettings {
+    /// Linear gain.
+    pub gain: f32,
+    /// From -1 (left) to 1 (right). Mono sources are panned with constant power, other sources
+    /// are balanced between the left and right channels.
+    pub pan: f32,
+    pub fade_in: Duration,
+    /// Add the voice without starting it.
+    pub paused: bool,
+    pub priority: Priority,
+    /// Places the voice in 3D space. Ignored unless the source is mono.
+    pub emitter: Option<Emitter>,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            pan: 0.0,
+            fade_in: Duration::ZERO,
+            paused: false,
+            priority: Priority::MEDIA,
+            emitter: None,
+        }
+    }
+}
+
+/// Mixes any number of sources into one stream. Each source is converted to the mixer's rate
+/// and channel count and has its own gain, pan and fades. Voices are removed once their source
+/// ends.
+///
+/// Mono voices can be given an [`Emitter`] to place them around the mixer's [`Listener`].
+///
+/// Priorities that have [`FocusSettings`] duck or pause lower-priority voices while any of their
+/// voices are playing, so music can make way for a voice prompt and come back afterwards.
+///
+/// The mixer doesn't play anything by itself. Pass it to
+/// [`AudioManager::write_mixer`](crate::AudioManager::write_mixer) or call [`mix`](Self::mix)
+/// directly.
+pub struct Mixer {
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    voices: Vec<Voice>,
+    master_gain: f32,
+    next_id: u64,
+    finished: Vec<VoiceId>,
+    focus: BTreeMap<Priority, FocusSettings>,
+    // Priorities with a voice that holds focus, reused between mixes
+    focus_holders: Vec<Priority>,
+    listener: Listener,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: SampleRate, channels: ChannelCount) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            voices: Vec::new(),
+            master_gain: 1.0,
+            next_id: 0,
+            finished: Vec::new(),
+            focus: BTreeMap::new(),
+            focus_holders: Vec::new(),
+            listener: Listener::default(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// Changes the format that's mixed to. Voices keep playing from where they were.
+    pub fn set_format(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        for voice in &mut self.voices {
+            voice.set_output_rate(sample_rate);
+        }
+    }
+
+    pub fn add(&mut self, source: impl MixerSource + 'static, settings: VoiceSettings) -> VoiceId {
+        let id = VoiceId(self.next_id);
+        self.next_id += 1;
+        self.voices.push(Voice::new(
+            id,
+            Box::new(source),
+            self.sample_rate,
+            &settings,
+        ));
+        id
+    }
+
+    /// Adds a decoder, resampled to the mixer's current rate.
+    pub fn add_decoder(
+        &mut self,
+        decoder: Decoder<f32>,
+        resampler_settings: ResamplerSettings,
+        settings: VoiceSettings,
+    ) -> Result<VoiceId, DecoderError> {
+        let source = DecoderSource::new(decoder, self.sample_rate, resampler_settings)?;
+        Ok(self.add(source, settings))
+    }
+
+    pub fn contains(&self, id: VoiceId) -> bool {
+        self.voices.iter().any(|voice| voice.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.voices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty()
+    }
+
+    pub fn voices(&self) -> impl Iterator<Item = VoiceId> + '_ {
+        self.voices.iter().map(|voice| voice.id)
+    }
+
+    /// The voice's current gain, part way through a fade if one is running.
+    pub fn gain(&self, id: VoiceId) -> Option<f32> {
+        self.voices
+            .iter()
+            .find(|voice| voice.id == id)
+            .map(|voice| voice.gain.value())
+    }
+
+    /// Returns `false` if the voice isn't playing.
+    pub fn set_gain(&mut self, id: VoiceId, gain: f32) -> bool {
+        self.with_voice(id, |voice, _| voice.gain.set(gain))
+    }
+
+    /// Ramps the gain to the new value over `duration`.
+    pub fn fade_to(&mut self, id: VoiceId, gain: f32, duration: Duration) -> bool {
+        self.with_voice(id, |voice, sample_rate| {
+            voice
+                .gain
+                .fade_to(gain, duration_frames(duration, sample_rate))
+        })
+    }
+
+    pub fn set_pan(&mut self, id: VoiceId, pan: f32) -> bool {
+        self.with_voice(id, |voice, _| voice.pan = pan.clamp(-1.0, 1.0))
+    }
+
+    pub fn pause(&mut self, id: VoiceId) -> bool {
+        self.with_voice(id, |voice, _| voice.paused = true)
+    }
+
+    pub fn resume(&mut self, id: VoiceId) -> bool {
+        self.with_voice(id, |voice, _| voice.paused = false)
+    }
+
+    pub fn is_paused(&self, id: VoiceId) -> bool {
+        self.voices
+            .iter()
+            .any(|voice| voice.id == id && voice.paused)
+    }
+
+    /// Fades the voice out and removes it.
+    pub fn stop(&mut self, id: VoiceId, fade_out: Duration) -> bool {
+        self.with_voice(id, |voice, sample_rate| {
+            voice.stop(duration_frames(fade_out, sample_rate))
+        })
+    }
+
+    pub fn stop_all(&mut self, fade_out: Duration) {
+        let frames = duration_frames(fade_out, self.sample_rate);
+        for voice in &mut self.voices {
+            voice.stop(frames);
+        }
+    }
+
+    pub fn priority(&self, id: VoiceId) -> Option<Priority> {
+        self.voices
+            .iter()
+            .find(|voice| voice.id == id)
+            .map(|voice| voice.priority)
+    }
+
+    pub fn set_priority(&mut self, id: VoiceId, priority: Priority) -> bool {
+        self.with_voice(id, |voice, _| voice.priority = priority)
+    }
+
+    /// Whether the voice is paused because a voice with exclusive focus is playing.
+    pub fn is_focus_paused(&self, id: VoiceId) -> bool {
+        self.voices
+            .iter()
+            .any(|voice| voice.id == id && voice.is_focus_paused())
+    }
+
+    pub fn focus(&self, priority: Priority) -> Option<&FocusSettings> {
+        self.focus.get(&priority)
+    }
+
+    /// Makes voices of this priority duck or pause lower-priority voices while they play.
+    pub fn set_focus(&mut self, priority: Priority, settings: FocusSettings) {
+        self.focus.insert(priority, settings);
+    }
+
+    /// Stops voices of this priority from affecting other voices.
+    pub fn clear_focus(&mut self, priority: Priority) {
+        self.focus.remove(&priority);
+    }
+
+    pub fn listener(&self) -> &Listener {
+        &self.listener
+    }
+
+    /// Moves the listener. Spatialized voices follow it from the next mix.
+    pub fn set_listener(&mut self, listener: Listener) {
+        self.listener = listener;
+    }
+
+    pub fn emitter(&self, id: VoiceId) -> Option<&Emitter> {
+        self.voices
+            .iter()
+            .find(|voice| voice.id == id)
+            .and_then(|voice| voice.emitter())
+    }
+
+    /// Places a mono voice in 3D space, or stops spatializing it if `emitter` is `None`. Returns
+    /// `false` if the voice isn't playing or its source isn't mono.
+    pub fn set_emitter(&mut self, id: VoiceId, emitter: Option<Emitter>) -> bool {
+        self.voices
+            .iter_mut()
+            .find(|voice| voice.id == id)
+            .is_some_and(|voice| voice.set_emitter(emitter))
+    }
+
+    /// Updates a spatialized voice's position, meant to be called every frame of a game loop.
+    /// Returns `false` if the voice isn't playing or isn't spatialized.
+    pub fn move_emitter(&mut self, id: VoiceId, position: Vec3, velocity: Vec3) -> bool {
+        match self
+            .voices
+            .iter_mut()
+            .find(|voice| voice.id == id)
+            .and_then(|voice| voice.emitter_mut())
+        {
+            Some(emitter) => {
+                emitter.position = position;
+                emitter.velocity = velocity;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn master_gain(&self) -> f32 {
+        self.master_gain
+    }
+
+    /// Linear gain applied after every voice is mixed.
+    pub fn set_master_gain(&mut self, gain: f32) {
+        self.master_gain = gain;
+    }
+
+    /// Voices that ended or were stopped since the last call.
+    pub fn take_finished(&mut self) -> Vec<VoiceId> {
+        std::mem::take(&mut self.finished)
+    }
+
+    /// Mixes the next frames into `out`, replacing its contents. `out` should hold whole frames.
+    pub fn mix(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        let channels = self.channels.0.max(1) as usize;
+        let len = out.len() - out.len() % channels;
+        self.update_focus();
+        for voice in &mut self.voices {
+            voice.update_spatial(&self.listener, self.sample_rate, len / channels);
+            voice.mix_into(&mut out[..len], channels);
+        }
+        for sample in out.iter_mut() {
+            *sample = (*sample * self.master_gain).clamp(-1.0, 1.0);
+        }
+
+        let finished = &mut self.finished;
+        self.voices.retain(|voice| {
+            if voice.is_finished() {
+                finished.push(voice.id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn update_focus(&mut self) {
+        self.focus_holders.clear();
+        self.focus_holders.extend(
+            self.voices
+                .iter()
+                .filter(|voice| voice.holds_focus() && self.focus.contains_key(&voice.priority))
+                .map(|voice| voice.priority),
+        );
+        self.focus_holders.sort_unstable();
+        self.focus_holders.dedup();
+
+        for voice in &mut self.voices {
+            let mut target = 1.0;
+            let mut attack = 0;
+            let mut release = 0;
+            let mut pause = false;
+            for priority in self.focus_holders.iter().rev() {
+                if *priority <= voice.priority {
+                    break;
+                }
+                let settings = &self.focus[priority];
+                let gain = if settings.exclusive {
+                    0.0
+                } else {
+                    settings.duck_gain
+                };
+                if gain < target || (settings.exclusive && !pause) {
+                    target = gain;
+                    attack = duration_frames(settings.attack, self.sample_rate);
+                    release = duration_frames(settings.release, self.sample_rate);
+                    pause = settings.exclusive;
+                }
+            }
+            voice.duck(target, attack, release, pause);
+        }
+    }
+
+    fn with_voice(&mut self, id: VoiceId, f: impl FnOnce(&mut Voice, SampleRate)) -> bool {
+        match self.voices.iter_mut().find(|voice| voice.id == id) {
+            Some(voice) => {
+                f(voice, self.sample_rate);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub(crate) fn duration_frames(duration: Duration, sample_rate: SampleRate) -> usize {
+    (duration.as_secs_f64() * sample_rate.0 as f64).round() as usize
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::info;
+
+use super::{BufferSource, DecoderSource, Mixer, MixerSource, Priority, VoiceId, VoiceSettings};
+use crate::decoder::{Decoder, DecoderError, ResamplerSettings};
+use crate::{ChannelCount, SampleRate};
+
+// Stolen voices fade out over this long rather than cutting off with a click
+const STEAL_FADE: Duration = Duration::from_millis(5);
+// Frames decoded at a time when loading a clip
+const LOAD_CHUNK: usize = 4096;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SoundBankError {
+    #[error(transparent)]
+    DecoderError(#[from] DecoderError),
+    #[error("Clip needs {needed} bytes, but only {available} are left in the memory budget")]
+    OverBudget { needed: usize, available: usize },
+}
+
+/// Identifies a clip in a [`SoundBank`]. IDs aren't reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClipId(u64);
+
+/// Which voice is stopped when a clip that's already at its polyphony limit is triggered again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StealMode {
+    #[default]
+    Oldest,
+    Quietest,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClipSettings {
+    /// How many voices of the clip can play at once.
+    pub max_voices: usize,
+    pub steal: StealMode,
+}
+
+impl Default for ClipSettings {
+    fn default() -> Self {
+        Self {
+            max_voices: 4,
+            steal: StealMode::Oldest,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TriggerSettings {
+    /// Linear gain.
+    pub gain: f32,
+    /// Playback speed. 2.0 plays an octave higher and twice as fast.
+    pub pitch: f32,
+    /// From -1 (left) to 1 (right).
+    pub pan: f32,
+    pub priority: Priority,
+}
+
+impl Default for TriggerSettings {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            pitch: 1.0,
+            pan: 0.0,
+            priority: Priority::MEDIA,
+        }
+    }
+}
+
+struct Clip {
+    samples: Arc<[f32]>,
+    channels: ChannelCount,
+    settings: ClipSettings,
+    // Voices playing the clip, oldest first
+    voices: Vec<VoiceId>,
+}
+
+/// Short clips decoded into memory ahead of time, so triggering one only has to add a voice to
+/// a [`Mixer`].
+pub struct SoundBank {
+    sample_rate: SampleRate,
+    resampler_settings: ResamplerSettings,
+    clips: HashMap<ClipId, Clip>,
+    next_id: u64,
+    memory_budget: Option<usize>,
+    memory_used: usize,
+}
+
+impl SoundBank {
+    /// Clips are resampled to `sample_rate` when they're loaded. This should match the mixer's
+    /// rate, otherwise every trigger is resampled again while it plays.
+    pub fn new(sample_rate: SampleRate, resampler_settings: ResamplerSettings) -> Self {
+        Self {
+            sample_rate,
+            resampler_settings,
+            clips: HashMap::new(),
+            next_id: 0,
+            memory_budget: None,
+            memory_used: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// Limits how many bytes of decoded audio the bank can hold. Clips that are already loaded
+    /// are kept even if they go over the new budget.
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    /// Bytes of decoded audio held by the bank.
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    /// Decodes the whole clip into memory.
+    pub fn load(
+        &mut self,
+        decoder: Decoder<f32>,
+        settings: ClipSettings,
+    ) -> Result<ClipId, SoundBankError> {
+        let channels = decoder.channels();
+        // Bail out before decoding if the clip clearly won't fit
+        if let Some(duration) = decoder.duration() {
+            let estimate = duration.as_secs_f64()
+                * self.sample_rate.0 as f64
+                * channels.0 as f64
+                * size_of::<f32>() as f64;
+            self.check_budget(estimate as usize)?;
+        }
+
+        let mut source =
+            DecoderSource::new(decoder, self.sample_rate, self.resampler_settings.clone())?;
+        let chunk = LOAD_CHUNK * channels.0.max(1) as usize;
+        let mut samples = Vec::new();
+        loop {
+            let start = samples.len();
+            samples.resize(start + chunk, 0.0);
+            let read = source.read(&mut samples[start..]);
+            samples.truncate(start + read);
+            if read < chunk {
+                break;
+            }
+        }
+        let bytes = samples.len() * size_of::<f32>();
+        self.check_budget(bytes)?;
+
+        let id = ClipId(self.next_id);
+        self.next_id += 1;
+        info!("Loaded clip {id:?}: {bytes} bytes");
+        self.memory_used += bytes;
+        self.clips.insert(
+            id,
+            Clip {
+                samples: samples.into(),
+                channels,
+                settings,
+                voices: Vec::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Frees the clip. Voices that are already playing it keep playing.
+    pub fn unload(&mut self, id: ClipId) -> bool {
+        match self.clips.remove(&id) {
+            Some(clip) => {
+                self.memory_used -= clip.samples.len() * size_of::<f32>();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, id: ClipId) -> bool {
+        self.clips.contains_key(&id)
+    }
+
+    pub fn clip_duration(&self, id: ClipId) -> Option<Duration> {
+        self.clips.get(&id).map(|clip| {
+            let frames = clip.samples.len() / clip.channels.0.max(1) as usize;
+            Duration::from_secs_f64(frames as f64 / self.sample_rate.0 as f64)
+        })
+    }
+
+    /// Returns `false` if the clip isn't loaded.
+    pub fn set_clip_settings(&mut self, id: ClipId, settings: ClipSettings) -> bool {
+        match self.clips.get_mut(&id) {
+            Some(clip) => {
+                clip.settings = settings;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts playing the clip on the mixer, stealing a voice if the clip is already playing as
+    /// many times as it's allowed to. Returns `None` if the clip isn't loaded.
+    pub fn trigger(
+        &mut self,
+        mixer: &mut Mixer,
+        id: ClipId,
+        settings: TriggerSettings,
+    ) -> Option<VoiceId> {
+        let clip = self.clips.get_mut(&id)?;
+        clip.voices.retain(|voice| mixer.contains(*voice));
+        while !clip.voices.is_empty() && clip.voices.len() >= clip.settings.max_voices.max(1) {
+            let index = match clip.settings.steal {
+                StealMode::Oldest => 0,
+                StealMode::Quietest => clip
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| {
+                        let a = mixer.gain(**a).unwrap_or(0.0);
+                        let b = mixer.gain(**b).unwrap_or(0.0);
+                        a.total_cmp(&b)
+                    })
+                    .map(|(index, _)| index)
+                    .unwrap_or(0),
+            };
+            mixer.stop(clip.voices.remove(index), STEAL_FADE);
+        }
+
+        // Pitch shifting is left to the mixer's rate conversion
+        let pitch = settings.pitch.max(0.01) as f64;
+        let sample_rate = SampleRate((self.sample_rate.0 as f64 * pitch).round().max(1.0) as u32);
+        let voice = mixer.add(
+            BufferSource::new(clip.samples.clone(), clip.channels, sample_rate),
+            VoiceSettings {
+                gain: settings.gain,
+                pan: settings.pan,
+                priority: settings.priority,
+                ..Default::default()
+            },
+        );
+        clip.voices.push(voice);
+        Some(voice)
+    }
+
+    fn check_budget(&self, needed: usize) -> Result<(), SoundBankError> {
+        let Some(budget) = self.memory_budget else {
+            return Ok(());
+        };
+        let available = budget.saturating_sub(self.memory_used);
+        if needed > available {
+            return Err(SoundBankError::OverBudget { needed, available });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use super::{
+    BufferSource, ClipSettings, Mixer, SoundBank, SoundBankError, StealMode, TriggerSettings,
+    VoiceSettings,
+};
+use crate::decoder::{Decoder, DecoderSettings, ReadSeekSource, ResamplerSettings};
+use crate::encoder::WavWriter;
+use crate::output::SampleFormat;
+use crate::{ChannelCount, SampleRate};
+
+const CLIP_FRAMES: usize = 1000;
+
+fn clip() -> Decoder<f32> {
+    let mut writer = WavWriter::new(
+        Cursor::new(vec![]),
+        ChannelCount(1),
+        SampleRate(44100),
+        SampleFormat::I16,
+    )
+    .unwrap();
+    writer.write_samples(&[0.5f32; CLIP_FRAMES]).unwrap();
+    let bytes = writer.finalize().unwrap().into_inner();
+    let len = bytes.len() as u64;
+    Decoder::new(
+        Box::new(ReadSeekSource::new(
+            Cursor::new(bytes),
+            Some(len),
+            Some("wav".to_owned()),
+        )),
+        1.0,
+        ChannelCount(1),
+        DecoderSettings::default(),
+    )
+    .unwrap()
+}
+
+fn bank() -> SoundBank {
+    SoundBank::new(SampleRate(44100), ResamplerSettings::default())
+}
+
+#[test]
+fn loads_and_triggers_clips() {
+    let mut bank = bank();
+    let id = bank.load(clip(), ClipSettings::default()).unwrap();
+    assert_eq!(CLIP_FRAMES * size_of::<f32>(), bank.memory_used());
+    assert_eq!(
+        Some(Duration::from_secs_f64(CLIP_FRAMES as f64 / 44100.0)),
+        bank.clip_duration(id)
+    );
+
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(1));
+    let voice = bank
+        .trigger(
+            &mut mixer,
+            id,
+            TriggerSettings {
+                gain: 0.5,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    let mut out = vec![0.0; CLIP_FRAMES * 2];
+    mixer.mix(&mut out);
+    assert!(out[..CLIP_FRAMES].iter().all(|s| (s - 0.25).abs() < 1e-4));
+    assert!(out[CLIP_FRAMES..].iter().all(|s| *s == 0.0));
+    assert_eq!(vec![voice], mixer.take_finished());
+}
+
+#[test]
+fn pitch_changes_speed() {
+    let mut bank = bank();
+    let id = bank.load(clip(), ClipSettings::default()).unwrap();
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(1));
+    bank.trigger(
+        &mut mixer,
+        id,
+        TriggerSettings {
+            pitch: 2.0,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let mut out = vec![0.0; CLIP_FRAMES];
+    mixer.mix(&mut out);
+    assert!(
+        out[..CLIP_FRAMES / 2 - 1]
+            .iter()
+            .all(|s| (s - 0.5).abs() < 1e-4)
+    );
+    assert!(out[CLIP_FRAMES / 2 + 1..].iter().all(|s| *s == 0.0));
+    assert!(mixer.is_empty());
+}
+
+#[test]
+fn steals_oldest_voice() {
+    let mut bank = bank();
+    let id = bank
+        .load(
+            clip(),
+            ClipSettings {
+                max_voices: 2,
+                steal: StealMode::Oldest,
+            },
+        )
+        .unwrap();
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(1));
+    let first = bank.trigger(&mut mixer, id, Default::default()).unwrap();
+    let second = bank.trigger(&mut mixer, id, Default::default()).unwrap();
+    let third = bank.trigger(&mut mixer, id, Default::default()).unwrap();
+
+    // The stolen voice fades out quickly
+    let mut out = vec![0.0; 256];
+    mixer.mix(&mut out);
+    assert_eq!(vec![first], mixer.take_finished());
+    assert!(mixer.contains(second));
+    assert!(mixer.contains(third));
+}
+
+#[test]
+fn steals_quietest_voice() {
+    let mut bank = bank();
+    let id = bank
+        .load(
+            clip(),
+            ClipSettings {
+                max_voices: 2,
+                steal: StealMode::Quietest,
+            },
+        )
+        .unwrap();
+    let mut mixer = Mixer::new(SampleRate(44100), ChannelCount(1));
+    // Voices that aren't from the bank don't count towards the limit
+    let other = mixer.add(
+        BufferSource::new(vec![0.0; 4096], ChannelCount(1), SampleRate(44100)),
+        VoiceSettings::default(),
+    );
+    let trigger = |gain| TriggerSettings {
+        gain,
+        ..Default::default()
+    };
+    let loud = bank.trigger(&mut mixer, id, trigger(1.0)).unwrap();
+    let quiet = bank.trigger(&mut mixer, id, trigger(0.2)).unwrap();
+    bank.trigger(&mut mixer, id, trigger(0.8)).unwrap();
+
+    let mut out = vec![0.0; 256];
+    mixer.mix(&mut out);
+    assert_eq!(vec![quiet], mixer.take_finished());
+    assert!(mixer.contains(loud));
+    assert!(mixer.contains(other));
+}
+
+#[test]
+fn enforces_memory_budget() {
+    let mut bank = bank();
+    bank.set_memory_budget(Some(CLIP_FRAMES * size_of::<f32>() * 3 / 2));
+    let id = bank.load(clip(), ClipSettings::default()).unwrap();
+
+    let err = bank.load(clip(), ClipSettings::default()).unwrap_err();
+    assert!(matches!(err, SoundBankError::OverBudget { .. }));
+    assert_eq!(CLIP_FRAMES * size_of::<f32>(), bank.memory_used());
+
+    assert!(bank.unload(id));
+    assert_eq!(0, bank.memory_used());
+    bank.load(clip(), ClipSettings::default()).unwrap();
+}
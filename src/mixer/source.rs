@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::decoder::{Decoder, DecoderError, DecoderResult, ResampledDecoder, ResamplerSettings};
+use crate::{ChannelCount, SampleRate};
+
+/// Audio that can be played by a [`Mixer`](super::Mixer).
+pub trait MixerSource: Send {
+    fn sample_rate(&self) -> SampleRate;
+
+    fn channels(&self) -> ChannelCount;
+
+    /// Fills `buf` with interleaved samples and returns how many were written. Returning fewer
+    /// samples than requested ends the source.
+    fn read(&mut self, buf: &mut [f32]) -> usize;
+}
+
+/// Plays a decoder, resampled to a fixed rate.
+pub struct DecoderSource {
+    decoder: Decoder<f32>,
+    resampled: ResampledDecoder<f32>,
+    offset: usize,
+    finished: bool,
+    tail: Vec<f32>,
+}
+
+impl DecoderSource {
+    pub fn new(
+        mut decoder: Decoder<f32>,
+        sample_rate: SampleRate,
+        resampler_settings: ResamplerSettings,
+    ) -> Result<Self, DecoderError> {
+        let mut resampled =
+            ResampledDecoder::new(sample_rate, decoder.channels(), resampler_settings);
+        resampled.initialize(&mut decoder)?;
+        Ok(Self {
+            decoder,
+            resampled,
+            offset: 0,
+            finished: false,
+            tail: Vec::new(),
+        })
+    }
+
+    pub fn decoder(&self) -> &Decoder<f32> {
+        &self.decoder
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut Decoder<f32> {
+        &mut self.decoder
+    }
+}
+
+impl MixerSource for DecoderSource {
+    fn sample_rate(&self) -> SampleRate {
+        self.resampled.out_sample_rate()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.decoder.channels()
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.finished {
+                let len = (buf.len() - written).min(self.tail.len() - self.offset);
+                buf[written..written + len]
+                    .copy_from_slice(&self.tail[self.offset..self.offset + len]);
+                self.offset += len;
+                return written + len;
+            }
+
+            let current = self.resampled.current(&mut self.decoder);
+            let available = &current[self.offset.min(current.len())..];
+            let len = (buf.len() - written).min(available.len());
+            buf[written..written + len].copy_from_slice(&available[..len]);
+            written += len;
+            self.offset += len;
+            if len < available.len() {
+                break;
+            }
+
+            self.offset = 0;
+            match self.resampled.decode_next_frame(&mut self.decoder) {
+                Ok(DecoderResult::Unfinished) => {}
+                Ok(DecoderResult::Finished) => {
+                    self.tail = self.resampled.flush().to_vec();
+                    self.finished = true;
+                }
+                Err(e) => {
+                    warn!("Error decoding mixer source: {e:?}");
+                    self.finished = true;
+                }
+            }
+        }
+        written
+    }
+}
+
+/// Plays samples that are already in memory. Cloning the samples is cheap, so the same buffer
+/// can be played by several voices.
+#[derive(Clone, Debug)]
+pub struct BufferSource {
+    samples: Arc<[f32]>,
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    position: usize,
+}
+
+impl BufferSource {
+    pub fn new(
+        samples: impl Into<Arc<[f32]>>,
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+    ) -> Self {
+        Self {
+            samples: samples.into(),
+            channels,
+            sample_rate,
+            position: 0,
+        }
+    }
+
+    pub fn samples(&self) -> &Arc<[f32]> {
+        &self.samples
+    }
+}
+
+impl MixerSource for BufferSource {
+    fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let len = buf.len().min(self.samples.len() - self.position);
+        buf[..len].copy_from_slice(&self.samples[self.position..self.position + len]);
+        self.position += len;
+        len
+    }
+}
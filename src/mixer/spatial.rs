@@ -0,0 +1,229 @@
+use std::f32::consts::TAU;
+use std::ops::{Add, Mul, Sub};
+
+use super::voice::Ramp;
+use crate::SampleRate;
+
+/// Speed of sound in metres per second, used for Doppler shift. Positions are in metres.
+pub const SPEED_OF_SOUND: f32 = 343.3;
+
+// Low-pass cutoff for an emitter at the listener's position when air absorption is enabled
+const MAX_AIR_CUTOFF: f32 = 20000.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub const ZERO: Self = Self::new(0.0, 0.0, 0.0);
+
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn dot(self, other: Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Self) -> Self {
+        Self::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    /// Returns zero for a zero vector.
+    pub fn normalize(self) -> Self {
+        let length = self.length();
+        if length > 0.0 {
+            self * (1.0 / length)
+        } else {
+            Self::ZERO
+        }
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Mul<f32> for Vec3 {
+    type Output = Self;
+
+    fn mul(self, scale: f32) -> Self {
+        Self::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listener {
+    pub position: Vec3,
+    /// Metres per second.
+    pub velocity: Vec3,
+    /// The direction the listener is facing.
+    pub forward: Vec3,
+    pub up: Vec3,
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            forward: Vec3::new(0.0, 0.0, -1.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DistanceModel {
+    /// `reference / (reference + rolloff * (distance - reference))`
+    #[default]
+    Inverse,
+    /// `1 - rolloff * (distance - reference) / (max - reference)`
+    Linear,
+    /// `(distance / reference) ^ -rolloff`
+    Exponential,
+}
+
+/// How an emitter gets quieter with distance. The distance is clamped between the reference and
+/// max distance, so emitters closer than the reference distance play at full volume.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attenuation {
+    pub model: DistanceModel,
+    pub reference_distance: f32,
+    pub max_distance: f32,
+    pub rolloff: f32,
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self {
+            model: DistanceModel::Inverse,
+            reference_distance: 1.0,
+            max_distance: 100.0,
+            rolloff: 1.0,
+        }
+    }
+}
+
+impl Attenuation {
+    pub fn gain(&self, distance: f32) -> f32 {
+        let reference = self.reference_distance.max(f32::EPSILON);
+        let max = self.max_distance.max(reference);
+        let distance = distance.clamp(reference, max);
+        let gain = match self.model {
+            DistanceModel::Inverse => {
+                reference / (reference + self.rolloff * (distance - reference))
+            }
+            DistanceModel::Linear if max > reference => {
+                1.0 - self.rolloff * (distance - reference) / (max - reference)
+            }
+            DistanceModel::Linear => 1.0,
+            DistanceModel::Exponential => (distance / reference).powf(-self.rolloff),
+        };
+        gain.clamp(0.0, 1.0)
+    }
+}
+
+/// Places a mono voice in 3D space relative to the mixer's [`Listener`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Emitter {
+    pub position: Vec3,
+    /// Metres per second. Only used for Doppler shift.
+    pub velocity: Vec3,
+    pub attenuation: Attenuation,
+    /// Scales the Doppler shift. 0 disables it.
+    pub doppler_factor: f32,
+    /// How quickly high frequencies fade with distance. 0 disables it. The low-pass cutoff is
+    /// 20 kHz divided by `1 + air_absorption * distance`.
+    pub air_absorption: f32,
+}
+
+/// Per-voice state for a spatialized voice.
+pub(super) struct Spatial {
+    pub(super) emitter: Emitter,
+    gain: Ramp,
+    pan: Ramp,
+    lowpass_coefficient: f32,
+    lowpass: f32,
+    initialized: bool,
+}
+
+impl Spatial {
+    pub(super) fn new(emitter: Emitter) -> Self {
+        Self {
+            emitter,
+            gain: Ramp::new(1.0),
+            pan: Ramp::new(0.0),
+            lowpass_coefficient: 1.0,
+            lowpass: 0.0,
+            initialized: false,
+        }
+    }
+
+    /// Works out the gain, pan and filter for the next `frames` frames, ramping to them from the
+    /// last update. Returns the Doppler pitch ratio.
+    pub(super) fn update(
+        &mut self,
+        listener: &Listener,
+        sample_rate: SampleRate,
+        frames: usize,
+    ) -> f64 {
+        let offset = self.emitter.position - listener.position;
+        let distance = offset.length();
+        let direction = offset.normalize();
+
+        let gain = self.emitter.attenuation.gain(distance);
+        let right = listener.forward.cross(listener.up).normalize();
+        let pan = direction.dot(right).clamp(-1.0, 1.0);
+        let frames = if self.initialized { frames } else { 0 };
+        self.gain.fade_to(gain, frames);
+        self.pan.fade_to(pan, frames);
+        self.initialized = true;
+
+        self.lowpass_coefficient = if self.emitter.air_absorption > 0.0 {
+            let cutoff = MAX_AIR_CUTOFF / (1.0 + self.emitter.air_absorption * distance);
+            1.0 - (-TAU * cutoff / sample_rate.0.max(1) as f32).exp()
+        } else {
+            1.0
+        };
+
+        let factor = self.emitter.doppler_factor;
+        if factor == 0.0 || distance == 0.0 {
+            return 1.0;
+        }
+        // Velocities along the line between the two, limited so the ratio stays positive
+        let limit = SPEED_OF_SOUND * 0.99;
+        let listener_speed = (listener.velocity.dot(direction) * factor).clamp(-limit, limit);
+        let emitter_speed = (self.emitter.velocity.dot(direction) * factor).clamp(-limit, limit);
+        ((SPEED_OF_SOUND + listener_speed) / (SPEED_OF_SOUND + emitter_speed)) as f64
+    }
+
+    /// Filters the next sample and returns it with its gain and pan.
+    pub(super) fn next(&mut self, sample: f32) -> (f32, f32, f32) {
+        self.lowpass += (sample - self.lowpass) * self.lowpass_coefficient;
+        (self.lowpass, self.gain.next(), self.pan.next())
+    }
+}
@@ -0,0 +1,156 @@
+use super::{
+    Attenuation, BufferSource, DistanceModel, Emitter, Listener, Mixer, SPEED_OF_SOUND, Vec3,
+    VoiceSettings,
+};
+use crate::{ChannelCount, SampleRate};
+
+fn mixer(channels: u16) -> Mixer {
+    Mixer::new(SampleRate(1000), ChannelCount(channels))
+}
+
+fn emitter(position: Vec3) -> VoiceSettings {
+    VoiceSettings {
+        emitter: Some(Emitter {
+            position,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn tone(frames: usize) -> BufferSource {
+    BufferSource::new(vec![1.0; frames], ChannelCount(1), SampleRate(1000))
+}
+
+fn assert_close(expected: &[f32], actual: &[f32]) {
+    assert_eq!(expected.len(), actual.len());
+    for (expected, actual) in expected.iter().zip(actual) {
+        assert!(
+            (expected - actual).abs() < 1e-4,
+            "expected {expected:?}, got {actual:?}"
+        );
+    }
+}
+
+#[test]
+fn attenuation_models() {
+    let attenuation = |model, rolloff| Attenuation {
+        model,
+        reference_distance: 1.0,
+        max_distance: 11.0,
+        rolloff,
+    };
+    assert_close(
+        &[0.5, 0.5, 0.5],
+        &[
+            attenuation(DistanceModel::Inverse, 1.0).gain(2.0),
+            attenuation(DistanceModel::Linear, 1.0).gain(6.0),
+            attenuation(DistanceModel::Exponential, 0.5).gain(4.0),
+        ],
+    );
+
+    // Clamped to the reference and max distance
+    assert_eq!(1.0, attenuation(DistanceModel::Inverse, 1.0).gain(0.1));
+    assert_eq!(0.0, attenuation(DistanceModel::Linear, 1.0).gain(50.0));
+}
+
+#[test]
+fn pans_by_azimuth() {
+    let mut mixer = mixer(2);
+    let id = mixer.add(tone(1000), emitter(Vec3::new(1.0, 0.0, 0.0)));
+
+    let mut out = vec![0.0; 8];
+    mixer.mix(&mut out);
+    assert_close(&[0.0, 1.0].repeat(4), &out);
+
+    // Ramps to the new position over the next mix
+    assert!(mixer.move_emitter(id, Vec3::new(-2.0, 0.0, 0.0), Vec3::ZERO));
+    mixer.mix(&mut out);
+    mixer.mix(&mut out);
+    assert_close(&[0.5, 0.0].repeat(4), &out);
+
+    // Turning the listener around swaps the sides
+    mixer.set_listener(Listener {
+        forward: Vec3::new(0.0, 0.0, 1.0),
+        ..Default::default()
+    });
+    mixer.mix(&mut out);
+    mixer.mix(&mut out);
+    assert_close(&[0.0, 0.5].repeat(4), &out);
+}
+
+#[test]
+fn mono_output_is_attenuated() {
+    let mut mixer = mixer(1);
+    mixer.add(tone(1000), emitter(Vec3::new(0.0, 0.0, -2.0)));
+
+    let mut out = vec![0.0; 4];
+    mixer.mix(&mut out);
+    assert_close(&[0.5; 4], &out);
+}
+
+#[test]
+fn doppler_shifts_pitch() {
+    let mut mixer = mixer(1);
+    // Coming straight at the listener at half the speed of sound doubles the pitch
+    let id = mixer.add(
+        tone(100),
+        VoiceSettings {
+            emitter: Some(Emitter {
+                position: Vec3::new(0.0, 0.0, -10.0),
+                velocity: Vec3::new(0.0, 0.0, SPEED_OF_SOUND / 2.0),
+                attenuation: Attenuation {
+                    rolloff: 0.0,
+                    ..Default::default()
+                },
+                doppler_factor: 1.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+
+    let mut out = vec![0.0; 60];
+    mixer.mix(&mut out);
+    assert_close(&[1.0; 50], &out[..50]);
+    assert_close(&[0.0; 10], &out[50..]);
+    assert!(!mixer.contains(id));
+}
+
+#[test]
+fn air_absorption_filters_distant_emitters() {
+    let mut mixer = mixer(1);
+    mixer.add(
+        tone(1000),
+        VoiceSettings {
+            emitter: Some(Emitter {
+                position: Vec3::new(0.0, 0.0, -1.0),
+                air_absorption: 1000.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+
+    let mut out = vec![0.0; 8];
+    mixer.mix(&mut out);
+    assert!(out[0] < 0.2);
+    assert!(out.windows(2).all(|pair| pair[1] > pair[0]));
+}
+
+#[test]
+fn only_mono_voices_are_spatialized() {
+    let mut mixer = mixer(2);
+    let stereo = mixer.add(
+        BufferSource::new(vec![1.0; 8], ChannelCount(2), SampleRate(1000)),
+        VoiceSettings::default(),
+    );
+    assert!(!mixer.set_emitter(stereo, Some(Emitter::default())));
+    assert!(!mixer.move_emitter(stereo, Vec3::ZERO, Vec3::ZERO));
+
+    let mono = mixer.add(tone(8), VoiceSettings::default());
+    assert!(mixer.set_emitter(mono, Some(Emitter::default())));
+    assert!(mixer.emitter(mono).is_some());
+    assert!(mixer.set_emitter(mono, None));
+    assert!(mixer.emitter(mono).is_none());
+}
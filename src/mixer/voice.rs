@@ -0,0 +1,368 @@
+use std::f32::consts::FRAC_PI_4;
+
+use super::spatial::Spatial;
+use super::{Emitter, Listener, MixerSource, Priority, VoiceId, VoiceSettings, duration_frames};
+use crate::SampleRate;
+use crate::decoder::{SpeakerLayout, downmix};
+
+// Frames read from a source at a time when converting its rate
+const CONVERTER_CHUNK: usize = 256;
+
+pub(super) struct Voice {
+    pub(super) id: VoiceId,
+    pub(super) gain: Ramp,
+    pub(super) pan: f32,
+    pub(super) paused: bool,
+    pub(super) priority: Priority,
+    // Applied on top of `gain` while higher-priority voices play
+    duck: Ramp,
+    duck_target: f32,
+    duck_release: usize,
+    // Whether the voice stops advancing once it's fully ducked
+    duck_pauses: bool,
+    spatial: Option<Spatial>,
+    source: Box<dyn MixerSource>,
+    channels: usize,
+    // Set for surround sources, which are downmixed for mono and stereo output
+    layout: Option<SpeakerLayout>,
+    converter: RateConverter,
+    stopping: bool,
+    finished: bool,
+    buf: Vec<f32>,
+    downmix_buf: Vec<f32>,
+}
+
+impl Voice {
+    pub(super) fn new(
+        id: VoiceId,
+        source: Box<dyn MixerSource>,
+        sample_rate: SampleRate,
+        settings: &VoiceSettings,
+    ) -> Self {
+        let channels = source.channels().0.max(1) as usize;
+        let mut gain = Ramp::new(0.0);
+        gain.fade_to(
+            settings.gain,
+            duration_frames(settings.fade_in, sample_rate),
+        );
+        Self {
+            id,
+            gain,
+            pan: settings.pan.clamp(-1.0, 1.0),
+            paused: settings.paused,
+            priority: settings.priority,
+            duck: Ramp::new(1.0),
+            duck_target: 1.0,
+            duck_release: 0,
+            duck_pauses: false,
+            spatial: settings
+                .emitter
+                .clone()
+                .filter(|_| channels == 1)
+                .map(Spatial::new),
+            converter: RateConverter::new(channels, source.sample_rate(), sample_rate),
+            source,
+            channels,
+            layout: SpeakerLayout::from_channels(channels),
+            stopping: false,
+            finished: false,
+            buf: Vec::new(),
+            downmix_buf: Vec::new(),
+        }
+    }
+
+    pub(super) fn set_output_rate(&mut self, sample_rate: SampleRate) {
+        self.converter
+            .set_rates(self.source.sample_rate(), sample_rate);
+    }
+
+    pub(super) fn stop(&mut self, fade_frames: usize) {
+        self.stopping = true;
+        self.gain.fade_to(0.0, fade_frames);
+        if self.gain.is_silent() {
+            self.finished = true;
+        }
+    }
+
+    pub(super) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Spatializes the voice, or stops spatializing it if `emitter` is `None`. Returns `false`
+    /// if the source isn't mono.
+    pub(super) fn set_emitter(&mut self, emitter: Option<Emitter>) -> bool {
+        if self.channels != 1 {
+            return false;
+        }
+        match (&mut self.spatial, emitter) {
+            (Some(spatial), Some(emitter)) => spatial.emitter = emitter,
+            (_, emitter) => {
+                self.spatial = emitter.map(Spatial::new);
+                self.converter.set_pitch(1.0);
+            }
+        }
+        true
+    }
+
+    pub(super) fn emitter(&self) -> Option<&Emitter> {
+        self.spatial.as_ref().map(|spatial| &spatial.emitter)
+    }
+
+    pub(super) fn emitter_mut(&mut self) -> Option<&mut Emitter> {
+        self.spatial.as_mut().map(|spatial| &mut spatial.emitter)
+    }
+
+    /// Moves the voice for the next `frames` frames.
+    pub(super) fn update_spatial(
+        &mut self,
+        listener: &Listener,
+        sample_rate: SampleRate,
+        frames: usize,
+    ) {
+        if let Some(spatial) = &mut self.spatial {
+            let pitch = spatial.update(listener, sample_rate, frames);
+            self.converter.set_pitch(pitch);
+        }
+    }
+
+    /// Whether the voice should duck lower-priority voices.
+    pub(super) fn holds_focus(&self) -> bool {
+        !self.paused && !self.stopping && !self.finished && !self.is_focus_paused()
+    }
+
+    /// Whether the voice was paused by a higher-priority voice with exclusive focus.
+    pub(super) fn is_focus_paused(&self) -> bool {
+        self.duck_pauses && self.duck.is_silent()
+    }
+
+    /// Ramps the duck gain to `target`, over `attack` frames if it's ducking further and over
+    /// the release of whatever ducked it if it's coming back up.
+    pub(super) fn duck(&mut self, target: f32, attack: usize, release: usize, pause: bool) {
+        self.duck_pauses = pause;
+        if target == self.duck_target {
+            return;
+        }
+        if target < self.duck_target {
+            self.duck.fade_to(target, attack);
+            self.duck_release = release;
+        } else {
+            self.duck.fade_to(target, self.duck_release);
+        }
+        self.duck_target = target;
+    }
+
+    /// Adds the voice's next frames to `out`.
+    pub(super) fn mix_into(&mut self, out: &mut [f32], out_channels: usize) {
+        if self.paused || self.is_focus_paused() {
+            // Nothing's audible, so there's nothing to fade out
+            if self.stopping {
+                self.finished = true;
+            }
+            return;
+        }
+        if self.finished {
+            return;
+        }
+        let frames = out.len() / out_channels;
+        self.buf.resize(frames * self.channels, 0.0);
+        let read = self.converter.read(&mut *self.source, &mut self.buf) / self.channels;
+        if read < frames {
+            self.finished = true;
+        }
+
+        let (buf, channels) = match self.layout {
+            Some(layout) if out_channels <= 2 => {
+                self.downmix_buf.resize(read * 2, 0.0);
+                downmix(
+                    layout,
+                    &self.buf[..read * self.channels],
+                    &mut self.downmix_buf,
+                    2,
+                );
+                (&self.downmix_buf, 2)
+            }
+            _ => (&self.buf, self.channels),
+        };
+        for (frame, out_frame) in buf
+            .chunks_exact(channels)
+            .zip(out.chunks_exact_mut(out_channels))
+            .take(read)
+        {
+            let gain = self.gain.next() * self.duck.next();
+            match &mut self.spatial {
+                Some(spatial) => {
+                    let (sample, spatial_gain, pan) = spatial.next(frame[0]);
+                    mix_frame(&[sample], out_frame, pan, gain * spatial_gain);
+                }
+                None => mix_frame(frame, out_frame, self.pan, gain),
+            }
+        }
+        if self.stopping && self.gain.is_silent() {
+            self.finished = true;
+        }
+    }
+}
+
+/// A gain that can move linearly towards a target.
+pub(super) struct Ramp {
+    current: f32,
+    target: f32,
+    step: f32,
+}
+
+impl Ramp {
+    pub(super) fn new(value: f32) -> Self {
+        Self {
+            current: value,
+            target: value,
+            step: 0.0,
+        }
+    }
+
+    pub(super) fn set(&mut self, value: f32) {
+        *self = Self::new(value);
+    }
+
+    pub(super) fn fade_to(&mut self, target: f32, frames: usize) {
+        if frames == 0 {
+            self.set(target);
+            return;
+        }
+        self.target = target;
+        self.step = (target - self.current) / frames as f32;
+    }
+
+    pub(super) fn value(&self) -> f32 {
+        self.current
+    }
+
+    pub(super) fn is_silent(&self) -> bool {
+        self.step == 0.0 && self.current == 0.0
+    }
+
+    /// Returns the gain for the next frame.
+    pub(super) fn next(&mut self) -> f32 {
+        let value = self.current;
+        if self.step != 0.0 {
+            self.current += self.step;
+            if (self.step > 0.0 && self.current >= self.target)
+                || (self.step < 0.0 && self.current <= self.target)
+            {
+                self.current = self.target;
+                self.step = 0.0;
+            }
+        }
+        value
+    }
+}
+
+/// Converts a source to another rate with linear interpolation, one block at a time.
+struct RateConverter {
+    channels: usize,
+    // Input frames per output frame, before the pitch is applied
+    rate_step: f64,
+    pitch: f64,
+    step: f64,
+    // Position in `input`, in frames
+    position: f64,
+    input: Vec<f32>,
+    source_ended: bool,
+}
+
+impl RateConverter {
+    fn new(channels: usize, from: SampleRate, to: SampleRate) -> Self {
+        let mut converter = Self {
+            channels,
+            rate_step: 1.0,
+            pitch: 1.0,
+            step: 1.0,
+            position: 0.0,
+            input: Vec::new(),
+            source_ended: false,
+        };
+        converter.set_rates(from, to);
+        converter
+    }
+
+    fn set_rates(&mut self, from: SampleRate, to: SampleRate) {
+        self.rate_step = from.0.max(1) as f64 / to.0.max(1) as f64;
+        self.step = self.rate_step * self.pitch;
+    }
+
+    fn set_pitch(&mut self, pitch: f64) {
+        self.pitch = pitch;
+        self.step = self.rate_step * pitch;
+    }
+
+    /// Fills `out` and returns the number of samples written, which is only less than requested
+    /// once the source has ended.
+    fn read(&mut self, source: &mut dyn MixerSource, out: &mut [f32]) -> usize {
+        let channels = self.channels;
+        if self.step == 1.0 && self.input.is_empty() {
+            return source.read(out);
+        }
+
+        let frames = out.len() / channels;
+        let mut written = 0;
+        for frame in out.chunks_exact_mut(channels).take(frames) {
+            let index = self.position as usize;
+            while index + 1 >= self.input.len() / channels && !self.source_ended {
+                let start = self.input.len();
+                self.input.resize(start + CONVERTER_CHUNK * channels, 0.0);
+                let read = source.read(&mut self.input[start..]);
+                self.input.truncate(start + read - read % channels);
+                if read < CONVERTER_CHUNK * channels {
+                    self.source_ended = true;
+                }
+            }
+            let available = self.input.len() / channels;
+            if index >= available {
+                break;
+            }
+            let next = (index + 1).min(available - 1);
+            let fraction = (self.position - index as f64) as f32;
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let current = self.input[index * channels + channel];
+                let next = self.input[next * channels + channel];
+                *sample = current + (next - current) * fraction;
+            }
+            self.position += self.step;
+            written += channels;
+        }
+
+        // Keep the frame that's still needed for interpolation
+        let consumed = (self.position as usize).min(self.input.len() / channels);
+        self.input.drain(..consumed * channels);
+        self.position -= consumed as f64;
+        written
+    }
+}
+
+/// Adds one frame to an output frame with a different channel count.
+fn mix_frame(input: &[f32], output: &mut [f32], pan: f32, gain: f32) {
+    match (input.len(), output.len()) {
+        (_, 0) => {}
+        (1, 1) => output[0] += input[0] * gain,
+        (1, _) => {
+            // Constant power, so the voice doesn't get quieter in the middle
+            let angle = (pan + 1.0) * FRAC_PI_4;
+            output[0] += input[0] * gain * angle.cos();
+            output[1] += input[0] * gain * angle.sin();
+        }
+        (channels, 1) => output[0] += input.iter().sum::<f32>() / channels as f32 * gain,
+        (channels, 2) => {
+            // Without a known layout, the extra channels alternate between left and right
+            let left = input.iter().step_by(2).sum::<f32>() / channels.div_ceil(2) as f32;
+            let right = input.iter().skip(1).step_by(2).sum::<f32>() / (channels / 2) as f32;
+            output[0] += left * gain * (1.0 - pan).min(1.0);
+            output[1] += right * gain * (1.0 + pan).min(1.0);
+        }
+        (channels, out_channels) => {
+            output[0] += input[0] * gain * (1.0 - pan).min(1.0);
+            output[1] += input[1] * gain * (1.0 + pan).min(1.0);
+            for channel in 2..channels.min(out_channels) {
+                output[channel] += input[channel] * gain;
+            }
+        }
+    }
+}
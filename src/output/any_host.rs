@@ -0,0 +1,611 @@
+use std::any::Any;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use super::{
+    BuildStreamError, DecalSample, DefaultStreamConfigError, Device, DeviceId, DeviceIdError,
+    DeviceNameError, DeviceNotificationHandle, DevicesError, FileHost, Host, HostUnavailableError,
+    NullHost, OfflineHost, PipeHost, SampleFormat, SampleRate, Stream, StreamConfig, StreamError,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError, TcpHost,
+};
+
+/// Identifies a backend, and the sub-API for backends that have more than one.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AnyHostId {
+    #[cfg(feature = "output-cpal")]
+    Cpal(cpal::HostId),
+    #[cfg(feature = "output-cubeb")]
+    Cubeb,
+    #[cfg(feature = "output-rtaudio")]
+    RtAudio(rtaudio::Api),
+    #[cfg(feature = "mock")]
+    Mock,
+    Null,
+    File,
+    Pipe,
+    Tcp,
+    Offline,
+    /// A host from outside this crate, wrapped with [`AnyHost::new`].
+    Custom(&'static str),
+}
+
+impl AnyHostId {
+    /// A stable, lowercase name that can be stored in settings, e.g. `cpal-alsa`.
+    pub fn name(&self) -> String {
+        match self {
+            #[cfg(feature = "output-cpal")]
+            Self::Cpal(id) => format!("cpal-{}", id.name().to_lowercase()),
+            #[cfg(feature = "output-cubeb")]
+            Self::Cubeb => "cubeb".to_owned(),
+            #[cfg(feature = "output-rtaudio")]
+            Self::RtAudio(api) => format!("rtaudio-{api:?}").to_lowercase(),
+            #[cfg(feature = "mock")]
+            Self::Mock => "mock".to_owned(),
+            Self::Null => "null".to_owned(),
+            Self::File => "file".to_owned(),
+            Self::Pipe => "pipe".to_owned(),
+            Self::Tcp => "tcp".to_owned(),
+            Self::Offline => "offline".to_owned(),
+            Self::Custom(name) => name.to_lowercase(),
+        }
+    }
+}
+
+impl fmt::Display for AnyHostId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+impl FromStr for AnyHostId {
+    type Err = HostUnavailableError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        #[cfg(feature = "mock")]
+        if name == "mock" {
+            return Ok(Self::Mock);
+        }
+        let software_host = match name.as_str() {
+            "null" => Some(Self::Null),
+            "file" => Some(Self::File),
+            "pipe" => Some(Self::Pipe),
+            "tcp" => Some(Self::Tcp),
+            "offline" => Some(Self::Offline),
+            _ => None,
+        };
+        if let Some(id) = software_host {
+            return Ok(id);
+        }
+        // A bare backend name selects its default sub-API
+        let default_host = match name.as_str() {
+            #[cfg(feature = "output-cpal")]
+            "cpal" => Some(Self::Cpal(cpal::default_host().id())),
+            #[cfg(feature = "output-rtaudio")]
+            "rtaudio" => Some(Self::RtAudio(rtaudio::Host::default().api())),
+            _ => None,
+        };
+        default_host
+            .or_else(|| available_hosts().into_iter().find(|h| h.name() == name))
+            .ok_or(HostUnavailableError::HostUnavailable)
+    }
+}
+
+/// Lists every host that's compiled in and usable on this platform.
+/// The mock and software hosts are left out since they don't play through a sound card.
+pub fn available_hosts() -> Vec<AnyHostId> {
+    #[allow(unused_mut)]
+    let mut hosts = vec![];
+    #[cfg(feature = "output-cpal")]
+    hosts.extend(cpal::available_hosts().into_iter().map(AnyHostId::Cpal));
+    #[cfg(feature = "output-cubeb")]
+    hosts.push(AnyHostId::Cubeb);
+    #[cfg(feature = "output-rtaudio")]
+    hosts.extend(rtaudio::compiled_apis().into_iter().map(AnyHostId::RtAudio));
+    hosts
+}
+
+/// A data callback for any sample type, so output streams can be built through a [`DynDevice`].
+pub struct OutputCallback {
+    format: SampleFormat,
+    callback: Box<dyn Any + Send>,
+}
+
+impl OutputCallback {
+    pub fn new<T: DecalSample>(callback: impl FnMut(&mut [T]) + Send + Sync + 'static) -> Self {
+        let callback: Box<dyn FnMut(&mut [T]) + Send + Sync> = Box::new(callback);
+        Self {
+            format: T::FORMAT,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// The sample type the callback was created with.
+    pub fn sample_format(&self) -> SampleFormat {
+        self.format
+    }
+
+    fn into_inner<T: DecalSample>(self) -> Box<dyn FnMut(&mut [T]) + Send + Sync> {
+        *self
+            .callback
+            .downcast()
+            .expect("callback is for the matching sample type")
+    }
+}
+
+/// A data callback for any sample type, so input streams can be built through a [`DynDevice`].
+pub struct InputCallback {
+    format: SampleFormat,
+    callback: Box<dyn Any + Send>,
+}
+
+impl InputCallback {
+    pub fn new<T: DecalSample>(callback: impl FnMut(&[T]) + Send + Sync + 'static) -> Self {
+        let callback: Box<dyn FnMut(&[T]) + Send + Sync> = Box::new(callback);
+        Self {
+            format: T::FORMAT,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// The sample type the callback was created with.
+    pub fn sample_format(&self) -> SampleFormat {
+        self.format
+    }
+
+    fn into_inner<T: DecalSample>(self) -> Box<dyn FnMut(&[T]) + Send + Sync> {
+        *self
+            .callback
+            .downcast()
+            .expect("callback is for the matching sample type")
+    }
+}
+
+/// An error callback that can be cloned, shared by every copy a backend makes.
+#[derive(Clone)]
+pub struct ErrorCallback(Arc<Mutex<Box<dyn FnMut(StreamError) + Send>>>);
+
+impl ErrorCallback {
+    pub fn new(callback: impl FnMut(StreamError) + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(Box::new(callback))))
+    }
+
+    pub fn call(&self, error: StreamError) {
+        let mut callback = self.0.lock().expect("lock poisoned");
+        (*callback)(error);
+    }
+}
+
+// Runs `$body` with `$sample` set to the type for the sample format. Formats without a
+// `DecalSample` type can't be streamed.
+macro_rules! with_sample_type {
+    ($format:expr, $sample:ident => $body:expr) => {
+        match $format {
+            SampleFormat::I8 => {
+                type $sample = i8;
+                $body
+            }
+            SampleFormat::I16 => {
+                type $sample = i16;
+                $body
+            }
+            SampleFormat::I32 => {
+                type $sample = i32;
+                $body
+            }
+            SampleFormat::I64 => {
+                type $sample = i64;
+                $body
+            }
+            SampleFormat::U8 => {
+                type $sample = u8;
+                $body
+            }
+            SampleFormat::U16 => {
+                type $sample = u16;
+                $body
+            }
+            SampleFormat::U32 => {
+                type $sample = u32;
+                $body
+            }
+            SampleFormat::U64 => {
+                type $sample = u64;
+                $body
+            }
+            SampleFormat::F32 => {
+                type $sample = f32;
+                $body
+            }
+            SampleFormat::F64 => {
+                type $sample = f64;
+                $body
+            }
+            SampleFormat::I24 | SampleFormat::U24 => {
+                Err(BuildStreamError::StreamConfigNotSupported)
+            }
+        }
+    };
+}
+
+/// The object-safe part of [`Device`]. It's implemented for every device, with the sample type
+/// of a stream carried by its callback instead of a type parameter.
+pub trait DynDevice: Send {
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError>;
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError>;
+    fn name(&self) -> Result<String, DeviceNameError>;
+    fn id(&self) -> Result<DeviceId, DeviceIdError>;
+    fn vendor(&self) -> Option<String>;
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError>;
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError>;
+    fn supported_sample_rates(&self) -> Result<Vec<SampleRate>, SupportedStreamConfigsError>;
+    fn waits_for_data(&self) -> bool;
+    fn build_output_stream_dyn(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: OutputCallback,
+        error_callback: ErrorCallback,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>;
+    fn build_input_stream_dyn(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: InputCallback,
+        error_callback: ErrorCallback,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<D: Device + Send + 'static> DynDevice for D {
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Device::default_output_config(self)
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Device::default_input_config(self)
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Device::name(self)
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Device::id(self)
+    }
+
+    fn vendor(&self) -> Option<String> {
+        Device::vendor(self)
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        Ok(Device::supported_output_configs(self)?.collect())
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        Device::supported_input_configs(self)
+    }
+
+    fn supported_sample_rates(&self) -> Result<Vec<SampleRate>, SupportedStreamConfigsError> {
+        Device::supported_sample_rates(self)
+    }
+
+    fn waits_for_data(&self) -> bool {
+        Device::waits_for_data(self)
+    }
+
+    fn build_output_stream_dyn(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: OutputCallback,
+        error_callback: ErrorCallback,
+    ) -> Result<Box<dyn Stream>, BuildStreamError> {
+        with_sample_type!(data_callback.sample_format(), S => {
+            self.build_output_stream::<S, _, _>(
+                config,
+                data_callback.into_inner::<S>(),
+                move |error| error_callback.call(error),
+            )
+        })
+    }
+
+    fn build_input_stream_dyn(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: InputCallback,
+        error_callback: ErrorCallback,
+    ) -> Result<Box<dyn Stream>, BuildStreamError> {
+        with_sample_type!(data_callback.sample_format(), S => {
+            self.build_input_stream::<S, _, _>(
+                config,
+                data_callback.into_inner::<S>(),
+                move |error| error_callback.call(error),
+            )
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The object-safe part of [`Host`]. It's implemented for every host whose devices can be sent
+/// between threads.
+pub trait DynHost: Send + Sync {
+    fn default_output_device(&self) -> Option<AnyDevice>;
+    fn output_devices(&self) -> Result<Vec<AnyDevice>, DevicesError>;
+    fn default_input_device(&self) -> Option<AnyDevice>;
+    fn input_devices(&self) -> Result<Vec<AnyDevice>, DevicesError>;
+    fn watch_devices(
+        &self,
+        on_change: Box<dyn Fn() + Send + Sync>,
+    ) -> Option<DeviceNotificationHandle>;
+    fn needs_default_device_restart(&self) -> bool;
+    fn devices_can_change(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<H: Host> DynHost for H
+where
+    H::Device: Send + 'static,
+{
+    fn default_output_device(&self) -> Option<AnyDevice> {
+        Host::default_output_device(self).map(AnyDevice::new)
+    }
+
+    fn output_devices(&self) -> Result<Vec<AnyDevice>, DevicesError> {
+        Ok(Host::output_devices(self)?.map(AnyDevice::new).collect())
+    }
+
+    fn default_input_device(&self) -> Option<AnyDevice> {
+        Host::default_input_device(self).map(AnyDevice::new)
+    }
+
+    fn input_devices(&self) -> Result<Vec<AnyDevice>, DevicesError> {
+        Ok(Host::input_devices(self)?
+            .into_iter()
+            .map(AnyDevice::new)
+            .collect())
+    }
+
+    fn watch_devices(
+        &self,
+        on_change: Box<dyn Fn() + Send + Sync>,
+    ) -> Option<DeviceNotificationHandle> {
+        Host::watch_devices(self, on_change)
+    }
+
+    fn needs_default_device_restart(&self) -> bool {
+        Host::needs_default_device_restart(self)
+    }
+
+    fn devices_can_change(&self) -> bool {
+        Host::devices_can_change(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A [`Host`] that wraps any other host so it can be chosen at runtime. [`from_id`](Host::from_id)
+/// and [`from_name`](Self::from_name) open the hosts in this crate with their default settings,
+/// and [`new`](Self::new) wraps any host, including ones from other crates.
+pub struct AnyHost {
+    id: AnyHostId,
+    host: Box<dyn DynHost>,
+}
+
+impl AnyHost {
+    pub fn new<H: Host>(id: AnyHostId, host: H) -> Self
+    where
+        H::Device: Send + 'static,
+    {
+        Self {
+            id,
+            host: Box::new(host),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, HostUnavailableError> {
+        Self::from_id(name.parse()?)
+    }
+
+    /// The wrapped host, if it's an `H`.
+    pub fn downcast_ref<H: Host>(&self) -> Option<&H> {
+        self.host.as_any().downcast_ref()
+    }
+}
+
+impl Default for AnyHost {
+    fn default() -> Self {
+        #[cfg(feature = "output-cpal")]
+        {
+            let host = super::CpalHost::default();
+            Self::new(AnyHostId::Cpal(host.id()), host)
+        }
+        #[cfg(all(not(feature = "output-cpal"), feature = "output-cubeb"))]
+        {
+            Self::new(AnyHostId::Cubeb, super::CubebHost::default())
+        }
+        #[cfg(all(
+            not(any(feature = "output-cpal", feature = "output-cubeb")),
+            feature = "output-rtaudio"
+        ))]
+        {
+            let host = super::RtAudioHost::default();
+            Self::new(AnyHostId::RtAudio(host.id()), host)
+        }
+        #[cfg(not(any(
+            feature = "output-cpal",
+            feature = "output-cubeb",
+            feature = "output-rtaudio"
+        )))]
+        {
+            Self::new(AnyHostId::Null, NullHost::default())
+        }
+    }
+}
+
+/// A [`Device`] from any host, returned by [`AnyHost`].
+pub struct AnyDevice(Box<dyn DynDevice>);
+
+impl AnyDevice {
+    pub fn new<D: Device + Send + 'static>(device: D) -> Self {
+        Self(Box::new(device))
+    }
+
+    /// The wrapped device, if it's a `D`.
+    pub fn downcast_ref<D: Device + 'static>(&self) -> Option<&D> {
+        self.0.as_any().downcast_ref()
+    }
+}
+
+impl Device for AnyDevice {
+    type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        self.0.default_output_config()
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        self.0.default_input_config()
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        self.0.name()
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        self.0.id()
+    }
+
+    fn vendor(&self) -> Option<String> {
+        self.0.vendor()
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(self.0.supported_output_configs()?.into_iter())
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        self.0.supported_input_configs()
+    }
+
+    fn supported_sample_rates(&self) -> Result<Vec<SampleRate>, SupportedStreamConfigsError> {
+        self.0.supported_sample_rates()
+    }
+
+    fn waits_for_data(&self) -> bool {
+        self.0.waits_for_data()
+    }
+
+    fn build_output_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&mut [T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        self.0.build_output_stream_dyn(
+            config,
+            OutputCallback::new(data_callback),
+            ErrorCallback::new(error_callback),
+        )
+    }
+
+    fn build_input_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&[T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        self.0.build_input_stream_dyn(
+            config,
+            InputCallback::new(data_callback),
+            ErrorCallback::new(error_callback),
+        )
+    }
+}
+
+impl Host for AnyHost {
+    type Device = AnyDevice;
+    type Id = AnyHostId;
+    type Devices = std::vec::IntoIter<AnyDevice>;
+
+    fn from_id(id: Self::Id) -> Result<Self, HostUnavailableError> {
+        let host = match id {
+            #[cfg(feature = "output-cpal")]
+            AnyHostId::Cpal(cpal_id) => Self::new(id, super::CpalHost::from_id(cpal_id)?),
+            #[cfg(feature = "output-cubeb")]
+            AnyHostId::Cubeb => Self::new(id, super::CubebHost::from_id(())?),
+            #[cfg(feature = "output-rtaudio")]
+            AnyHostId::RtAudio(api) => Self::new(id, super::RtAudioHost::from_id(api)?),
+            #[cfg(feature = "mock")]
+            AnyHostId::Mock => Self::new(id, super::MockHost::from_id(())?),
+            AnyHostId::Null => Self::new(id, NullHost::from_id(())?),
+            AnyHostId::File => Self::new(id, FileHost::from_id(())?),
+            AnyHostId::Pipe => Self::new(id, PipeHost::from_id(())?),
+            AnyHostId::Tcp => Self::new(id, TcpHost::from_id(())?),
+            AnyHostId::Offline => Self::new(id, OfflineHost::from_id(())?),
+            // Only the application knows how to create its own hosts
+            AnyHostId::Custom(_) => return Err(HostUnavailableError::HostUnavailable),
+        };
+        Ok(host)
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        self.host.default_output_device()
+    }
+
+    fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(self.host.output_devices()?.into_iter())
+    }
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        self.host.default_input_device()
+    }
+
+    fn input_devices(&self) -> Result<Vec<Self::Device>, DevicesError> {
+        self.host.input_devices()
+    }
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn watch_devices(
+        &self,
+        on_change: Box<dyn Fn() + Send + Sync>,
+    ) -> Option<DeviceNotificationHandle> {
+        self.host.watch_devices(on_change)
+    }
+
+    fn needs_default_device_restart(&self) -> bool {
+        self.host.needs_default_device_restart()
+    }
+
+    fn devices_can_change(&self) -> bool {
+        self.host.devices_can_change()
+    }
+}
@@ -0,0 +1,48 @@
+use super::{AnyHost, AnyHostId, Host, MockDevice, MockHost, OutputBuilder, available_hosts};
+
+#[test]
+fn any_host_from_name() {
+    let host = AnyHost::from_name(" Mock ").unwrap();
+    assert_eq!(AnyHostId::Mock, host.id());
+    assert!("missing-host".parse::<AnyHostId>().is_err());
+    assert!(!available_hosts().contains(&AnyHostId::Mock));
+
+    for id in available_hosts() {
+        assert_eq!(id, id.name().parse().unwrap());
+    }
+    for id in [
+        AnyHostId::Null,
+        AnyHostId::File,
+        AnyHostId::Pipe,
+        AnyHostId::Tcp,
+        AnyHostId::Offline,
+    ] {
+        assert_eq!(id, id.name().parse().unwrap());
+    }
+    assert!(AnyHost::from_id(AnyHostId::Custom("custom")).is_err());
+}
+
+fn write_output(host: AnyHost) {
+    let output_builder = OutputBuilder::new(host, Default::default(), move || {}, |_| {});
+    let mut output = output_builder
+        .new_output::<f32>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+
+    output.start().unwrap();
+    output.write_blocking(&[1.0; 1024]).unwrap();
+    let device = output.device().downcast_ref::<MockDevice>().unwrap();
+    assert_eq!([1.0; 1024], device.trigger_callback());
+}
+
+#[test]
+fn any_host_write_output() {
+    write_output(AnyHost::from_name("mock").unwrap());
+}
+
+#[test]
+fn any_host_wraps_other_hosts() {
+    let host = AnyHost::new(AnyHostId::Custom("Custom"), MockHost::default());
+    assert_eq!("custom", host.id().name());
+    assert!(host.downcast_ref::<MockHost>().is_some());
+    write_output(host);
+}
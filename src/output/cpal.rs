@@ -2,8 +2,8 @@ use cpal::traits::{DeviceTrait as _, HostTrait as _, StreamTrait as _};
 
 use super::{
     BackendSpecificError, BufferSize, BuildStreamError, DecalSample, DefaultStreamConfigError,
-    Device, DeviceNameError, DevicesError, Host, PlayStreamError, SampleFormat, Stream,
-    StreamConfig, StreamError, SupportedBufferSize, SupportedStreamConfig,
+    Device, DeviceId, DeviceIdError, DeviceNameError, DevicesError, Host, PlayStreamError,
+    SampleFormat, Stream, StreamConfig, StreamError, SupportedBufferSize, SupportedStreamConfig,
     SupportedStreamConfigRange, SupportedStreamConfigsError,
 };
 use crate::{ChannelCount, SampleRate, output::HostUnavailableError};
@@ -18,6 +18,7 @@ impl Default for CpalHost {
 
 pub struct CpalDevice(cpal::Device);
 
+// Input and output devices are the same type in cpal
 pub struct CpalDevices(cpal::OutputDevices<<cpal::Host as cpal::traits::HostTrait>::Devices>);
 
 impl Iterator for CpalDevices {
@@ -32,13 +33,23 @@ pub struct CpalStream(cpal::Stream);
 
 impl Stream for CpalStream {
     fn play(&mut self) -> Result<(), PlayStreamError> {
-        self.0.play().unwrap();
-        Ok(())
+        self.0.play().map_err(|e| match e {
+            cpal::PlayStreamError::DeviceNotAvailable => PlayStreamError::DeviceNotAvailable,
+            cpal::PlayStreamError::BackendSpecific { err } => {
+                PlayStreamError::BackendSpecific(BackendSpecificError(err.to_string()))
+            }
+            e => PlayStreamError::Unknown(e.to_string()),
+        })
     }
 
     fn pause(&mut self) -> Result<(), PlayStreamError> {
-        self.0.pause().unwrap();
-        Ok(())
+        self.0.pause().map_err(|e| match e {
+            cpal::PauseStreamError::DeviceNotAvailable => PlayStreamError::DeviceNotAvailable,
+            cpal::PauseStreamError::BackendSpecific { err } => {
+                PlayStreamError::BackendSpecific(BackendSpecificError(err.to_string()))
+            }
+            e => PlayStreamError::Unknown(e.to_string()),
+        })
     }
 
     fn stop(&mut self) -> Result<(), PlayStreamError> {
@@ -46,74 +57,209 @@ impl Stream for CpalStream {
     }
 }
 
+fn convert_buffer_size(buffer_size: &cpal::SupportedBufferSize) -> SupportedBufferSize {
+    match buffer_size {
+        cpal::SupportedBufferSize::Range { min, max } => SupportedBufferSize::Range {
+            min: *min,
+            max: *max,
+        },
+        cpal::SupportedBufferSize::Unknown => SupportedBufferSize::Unknown,
+    }
+}
+
+fn convert_sample_format(sample_format: cpal::SampleFormat) -> Option<SampleFormat> {
+    match sample_format {
+        cpal::SampleFormat::I8 => Some(SampleFormat::I8),
+        cpal::SampleFormat::I16 => Some(SampleFormat::I16),
+        cpal::SampleFormat::I24 => Some(SampleFormat::I24),
+        cpal::SampleFormat::I32 => Some(SampleFormat::I32),
+        cpal::SampleFormat::I64 => Some(SampleFormat::I64),
+        cpal::SampleFormat::U8 => Some(SampleFormat::U8),
+        cpal::SampleFormat::U16 => Some(SampleFormat::U16),
+        cpal::SampleFormat::U24 => Some(SampleFormat::U24),
+        cpal::SampleFormat::U32 => Some(SampleFormat::U32),
+        cpal::SampleFormat::U64 => Some(SampleFormat::U64),
+        cpal::SampleFormat::F32 => Some(SampleFormat::F32),
+        cpal::SampleFormat::F64 => Some(SampleFormat::F64),
+        _ => None,
+    }
+}
+
+fn convert_default_config_error(e: cpal::DefaultStreamConfigError) -> DefaultStreamConfigError {
+    match e {
+        cpal::DefaultStreamConfigError::DeviceNotAvailable => {
+            DefaultStreamConfigError::DeviceNotAvailable
+        }
+        cpal::DefaultStreamConfigError::StreamTypeNotSupported => {
+            DefaultStreamConfigError::StreamTypeNotSupported
+        }
+        cpal::DefaultStreamConfigError::BackendSpecific { err } => {
+            DefaultStreamConfigError::BackendSpecific(BackendSpecificError(err.to_string()))
+        }
+        e => DefaultStreamConfigError::Unknown(e.to_string()),
+    }
+}
+
+fn convert_default_config(
+    config: cpal::SupportedStreamConfig,
+) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+    let sample_format = convert_sample_format(config.sample_format()).ok_or_else(|| {
+        DefaultStreamConfigError::BackendSpecific(BackendSpecificError(format!(
+            "Unsupported sample format: {:?}",
+            config.sample_format()
+        )))
+    })?;
+
+    Ok(SupportedStreamConfig {
+        channels: ChannelCount(config.channels()),
+        sample_rate: SampleRate(config.sample_rate()),
+        buffer_size: convert_buffer_size(config.buffer_size()),
+        sample_format,
+    })
+}
+
+fn convert_supported_configs_error(
+    e: cpal::SupportedStreamConfigsError,
+) -> SupportedStreamConfigsError {
+    match e {
+        cpal::SupportedStreamConfigsError::DeviceNotAvailable => {
+            SupportedStreamConfigsError::DeviceNotAvailable
+        }
+        cpal::SupportedStreamConfigsError::InvalidArgument => {
+            SupportedStreamConfigsError::InvalidArgument
+        }
+        cpal::SupportedStreamConfigsError::BackendSpecific { err } => {
+            SupportedStreamConfigsError::BackendSpecific(BackendSpecificError(err.to_string()))
+        }
+        e => SupportedStreamConfigsError::Unknown(e.to_string()),
+    }
+}
+
+fn convert_config_range(c: cpal::SupportedStreamConfigRange) -> Option<SupportedStreamConfigRange> {
+    Some(SupportedStreamConfigRange {
+        channels: ChannelCount(c.channels()),
+        min_sample_rate: SampleRate(c.min_sample_rate()),
+        max_sample_rate: SampleRate(c.max_sample_rate()),
+        buffer_size: convert_buffer_size(c.buffer_size()),
+        sample_format: convert_sample_format(c.sample_format())?,
+    })
+}
+
+fn convert_stream_config(config: &StreamConfig) -> cpal::StreamConfig {
+    cpal::StreamConfig {
+        channels: config.channels.0,
+        sample_rate: config.sample_rate.0,
+        buffer_size: match config.buffer_size {
+            BufferSize::Fixed(val) => cpal::BufferSize::Fixed(val),
+            BufferSize::Default => cpal::BufferSize::Default,
+        },
+    }
+}
+
+fn convert_stream_error(stream_error: cpal::StreamError) -> StreamError {
+    match stream_error {
+        cpal::StreamError::DeviceNotAvailable => StreamError::DeviceNotAvailable,
+        cpal::StreamError::StreamInvalidated => StreamError::StreamInvalidated,
+        cpal::StreamError::BufferUnderrun => StreamError::BufferUnderrun,
+        cpal::StreamError::BackendSpecific { err } => {
+            StreamError::BackendSpecific(BackendSpecificError(err.to_string()))
+        }
+        err => StreamError::Unknown(err.to_string()),
+    }
+}
+
+fn convert_build_stream_error(e: cpal::BuildStreamError) -> BuildStreamError {
+    match e {
+        cpal::BuildStreamError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+        cpal::BuildStreamError::StreamConfigNotSupported => {
+            BuildStreamError::StreamConfigNotSupported
+        }
+        cpal::BuildStreamError::InvalidArgument => BuildStreamError::InvalidArgument,
+        cpal::BuildStreamError::StreamIdOverflow => BuildStreamError::StreamIdOverflow,
+        cpal::BuildStreamError::BackendSpecific { err } => {
+            BuildStreamError::BackendSpecific(BackendSpecificError(err.to_string()))
+        }
+        cpal::BuildStreamError::DeviceBusy => BuildStreamError::DeviceBusy,
+        e => BuildStreamError::Unknown(e.to_string()),
+    }
+}
+
+fn convert_devices_error(e: cpal::DevicesError) -> DevicesError {
+    match e {
+        cpal::DevicesError::BackendSpecific { err } => {
+            DevicesError::BackendSpecific(BackendSpecificError(err.to_string()))
+        }
+        e => DevicesError::Unknown(e.to_string()),
+    }
+}
+
 impl Device for CpalDevice {
     type SupportedOutputConfigs = Box<dyn Iterator<Item = SupportedStreamConfigRange>>;
 
     fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
-        let config = self.0.default_output_config().unwrap();
-
-        Ok(SupportedStreamConfig {
-            channels: ChannelCount(config.channels()),
-            sample_rate: SampleRate(config.sample_rate()),
-            buffer_size: match config.buffer_size() {
-                cpal::SupportedBufferSize::Range { min, max } => SupportedBufferSize::Range {
-                    min: *min,
-                    max: *max,
-                },
-                cpal::SupportedBufferSize::Unknown => SupportedBufferSize::Unknown,
-            },
-            sample_format: match config.sample_format() {
-                cpal::SampleFormat::I8 => SampleFormat::I8,
-                cpal::SampleFormat::I16 => SampleFormat::I16,
-                cpal::SampleFormat::I32 => SampleFormat::I32,
-                cpal::SampleFormat::I64 => SampleFormat::I64,
-                cpal::SampleFormat::U8 => SampleFormat::U8,
-                cpal::SampleFormat::U16 => SampleFormat::U16,
-                cpal::SampleFormat::U32 => SampleFormat::U32,
-                cpal::SampleFormat::U64 => SampleFormat::U64,
-                cpal::SampleFormat::F32 => SampleFormat::F32,
-                cpal::SampleFormat::F64 => SampleFormat::F64,
-                _ => unimplemented!("unsupported"),
-            },
-        })
+        convert_default_config(
+            self.0
+                .default_output_config()
+                .map_err(convert_default_config_error)?,
+        )
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        convert_default_config(
+            self.0
+                .default_input_config()
+                .map_err(convert_default_config_error)?,
+        )
     }
 
     fn name(&self) -> Result<String, DeviceNameError> {
-        Ok(self.0.description().unwrap().name().to_string())
+        let description = self.0.description().map_err(|e| match e {
+            cpal::DeviceNameError::BackendSpecific { err } => {
+                DeviceNameError::BackendSpecific(BackendSpecificError(err.to_string()))
+            }
+            e => DeviceNameError::Unknown(e.to_string()),
+        })?;
+        Ok(description.name().to_string())
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        self.0
+            .id()
+            .map(|id| DeviceId(id.to_string()))
+            .map_err(|e| match e {
+                cpal::DeviceIdError::UnsupportedPlatform => DeviceIdError::Unsupported,
+                cpal::DeviceIdError::BackendSpecific { err } => {
+                    DeviceIdError::BackendSpecific(BackendSpecificError(err.to_string()))
+                }
+                e => DeviceIdError::Unknown(e.to_string()),
+            })
+    }
+
+    fn vendor(&self) -> Option<String> {
+        self.0
+            .description()
+            .ok()
+            .and_then(|d| d.manufacturer().map(|m| m.to_string()))
     }
 
     fn supported_output_configs(
         &self,
     ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
-        Ok(Box::new(self.0.supported_output_configs().unwrap().map(
-            |c| SupportedStreamConfigRange {
-                channels: ChannelCount(c.channels()),
-                min_sample_rate: SampleRate(c.max_sample_rate()),
-                max_sample_rate: SampleRate(c.max_sample_rate()),
-                buffer_size: match c.buffer_size() {
-                    cpal::SupportedBufferSize::Range { min, max } => SupportedBufferSize::Range {
-                        min: *min,
-                        max: *max,
-                    },
-                    cpal::SupportedBufferSize::Unknown => SupportedBufferSize::Unknown,
-                },
-                sample_format: match c.sample_format() {
-                    cpal::SampleFormat::I8 => SampleFormat::I8,
-                    cpal::SampleFormat::I16 => SampleFormat::I16,
-                    cpal::SampleFormat::I24 => SampleFormat::I24,
-                    cpal::SampleFormat::I32 => SampleFormat::I32,
-                    cpal::SampleFormat::I64 => SampleFormat::I64,
-                    cpal::SampleFormat::U8 => SampleFormat::U8,
-                    cpal::SampleFormat::U16 => SampleFormat::U16,
-                    cpal::SampleFormat::U24 => SampleFormat::U24,
-                    cpal::SampleFormat::U32 => SampleFormat::U32,
-                    cpal::SampleFormat::U64 => SampleFormat::U64,
-                    cpal::SampleFormat::F32 => SampleFormat::F32,
-                    cpal::SampleFormat::F64 => SampleFormat::F64,
-                    c => unimplemented!("unsupported: {c:?}"),
-                },
-            },
-        )))
+        let configs = self
+            .0
+            .supported_output_configs()
+            .map_err(convert_supported_configs_error)?;
+        Ok(Box::new(configs.filter_map(convert_config_range)))
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        let configs = self
+            .0
+            .supported_input_configs()
+            .map_err(convert_supported_configs_error)?;
+        Ok(configs.filter_map(convert_config_range).collect())
     }
 
     fn build_output_stream<T, D, E>(
@@ -130,56 +276,60 @@ impl Device for CpalDevice {
         let stream = self
             .0
             .build_output_stream(
-                &cpal::StreamConfig {
-                    channels: config.channels.0,
-                    sample_rate: config.sample_rate.0,
-                    buffer_size: match config.buffer_size {
-                        BufferSize::Fixed(val) => cpal::BufferSize::Fixed(val),
-                        BufferSize::Default => cpal::BufferSize::Default,
-                    },
-                },
+                &convert_stream_config(config),
                 move |data: &mut [T], _| {
                     data_callback(data);
                 },
                 move |stream_error| {
-                    error_callback(match stream_error {
-                        cpal::StreamError::DeviceNotAvailable => StreamError::DeviceNotAvailable,
-                        cpal::StreamError::StreamInvalidated => StreamError::StreamInvalidated,
-                        cpal::StreamError::BufferUnderrun => StreamError::BufferUnderrun,
-                        cpal::StreamError::BackendSpecific { err } => {
-                            StreamError::BackendSpecific(BackendSpecificError(err.to_string()))
-                        }
-                        err => StreamError::Unknown(err.to_string()),
-                    });
+                    error_callback(convert_stream_error(stream_error));
                 },
                 None,
             )
-            .map_err(|e| match e {
-                cpal::BuildStreamError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
-                //cpal::BuildStreamError::DeviceBusy => BuildStreamError::DeviceBusy,
-                cpal::BuildStreamError::StreamConfigNotSupported => {
-                    BuildStreamError::StreamConfigNotSupported
-                }
-                cpal::BuildStreamError::InvalidArgument => BuildStreamError::InvalidArgument,
-                cpal::BuildStreamError::StreamIdOverflow => BuildStreamError::StreamIdOverflow,
-                cpal::BuildStreamError::BackendSpecific { err } => {
-                    BuildStreamError::BackendSpecific(BackendSpecificError(err.to_string()))
-                }
-                cpal::BuildStreamError::DeviceBusy => BuildStreamError::DeviceBusy,
-                e => BuildStreamError::Unknown(e.to_string()),
-            })?;
+            .map_err(convert_build_stream_error)?;
+
+        Ok(Box::new(CpalStream(stream)))
+    }
+
+    fn build_input_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        mut error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&[T]) + Send + 'static,
+        E: FnMut(StreamError) + Send + Sync + 'static,
+    {
+        let stream = self
+            .0
+            .build_input_stream(
+                &convert_stream_config(config),
+                move |data: &[T], _| {
+                    data_callback(data);
+                },
+                move |stream_error| {
+                    error_callback(convert_stream_error(stream_error));
+                },
+                None,
+            )
+            .map_err(convert_build_stream_error)?;
 
         Ok(Box::new(CpalStream(stream)))
     }
 }
 
+// cpal doesn't expose host-level device notifications. Changes to the active device are reported
+// through the stream's error callback, and the device watcher polls for everything else.
 impl Host for CpalHost {
     type Device = CpalDevice;
     type Id = cpal::HostId;
     type Devices = CpalDevices;
 
     fn from_id(id: cpal::HostId) -> Result<Self, HostUnavailableError> {
-        Ok(cpal::host_from_id(id).map(CpalHost).unwrap())
+        cpal::host_from_id(id)
+            .map(CpalHost)
+            .map_err(|_| HostUnavailableError::HostUnavailable)
     }
 
     fn default_output_device(&self) -> Option<Self::Device> {
@@ -187,10 +337,25 @@ impl Host for CpalHost {
     }
 
     fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
-        Ok(CpalDevices(self.0.output_devices().unwrap()))
+        let devices = self.0.output_devices().map_err(convert_devices_error)?;
+        Ok(CpalDevices(devices))
+    }
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        self.0.default_input_device().map(CpalDevice)
+    }
+
+    fn input_devices(&self) -> Result<Vec<Self::Device>, DevicesError> {
+        let devices = self.0.input_devices().map_err(convert_devices_error)?;
+        Ok(CpalDevices(devices).collect())
     }
 
     fn id(&self) -> Self::Id {
         self.0.id()
     }
+
+    // WASAPI streams stay on the device they were opened on
+    fn needs_default_device_restart(&self) -> bool {
+        cfg!(windows)
+    }
 }
@@ -0,0 +1,152 @@
+use std::f32::consts::TAU;
+
+use dasp::Sample;
+
+use crate::SampleRate;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrossfeedSettings {
+    /// Cutoff of the low-pass filter on the signal fed to the opposite ear, from 300 to 2000 Hz.
+    pub cutoff: f32,
+    /// How much quieter the opposite ear's low frequencies are, from 1 to 15 dB.
+    pub feed: f32,
+}
+
+impl CrossfeedSettings {
+    /// Close to a virtual speaker placement with about 3 m distance and 30° angle.
+    pub const DEFAULT: Self = Self {
+        cutoff: 700.0,
+        feed: 4.5,
+    };
+    /// Chu Moy's circuit.
+    pub const CHU_MOY: Self = Self {
+        cutoff: 700.0,
+        feed: 6.0,
+    };
+    /// Jan Meier's circuit.
+    pub const JAN_MEIER: Self = Self {
+        cutoff: 650.0,
+        feed: 9.5,
+    };
+}
+
+impl Default for CrossfeedSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Coefficients {
+    a0_lo: f32,
+    b1_lo: f32,
+    a0_hi: f32,
+    a1_hi: f32,
+    b1_hi: f32,
+}
+
+impl Coefficients {
+    fn new(settings: CrossfeedSettings, sample_rate: SampleRate) -> Self {
+        let cutoff = settings.cutoff.clamp(300.0, 2000.0);
+        let feed = settings.feed.clamp(1.0, 15.0);
+        let rate = sample_rate.0.max(1) as f32;
+
+        // Bauer's stereophonic-to-binaural filter, as in bs2b
+        let gain_lo_db = feed * -5.0 / 6.0 - 3.0;
+        let gain_hi_db = feed / 6.0 - 3.0;
+        let gain_lo = 10f32.powf(gain_lo_db / 20.0);
+        let gain_hi = 1.0 - 10f32.powf(gain_hi_db / 20.0);
+        let cutoff_hi = cutoff * 2f32.powf((gain_lo_db - 20.0 * gain_hi.log10()) / 12.0);
+
+        // Keeps sounds in the center at the same level
+        let gain = 1.0 / (1.0 - gain_hi + gain_lo);
+
+        let x = (-TAU * cutoff / rate).exp();
+        let b1_lo = x;
+        let a0_lo = gain_lo * (1.0 - x) * gain;
+        let x = (-TAU * cutoff_hi / rate).exp();
+        Self {
+            a0_lo,
+            b1_lo,
+            a0_hi: (1.0 - gain_hi * (1.0 - x)) * gain,
+            a1_hi: -x * gain,
+            b1_hi: x,
+        }
+    }
+}
+
+/// Bauer stereophonic-to-binaural crossfeed. Mixes a low-passed copy of each stereo channel into
+/// the other one, so music that was mixed for speakers is less tiring to listen to on headphones.
+pub struct Crossfeed {
+    settings: CrossfeedSettings,
+    sample_rate: SampleRate,
+    coefficients: Coefficients,
+    // Previous input, and the low-pass and high-shelf outputs, for each channel
+    input: [f32; 2],
+    lo: [f32; 2],
+    hi: [f32; 2],
+}
+
+impl Crossfeed {
+    pub fn new(settings: CrossfeedSettings, sample_rate: SampleRate) -> Self {
+        Self {
+            settings,
+            sample_rate,
+            coefficients: Coefficients::new(settings, sample_rate),
+            input: [0.0; 2],
+            lo: [0.0; 2],
+            hi: [0.0; 2],
+        }
+    }
+
+    pub fn settings(&self) -> CrossfeedSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: CrossfeedSettings) {
+        self.settings = settings;
+        self.coefficients = Coefficients::new(settings, self.sample_rate);
+    }
+
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// Recomputes the filters for a new rate and clears their state.
+    pub fn set_sample_rate(&mut self, sample_rate: SampleRate) {
+        self.sample_rate = sample_rate;
+        self.coefficients = Coefficients::new(self.settings, sample_rate);
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.input = [0.0; 2];
+        self.lo = [0.0; 2];
+        self.hi = [0.0; 2];
+    }
+
+    /// Processes interleaved stereo samples in place.
+    pub fn process<T: Sample>(&mut self, samples: &mut [T]) {
+        let c = self.coefficients;
+        for frame in samples.chunks_exact_mut(2) {
+            let input = [to_f32(frame[0]), to_f32(frame[1])];
+            for channel in 0..2 {
+                self.lo[channel] = c.a0_lo * input[channel] + c.b1_lo * self.lo[channel];
+                self.hi[channel] = c.a0_hi * input[channel]
+                    + c.a1_hi * self.input[channel]
+                    + c.b1_hi * self.hi[channel];
+            }
+            self.input = input;
+            frame[0] = from_f32(self.hi[0] + self.lo[1]);
+            frame[1] = from_f32(self.hi[1] + self.lo[0]);
+        }
+    }
+}
+
+fn to_f32<T: Sample>(sample: T) -> f32 {
+    sample.to_float_sample().to_sample::<f32>()
+}
+
+fn from_f32<T: Sample>(value: f32) -> T {
+    T::from_float_sample(<T::Float as Sample>::from_sample(value.clamp(-1.0, 1.0)))
+}
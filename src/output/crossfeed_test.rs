@@ -0,0 +1,93 @@
+use super::{Crossfeed, CrossfeedSettings};
+use crate::SampleRate;
+
+fn impulse_response(crossfeed: &mut Crossfeed, left: f32, right: f32, frames: usize) -> Vec<f32> {
+    let mut samples = vec![0.0; frames * 2];
+    samples[0] = left;
+    samples[1] = right;
+    crossfeed.process(&mut samples);
+    samples
+}
+
+fn dc_gain(settings: CrossfeedSettings, left: f32, right: f32) -> (f32, f32) {
+    let mut crossfeed = Crossfeed::new(settings, SampleRate(44100));
+    let mut samples = [left, right].repeat(44100);
+    crossfeed.process(&mut samples);
+    (samples[samples.len() - 2], samples[samples.len() - 1])
+}
+
+#[test]
+fn feeds_left_into_right() {
+    let mut crossfeed = Crossfeed::new(CrossfeedSettings::DEFAULT, SampleRate(44100));
+    let samples = impulse_response(&mut crossfeed, 0.5, 0.0, 64);
+    let right: f32 = samples.iter().skip(1).step_by(2).sum();
+    assert!(right > 0.0);
+    assert!(samples[0] > samples[1]);
+}
+
+#[test]
+fn stronger_presets_feed_more_bass() {
+    // The feed level is how far below the direct signal the crossfed low frequencies are
+    for settings in [
+        CrossfeedSettings::DEFAULT,
+        CrossfeedSettings::CHU_MOY,
+        CrossfeedSettings::JAN_MEIER,
+    ] {
+        let (direct, crossed) = dc_gain(settings, 0.25, 0.0);
+        let feed = 20.0 * (direct / crossed).log10();
+        assert!(
+            (feed - settings.feed).abs() < 0.1,
+            "{settings:?}: {feed} dB"
+        );
+    }
+}
+
+#[test]
+fn centered_signal_keeps_its_level() {
+    // Low frequencies in both channels sum back to about the input level
+    let (left, right) = dc_gain(CrossfeedSettings::DEFAULT, 0.25, 0.25);
+    assert_eq!(left, right);
+    assert!((left - 0.25).abs() < 0.02, "{left}");
+}
+
+#[test]
+fn settings_are_clamped() {
+    let extreme = CrossfeedSettings {
+        cutoff: 10.0,
+        feed: 40.0,
+    };
+    let clamped = CrossfeedSettings {
+        cutoff: 300.0,
+        feed: 15.0,
+    };
+    let mut a = Crossfeed::new(extreme, SampleRate(44100));
+    let mut b = Crossfeed::new(clamped, SampleRate(44100));
+    assert_eq!(
+        impulse_response(&mut a, 1.0, 0.0, 16),
+        impulse_response(&mut b, 1.0, 0.0, 16)
+    );
+    assert_eq!(extreme, a.settings());
+}
+
+#[test]
+fn sample_rate_change_recomputes_filters() {
+    let mut crossfeed = Crossfeed::new(CrossfeedSettings::DEFAULT, SampleRate(44100));
+    let at_44100 = impulse_response(&mut crossfeed, 1.0, 0.0, 16);
+
+    crossfeed.set_sample_rate(SampleRate(96000));
+    assert_eq!(SampleRate(96000), crossfeed.sample_rate());
+    let at_96000 = impulse_response(&mut crossfeed, 1.0, 0.0, 16);
+    assert_ne!(at_44100, at_96000);
+
+    let mut fresh = Crossfeed::new(CrossfeedSettings::DEFAULT, SampleRate(96000));
+    assert_eq!(at_96000, impulse_response(&mut fresh, 1.0, 0.0, 16));
+}
+
+#[test]
+fn converts_integer_samples() {
+    let mut crossfeed = Crossfeed::new(CrossfeedSettings::DEFAULT, SampleRate(44100));
+    let mut samples = [i16::MAX / 2, 0].repeat(4410);
+    crossfeed.process(&mut samples);
+    let last = &samples[samples.len() - 2..];
+    assert!(last[0] > last[1] && last[1] > 0);
+}
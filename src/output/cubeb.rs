@@ -1,18 +1,24 @@
 use std::cell::OnceCell;
+use std::ffi::c_void;
+use std::ptr;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use cubeb::{
-    self, ChannelLayout, Context, DeviceFormat, DeviceId, DeviceInfo, DeviceState, DeviceType,
-    MonoFrame, StereoFrame, StreamPrefs,
+    self, ChannelLayout, Context, DeviceFormat, DeviceInfo, DeviceState, DeviceType, MonoFrame,
+    StereoFrame, StreamPrefs,
 };
-use cubeb_core::DevicePref;
+use cubeb_core::{DevicePref, ffi};
+use tap::TapFallible;
+use tracing::warn;
 
 use crate::ChannelCount;
 
 use super::{
-    DecalSample, DefaultStreamConfigError, Device, Host, SampleFormat, SampleRate, Stream,
-    StreamConfig, StreamError, SupportedBufferSize, SupportedStreamConfig,
-    SupportedStreamConfigRange,
+    BackendSpecificError, BuildStreamError, DecalSample, DefaultStreamConfigError, Device,
+    DeviceId, DeviceIdError, DeviceNameError, DeviceNotificationHandle, DevicesError, Host,
+    HostUnavailableError, PlayStreamError, SampleFormat, SampleRate, Stream, StreamConfig,
+    StreamError, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError,
 };
 
 thread_local! {
@@ -21,9 +27,9 @@ thread_local! {
     static COM_INIT: AtomicBool = const { std::sync::atomic::AtomicBool::new(false) };
 }
 
-fn with_context<F, T>(f: F) -> T
+fn with_context<F, T>(f: F) -> Result<T, cubeb::Error>
 where
-    F: FnOnce(&Context) -> T,
+    F: FnOnce(&Context) -> Result<T, cubeb::Error>,
 {
     #[cfg(windows)]
     COM_INIT.with(|init| {
@@ -44,22 +50,36 @@ where
         }
     });
     CONTEXT.with(|c| {
-        let context =
-            c.get_or_init(|| cubeb::init("context").expect("failed to initialize context"));
-        f(context)
+        if c.get().is_none() {
+            // If initialization fails, we'll try again the next time the context is requested
+            let _ = c.set(cubeb::init("context")?);
+        }
+        f(c.get().expect("context initialized"))
     })
 }
 
+fn backend_error(err: cubeb::Error) -> BackendSpecificError {
+    BackendSpecificError(err.to_string())
+}
+
 #[derive(Clone)]
 pub struct CubebDevice {
-    default_output_config: SupportedStreamConfig,
+    default_config: SupportedStreamConfig,
     name: String,
-    output_configs: Vec<SupportedStreamConfigRange>,
-    device_id: DeviceId,
+    vendor: Option<String>,
+    id: DeviceId,
+    configs: Vec<SupportedStreamConfigRange>,
+    device_id: cubeb::DeviceId,
+    // Cubeb enumerates input and output devices separately
+    device_type: DeviceType,
 }
 
+// `device_id` is an opaque handle that's only passed back to cubeb when opening a stream, which
+// works from any thread
+unsafe impl Send for CubebDevice {}
+
 impl CubebDevice {
-    fn new(device: &DeviceInfo) -> Self {
+    fn new(device: &DeviceInfo, device_type: DeviceType) -> Self {
         let default_format = device.default_format();
 
         let name = device
@@ -96,7 +116,7 @@ impl CubebDevice {
         }
 
         Self {
-            default_output_config: SupportedStreamConfig {
+            default_config: SupportedStreamConfig {
                 channels: ChannelCount(device.max_channels() as u16),
                 sample_rate: SampleRate(device.default_rate()),
                 buffer_size: SupportedBufferSize::Unknown,
@@ -106,29 +126,53 @@ impl CubebDevice {
                     _ => SampleFormat::F32,
                 },
             },
-            output_configs: configs,
+            configs,
+            // devid is only a handle into the current device collection, the backend's device_id
+            // string is what stays stable between enumerations
+            id: DeviceId(
+                device
+                    .device_id()
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| name.clone()),
+            ),
             name,
+            vendor: device.vendor_name().map(|n| n.to_string()),
             device_id: device.devid(),
+            device_type,
         }
     }
 
+    fn supported_configs(
+        &self,
+        device_type: DeviceType,
+    ) -> Result<Box<dyn Iterator<Item = SupportedStreamConfigRange>>, SupportedStreamConfigsError>
+    {
+        let configs = if self.device_type == device_type {
+            self.configs.clone()
+        } else {
+            Vec::new()
+        };
+        Ok(Box::new(configs.into_iter()))
+    }
+
     fn get_stream<S: DecalSample, T, D, E>(
         &self,
         config: &StreamConfig,
+        device_type: DeviceType,
         mut data_callback: D,
         mut error_callback: E,
-    ) -> Box<dyn Stream>
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
     where
         T: 'static,
-        D: FnMut(&mut [T]) + Send + Sync + 'static,
+        D: FnMut(&[T], &mut [T]) + Send + Sync + 'static,
         E: FnMut(StreamError) + Clone + Send + Sync + 'static,
     {
         let params = cubeb::StreamParamsBuilder::new()
             .channels(config.channels.0 as u32)
             .format(match <S as DecalSample>::FORMAT {
-                SampleFormat::I32 => cubeb::SampleFormat::S16NE,
+                SampleFormat::I16 => cubeb::SampleFormat::S16NE,
                 SampleFormat::F32 => cubeb::SampleFormat::Float32NE,
-                _ => unimplemented!(),
+                _ => return Err(BuildStreamError::StreamConfigNotSupported),
             })
             .layout(if config.channels.0 > 1 {
                 ChannelLayout::STEREO
@@ -138,17 +182,27 @@ impl CubebDevice {
             .rate(config.sample_rate.0)
             .prefs(StreamPrefs::NONE)
             .take();
-        let latency = with_context(|c| c.min_latency(&params).unwrap());
+        let latency = with_context(|c| c.min_latency(&params))
+            .map_err(|e| BuildStreamError::BackendSpecific(backend_error(e)))?;
         #[cfg(target_os = "macos")]
         let mut error_callback_ = error_callback.clone();
         let mut builder = cubeb::StreamBuilder::<T>::new();
+        let input = device_type == DeviceType::INPUT;
+        if input {
+            builder.input(self.device_id, &params);
+        } else {
+            builder.output(self.device_id, &params);
+        }
         builder
             .name("stream")
             .latency(latency)
-            .output(self.device_id, &params)
-            .data_callback(move |_, output| {
-                data_callback(output);
-                output.len() as isize
+            .data_callback(move |input_frames, output_frames| {
+                data_callback(input_frames, output_frames);
+                if input {
+                    input_frames.len() as isize
+                } else {
+                    output_frames.len() as isize
+                }
             })
             .state_callback(move |state| {
                 match state {
@@ -166,17 +220,18 @@ impl CubebDevice {
             error_callback_(StreamError::DeviceNotAvailable);
         });
         let stream = with_context(move |ctx| {
-            let stream = builder.init(ctx).unwrap();
+            let stream = builder.init(ctx)?;
             // On Windows, if we don't call start here and wait to call it when play() is invoked,
             // cubeb throws an error. If we don't call start here and call stop() before
             // calling start() later, the error doesn't happen. No idea why...
-            stream.start().unwrap();
-            stream
-        });
-        Box::new(CubebStream {
+            stream.start()?;
+            Ok(stream)
+        })
+        .map_err(|e| BuildStreamError::BackendSpecific(backend_error(e)))?;
+        Ok(Box::new(CubebStream {
             stream,
             started: AtomicBool::new(true),
-        })
+        }))
     }
 }
 
@@ -184,17 +239,41 @@ impl Device for CubebDevice {
     type SupportedOutputConfigs = Box<dyn Iterator<Item = SupportedStreamConfigRange>>;
 
     fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
-        Ok(self.default_output_config.clone())
+        if self.device_type != DeviceType::OUTPUT {
+            return Err(DefaultStreamConfigError::StreamTypeNotSupported);
+        }
+        Ok(self.default_config.clone())
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        if self.device_type != DeviceType::INPUT {
+            return Err(DefaultStreamConfigError::StreamTypeNotSupported);
+        }
+        Ok(self.default_config.clone())
     }
 
-    fn name(&self) -> Result<String, super::DeviceNameError> {
+    fn name(&self) -> Result<String, DeviceNameError> {
         Ok(self.name.clone())
     }
 
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(self.id.clone())
+    }
+
+    fn vendor(&self) -> Option<String> {
+        self.vendor.clone()
+    }
+
     fn supported_output_configs(
         &self,
-    ) -> Result<Self::SupportedOutputConfigs, super::SupportedStreamConfigsError> {
-        Ok(Box::new(self.output_configs.clone().into_iter()))
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        self.supported_configs(DeviceType::OUTPUT)
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        Ok(self.supported_configs(DeviceType::INPUT)?.collect())
     }
 
     fn build_output_stream<T, D, E>(
@@ -202,18 +281,22 @@ impl Device for CubebDevice {
         config: &StreamConfig,
         mut data_callback: D,
         error_callback: E,
-    ) -> Result<Box<dyn Stream>, super::BuildStreamError>
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
     where
         T: DecalSample,
         D: FnMut(&mut [T]) + Send + Sync + 'static,
-        E: FnMut(super::StreamError) + Clone + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
     {
+        if self.device_type != DeviceType::OUTPUT {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
         let mut buf = vec![T::EQUILIBRIUM; 1024];
 
         if config.channels.0 > 1 {
-            Ok(self.get_stream::<T, _, _, _>(
+            self.get_stream::<T, _, _, _>(
                 config,
-                move |output| {
+                DeviceType::OUTPUT,
+                move |_, output| {
                     let samples = output.len() * 2;
                     if buf.len() < samples {
                         buf.resize(samples, T::EQUILIBRIUM);
@@ -230,11 +313,12 @@ impl Device for CubebDevice {
                     }
                 },
                 error_callback,
-            ))
+            )
         } else {
-            Ok(self.get_stream::<T, _, _, _>(
+            self.get_stream::<T, _, _, _>(
                 config,
-                move |output| {
+                DeviceType::OUTPUT,
+                move |_, output| {
                     let samples = output.len() * 2;
                     if buf.len() < samples {
                         buf.resize(samples, T::EQUILIBRIUM);
@@ -246,7 +330,48 @@ impl Device for CubebDevice {
                     }
                 },
                 error_callback,
-            ))
+            )
+        }
+    }
+
+    fn build_input_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&[T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        if self.device_type != DeviceType::INPUT {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
+        let mut buf = Vec::with_capacity(1024);
+
+        if config.channels.0 > 1 {
+            self.get_stream::<T, _, _, _>(
+                config,
+                DeviceType::INPUT,
+                move |input: &[StereoFrame<T>], _| {
+                    buf.clear();
+                    buf.extend(input.iter().flat_map(|frame| [frame.l, frame.r]));
+                    data_callback(&buf);
+                },
+                error_callback,
+            )
+        } else {
+            self.get_stream::<T, _, _, _>(
+                config,
+                DeviceType::INPUT,
+                move |input: &[MonoFrame<T>], _| {
+                    buf.clear();
+                    buf.extend(input.iter().map(|frame| frame.m));
+                    data_callback(&buf);
+                },
+                error_callback,
+            )
         }
     }
 }
@@ -271,26 +396,86 @@ impl<T> Drop for CubebStream<T> {
 }
 
 impl<T> Stream for CubebStream<T> {
-    fn play(&mut self) -> Result<(), super::PlayStreamError> {
+    fn play(&mut self) -> Result<(), PlayStreamError> {
         if !self.started.swap(true, Ordering::SeqCst) {
-            self.stream.start().unwrap();
+            self.stream.start().map_err(|e| {
+                self.started.store(false, Ordering::SeqCst);
+                PlayStreamError::BackendSpecific(backend_error(e))
+            })?;
         }
 
         Ok(())
     }
 
-    fn pause(&mut self) -> Result<(), super::PlayStreamError> {
+    fn pause(&mut self) -> Result<(), PlayStreamError> {
         Ok(())
     }
 
-    fn stop(&mut self) -> Result<(), super::PlayStreamError> {
+    fn stop(&mut self) -> Result<(), PlayStreamError> {
         if self.started.swap(false, Ordering::SeqCst) {
-            self.stream.stop().unwrap();
+            self.stream
+                .stop()
+                .map_err(|e| PlayStreamError::BackendSpecific(backend_error(e)))?;
         }
         Ok(())
     }
 }
 
+type DeviceChangedCallback = Box<dyn Fn() + Send + Sync>;
+
+unsafe extern "C" fn device_collection_changed(_context: *mut ffi::cubeb, user_ptr: *mut c_void) {
+    // SAFETY: user_ptr was created from a boxed callback in watch_devices and stays alive until
+    // the callback is unregistered
+    let callback = unsafe { &*(user_ptr as *const DeviceChangedCallback) };
+    callback();
+}
+
+struct CubebDeviceNotification {
+    callback: *mut DeviceChangedCallback,
+}
+
+impl Drop for CubebDeviceNotification {
+    fn drop(&mut self) {
+        let unregistered = with_context(|ctx| unsafe {
+            ctx.register_device_collection_changed(DeviceType::OUTPUT, None, ptr::null_mut())
+        })
+        .tap_err(|e| warn!("Error unregistering device notification: {e:?}"));
+        // If unregistering failed, cubeb may still call into the callback so we have to leak it
+        if unregistered.is_ok() {
+            drop(unsafe { Box::from_raw(self.callback) });
+        }
+    }
+}
+
+fn default_device(device_type: DeviceType) -> Option<CubebDevice> {
+    with_context(|ctx| {
+        Ok(ctx
+            .enumerate_devices(device_type)?
+            .iter()
+            .find(|d| {
+                d.preferred()
+                    .intersects(DevicePref::MULTIMEDIA | DevicePref::ALL)
+                    && d.state() == DeviceState::Enabled
+            })
+            .map(|d| CubebDevice::new(d, device_type)))
+    })
+    .tap_err(|e| tracing::error!("Error enumerating devices: {e:?}"))
+    .ok()
+    .flatten()
+}
+
+fn devices(device_type: DeviceType) -> Result<Box<dyn Iterator<Item = CubebDevice>>, DevicesError> {
+    let devices: Vec<_> = with_context(|ctx| {
+        Ok(ctx
+            .enumerate_devices(device_type)?
+            .iter()
+            .map(|d| CubebDevice::new(d, device_type))
+            .collect())
+    })
+    .map_err(|e| DevicesError::BackendSpecific(backend_error(e)))?;
+    Ok(Box::new(devices.into_iter()))
+}
+
 #[derive(Default)]
 pub struct CubebHost {}
 
@@ -299,34 +484,45 @@ impl Host for CubebHost {
     type Id = ();
     type Devices = Box<dyn Iterator<Item = CubebDevice>>;
 
-    fn from_id(_id: Self::Id) -> Result<Self, super::HostUnavailableError> {
+    fn from_id(_id: Self::Id) -> Result<Self, HostUnavailableError> {
         Ok(Self::default())
     }
 
     fn default_output_device(&self) -> Option<Self::Device> {
-        with_context(|ctx| {
-            ctx.enumerate_devices(DeviceType::OUTPUT)
-                .unwrap()
-                .iter()
-                .find(|d| {
-                    d.preferred()
-                        .intersects(DevicePref::MULTIMEDIA | DevicePref::ALL)
-                        && d.state() == DeviceState::Enabled
-                })
-                .map(CubebDevice::new)
-        })
+        default_device(DeviceType::OUTPUT)
     }
 
-    fn output_devices(&self) -> Result<Self::Devices, super::DevicesError> {
-        let devices: Vec<_> = with_context(|ctx| {
-            ctx.enumerate_devices(DeviceType::OUTPUT)
-                .unwrap()
-                .iter()
-                .map(CubebDevice::new)
-                .collect()
-        });
-        Ok(Box::new(devices.into_iter()))
+    fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
+        devices(DeviceType::OUTPUT)
+    }
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        default_device(DeviceType::INPUT)
+    }
+
+    fn input_devices(&self) -> Result<Vec<Self::Device>, DevicesError> {
+        Ok(devices(DeviceType::INPUT)?.collect())
     }
 
     fn id(&self) -> Self::Id {}
+
+    fn watch_devices(&self, on_change: DeviceChangedCallback) -> Option<DeviceNotificationHandle> {
+        let callback = Box::into_raw(Box::new(on_change));
+        match with_context(|ctx| unsafe {
+            ctx.register_device_collection_changed(
+                DeviceType::OUTPUT,
+                Some(device_collection_changed),
+                callback as *mut c_void,
+            )
+        }) {
+            Ok(()) => Some(DeviceNotificationHandle::new(CubebDeviceNotification {
+                callback,
+            })),
+            Err(e) => {
+                warn!("Error registering device notification: {e:?}");
+                drop(unsafe { Box::from_raw(callback) });
+                None
+            }
+        }
+    }
 }
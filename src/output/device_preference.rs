@@ -0,0 +1,36 @@
+use super::DeviceId;
+
+/// One entry in a device preference list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceMatcher {
+    /// The device with this ID.
+    Id(DeviceId),
+    /// Any device whose name contains the text, ignoring case.
+    NameContains(String),
+    /// Whichever device is the system default.
+    Default,
+}
+
+impl DeviceMatcher {
+    pub fn matches(&self, id: &DeviceId, name: &str, is_default: bool) -> bool {
+        match self {
+            Self::Id(expected) => expected == id,
+            Self::NameContains(text) => name.to_lowercase().contains(&text.trim().to_lowercase()),
+            Self::Default => is_default,
+        }
+    }
+}
+
+/// How the output device is chosen.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum DeviceSelection {
+    /// Use the system default and move to the new default whenever it changes.
+    #[default]
+    FollowDefault,
+    /// Always use this device, found by ID or name. The output is paused while it's unavailable
+    /// and moves back to it once it returns.
+    Pinned(String),
+    /// Use the first available device in the list, switching as devices come and go. Falls back
+    /// to the system default if none of them are available.
+    Preferred(Vec<DeviceMatcher>),
+}
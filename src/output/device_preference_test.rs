@@ -0,0 +1,60 @@
+use super::{DeviceId, DeviceMatcher, MockDevice, MockHost, OutputBuilder};
+
+fn host() -> MockHost {
+    let speakers = MockHost::default().devices().remove(0);
+    let device = |name: &str| {
+        MockDevice::new(
+            name.to_owned(),
+            speakers.default_config.clone(),
+            speakers.default_min_sample_rate,
+            speakers.default_max_sample_rate,
+            vec![],
+        )
+    };
+    MockHost::new(device("Speakers"), vec![device("HDMI Output")])
+}
+
+#[test]
+fn matcher_name_ignores_case() {
+    let id = DeviceId("usb-1".to_owned());
+    assert!(DeviceMatcher::NameContains("usb dac".to_owned()).matches(&id, "My USB DAC", false));
+    assert!(!DeviceMatcher::NameContains("hdmi".to_owned()).matches(&id, "My USB DAC", false));
+    assert!(DeviceMatcher::Id(id.clone()).matches(&id, "", false));
+    assert!(DeviceMatcher::Default.matches(&id, "", true));
+}
+
+#[test]
+fn find_preferred_device_in_order() {
+    let host = host();
+    let output_builder = OutputBuilder::new(host.clone(), Default::default(), || {}, |_| {});
+    let preferences = [
+        DeviceMatcher::NameContains("USB DAC".to_owned()),
+        DeviceMatcher::NameContains("HDMI".to_owned()),
+        DeviceMatcher::Default,
+    ];
+
+    assert_eq!(
+        Some(DeviceId("HDMI Output".to_owned())),
+        output_builder.find_preferred_device(&preferences).unwrap()
+    );
+
+    let usb = MockDevice::new(
+        "USB DAC".to_owned(),
+        host.devices()[0].default_config.clone(),
+        host.devices()[0].default_min_sample_rate,
+        host.devices()[0].default_max_sample_rate,
+        vec![],
+    );
+    host.add_device(usb);
+    assert_eq!(
+        Some(DeviceId("USB DAC".to_owned())),
+        output_builder.find_preferred_device(&preferences).unwrap()
+    );
+
+    host.remove_device(&DeviceId("USB DAC".to_owned()));
+    host.remove_device(&DeviceId("HDMI Output".to_owned()));
+    assert_eq!(
+        None,
+        output_builder.find_preferred_device(&preferences).unwrap()
+    );
+}
@@ -0,0 +1,270 @@
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tap::TapFallible;
+use tracing::{info, warn};
+
+use super::{Device, DeviceId, Host};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added(DeviceId),
+    Removed(DeviceId),
+    DefaultChanged(Option<DeviceId>),
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceWatcherSettings {
+    /// How often to rescan the device list. Backends with native notifications will also trigger
+    /// a rescan as soon as a change is reported.
+    pub poll_interval: Duration,
+}
+
+impl Default for DeviceWatcherSettings {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Keeps a backend's device notification registered until it's dropped.
+pub struct DeviceNotificationHandle {
+    _inner: Box<dyn Any>,
+}
+
+impl DeviceNotificationHandle {
+    pub(crate) fn new<T: Any>(inner: T) -> Self {
+        Self {
+            _inner: Box::new(inner),
+        }
+    }
+}
+
+enum WatcherMessage {
+    Rescan,
+    Stop,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct DeviceSnapshot {
+    pub(crate) devices: BTreeSet<DeviceId>,
+    pub(crate) default_device: Option<DeviceId>,
+}
+
+impl DeviceSnapshot {
+    fn capture<H: Host>(host: &H) -> Option<Self> {
+        let devices = host
+            .output_devices()
+            .tap_err(|e| warn!("Error loading devices: {e:?}"))
+            .ok()?
+            .filter_map(|d| d.id().ok())
+            .collect();
+        let default_device = host.default_output_device().and_then(|d| d.id().ok());
+        Some(Self {
+            devices,
+            default_device,
+        })
+    }
+
+    pub(crate) fn diff(&self, new: &Self) -> Vec<DeviceEvent> {
+        let mut events: Vec<_> = new
+            .devices
+            .difference(&self.devices)
+            .map(|d| DeviceEvent::Added(d.clone()))
+            .chain(
+                self.devices
+                    .difference(&new.devices)
+                    .map(|d| DeviceEvent::Removed(d.clone())),
+            )
+            .collect();
+        if self.default_device != new.default_device {
+            events.push(DeviceEvent::DefaultChanged(new.default_device.clone()));
+        }
+        events
+    }
+}
+
+/// Watches a host for devices being added or removed and for changes to the default device.
+///
+/// The background thread is stopped when the watcher is dropped.
+pub struct DeviceWatcher {
+    message_tx: mpsc::Sender<WatcherMessage>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DeviceWatcher {
+    pub fn new<H, F>(host: Arc<H>, settings: DeviceWatcherSettings, on_event: F) -> Self
+    where
+        H: Host,
+        F: Fn(DeviceEvent) + Send + 'static,
+    {
+        let (message_tx, message_rx) = mpsc::channel();
+        let notify_tx = message_tx.clone();
+
+        let handle = thread::spawn(move || {
+            // Register on the watcher thread since some backends tie notifications to the
+            // thread that created them
+            let notification = host.watch_devices(Box::new(move || {
+                notify_tx.send(WatcherMessage::Rescan).ok();
+            }));
+            if notification.is_none() {
+                info!("Native device notifications unavailable, polling for device changes");
+            }
+            let mut snapshot = DeviceSnapshot::capture(&*host).unwrap_or_default();
+
+            loop {
+                match message_rx.recv_timeout(settings.poll_interval) {
+                    Ok(WatcherMessage::Rescan) | Err(RecvTimeoutError::Timeout) => {}
+                    Ok(WatcherMessage::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+                let Some(new_snapshot) = DeviceSnapshot::capture(&*host) else {
+                    continue;
+                };
+                for event in snapshot.diff(&new_snapshot) {
+                    info!("Device event: {event:?}");
+                    on_event(event);
+                }
+                snapshot = new_snapshot;
+            }
+            drop(notification);
+        });
+
+        Self {
+            message_tx,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stops the watcher and waits for the background thread to exit.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.message_tx.send(WatcherMessage::Stop).ok();
+            // Dropped from one of its own callbacks. The thread exits once the callback returns.
+            if handle.thread().id() == thread::current().id() {
+                return;
+            }
+            handle
+                .join()
+                .tap_err(|e| warn!("Device watcher panicked: {e:?}"))
+                .ok();
+        }
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+type Subscriber = Arc<dyn Fn(DeviceEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    callbacks: BTreeMap<u64, Subscriber>,
+    watcher: Option<DeviceWatcher>,
+}
+
+/// Shares one [`DeviceWatcher`] between everything that subscribes to it. The watcher is started
+/// by the first subscription and stopped when the last one is dropped. Hosts whose devices can't
+/// change are never watched.
+pub(crate) struct SharedDeviceWatcher<H: Host> {
+    host: Arc<H>,
+    settings: DeviceWatcherSettings,
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl<H: Host> SharedDeviceWatcher<H> {
+    pub(crate) fn new(host: Arc<H>, settings: DeviceWatcherSettings) -> Self {
+        Self {
+            host,
+            settings,
+            subscribers: Default::default(),
+        }
+    }
+
+    pub(crate) fn subscribe<F>(&self, on_event: F) -> DeviceSubscription
+    where
+        F: Fn(DeviceEvent) + Send + Sync + 'static,
+    {
+        let mut subscribers = self.subscribers.lock().expect("lock poisoned");
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.callbacks.insert(id, Arc::new(on_event));
+        if subscribers.watcher.is_none() && self.host.devices_can_change() {
+            let weak = Arc::downgrade(&self.subscribers);
+            subscribers.watcher = Some(DeviceWatcher::new(
+                self.host.clone(),
+                self.settings.clone(),
+                move |event| {
+                    let Some(subscribers) = weak.upgrade() else {
+                        return;
+                    };
+                    // Callbacks can subscribe or unsubscribe, so they're called without the lock
+                    let callbacks: Vec<_> = subscribers
+                        .lock()
+                        .expect("lock poisoned")
+                        .callbacks
+                        .values()
+                        .cloned()
+                        .collect();
+                    for callback in callbacks {
+                        callback(event.clone());
+                    }
+                },
+            ));
+        }
+        DeviceSubscription {
+            id,
+            subscribers: Arc::downgrade(&self.subscribers),
+        }
+    }
+}
+
+impl<H: Host> Drop for SharedDeviceWatcher<H> {
+    fn drop(&mut self) {
+        // Stop the thread here so it never ends up dropping (and joining) itself
+        let watcher = self
+            .subscribers
+            .lock()
+            .expect("lock poisoned")
+            .watcher
+            .take();
+        drop(watcher);
+    }
+}
+
+/// Receives device events until it's dropped.
+pub struct DeviceSubscription {
+    id: u64,
+    subscribers: Weak<Mutex<Subscribers>>,
+}
+
+impl Drop for DeviceSubscription {
+    fn drop(&mut self) {
+        let Some(subscribers) = self.subscribers.upgrade() else {
+            return;
+        };
+        let watcher = {
+            let mut subscribers = subscribers.lock().expect("lock poisoned");
+            subscribers.callbacks.remove(&self.id);
+            if subscribers.callbacks.is_empty() {
+                subscribers.watcher.take()
+            } else {
+                None
+            }
+        };
+        // Joins the thread, so the lock has to be released first
+        drop(watcher);
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{
+    DeviceEvent, DeviceId, DeviceSnapshot, DeviceWatcher, DeviceWatcherSettings, MockDevice,
+    MockHost, SharedDeviceWatcher,
+};
+
+#[test]
+fn diff_added_and_removed() {
+    let old = DeviceSnapshot {
+        devices: [
+            DeviceId("speakers".to_owned()),
+            DeviceId("headphones".to_owned()),
+        ]
+        .into(),
+        default_device: Some(DeviceId("speakers".to_owned())),
+    };
+    let new = DeviceSnapshot {
+        devices: [
+            DeviceId("speakers".to_owned()),
+            DeviceId("usb-dac".to_owned()),
+        ]
+        .into(),
+        default_device: Some(DeviceId("speakers".to_owned())),
+    };
+
+    assert_eq!(
+        vec![
+            DeviceEvent::Added(DeviceId("usb-dac".to_owned())),
+            DeviceEvent::Removed(DeviceId("headphones".to_owned()))
+        ],
+        old.diff(&new)
+    );
+}
+
+#[test]
+fn diff_default_changed() {
+    let old = DeviceSnapshot {
+        devices: [
+            DeviceId("speakers".to_owned()),
+            DeviceId("headphones".to_owned()),
+        ]
+        .into(),
+        default_device: Some(DeviceId("speakers".to_owned())),
+    };
+    let new = DeviceSnapshot {
+        devices: [
+            DeviceId("speakers".to_owned()),
+            DeviceId("headphones".to_owned()),
+        ]
+        .into(),
+        default_device: Some(DeviceId("headphones".to_owned())),
+    };
+
+    assert_eq!(
+        vec![DeviceEvent::DefaultChanged(Some(DeviceId(
+            "headphones".to_owned()
+        )))],
+        old.diff(&new)
+    );
+    assert!(new.diff(&new).is_empty());
+}
+
+#[test]
+fn watcher_stops() {
+    let (event_tx, event_rx) = mpsc::channel();
+    let watcher = DeviceWatcher::new(
+        Arc::new(MockHost::default()),
+        DeviceWatcherSettings {
+            poll_interval: Duration::from_millis(1),
+        },
+        move |event| event_tx.send(event).unwrap(),
+    );
+    std::thread::sleep(Duration::from_millis(10));
+    watcher.stop();
+
+    // The device list never changed and the sender was dropped with the thread
+    assert_eq!(Err(mpsc::RecvError), event_rx.recv());
+}
+
+#[test]
+fn shared_watcher_stops_with_last_subscription() {
+    let host = MockHost::default();
+    let shared = SharedDeviceWatcher::new(Arc::new(host.clone()), Default::default());
+    let (event_tx, event_rx) = mpsc::channel();
+    let first = shared.subscribe({
+        let event_tx = event_tx.clone();
+        move |event| event_tx.send((1, event)).unwrap()
+    });
+    let second = shared.subscribe(move |event| event_tx.send((2, event)).unwrap());
+    // Wait for the watcher to take its first snapshot
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(1, host.notification_count());
+
+    let speakers = host.devices().remove(0);
+    host.add_device(MockDevice::new(
+        "headphones".to_owned(),
+        speakers.default_config.clone(),
+        speakers.default_min_sample_rate,
+        speakers.default_max_sample_rate,
+        vec![],
+    ));
+    let added = DeviceEvent::Added(DeviceId("headphones".to_owned()));
+    let recv = || event_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!((1, added.clone()), recv());
+    assert_eq!((2, added), recv());
+
+    drop(first);
+    assert_eq!(1, host.notification_count());
+    drop(second);
+    assert_eq!(0, host.notification_count());
+}
+
+#[test]
+fn fixed_devices_arent_watched() {
+    let host = MockHost::default();
+    host.set_fixed_devices(true);
+    let shared = SharedDeviceWatcher::new(Arc::new(host.clone()), Default::default());
+    let _subscription = shared.subscribe(|_| {});
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(0, host.notification_count());
+}
+
+#[test]
+fn subscription_can_drop_itself() {
+    let host = MockHost::default();
+    let shared = SharedDeviceWatcher::new(Arc::new(host.clone()), Default::default());
+    let subscription = Arc::new(Mutex::new(None));
+    let (dropped_tx, dropped_rx) = mpsc::channel();
+    *subscription.lock().unwrap() = Some(shared.subscribe({
+        let subscription = subscription.clone();
+        // Unsubscribing from the watcher thread stops the watcher without joining itself
+        move |_| {
+            drop(subscription.lock().unwrap().take());
+            dropped_tx.send(()).ok();
+        }
+    }));
+    std::thread::sleep(Duration::from_millis(50));
+
+    let speakers = host.devices().remove(0);
+    host.add_device(MockDevice::new(
+        "headphones".to_owned(),
+        speakers.default_config.clone(),
+        speakers.default_min_sample_rate,
+        speakers.default_max_sample_rate,
+        vec![],
+    ));
+    dropped_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(subscription.lock().unwrap().is_none());
+}
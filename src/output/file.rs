@@ -0,0 +1,303 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use super::{
+    BackendSpecificError, BuildStreamError, DecalSample, DefaultStreamConfigError, Device,
+    DeviceId, DeviceIdError, DeviceNameError, DevicesError, Host, HostUnavailableError, Pacing,
+    RenderSink, RenderStream, SampleFormat, Stream, StreamConfig, StreamError,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
+    virtual_config_ranges, virtual_default_config,
+};
+use crate::encoder::{Endianness, PcmFormat, WavWriter};
+use crate::recorder::numbered_path;
+use crate::{ChannelCount, SampleRate};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FileContainer {
+    /// A WAV file. Files larger than 4 GiB are written as RF64.
+    Wav,
+    /// Headerless interleaved PCM.
+    Raw(Endianness),
+}
+
+#[derive(Clone, Debug)]
+pub struct FileOutputSettings {
+    /// Rebuilding the stream keeps appending to the same file. If the format changes, the file is
+    /// finished and the rest is written to a numbered one next to it, e.g. `output-2.wav`.
+    pub path: PathBuf,
+    pub container: FileContainer,
+    /// The format samples are written in. Defaults to the stream's sample format.
+    pub sample_format: Option<SampleFormat>,
+    /// With [`Pacing::Unpaced`], the device waits for audio to be written instead of padding the
+    /// file with silence.
+    pub pacing: Pacing,
+    /// The config reported as the device's default.
+    pub default_config: SupportedStreamConfig,
+}
+
+impl Default for FileOutputSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("output.wav"),
+            container: FileContainer::Wav,
+            sample_format: None,
+            pacing: Pacing::RealTime,
+            default_config: virtual_default_config(),
+        }
+    }
+}
+
+enum FileWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Raw {
+        writer: BufWriter<File>,
+        format: PcmFormat,
+        buf: Vec<u8>,
+    },
+}
+
+impl FileWriter {
+    fn write_samples<T: DecalSample>(&mut self, samples: &[T]) -> io::Result<()> {
+        match self {
+            Self::Wav(writer) => writer.write_samples(samples),
+            Self::Raw {
+                writer,
+                format,
+                buf,
+            } => {
+                buf.clear();
+                format.encode(samples, buf);
+                writer.write_all(buf)
+            }
+        }
+    }
+
+    /// Makes everything written so far readable without closing the file.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Wav(writer) => writer.flush(),
+            Self::Raw { writer, .. } => writer.flush(),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Wav(writer) => writer.finalize().map(drop),
+            Self::Raw { mut writer, .. } => writer.flush(),
+        }
+    }
+}
+
+struct OpenFile {
+    writer: FileWriter,
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    sample_format: SampleFormat,
+}
+
+// The file is shared by every stream built on the device, so rebuilding the stream doesn't
+// truncate it
+#[derive(Default)]
+struct FileState {
+    file: Option<OpenFile>,
+    // Number of files opened so far
+    files: usize,
+}
+
+type SharedFile = Arc<Mutex<FileState>>;
+
+struct FileSink {
+    state: SharedFile,
+}
+
+fn write_error(e: io::Error) -> StreamError {
+    StreamError::BackendSpecific(BackendSpecificError(e.to_string()))
+}
+
+impl<T: DecalSample> RenderSink<T> for FileSink {
+    fn write(&mut self, samples: &[T]) -> Result<(), StreamError> {
+        match &mut self.state.lock().expect("lock poisoned").file {
+            Some(file) => file.writer.write_samples(samples).map_err(write_error),
+            None => Err(StreamError::DeviceNotAvailable),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), StreamError> {
+        match &mut self.state.lock().expect("lock poisoned").file {
+            Some(file) => file.writer.flush().map_err(write_error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A device that writes everything it plays to a file. The file is opened when the first stream
+/// is built and finished once the device and its host are dropped.
+#[derive(Clone)]
+pub struct FileDevice {
+    settings: FileOutputSettings,
+    state: SharedFile,
+}
+
+impl fmt::Debug for FileDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileDevice")
+            .field("settings", &self.settings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileDevice {
+    pub fn settings(&self) -> &FileOutputSettings {
+        &self.settings
+    }
+
+    /// Opens the file for a stream, or keeps the open one if the format hasn't changed.
+    fn open(
+        &self,
+        state: &mut FileState,
+        config: &StreamConfig,
+        sample_format: SampleFormat,
+    ) -> Result<(), BuildStreamError> {
+        if let Some(file) = &state.file
+            && file.channels == config.channels
+            && file.sample_rate == config.sample_rate
+            && file.sample_format == sample_format
+        {
+            return Ok(());
+        }
+        if let Some(file) = state.file.take() {
+            file.writer.finish().map_err(build_error)?;
+        }
+
+        let path = numbered_path(&self.settings.path, state.files);
+        let writer = BufWriter::new(File::create(&path).map_err(build_error)?);
+        state.files += 1;
+        let writer = match self.settings.container {
+            FileContainer::Wav => FileWriter::Wav(
+                WavWriter::new(writer, config.channels, config.sample_rate, sample_format)
+                    .map_err(build_error)?,
+            ),
+            FileContainer::Raw(endianness) => FileWriter::Raw {
+                writer,
+                format: PcmFormat::new(sample_format, endianness),
+                buf: Vec::new(),
+            },
+        };
+        state.file = Some(OpenFile {
+            writer,
+            channels: config.channels,
+            sample_rate: config.sample_rate,
+            sample_format,
+        });
+        Ok(())
+    }
+}
+
+fn build_error(e: io::Error) -> BuildStreamError {
+    BuildStreamError::BackendSpecific(BackendSpecificError(e.to_string()))
+}
+
+impl Device for FileDevice {
+    type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(self.settings.default_config.clone())
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok(self.settings.path.display().to_string())
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(DeviceId(format!("file:{}", self.settings.path.display())))
+    }
+
+    fn waits_for_data(&self) -> bool {
+        matches!(self.settings.pacing, Pacing::Unpaced)
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(virtual_config_ranges(self.settings.default_config.buffer_size.clone()).into_iter())
+    }
+
+    fn build_output_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&mut [T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        let sample_format = self.settings.sample_format.unwrap_or(T::FORMAT);
+        self.open(
+            &mut self.state.lock().expect("lock poisoned"),
+            config,
+            sample_format,
+        )?;
+
+        Ok(Box::new(RenderStream::new(
+            config,
+            self.settings.pacing.clone(),
+            Default::default(),
+            data_callback,
+            error_callback,
+            FileSink {
+                state: self.state.clone(),
+            },
+        )?))
+    }
+}
+
+/// A host with a single device that renders to a file instead of a sound card.
+#[derive(Clone, Debug)]
+pub struct FileHost {
+    device: FileDevice,
+}
+
+impl Default for FileHost {
+    fn default() -> Self {
+        Self::new(FileOutputSettings::default())
+    }
+}
+
+impl FileHost {
+    pub fn new(settings: FileOutputSettings) -> Self {
+        Self {
+            device: FileDevice {
+                settings,
+                state: Default::default(),
+            },
+        }
+    }
+}
+
+impl Host for FileHost {
+    type Device = FileDevice;
+    type Id = ();
+    type Devices = std::option::IntoIter<FileDevice>;
+
+    fn from_id(_id: Self::Id) -> Result<Self, HostUnavailableError> {
+        Ok(Self::default())
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        Some(self.device.clone())
+    }
+
+    fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(self.default_output_device().into_iter())
+    }
+
+    fn id(&self) -> Self::Id {}
+
+    fn devices_can_change(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use super::{
+    BufferSize, Device, FileContainer, FileDevice, FileHost, FileOutputSettings, Host, Pacing,
+    StreamConfig,
+};
+use crate::encoder::Endianness;
+use crate::output::SampleFormat;
+use crate::recorder::numbered_path;
+use crate::{ChannelCount, SampleRate};
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("decal-{}-{name}", std::process::id()))
+}
+
+fn config(sample_rate: u32) -> StreamConfig {
+    StreamConfig {
+        channels: ChannelCount(2),
+        sample_rate: SampleRate(sample_rate),
+        buffer_size: BufferSize::Fixed(64),
+    }
+}
+
+// Plays a stream that fills each buffer with `fill` and returns how many buffers were rendered
+fn play(
+    device: &mut FileDevice,
+    config: &StreamConfig,
+    fill: impl Fn(usize) -> i16 + Send + Sync + 'static,
+) -> usize {
+    let buffers = Arc::new(AtomicUsize::new(0));
+    let counter = buffers.clone();
+    let mut stream = device
+        .build_output_stream::<i16, _, _>(
+            config,
+            move |data: &mut [i16]| {
+                let index = counter.fetch_add(1, Ordering::SeqCst);
+                data.fill(fill(index));
+            },
+            |e| panic!("{e:?}"),
+        )
+        .unwrap();
+    stream.play().unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    stream.stop().unwrap();
+    buffers.load(Ordering::SeqCst)
+}
+
+fn render(settings: FileOutputSettings) -> Vec<u8> {
+    let path = settings.path.clone();
+    let host = FileHost::new(settings);
+    let mut device = host.default_output_device().unwrap();
+    play(&mut device, &config(48000), |index| index as i16);
+    drop((host, device));
+
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    bytes
+}
+
+// Each buffer is filled with its index so the file contents can be checked no matter how many
+// buffers were rendered
+fn assert_buffers(data: &[u8], to_sample: fn([u8; 2]) -> i16) {
+    let buffer_bytes = 64 * 2 * 2;
+    assert!(!data.is_empty());
+    assert_eq!(0, data.len() % buffer_bytes);
+    for (i, buffer) in data.chunks(buffer_bytes).enumerate() {
+        assert!(
+            buffer
+                .chunks(2)
+                .all(|s| to_sample([s[0], s[1]]) == i as i16)
+        );
+    }
+}
+
+#[test]
+fn file_host_raw() {
+    let data = render(FileOutputSettings {
+        path: temp_path("raw.pcm"),
+        container: FileContainer::Raw(Endianness::Big),
+        pacing: Pacing::Unpaced,
+        ..Default::default()
+    });
+    assert_buffers(&data, i16::from_be_bytes);
+}
+
+#[test]
+fn file_host_wav() {
+    let bytes = render(FileOutputSettings {
+        path: temp_path("out.wav"),
+        container: FileContainer::Wav,
+        sample_format: Some(SampleFormat::I16),
+        pacing: Pacing::Unpaced,
+        ..Default::default()
+    });
+
+    let data_len = u32::from_le_bytes(bytes[76..80].try_into().unwrap()) as usize;
+    assert_eq!(b"data", &bytes[72..76]);
+    assert_eq!(bytes.len() - 80, data_len);
+    assert_buffers(&bytes[80..], i16::from_le_bytes);
+}
+
+#[test]
+fn file_host_appends_between_streams() {
+    let path = temp_path("append.pcm");
+    let host = FileHost::new(FileOutputSettings {
+        path: path.clone(),
+        container: FileContainer::Raw(Endianness::Little),
+        pacing: Pacing::Unpaced,
+        ..Default::default()
+    });
+    let first = play(
+        &mut host.default_output_device().unwrap(),
+        &config(48000),
+        |_| 1,
+    );
+    let second = play(
+        &mut host.default_output_device().unwrap(),
+        &config(48000),
+        |_| 2,
+    );
+    drop(host);
+
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let samples: Vec<_> = data
+        .chunks(2)
+        .map(|s| i16::from_le_bytes([s[0], s[1]]))
+        .collect();
+    let first_len = first * 64 * 2;
+    assert_eq!(first_len + second * 64 * 2, samples.len());
+    assert!(samples[..first_len].iter().all(|s| *s == 1));
+    assert!(samples[first_len..].iter().all(|s| *s == 2));
+}
+
+#[test]
+fn file_host_numbers_files_on_format_change() {
+    let path = temp_path("formats.wav");
+    let host = FileHost::new(FileOutputSettings {
+        path: path.clone(),
+        sample_format: Some(SampleFormat::I16),
+        pacing: Pacing::Unpaced,
+        ..Default::default()
+    });
+    play(
+        &mut host.default_output_device().unwrap(),
+        &config(48000),
+        |_| 1,
+    );
+    play(
+        &mut host.default_output_device().unwrap(),
+        &config(44100),
+        |_| 2,
+    );
+    drop(host);
+
+    let second_path = numbered_path(&path, 1);
+    let first = std::fs::read(&path).unwrap();
+    let second = std::fs::read(&second_path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&second_path).unwrap();
+    assert_eq!(48000, u32::from_le_bytes(first[60..64].try_into().unwrap()));
+    assert_eq!(
+        44100,
+        u32::from_le_bytes(second[60..64].try_into().unwrap())
+    );
+    assert!(
+        first[80..]
+            .chunks(2)
+            .all(|s| i16::from_le_bytes([s[0], s[1]]) == 1)
+    );
+    assert!(
+        second[80..]
+            .chunks(2)
+            .all(|s| i16::from_le_bytes([s[0], s[1]]) == 2)
+    );
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use rb::{RB, RbConsumer, RbInspector, RbProducer, SpscRb};
+use tracing::{info, warn};
+
+use super::{
+    AudioOutputError, BackendSpecificError, BufferSize, DecalSample, Device, Host, OutputSettings,
+    Stream, StreamConfig, SupportedStreamConfig, stream_error_handler,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReadBlockingError {
+    #[error("Input stalled")]
+    InputStalled,
+}
+
+/// Captures audio from an input device into a ring buffer that can be read from another thread.
+pub struct AudioInput<T, H: Host> {
+    ring_buf_consumer: rb::Consumer<T>,
+    ring_buf: SpscRb<T>,
+    stream: Option<Box<dyn Stream>>,
+    on_configuration_changed: Arc<Box<dyn Fn() + Send + Sync>>,
+    on_error: Arc<Box<dyn Fn(BackendSpecificError) + Send + Sync>>,
+    device: H::Device,
+    config: SupportedStreamConfig,
+    settings: OutputSettings,
+}
+
+impl<T: DecalSample + Default + 'static, H: Host> AudioInput<T, H> {
+    pub(crate) fn new(
+        device: H::Device,
+        config: SupportedStreamConfig,
+        on_configuration_changed: Arc<Box<dyn Fn() + Send + Sync>>,
+        on_error: Arc<Box<dyn Fn(BackendSpecificError) + Send + Sync>>,
+        settings: OutputSettings,
+    ) -> Self {
+        let buffer_ms: usize = settings
+            .input_buffer_duration
+            .as_millis()
+            .try_into()
+            .unwrap();
+        let ring_buf = SpscRb::<T>::new(
+            ((buffer_ms * config.sample_rate.0 as usize) / 1000) * config.channels.0 as usize,
+        );
+
+        Self {
+            ring_buf_consumer: ring_buf.consumer(),
+            ring_buf,
+            stream: None,
+            device,
+            config,
+            on_configuration_changed,
+            on_error,
+            settings,
+        }
+    }
+
+    pub fn start(&mut self) -> Result<(), AudioOutputError> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+
+        let mut stream = self.create_stream(self.ring_buf.producer())?;
+        stream.play()?;
+        self.stream = Some(stream);
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<(), AudioOutputError> {
+        if let Some(mut stream) = self.stream.take() {
+            stream.stop().map_err(AudioOutputError::StopStreamError)?;
+        }
+        Ok(())
+    }
+
+    pub fn pause(&mut self) -> Result<(), AudioOutputError> {
+        if let Some(stream) = self.stream.as_mut() {
+            stream.pause().map_err(AudioOutputError::PauseStreamError)?;
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> &SupportedStreamConfig {
+        &self.config
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.ring_buf.count()
+    }
+
+    pub fn buffer_capacity(&self) -> usize {
+        self.ring_buf.capacity()
+    }
+
+    /// Reads as many captured samples as are available, up to the length of `samples`.
+    pub fn read(&self, samples: &mut [T]) -> Result<usize, rb::RbError> {
+        self.ring_buf_consumer.read(samples)
+    }
+
+    pub fn settings(&self) -> &OutputSettings {
+        &self.settings
+    }
+
+    pub fn device(&self) -> &H::Device {
+        &self.device
+    }
+
+    /// Fills `samples` with captured audio, waiting for the device to produce it.
+    pub fn read_blocking(&self, mut samples: &mut [T]) -> Result<(), ReadBlockingError> {
+        let timeout = self.settings.buffer_duration;
+        while !samples.is_empty() {
+            match self
+                .ring_buf_consumer
+                .read_blocking_timeout(samples, timeout)
+            {
+                Ok(Some(read)) => {
+                    samples = &mut samples[read..];
+                }
+                Ok(None) => {
+                    break;
+                }
+                Err(_) => {
+                    warn!("Audio stream stalled. Cancelling read.");
+                    return Err(ReadBlockingError::InputStalled);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn create_stream(
+        &mut self,
+        ring_buf_producer: rb::Producer<T>,
+    ) -> Result<Box<dyn Stream>, AudioOutputError> {
+        let config = StreamConfig {
+            channels: self.config.channels,
+            sample_rate: self.config.sample_rate,
+            buffer_size: BufferSize::Default,
+        };
+
+        info!("Input channels = {}", self.config.channels.0);
+        info!("Input sample rate = {}", self.config.sample_rate.0);
+
+        let stream = self
+            .device
+            .build_input_stream(
+                &config,
+                move |data: &[T]| {
+                    let written = ring_buf_producer.write(data).unwrap_or(0);
+                    if data.len() > written {
+                        warn!("Input buffer full, dropping samples");
+                    }
+                },
+                stream_error_handler(self.on_configuration_changed.clone(), self.on_error.clone()),
+            )
+            .map_err(AudioOutputError::OpenStreamError)?;
+
+        Ok(stream)
+    }
+}
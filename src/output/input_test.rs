@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use super::{MockHost, NullHost, OutputBuilder, OutputSettings, ReadBlockingError};
+
+#[test]
+fn mock_input_reads_samples() {
+    let output_builder = OutputBuilder::new(MockHost::default(), Default::default(), || {}, |_| {});
+    let mut input = output_builder
+        .new_input::<i16>(None, output_builder.default_input_config().unwrap())
+        .unwrap();
+    input.start().unwrap();
+
+    input.device().send_input(vec![0.5; 64]);
+    input.device().send_input(vec![-0.5; 64]);
+    let mut samples = [0; 128];
+    input.read_blocking(&mut samples).unwrap();
+    assert_eq!([16384; 64], samples[..64]);
+    assert_eq!([-16384; 64], samples[64..]);
+}
+
+#[test]
+fn mock_input_stalls_without_data() {
+    let output_builder = OutputBuilder::new(MockHost::default(), Default::default(), || {}, |_| {});
+    let mut input = output_builder
+        .new_input::<f32>(None, output_builder.default_input_config().unwrap())
+        .unwrap();
+    input.start().unwrap();
+
+    let mut samples = [0.0; 16];
+    assert!(matches!(
+        input.read_blocking(&mut samples),
+        Err(ReadBlockingError::InputStalled)
+    ));
+}
+
+#[test]
+fn null_input_captures_silence() {
+    let output_builder = OutputBuilder::new(NullHost::default(), Default::default(), || {}, |_| {});
+    let mut input = output_builder
+        .new_input::<f32>(None, output_builder.default_input_config().unwrap())
+        .unwrap();
+    input.start().unwrap();
+
+    let mut samples = [1.0; 2048];
+    input.read_blocking(&mut samples).unwrap();
+    assert!(samples.iter().all(|s| *s == 0.0));
+    input.stop().unwrap();
+}
+
+#[test]
+fn input_buffer_duration_from_settings() {
+    let output_builder = OutputBuilder::new(
+        MockHost::default(),
+        OutputSettings {
+            input_buffer_duration: Duration::from_millis(10),
+            ..Default::default()
+        },
+        || {},
+        |_| {},
+    );
+    let input = output_builder
+        .new_input::<f32>(None, output_builder.default_input_config().unwrap())
+        .unwrap();
+    // 10ms of stereo at 44.1kHz
+    assert_eq!(441 * 2, input.buffer_capacity());
+}
@@ -0,0 +1,312 @@
+use std::time::Duration;
+
+use dasp::Sample;
+use tracing::{debug, info, warn};
+
+use super::{
+    AudioOutput, AudioOutputError, DecalSample, Host, OUTPUT_BUFFER_DURATION, OutputBuilder,
+    RequestedOutputConfig, SupportedStreamConfig,
+};
+
+// How quickly the measured drift follows new measurements
+const DRIFT_SMOOTHING: f64 = 0.05;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorTarget {
+    /// The device's ID or name.
+    pub device: String,
+    pub volume: f32,
+    /// Extra latency for this device, to line it up with devices that take longer to play
+    /// what's written to them.
+    pub delay: Duration,
+}
+
+impl MirrorTarget {
+    pub fn new(device: impl Into<String>) -> Self {
+        Self {
+            device: device.into(),
+            volume: 1.0,
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MirrorStatus {
+    Playing,
+    /// The device couldn't be opened or was lost. It's skipped until
+    /// [`retry_failed`](MirrorOutput::retry_failed) is called.
+    Failed(String),
+}
+
+struct MirrorDevice<T, H: Host> {
+    target: MirrorTarget,
+    output: Option<AudioOutput<T, H>>,
+    status: MirrorStatus,
+    // Whether the buffer has been lined up with the reference output yet
+    aligned: bool,
+    // Smoothed difference between this device's buffer and the reference, in frames
+    drift: f64,
+    buf: Vec<T>,
+}
+
+/// Plays the same audio as another output on extra devices. Each device has its own buffer,
+/// volume and delay, and is kept in sync with the reference output by dropping or repeating
+/// single frames as the device clocks drift apart.
+///
+/// A device failing only stops that device.
+pub struct MirrorOutput<T, H: Host> {
+    output_builder: OutputBuilder<H>,
+    config: SupportedStreamConfig,
+    devices: Vec<MirrorDevice<T, H>>,
+    started: bool,
+}
+
+impl<T: DecalSample, H: Host> MirrorOutput<T, H> {
+    /// Opens every target with the reference output's config. Devices that can't be opened are
+    /// marked as failed.
+    pub fn new(
+        output_builder: OutputBuilder<H>,
+        config: SupportedStreamConfig,
+        targets: Vec<MirrorTarget>,
+    ) -> Self {
+        let mut mirror = Self {
+            output_builder,
+            config,
+            devices: targets
+                .into_iter()
+                .map(|target| MirrorDevice {
+                    target,
+                    output: None,
+                    status: MirrorStatus::Failed("Not opened".to_owned()),
+                    aligned: false,
+                    drift: 0.0,
+                    buf: Vec::new(),
+                })
+                .collect(),
+            started: false,
+        };
+        for index in 0..mirror.devices.len() {
+            mirror.open(index);
+        }
+        mirror
+    }
+
+    pub fn config(&self) -> &SupportedStreamConfig {
+        &self.config
+    }
+
+    pub fn status(&self) -> Vec<(&MirrorTarget, &MirrorStatus)> {
+        self.devices
+            .iter()
+            .map(|device| (&device.target, &device.status))
+            .collect()
+    }
+
+    /// Returns `false` if there's no target for the device.
+    pub fn set_volume(&mut self, device: &str, volume: f32) -> bool {
+        match self.find(device) {
+            Some(index) => {
+                self.devices[index].target.volume = volume;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reopens the device so its buffer can be lined up with the new delay. Returns `false` if
+    /// there's no target for the device.
+    pub fn set_delay(&mut self, device: &str, delay: Duration) -> bool {
+        match self.find(device) {
+            Some(index) => {
+                self.devices[index].target.delay = delay;
+                self.open(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Tries to open every device that failed.
+    pub fn retry_failed(&mut self) {
+        for index in 0..self.devices.len() {
+            if self.devices[index].output.is_none() {
+                self.open(index);
+            }
+        }
+    }
+
+    /// Reopens every device with a new config, dropping anything that's buffered.
+    pub fn reconfigure(&mut self, config: SupportedStreamConfig) {
+        self.config = config;
+        for index in 0..self.devices.len() {
+            self.open(index);
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.started = true;
+        for index in 0..self.devices.len() {
+            let result = match &mut self.devices[index].output {
+                Some(output) => output.start(),
+                None => continue,
+            };
+            if let Err(e) = result {
+                self.fail(index, e.to_string());
+            }
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.started = false;
+        for index in 0..self.devices.len() {
+            let result = match &mut self.devices[index].output {
+                Some(output) => output.pause(),
+                None => continue,
+            };
+            if let Err(e) = result {
+                self.fail(index, e.to_string());
+            }
+        }
+    }
+
+    /// Stops every device and drops anything that's buffered.
+    pub fn stop(&mut self) {
+        self.started = false;
+        for device in &mut self.devices {
+            if let Some(output) = &mut device.output {
+                output.stop().ok();
+                output.take_buffered();
+                device.aligned = false;
+            }
+        }
+    }
+
+    /// Copies the samples to every device without blocking. `reference_buffered` is the number
+    /// of samples waiting in the reference output after the same samples were written to it.
+    pub fn write(&mut self, samples: &[T], reference_buffered: usize) {
+        let channels = self.config.channels.0.max(1) as usize;
+        let threshold = (self.config.sample_rate.0 / 1000).max(8) as f64;
+
+        for index in 0..self.devices.len() {
+            let device = &mut self.devices[index];
+            let Some(output) = &mut device.output else {
+                continue;
+            };
+            if output.is_device_lost() {
+                self.fail(index, "Device lost".to_owned());
+                continue;
+            }
+
+            let delay = (device.target.delay.as_secs_f64() * self.config.sample_rate.0 as f64)
+                as usize
+                * channels;
+            if !device.aligned {
+                // Start out as far behind the reference as the delay asks for
+                let silence = (reference_buffered.saturating_sub(samples.len()) + delay)
+                    .saturating_sub(output.buffer_size());
+                let silence = silence - silence % channels;
+                output.write(&vec![T::EQUILIBRIUM; silence]).ok();
+                device.aligned = true;
+                device.drift = 0.0;
+            }
+
+            let volume = device.target.volume as f64;
+            device.buf.clear();
+            device.buf.extend(samples.iter().map(|sample| {
+                T::from_float_sample(<T::Float as Sample>::from_sample(
+                    sample.to_float_sample().to_sample::<f64>() * volume,
+                ))
+            }));
+            if device.drift > threshold && device.buf.len() >= channels * 2 {
+                // This device is playing slower than the reference
+                device.buf.truncate(device.buf.len() - channels);
+                device.drift -= 1.0;
+            } else if device.drift < -threshold && device.buf.len() >= channels {
+                // This device is playing faster than the reference
+                let last = device.buf.len() - channels;
+                device.buf.extend_from_within(last..);
+                device.drift += 1.0;
+            }
+
+            let written = output.write(&device.buf).unwrap_or(0);
+            if written < device.buf.len() {
+                debug!("Mirror buffer full, dropping samples");
+            }
+            let error = (output.buffer_size() as f64 - (reference_buffered + delay) as f64)
+                / channels as f64;
+            device.drift += (error - device.drift) * DRIFT_SMOOTHING;
+        }
+    }
+
+    fn find(&self, device: &str) -> Option<usize> {
+        self.devices.iter().position(|d| d.target.device == device)
+    }
+
+    fn open(&mut self, index: usize) {
+        let device = &mut self.devices[index];
+        device.output = None;
+        device.aligned = false;
+        device.drift = 0.0;
+        match open_output(
+            &self.output_builder,
+            &self.config,
+            &device.target,
+            self.started,
+        ) {
+            Ok(output) => {
+                info!("Mirroring output to {:?}", device.target.device);
+                device.output = Some(output);
+                device.status = MirrorStatus::Playing;
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to open mirror device {:?}: {e:?}",
+                    device.target.device
+                );
+                device.status = MirrorStatus::Failed(e.to_string());
+            }
+        }
+    }
+
+    fn fail(&mut self, index: usize, reason: String) {
+        let device = &mut self.devices[index];
+        warn!("Mirror device {:?} failed: {reason}", device.target.device);
+        device.output = None;
+        device.status = MirrorStatus::Failed(reason);
+    }
+}
+
+fn open_output<T: DecalSample, H: Host>(
+    output_builder: &OutputBuilder<H>,
+    config: &SupportedStreamConfig,
+    target: &MirrorTarget,
+    start: bool,
+) -> Result<AudioOutput<T, H>, AudioOutputError> {
+    let device_config = output_builder.find_closest_config(
+        Some(&target.device),
+        RequestedOutputConfig {
+            sample_rate: Some(config.sample_rate),
+            channels: Some(config.channels),
+            sample_format: Some(T::FORMAT),
+        },
+    )?;
+    if device_config.sample_rate != config.sample_rate || device_config.channels != config.channels
+    {
+        return Err(AudioOutputError::UnsupportedConfiguration(format!(
+            "Device can't play {} channels at {} Hz",
+            config.channels.0, config.sample_rate.0
+        )));
+    }
+
+    // Leave room for the delay on top of what the reference output buffers
+    let mut output = output_builder.new_mirror_output(
+        &target.device,
+        device_config,
+        OUTPUT_BUFFER_DURATION * 2 + target.delay,
+    )?;
+    if start {
+        output.start()?;
+    }
+    Ok(output)
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use super::{
+    DeviceId, MirrorOutput, MirrorStatus, MirrorTarget, MockDevice, MockHost, OutputBuilder,
+    StreamError,
+};
+
+fn host() -> MockHost {
+    let speakers = MockHost::default().devices().remove(0);
+    MockHost::new(
+        speakers.clone(),
+        vec![device(&speakers, "kitchen"), device(&speakers, "garage")],
+    )
+}
+
+fn device(template: &MockDevice, name: &str) -> MockDevice {
+    MockDevice::new(
+        name.to_owned(),
+        template.default_config.clone(),
+        template.default_min_sample_rate,
+        template.default_max_sample_rate,
+        vec![],
+    )
+}
+
+fn mirror(host: &MockHost, targets: Vec<MirrorTarget>) -> MirrorOutput<f32, MockHost> {
+    let output_builder = OutputBuilder::new(host.clone(), Default::default(), || {}, |_| {});
+    let config = output_builder.default_output_config().unwrap();
+    MirrorOutput::new(output_builder, config, targets)
+}
+
+fn mock_device(host: &MockHost, name: &str) -> MockDevice {
+    host.device(&DeviceId(name.to_owned())).unwrap()
+}
+
+#[test]
+fn mirror_volume_and_delay() {
+    let host = host();
+    let mut mirror = mirror(
+        &host,
+        vec![
+            MirrorTarget {
+                volume: 0.5,
+                ..MirrorTarget::new("kitchen")
+            },
+            // 100 frames at 44.1 kHz
+            MirrorTarget {
+                delay: Duration::from_micros(2268),
+                ..MirrorTarget::new("garage")
+            },
+        ],
+    );
+    mirror.start();
+    mirror.write(&[1.0; 200], 200);
+
+    let kitchen = mock_device(&host, "kitchen").trigger_callback();
+    assert_eq!(vec![0.5; 200], kitchen[..200]);
+    assert_eq!(vec![0.0; 1024 - 200], kitchen[200..]);
+
+    let garage = mock_device(&host, "garage").trigger_callback();
+    assert_eq!(vec![0.0; 200], garage[..200]);
+    assert_eq!(vec![1.0; 200], garage[200..400]);
+}
+
+#[test]
+fn mirror_device_failure_is_isolated() {
+    let host = host();
+    let mut mirror = mirror(
+        &host,
+        vec![
+            MirrorTarget::new("kitchen"),
+            MirrorTarget::new("garage"),
+            MirrorTarget::new("attic"),
+        ],
+    );
+    mirror.start();
+    assert!(matches!(mirror.status()[2].1, MirrorStatus::Failed(_)));
+
+    let kitchen = mock_device(&host, "kitchen");
+    kitchen.send_error(StreamError::DeviceNotAvailable);
+    mirror.write(&[1.0; 200], 200);
+    assert!(matches!(mirror.status()[0].1, MirrorStatus::Failed(_)));
+    assert_eq!(&MirrorStatus::Playing, mirror.status()[1].1);
+    assert_eq!(
+        vec![1.0; 200],
+        mock_device(&host, "garage").trigger_callback()[..200]
+    );
+
+    host.add_device(device(&kitchen, "attic"));
+    mirror.retry_failed();
+    assert!(
+        mirror
+            .status()
+            .iter()
+            .all(|(_, status)| **status == MirrorStatus::Playing)
+    );
+}
+
+#[test]
+fn mirror_corrects_drift() {
+    let host = host();
+    let mut mirror = mirror(&host, vec![MirrorTarget::new("kitchen")]);
+    mirror.start();
+
+    // The reference keeps a constant amount buffered while nothing plays the mirror, as if
+    // the mirror's clock were much slower
+    for _ in 0..100 {
+        mirror.write(&[1.0; 64], 64);
+    }
+
+    let kitchen = mock_device(&host, "kitchen");
+    kitchen.set_buffer_len(6400);
+    let written = kitchen
+        .trigger_callback()
+        .iter()
+        .filter(|sample| **sample == 1.0)
+        .count();
+    assert!(written < 6400);
+    assert!(written > 6400 - 200);
+}
@@ -1,46 +1,130 @@
+use std::any::Any;
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{self};
-use std::sync::{Arc, Mutex, RwLock};
-use std::thread;
+use std::sync::{Arc, Mutex, RwLock, Weak};
 
 use super::{
-    BuildStreamError, DecalSample, DefaultStreamConfigError, Device, DeviceNameError, DevicesError,
-    Host, PlayStreamError, Stream, StreamConfig, StreamError, SupportedStreamConfig,
-    SupportedStreamConfigRange, SupportedStreamConfigsError,
+    BuildStreamError, DecalSample, DefaultStreamConfigError, Device, DeviceId, DeviceIdError,
+    DeviceNameError, DeviceNotificationHandle, DevicesError, Host, PlayStreamError, Stream,
+    StreamConfig, StreamError, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError,
 };
 use crate::output::{SampleFormat, SupportedBufferSize};
 use crate::{ChannelCount, SampleRate};
 use dasp::Sample;
 
+/// Number of samples requested by each call to [`MockDevice::trigger_callback`] by default.
+pub const MOCK_BUFFER_LEN: usize = 1024;
+
+// Returns the samples converted to f32 along with the original `Vec<T>`
+type RenderCallback = Box<dyn FnMut(usize) -> (Vec<f32>, Box<dyn Any + Send>) + Send>;
+type InputCallback = Box<dyn FnMut(&[f32]) + Send>;
+type ErrorCallback = Box<dyn FnMut(StreamError) + Send>;
+
+struct MockStreamCallbacks<D> {
+    id: u64,
+    data: Arc<Mutex<D>>,
+    error: Arc<Mutex<ErrorCallback>>,
+    playing: Arc<AtomicBool>,
+}
+
+impl<D> Clone for MockStreamCallbacks<D> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            data: self.data.clone(),
+            error: self.error.clone(),
+            playing: self.playing.clone(),
+        }
+    }
+}
+
+struct MockDeviceState {
+    buffer_len: usize,
+    next_stream_id: u64,
+    output: Option<MockStreamCallbacks<RenderCallback>>,
+    input: Option<MockStreamCallbacks<InputCallback>>,
+    written: Vec<f32>,
+    build_errors: VecDeque<BuildStreamError>,
+}
+
+impl Default for MockDeviceState {
+    fn default() -> Self {
+        Self {
+            buffer_len: MOCK_BUFFER_LEN,
+            next_stream_id: 0,
+            output: None,
+            input: None,
+            written: Vec::new(),
+            build_errors: VecDeque::new(),
+        }
+    }
+}
+
 pub struct MockStream {
-    started: Arc<AtomicBool>,
+    id: u64,
+    is_input: bool,
+    playing: Arc<AtomicBool>,
+    state: Arc<Mutex<MockDeviceState>>,
+}
+
+impl MockStream {
+    fn release(&self) {
+        self.playing.store(false, Ordering::SeqCst);
+        let mut state = self.state.lock().unwrap();
+        // A newer stream may have replaced this one already
+        if self.is_input {
+            if state
+                .input
+                .as_ref()
+                .is_some_and(|input| input.id == self.id)
+            {
+                state.input = None;
+            }
+        } else if state
+            .output
+            .as_ref()
+            .is_some_and(|output| output.id == self.id)
+        {
+            state.output = None;
+        }
+    }
 }
 
 impl Stream for MockStream {
     fn play(&mut self) -> Result<(), PlayStreamError> {
-        self.started.store(true, Ordering::SeqCst);
+        self.playing.store(true, Ordering::SeqCst);
         Ok(())
     }
 
     fn pause(&mut self) -> Result<(), PlayStreamError> {
+        self.playing.store(false, Ordering::SeqCst);
         Ok(())
     }
 
     fn stop(&mut self) -> Result<(), PlayStreamError> {
-        self.started.store(false, Ordering::SeqCst);
+        self.release();
         Ok(())
     }
 }
 
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// A device that only runs its stream callbacks when a test asks it to. Clones share the same
+/// streams.
 #[derive(Clone)]
 pub struct MockDevice {
     pub name: String,
+    pub id: DeviceId,
     pub default_config: SupportedStreamConfig,
     pub default_min_sample_rate: SampleRate,
     pub default_max_sample_rate: SampleRate,
     pub additional_configs: Vec<SupportedStreamConfigRange>,
-    stream_tx: Arc<RwLock<mpsc::SyncSender<()>>>,
-    data_rx: Arc<Mutex<mpsc::Receiver<[f32; 1024]>>>,
+    state: Arc<Mutex<MockDeviceState>>,
 }
 
 impl MockDevice {
@@ -51,23 +135,164 @@ impl MockDevice {
         default_max_sample_rate: SampleRate,
         additional_configs: Vec<SupportedStreamConfigRange>,
     ) -> Self {
-        let (stream_tx, _) = mpsc::sync_channel(0);
-        let (_, data_rx) = mpsc::channel();
-
         Self {
+            id: DeviceId(name.clone()),
             name,
             default_config,
             default_min_sample_rate,
             default_max_sample_rate,
             additional_configs,
-            stream_tx: Arc::new(RwLock::new(stream_tx)),
-            data_rx: Arc::new(Mutex::new(data_rx)),
+            state: Default::default(),
+        }
+    }
+
+    /// Sets the number of samples requested by each call to
+    /// [`trigger_callback`](Self::trigger_callback).
+    pub fn set_buffer_len(&self, samples: usize) {
+        self.state.lock().unwrap().buffer_len = samples;
+    }
+
+    /// Runs the output stream's data callback once and returns the samples converted to `f32`.
+    /// Returns nothing if there's no output stream or it isn't playing.
+    pub fn trigger_callback(&self) -> Vec<f32> {
+        self.render()
+            .map(|(samples, _)| samples)
+            .unwrap_or_default()
+    }
+
+    /// Like [`trigger_callback`](Self::trigger_callback), but returns the samples in the type the
+    /// stream was built with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stream was built with a different sample type.
+    pub fn trigger_callback_as<T: DecalSample>(&self) -> Vec<T> {
+        self.render()
+            .map(|(_, samples)| {
+                *samples
+                    .downcast::<Vec<T>>()
+                    .expect("stream was built with a different sample type")
+            })
+            .unwrap_or_default()
+    }
+
+    /// Every sample returned by the output stream's data callback so far, converted to `f32`.
+    pub fn written(&self) -> Vec<f32> {
+        self.state.lock().unwrap().written.clone()
+    }
+
+    /// Passes the samples to the input stream's data callback. Returns `false` if there's no
+    /// input stream or it isn't playing.
+    pub fn send_input(&self, samples: Vec<f32>) -> bool {
+        let Some(input) = self.state.lock().unwrap().input.clone() else {
+            return false;
+        };
+        if !input.playing.load(Ordering::SeqCst) {
+            return false;
+        }
+        (*input.data.lock().unwrap())(&samples);
+        true
+    }
+
+    /// Reports the error to the error callback of each of the device's streams, like a backend
+    /// would when the device is lost or the stream glitches.
+    pub fn send_error(&self, error: StreamError) {
+        let (output, input) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.output.as_ref().map(|output| output.error.clone()),
+                state.input.as_ref().map(|input| input.error.clone()),
+            )
+        };
+        match (output, input) {
+            (Some(output), Some(input)) => {
+                (*output.lock().unwrap())(clone_stream_error(&error));
+                (*input.lock().unwrap())(error);
+            }
+            (Some(callback), None) | (None, Some(callback)) => (*callback.lock().unwrap())(error),
+            (None, None) => {}
+        }
+    }
+
+    /// Makes the next attempt to build a stream fail with the error. Errors are returned in the
+    /// order they were added.
+    pub fn fail_next_build(&self, error: BuildStreamError) {
+        self.state.lock().unwrap().build_errors.push_back(error);
+    }
+
+    pub fn has_output_stream(&self) -> bool {
+        self.state.lock().unwrap().output.is_some()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .output
+            .as_ref()
+            .is_some_and(|output| output.playing.load(Ordering::SeqCst))
+    }
+
+    fn render(&self) -> Option<(Vec<f32>, Box<dyn Any + Send>)> {
+        let (output, buffer_len) = {
+            let state = self.state.lock().unwrap();
+            (state.output.clone()?, state.buffer_len)
+        };
+        if !output.playing.load(Ordering::SeqCst) {
+            return None;
         }
+        // The callback runs without holding the device lock so it can call back into the device
+        let rendered = (*output.data.lock().unwrap())(buffer_len);
+        self.state
+            .lock()
+            .unwrap()
+            .written
+            .extend_from_slice(&rendered.0);
+        Some(rendered)
     }
 
-    pub fn trigger_callback(&self) -> [f32; 1024] {
-        self.stream_tx.read().unwrap().send(()).unwrap();
-        self.data_rx.lock().unwrap().recv().unwrap()
+    fn next_stream(
+        &self,
+        error_callback: impl FnMut(StreamError) + Send + 'static,
+    ) -> Result<(u64, Arc<AtomicBool>, Arc<Mutex<ErrorCallback>>), BuildStreamError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = state.build_errors.pop_front() {
+            return Err(error);
+        }
+        state.next_stream_id += 1;
+        Ok((
+            state.next_stream_id,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(Mutex::new(Box::new(error_callback))),
+        ))
+    }
+
+    fn supported_configs(&self) -> Vec<SupportedStreamConfigRange> {
+        [
+            vec![SupportedStreamConfigRange {
+                channels: self.default_config.channels,
+                min_sample_rate: self.default_min_sample_rate,
+                max_sample_rate: self.default_max_sample_rate,
+                buffer_size: self.default_config.buffer_size.clone(),
+                sample_format: self.default_config.sample_format,
+            }],
+            self.additional_configs.clone(),
+        ]
+        .concat()
+    }
+}
+
+fn clone_stream_error(error: &StreamError) -> StreamError {
+    match error {
+        StreamError::DeviceNotAvailable => StreamError::DeviceNotAvailable,
+        StreamError::StreamInvalidated => StreamError::StreamInvalidated,
+        StreamError::BufferUnderrun => StreamError::BufferUnderrun,
+        StreamError::InputOverflow => StreamError::InputOverflow,
+        StreamError::InvalidConfiguration(e) => StreamError::InvalidConfiguration(e.clone()),
+        StreamError::BackendSpecific(e) => {
+            StreamError::BackendSpecific(super::BackendSpecificError(e.0.clone()))
+        }
+        StreamError::Unknown(e) => StreamError::Unknown(e.clone()),
     }
 }
 
@@ -78,72 +303,120 @@ impl Device for MockDevice {
         Ok(self.default_config.clone())
     }
 
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(self.default_config.clone())
+    }
+
     fn name(&self) -> Result<String, DeviceNameError> {
         Ok(self.name.to_owned())
     }
 
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(self.id.clone())
+    }
+
     fn supported_output_configs(
         &self,
     ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
-        Ok(Box::new(
-            [
-                vec![SupportedStreamConfigRange {
-                    channels: self.default_config.channels,
-                    min_sample_rate: self.default_min_sample_rate,
-                    max_sample_rate: self.default_max_sample_rate,
-                    buffer_size: self.default_config.buffer_size.clone(),
-                    sample_format: self.default_config.sample_format,
-                }],
-                self.additional_configs.clone(),
-            ]
-            .concat()
-            .into_iter(),
-        ))
+        Ok(Box::new(self.supported_configs().into_iter()))
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        Ok(self.supported_configs())
     }
 
     fn build_output_stream<T, D, E>(
         &mut self,
         _config: &StreamConfig,
         mut data_callback: D,
-        _error_callback: E,
+        error_callback: E,
     ) -> Result<Box<dyn Stream>, BuildStreamError>
     where
         T: DecalSample,
         D: FnMut(&mut [T]) + Send + 'static,
         E: FnMut(StreamError) + Send + Sync + 'static,
     {
-        let started = Arc::new(AtomicBool::new(false));
-
-        let (stream_tx, stream_rx) = mpsc::sync_channel(10000);
-        *self.stream_tx.write().unwrap() = stream_tx;
-        let (data_tx, data_rx) = mpsc::channel();
-        *self.data_rx.lock().unwrap() = data_rx;
-        thread::spawn(move || {
-            const BUF_SIZE: usize = 1024;
-            let mut buf = [T::EQUILIBRIUM; BUF_SIZE];
-
-            while let Ok(()) = stream_rx.recv() {
-                data_callback(&mut buf);
-                data_tx
-                    .send(buf.map(|b| b.to_float_sample().to_sample()))
-                    .unwrap();
-            }
+        let (id, playing, error) = self.next_stream(error_callback)?;
+        let render: RenderCallback = Box::new(move |len: usize| {
+            let mut buf = vec![T::EQUILIBRIUM; len];
+            data_callback(&mut buf);
+            let converted: Vec<f32> = buf
+                .iter()
+                .map(|s| s.to_float_sample().to_sample())
+                .collect();
+            (converted, Box::new(buf) as Box<dyn Any + Send>)
+        });
+        self.state.lock().unwrap().output = Some(MockStreamCallbacks {
+            id,
+            data: Arc::new(Mutex::new(render)),
+            error,
+            playing: playing.clone(),
+        });
+
+        Ok(Box::new(MockStream {
+            id,
+            is_input: false,
+            playing,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn build_input_stream<T, D, E>(
+        &mut self,
+        _config: &StreamConfig,
+        mut data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&[T]) + Send + 'static,
+        E: FnMut(StreamError) + Send + Sync + 'static,
+    {
+        let (id, playing, error) = self.next_stream(error_callback)?;
+        let input: InputCallback = Box::new(move |samples: &[f32]| {
+            let data: Vec<T> = samples
+                .iter()
+                .map(|s| <T::Float as Sample>::from_sample(*s).to_sample())
+                .collect();
+            data_callback(&data);
+        });
+        self.state.lock().unwrap().input = Some(MockStreamCallbacks {
+            id,
+            data: Arc::new(Mutex::new(input)),
+            error,
+            playing: playing.clone(),
         });
 
-        Ok(Box::new(MockStream { started }))
+        Ok(Box::new(MockStream {
+            id,
+            is_input: true,
+            playing,
+            state: self.state.clone(),
+        }))
     }
 }
 
+type ChangeCallback = Box<dyn Fn() + Send + Sync>;
+
+struct MockHostState {
+    devices: Vec<MockDevice>,
+    default_device: Option<DeviceId>,
+    fixed_devices: bool,
+}
+
+/// A host whose devices can be changed while it's in use. Clones share the same devices.
 #[derive(Clone)]
 pub struct MockHost {
-    pub default_device: MockDevice,
-    pub additional_devices: Vec<MockDevice>,
+    state: Arc<RwLock<MockHostState>>,
+    watchers: Arc<Mutex<Vec<Weak<ChangeCallback>>>>,
 }
 
 impl Default for MockHost {
     fn default() -> Self {
-        Self {
-            default_device: MockDevice::new(
+        Self::new(
+            MockDevice::new(
                 "".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -155,7 +428,84 @@ impl Default for MockHost {
                 SampleRate(192000),
                 vec![],
             ),
-            additional_devices: vec![],
+            vec![],
+        )
+    }
+}
+
+impl MockHost {
+    pub fn new(default_device: MockDevice, additional_devices: Vec<MockDevice>) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(MockHostState {
+                default_device: Some(default_device.id.clone()),
+                devices: [vec![default_device], additional_devices].concat(),
+                fixed_devices: false,
+            })),
+            watchers: Default::default(),
+        }
+    }
+
+    pub fn devices(&self) -> Vec<MockDevice> {
+        self.state.read().unwrap().devices.clone()
+    }
+
+    pub fn device(&self, id: &DeviceId) -> Option<MockDevice> {
+        self.state
+            .read()
+            .unwrap()
+            .devices
+            .iter()
+            .find(|d| &d.id == id)
+            .cloned()
+    }
+
+    pub fn add_device(&self, device: MockDevice) {
+        self.state.write().unwrap().devices.push(device);
+        self.notify();
+    }
+
+    /// Removes the device, and clears the default device if it was the default. Streams that are
+    /// already open keep working until an error is sent with [`MockDevice::send_error`].
+    pub fn remove_device(&self, id: &DeviceId) -> Option<MockDevice> {
+        let removed = {
+            let mut state = self.state.write().unwrap();
+            let index = state.devices.iter().position(|d| &d.id == id)?;
+            if state.default_device.as_ref() == Some(id) {
+                state.default_device = None;
+            }
+            state.devices.remove(index)
+        };
+        self.notify();
+        Some(removed)
+    }
+
+    /// Changes the default device. IDs that don't match any device leave the host without a
+    /// default.
+    pub fn set_default_device(&self, id: Option<DeviceId>) {
+        self.state.write().unwrap().default_device = id;
+        self.notify();
+    }
+
+    /// Reports that devices can't change, like the virtual hosts do.
+    pub fn set_fixed_devices(&self, fixed: bool) {
+        self.state.write().unwrap().fixed_devices = fixed;
+    }
+
+    /// Number of device notifications that are currently registered.
+    pub fn notification_count(&self) -> usize {
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|w| w.strong_count() > 0);
+        watchers.len()
+    }
+
+    fn notify(&self) {
+        let watchers: Vec<_> = {
+            let mut watchers = self.watchers.lock().unwrap();
+            watchers.retain(|w| w.strong_count() > 0);
+            watchers.iter().filter_map(Weak::upgrade).collect()
+        };
+        for on_change in watchers {
+            on_change();
         }
     }
 }
@@ -170,19 +520,44 @@ impl Host for MockHost {
     }
 
     fn default_output_device(&self) -> Option<Self::Device> {
-        Some(self.default_device.clone())
+        let state = self.state.read().unwrap();
+        let default_device = state.default_device.as_ref()?;
+        state
+            .devices
+            .iter()
+            .find(|d| &d.id == default_device)
+            .cloned()
     }
 
     fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
-        Ok(Box::new(
-            [
-                vec![self.default_device.clone()],
-                self.additional_devices.clone(),
-            ]
-            .concat()
-            .into_iter(),
-        ))
+        Ok(Box::new(self.devices().into_iter()))
     }
 
     fn id(&self) -> Self::Id {}
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        self.default_output_device()
+    }
+
+    fn input_devices(&self) -> Result<Vec<Self::Device>, DevicesError> {
+        Ok(self.devices())
+    }
+
+    fn watch_devices(&self, on_change: ChangeCallback) -> Option<DeviceNotificationHandle> {
+        let on_change = Arc::new(on_change);
+        self.watchers
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&on_change));
+        Some(DeviceNotificationHandle::new(on_change))
+    }
+
+    // Behaves like the backends that need it so the restart is covered by tests
+    fn needs_default_device_restart(&self) -> bool {
+        true
+    }
+
+    fn devices_can_change(&self) -> bool {
+        !self.state.read().unwrap().fixed_devices
+    }
 }
@@ -0,0 +1,138 @@
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use super::{
+    AudioOutputError, BuildStreamError, DeviceEvent, DeviceId, DeviceWatcher,
+    DeviceWatcherSettings, MockDevice, MockHost, OutputBuilder, StreamError,
+};
+
+fn mock_device(name: &str) -> MockDevice {
+    let default_device = MockHost::default().devices().remove(0);
+    MockDevice::new(
+        name.to_owned(),
+        default_device.default_config,
+        default_device.default_min_sample_rate,
+        default_device.default_max_sample_rate,
+        vec![],
+    )
+}
+
+#[test]
+fn mock_callback_buffer_len_and_type() {
+    let output_builder = OutputBuilder::new(MockHost::default(), Default::default(), || {}, |_| {});
+    let mut output = output_builder
+        .new_output::<i16>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+    output.start().unwrap();
+    output.device().set_buffer_len(4);
+
+    output.write_blocking(&[16384, -16384, 1, 2, 3, 4]).unwrap();
+    assert_eq!(
+        vec![16384, -16384, 1, 2],
+        output.device().trigger_callback_as::<i16>()
+    );
+    // The rest of the buffer is filled with silence
+    assert_eq!(
+        vec![3, 4, 0, 0],
+        output.device().trigger_callback_as::<i16>()
+    );
+    assert_eq!(8, output.device().written().len());
+    assert_eq!([0.5, -0.5], output.device().written()[..2]);
+}
+
+#[test]
+fn mock_paused_stream_isnt_called() {
+    let output_builder = OutputBuilder::new(MockHost::default(), Default::default(), || {}, |_| {});
+    let mut output = output_builder
+        .new_output::<f32>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+    output.start().unwrap();
+    output.write_blocking(&[1.0; 1024]).unwrap();
+
+    output.pause().unwrap();
+    assert!(!output.device().is_playing());
+    assert!(output.device().trigger_callback().is_empty());
+
+    output.stop().unwrap();
+    assert!(!output.device().has_output_stream());
+}
+
+#[test]
+fn mock_injected_stream_errors() {
+    let (changed_tx, changed_rx) = mpsc::channel();
+    let (error_tx, error_rx) = mpsc::channel();
+    let output_builder = OutputBuilder::new(
+        MockHost::default(),
+        Default::default(),
+        move || changed_tx.send(()).unwrap(),
+        move |e| error_tx.send(e.0).unwrap(),
+    );
+    let mut output = output_builder
+        .new_output::<f32>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+    output.start().unwrap();
+
+    output.device().send_error(StreamError::DeviceNotAvailable);
+    changed_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    output
+        .device()
+        .send_error(StreamError::BackendSpecific(super::BackendSpecificError(
+            "glitch".to_owned(),
+        )));
+    assert_eq!(
+        "glitch",
+        error_rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    );
+}
+
+#[test]
+fn mock_injected_build_errors() {
+    let output_builder = OutputBuilder::new(MockHost::default(), Default::default(), || {}, |_| {});
+    let mut output = output_builder
+        .new_output::<f32>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+    output
+        .device()
+        .fail_next_build(BuildStreamError::DeviceBusy);
+
+    assert!(matches!(
+        output.start(),
+        Err(AudioOutputError::OpenStreamError(
+            BuildStreamError::DeviceBusy
+        ))
+    ));
+    output.start().unwrap();
+    assert!(output.device().is_playing());
+}
+
+#[test]
+fn mock_host_devices_change() {
+    let host = MockHost::default();
+    let (event_tx, event_rx) = mpsc::channel();
+    let _watcher = DeviceWatcher::new(
+        Arc::new(host.clone()),
+        // Changes are reported through notifications, not polling
+        DeviceWatcherSettings {
+            poll_interval: Duration::from_secs(3600),
+        },
+        move |event| event_tx.send(event).unwrap(),
+    );
+    // Wait for the watcher to take its first snapshot
+    std::thread::sleep(Duration::from_millis(50));
+    let recv = || event_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let headphones = DeviceId("headphones".to_owned());
+    host.add_device(mock_device("headphones"));
+    assert_eq!(DeviceEvent::Added(headphones.clone()), recv());
+
+    host.set_default_device(Some(headphones.clone()));
+    assert_eq!(
+        DeviceEvent::DefaultChanged(Some(headphones.clone())),
+        recv()
+    );
+
+    host.remove_device(&headphones).unwrap();
+    assert_eq!(DeviceEvent::Removed(headphones), recv());
+    assert_eq!(DeviceEvent::DefaultChanged(None), recv());
+    assert_eq!(1, host.devices().len());
+}
@@ -1,11 +1,43 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::thread;
 use std::time::Duration;
 
 use crate::{ChannelCount, SampleRate};
 use rb::{RB, RbConsumer, RbInspector, RbProducer, SpscRb};
 use thiserror::Error;
-use tracing::{error, info, warn};
-
+use tracing::{debug, error, info, warn};
+
+mod any_host;
+pub use any_host::*;
+mod crossfeed;
+pub use crossfeed::*;
+mod device_preference;
+pub use device_preference::*;
+mod device_watcher;
+pub use device_watcher::*;
+mod file;
+pub use file::*;
+mod input;
+pub use input::*;
+mod mirror;
+pub use mirror::*;
+mod negotiation;
+pub use negotiation::*;
+mod null;
+pub use null::*;
+mod offline;
+pub use offline::*;
+mod pipe;
+pub use pipe::*;
+mod tcp;
+pub use tcp::*;
+mod render_thread;
+pub use render_thread::Pacing;
+pub(crate) use render_thread::{RenderSink, RenderStream};
+mod virtual_clock;
+pub use virtual_clock::*;
 #[cfg(feature = "output-cpal")]
 mod cpal;
 #[cfg(feature = "output-cpal")]
@@ -24,16 +56,62 @@ mod rtaudio;
 pub use rtaudio::*;
 
 #[derive(thiserror::Error, Debug)]
-pub enum PlayStreamError {}
+pub enum PlayStreamError {
+    #[error("The device associated with the stream is no longer available.")]
+    DeviceNotAvailable,
+    #[error("{0}")]
+    BackendSpecific(BackendSpecificError),
+    #[error("{0}")]
+    Unknown(String),
+}
 
 #[derive(thiserror::Error, Debug)]
-pub enum DeviceNameError {}
+pub enum DeviceIdError {
+    #[error("Device IDs are not supported by this backend")]
+    Unsupported,
+    #[error("{0}")]
+    BackendSpecific(BackendSpecificError),
+    #[error("{0}")]
+    Unknown(String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeviceNameError {
+    #[error("{0}")]
+    BackendSpecific(BackendSpecificError),
+    #[error("{0}")]
+    Unknown(String),
+}
 
 #[derive(thiserror::Error, Debug)]
-pub enum SupportedStreamConfigsError {}
+pub enum SupportedStreamConfigsError {
+    #[error(
+        "The device no longer exists. This can happen if the device is disconnected while the \
+         program is running."
+    )]
+    DeviceNotAvailable,
+    #[error("Called something the device didn't understand")]
+    InvalidArgument,
+    #[error("{0}")]
+    BackendSpecific(BackendSpecificError),
+    #[error("{0}")]
+    Unknown(String),
+}
 
 #[derive(thiserror::Error, Debug)]
-pub enum DefaultStreamConfigError {}
+pub enum DefaultStreamConfigError {
+    #[error(
+        "The device no longer exists. This can happen if the device is disconnected while the \
+         program is running."
+    )]
+    DeviceNotAvailable,
+    #[error("The requested stream type is not supported by the device.")]
+    StreamTypeNotSupported,
+    #[error("{0}")]
+    BackendSpecific(BackendSpecificError),
+    #[error("{0}")]
+    Unknown(String),
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum BuildStreamError {
@@ -75,10 +153,20 @@ pub enum StreamError {
 }
 
 #[derive(thiserror::Error, Debug)]
-pub enum DevicesError {}
+pub enum DevicesError {
+    #[error("{0}")]
+    BackendSpecific(BackendSpecificError),
+    #[error("{0}")]
+    Unknown(String),
+}
 
 #[derive(thiserror::Error, Debug)]
-pub enum HostUnavailableError {}
+pub enum HostUnavailableError {
+    #[error("The requested host is unavailable")]
+    HostUnavailable,
+    #[error("{0}")]
+    BackendSpecific(BackendSpecificError),
+}
 
 #[derive(thiserror::Error, Debug)]
 #[error("{0}")]
@@ -139,6 +227,30 @@ pub enum SampleFormat {
     F64,
 }
 
+impl SampleFormat {
+    /// Number of bytes used to store a single sample.
+    pub fn sample_size(&self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I24 | Self::U24 | Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::I64 | Self::U64 | Self::F64 => 8,
+        }
+    }
+
+    /// Number of significant bits in a sample.
+    pub fn bits_per_sample(&self) -> u32 {
+        match self {
+            Self::I24 | Self::U24 => 24,
+            format => format.sample_size() as u32 * 8,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Self::F32 | Self::F64)
+    }
+}
+
 pub type FrameCount = u32;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -155,6 +267,82 @@ pub enum SupportedBufferSize {
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub struct HostId(u32);
 
+/// Identifies a device within a host. Unlike names, IDs are unique even if two devices are the
+/// same model.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
+pub struct DeviceId(pub String);
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DeviceInfo {
+    pub id: DeviceId,
+    pub name: String,
+    pub vendor: Option<String>,
+    pub is_default: bool,
+    pub max_channels: ChannelCount,
+    pub sample_rates: Vec<SampleRate>,
+}
+
+const COMMON_SAMPLE_RATES: [u32; 13] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000, 352800, 384000,
+];
+
+const ALL_SAMPLE_FORMATS: [SampleFormat; 12] = [
+    SampleFormat::I8,
+    SampleFormat::I16,
+    SampleFormat::I24,
+    SampleFormat::I32,
+    SampleFormat::I64,
+    SampleFormat::U8,
+    SampleFormat::U16,
+    SampleFormat::U24,
+    SampleFormat::U32,
+    SampleFormat::U64,
+    SampleFormat::F32,
+    SampleFormat::F64,
+];
+
+/// Max channel count advertised by software devices.
+pub(crate) const MAX_VIRTUAL_CHANNELS: u16 = 8;
+
+/// The default config for software devices.
+pub(crate) fn virtual_default_config() -> SupportedStreamConfig {
+    SupportedStreamConfig {
+        channels: ChannelCount(2),
+        sample_rate: SampleRate(44100),
+        buffer_size: SupportedBufferSize::Range {
+            min: 16,
+            max: 16384,
+        },
+        sample_format: SampleFormat::F32,
+    }
+}
+
+/// Config ranges for software devices that can be opened with any common config.
+pub(crate) fn virtual_config_ranges(
+    buffer_size: SupportedBufferSize,
+) -> Vec<SupportedStreamConfigRange> {
+    (1..=MAX_VIRTUAL_CHANNELS)
+        .flat_map(|channels| {
+            let buffer_size = buffer_size.clone();
+            ALL_SAMPLE_FORMATS
+                .iter()
+                .map(move |sample_format| SupportedStreamConfigRange {
+                    channels: ChannelCount(channels),
+                    min_sample_rate: SampleRate(COMMON_SAMPLE_RATES[0]),
+                    max_sample_rate: SampleRate(COMMON_SAMPLE_RATES[COMMON_SAMPLE_RATES.len() - 1]),
+                    buffer_size: buffer_size.clone(),
+                    sample_format: *sample_format,
+                })
+        })
+        .collect()
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub enum BufferSize {
     Default,
@@ -189,6 +377,26 @@ pub struct SupportedStreamConfigRange {
 }
 
 impl SupportedStreamConfigRange {
+    pub fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    pub fn min_sample_rate(&self) -> SampleRate {
+        self.min_sample_rate
+    }
+
+    pub fn max_sample_rate(&self) -> SampleRate {
+        self.max_sample_rate
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    pub fn contains_sample_rate(&self, sample_rate: SampleRate) -> bool {
+        self.min_sample_rate <= sample_rate && self.max_sample_rate >= sample_rate
+    }
+
     fn with_sample_rate(self, sample_rate: SampleRate) -> SupportedStreamConfig {
         SupportedStreamConfig {
             channels: self.channels,
@@ -250,12 +458,55 @@ pub trait Device {
 
     fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError>;
 
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Err(DefaultStreamConfigError::StreamTypeNotSupported)
+    }
+
     fn name(&self) -> Result<String, DeviceNameError>;
 
+    fn id(&self) -> Result<DeviceId, DeviceIdError>;
+
+    fn vendor(&self) -> Option<String> {
+        None
+    }
+
     fn supported_output_configs(
         &self,
     ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError>;
 
+    /// Empty for devices that can't capture audio.
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        Ok(vec![])
+    }
+
+    /// Discrete sample rates the device can be opened with. Backends that only report ranges
+    /// return the common rates that fall within them.
+    fn supported_sample_rates(&self) -> Result<Vec<SampleRate>, SupportedStreamConfigsError> {
+        let configs: Vec<_> = self.supported_output_configs()?.collect();
+        let mut sample_rates: Vec<_> = COMMON_SAMPLE_RATES
+            .iter()
+            .map(|r| SampleRate(*r))
+            .chain(
+                configs
+                    .iter()
+                    .flat_map(|c| [c.min_sample_rate, c.max_sample_rate]),
+            )
+            .filter(|r| configs.iter().any(|c| c.contains_sample_rate(*r)))
+            .collect();
+        sample_rates.sort();
+        sample_rates.dedup();
+        Ok(sample_rates)
+    }
+
+    /// Whether the data callback waits for audio to be written instead of padding the buffer with
+    /// silence. Only software devices that render as fast as they can do this, so their output
+    /// doesn't depend on timing.
+    fn waits_for_data(&self) -> bool {
+        false
+    }
+
     fn build_output_stream<T, D, E>(
         &mut self,
         config: &StreamConfig,
@@ -266,6 +517,20 @@ pub trait Device {
         T: DecalSample,
         D: FnMut(&mut [T]) + Send + Sync + 'static,
         E: FnMut(StreamError) + Clone + Send + Sync + 'static;
+
+    fn build_input_stream<T, D, E>(
+        &mut self,
+        _config: &StreamConfig,
+        _data_callback: D,
+        _error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&[T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        Err(BuildStreamError::StreamConfigNotSupported)
+    }
 }
 
 pub trait Host: Default + Send + Sync + 'static {
@@ -277,18 +542,61 @@ pub trait Host: Default + Send + Sync + 'static {
     fn default_output_device(&self) -> Option<Self::Device>;
     fn output_devices(&self) -> Result<Self::Devices, DevicesError>;
     fn id(&self) -> Self::Id;
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        None
+    }
+
+    fn input_devices(&self) -> Result<Vec<Self::Device>, DevicesError> {
+        Ok(vec![])
+    }
+
+    /// Registers a callback that's invoked when the backend reports a change to its devices.
+    /// Returns `None` if the backend has no native notifications, in which case the
+    /// [`DeviceWatcher`] falls back to polling.
+    fn watch_devices(
+        &self,
+        _on_change: Box<dyn Fn() + Send + Sync>,
+    ) -> Option<DeviceNotificationHandle> {
+        None
+    }
+
+    /// Whether streams on the default device keep playing on the old device when the default
+    /// changes. If so, the [`OutputBuilder`] watches for changes and calls
+    /// `on_configuration_changed` so the stream can be rebuilt.
+    fn needs_default_device_restart(&self) -> bool {
+        false
+    }
+
+    /// Whether devices can be added, removed or change their default. Hosts with a fixed device
+    /// return `false` so nothing watches them.
+    fn devices_can_change(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum AudioOutputError {
     #[error("No default device found")]
     NoDefaultDevice,
+    #[error("No default input device found")]
+    NoDefaultInputDevice,
+    #[error("Device not found: {0}")]
+    DeviceNotFound(String),
+    #[error("Error getting device name: {0}")]
+    DeviceNameError(#[from] DeviceNameError),
+    #[error("Error getting device ID: {0}")]
+    DeviceIdError(#[from] DeviceIdError),
     #[error("Error getting default device config: {0}")]
     OutputDeviceConfigError(#[from] DefaultStreamConfigError),
     #[error("Error opening output stream: {0}")]
     OpenStreamError(#[from] BuildStreamError),
     #[error("Error starting stream: {0}")]
     StartStreamError(#[from] PlayStreamError),
+    #[error("Error pausing stream: {0}")]
+    PauseStreamError(PlayStreamError),
+    #[error("Error stopping stream: {0}")]
+    StopStreamError(PlayStreamError),
     #[error("Unsupported device configuration: {0}")]
     UnsupportedConfiguration(String),
     #[error("Error loading devices: {0}")]
@@ -297,6 +605,7 @@ pub enum AudioOutputError {
     LoadConfigsError(#[from] SupportedStreamConfigsError),
 }
 
+#[derive(Clone, Debug, Default)]
 pub struct RequestedOutputConfig {
     pub sample_rate: Option<SampleRate>,
     pub channels: Option<ChannelCount>,
@@ -306,12 +615,19 @@ pub struct RequestedOutputConfig {
 #[derive(Clone)]
 pub struct OutputSettings {
     pub buffer_duration: Duration,
+    /// How much captured audio an [`AudioInput`] holds before it's read. New samples are dropped
+    /// while it's full.
+    pub input_buffer_duration: Duration,
+    /// Only takes effect when the [`OutputBuilder`] is created.
+    pub device_watcher: DeviceWatcherSettings,
 }
 
 impl Default for OutputSettings {
     fn default() -> Self {
         Self {
             buffer_duration: Duration::from_millis(250),
+            input_buffer_duration: INPUT_BUFFER_DURATION,
+            device_watcher: DeviceWatcherSettings::default(),
         }
     }
 }
@@ -328,6 +644,8 @@ pub struct OutputBuilder<H: Host> {
     on_error: Arc<Box<dyn Fn(BackendSpecificError) + Send + Sync>>,
     current_device: Arc<RwLock<Option<String>>>,
     settings: OutputSettings,
+    device_watcher: Arc<SharedDeviceWatcher<H>>,
+    _default_device_subscription: Option<Arc<DeviceSubscription>>,
 }
 
 impl<H: Host> Clone for OutputBuilder<H> {
@@ -338,6 +656,8 @@ impl<H: Host> Clone for OutputBuilder<H> {
             on_error: self.on_error.clone(),
             current_device: self.current_device.clone(),
             settings: self.settings.clone(),
+            device_watcher: self.device_watcher.clone(),
+            _default_device_subscription: self._default_device_subscription.clone(),
         }
     }
 }
@@ -353,47 +673,47 @@ impl<H: Host> OutputBuilder<H> {
         F1: Fn() + Send + Sync + 'static,
         F2: Fn(BackendSpecificError) + Send + Sync + 'static,
     {
-        let builder = Self {
-            host: Arc::new(host),
-            on_configuration_changed: Arc::new(Box::new(on_configuration_changed)),
+        let host = Arc::new(host);
+        let on_configuration_changed: Arc<Box<dyn Fn() + Send + Sync>> =
+            Arc::new(Box::new(on_configuration_changed));
+        let current_device: Arc<RwLock<Option<String>>> = Default::default();
+
+        let device_watcher = Arc::new(SharedDeviceWatcher::new(
+            host.clone(),
+            settings.device_watcher.clone(),
+        ));
+        // Some backends don't move the stream to the new default device automatically so we
+        // force a restart when it changes, unless the user has pinned a specific device.
+        let default_device_subscription = host.needs_default_device_restart().then(|| {
+            let current_device = current_device.clone();
+            let on_configuration_changed = on_configuration_changed.clone();
+            Arc::new(device_watcher.subscribe(move |event| {
+                if let DeviceEvent::DefaultChanged(_) = event
+                    && current_device.read().expect("lock poisoned").is_none()
+                {
+                    on_configuration_changed();
+                }
+            }))
+        });
+
+        Self {
+            host,
+            on_configuration_changed,
             on_error: Arc::new(Box::new(on_error)),
-            current_device: Default::default(),
+            current_device,
             settings,
-        };
-
-        #[cfg(any(windows, test))]
-        {
-            let current_device = builder.current_device.clone();
-            let host = builder.host.clone();
-            let on_configuration_changed = builder.on_configuration_changed.clone();
-
-            // On Windows, changes to the default device aren't picked up automatically
-            // We have to poll the current device and force a restart.
-            std::thread::spawn(move || {
-                let mut current_default_device = host
-                    .default_output_device()
-                    .map(|d| d.name().unwrap_or_default())
-                    .unwrap_or_default();
-                loop {
-                    if current_device.read().expect("lock poisoned").is_none() {
-                        let default_device = host
-                            .default_output_device()
-                            .map(|d| d.name().unwrap_or_default())
-                            .unwrap_or_default();
-
-                        if default_device != current_default_device {
-                            on_configuration_changed();
-                            current_default_device = default_device;
-                        }
-                    }
-
-                    std::thread::sleep(Duration::from_secs(1));
-                }
-            });
+            device_watcher,
+            _default_device_subscription: default_device_subscription,
         }
+    }
 
-        #[allow(clippy::let_and_return)]
-        builder
+    /// Subscribes to device events. The builder and its clones share one [`DeviceWatcher`],
+    /// which only runs while something is subscribed.
+    pub fn watch_devices<F>(&self, on_event: F) -> DeviceSubscription
+    where
+        F: Fn(DeviceEvent) + Send + Sync + 'static,
+    {
+        self.device_watcher.subscribe(on_event)
     }
 
     pub fn settings(&self) -> &OutputSettings {
@@ -412,54 +732,102 @@ impl<H: Host> OutputBuilder<H> {
         Ok(device.default_output_config()?)
     }
 
+    /// Ranks every config the device supports against the request. See [`rank_configs`] for
+    /// how candidates are ordered.
+    pub fn rank_configs(
+        &self,
+        device_name: Option<&str>,
+        config: &RequestedOutputConfig,
+    ) -> Result<RankedConfigs, AudioOutputError> {
+        let device = self.find_device(device_name)?;
+        let default_config = device.default_output_config()?;
+        Ok(rank_configs(
+            &default_config,
+            device
+                .supported_output_configs()
+                .map_err(AudioOutputError::LoadConfigsError)?,
+            config,
+        ))
+    }
+
     pub fn find_closest_config(
         &self,
         device_name: Option<&str>,
         config: RequestedOutputConfig,
     ) -> Result<SupportedStreamConfig, AudioOutputError> {
-        let default_device = self
-            .host
-            .default_output_device()
-            .ok_or(AudioOutputError::NoDefaultDevice)?;
-        let device = match &device_name {
-            Some(device_name) => self
+        let ranked = self.rank_configs(device_name, &config)?;
+        for rejected in &ranked.rejected {
+            debug!("Rejected config: {rejected:?}");
+        }
+        ranked
+            .candidates
+            .into_iter()
+            .next()
+            .map(|c| c.config)
+            .ok_or_else(|| {
+                AudioOutputError::UnsupportedConfiguration("No usable output configs".to_owned())
+            })
+    }
+
+    /// Finds a device by its ID or, failing that, by its name. `None` selects the default
+    /// device.
+    pub fn find_device(&self, device: Option<&str>) -> Result<H::Device, AudioOutputError> {
+        let Some(device) = device else {
+            return self
                 .host
-                .output_devices()?
-                .find(|d| {
-                    d.name()
-                        .map(|n| n.trim() == device_name.trim())
-                        .unwrap_or(false)
-                })
-                .unwrap_or(default_device),
-            None => default_device,
+                .default_output_device()
+                .ok_or(AudioOutputError::NoDefaultDevice);
         };
-        let default_config = device.default_output_config()?;
-
-        let channels = config.channels.unwrap_or(default_config.channels);
-        let sample_rate = config.sample_rate.unwrap_or(default_config.sample_rate);
-        let sample_format = config.sample_format.unwrap_or(default_config.sample_format);
-
-        if default_config.channels == channels
-            && default_config.sample_rate == sample_rate
-            && default_config.sample_format == sample_format
-        {
-            return Ok(default_config);
+        let mut name_match = None;
+        for d in self.host.output_devices()? {
+            if d.id().map(|id| id.0 == device).unwrap_or(false) {
+                return Ok(d);
+            }
+            if name_match.is_none() && d.name().map(|n| n.trim() == device.trim()).unwrap_or(false)
+            {
+                name_match = Some(d);
+            }
         }
+        name_match.ok_or_else(|| AudioOutputError::DeviceNotFound(device.to_owned()))
+    }
 
-        if let Some(matched_config) = device
-            .supported_output_configs()
-            .map_err(AudioOutputError::LoadConfigsError)?
-            .find(|c| {
-                c.channels == channels
-                    && c.sample_format == sample_format
-                    && c.min_sample_rate <= sample_rate
-                    && c.max_sample_rate >= sample_rate
-            })
-        {
-            return Ok(matched_config.with_sample_rate(sample_rate));
+    /// Returns the ID of the first available device that matches the preferences, in order.
+    /// `None` means the system default should be used.
+    pub fn find_preferred_device(
+        &self,
+        preferences: &[DeviceMatcher],
+    ) -> Result<Option<DeviceId>, AudioOutputError> {
+        let default_id = self.host.default_output_device().and_then(|d| d.id().ok());
+        let devices = self
+            .host
+            .output_devices()?
+            .filter_map(|d| Some((d.id().ok()?, d.name().unwrap_or_default())))
+            .collect::<Vec<_>>();
+        for preference in preferences {
+            if *preference == DeviceMatcher::Default {
+                return Ok(None);
+            }
+            if let Some((id, _)) = devices
+                .iter()
+                .find(|(id, name)| preference.matches(id, name, default_id.as_ref() == Some(id)))
+            {
+                return Ok(Some(id.clone()));
+            }
         }
+        Ok(None)
+    }
+
+    pub fn device_info(&self, device: &H::Device) -> Result<DeviceInfo, AudioOutputError> {
+        let default_id = self.host.default_output_device().and_then(|d| d.id().ok());
+        build_device_info(device, default_id.as_ref())
+    }
 
-        Ok(default_config)
+    pub fn output_device_info(&self) -> Result<Vec<DeviceInfo>, AudioOutputError> {
+        let default_id = self.host.default_output_device().and_then(|d| d.id().ok());
+        self.host
+            .output_devices()?
+            .map(|d| build_device_info(&d, default_id.as_ref()))
+            .collect()
     }
 
     pub fn default_output_device(&self) -> Option<H::Device> {
@@ -470,29 +838,69 @@ impl<H: Host> OutputBuilder<H> {
         self.host.output_devices()
     }
 
+    pub fn default_input_config(&self) -> Result<SupportedStreamConfig, AudioOutputError> {
+        let device = self
+            .host
+            .default_input_device()
+            .ok_or(AudioOutputError::NoDefaultInputDevice)?;
+        Ok(device.default_input_config()?)
+    }
+
+    /// Finds an input device by its ID or, failing that, by its name. `None` selects the
+    /// default input device.
+    pub fn find_input_device(&self, device: Option<&str>) -> Result<H::Device, AudioOutputError> {
+        let Some(device) = device else {
+            return self
+                .host
+                .default_input_device()
+                .ok_or(AudioOutputError::NoDefaultInputDevice);
+        };
+        let mut name_match = None;
+        for d in self.host.input_devices()? {
+            if d.id().map(|id| id.0 == device).unwrap_or(false) {
+                return Ok(d);
+            }
+            if name_match.is_none() && d.name().map(|n| n.trim() == device.trim()).unwrap_or(false)
+            {
+                name_match = Some(d);
+            }
+        }
+        name_match.ok_or_else(|| AudioOutputError::DeviceNotFound(device.to_owned()))
+    }
+
+    pub fn default_input_device(&self) -> Option<H::Device> {
+        self.host.default_input_device()
+    }
+
+    pub fn input_devices(&self) -> Result<Vec<H::Device>, DevicesError> {
+        self.host.input_devices()
+    }
+
+    pub fn new_input<T: DecalSample + Default + 'static>(
+        &self,
+        device_name: Option<&str>,
+        config: SupportedStreamConfig,
+    ) -> Result<AudioInput<T, H>, AudioOutputError> {
+        let device = self.find_input_device(device_name)?;
+        info!("Using input device: {:?}", device.name());
+        info!("Input device config: {config:?}");
+
+        Ok(AudioInput::<T, H>::new(
+            device,
+            config,
+            self.on_configuration_changed.clone(),
+            self.on_error.clone(),
+            self.settings.clone(),
+        ))
+    }
+
     pub fn new_output<T: DecalSample + Default + 'static>(
         &self,
         device_name: Option<String>,
         config: SupportedStreamConfig,
     ) -> Result<AudioOutput<T, H>, AudioOutputError> {
-        *self.current_device.write().expect("lock poisoned") = device_name.clone();
-        let default_device = self
-            .host
-            .default_output_device()
-            .ok_or(AudioOutputError::NoDefaultDevice)?;
-
-        let device = match &device_name {
-            Some(device_name) => self
-                .host
-                .output_devices()?
-                .find(|d| {
-                    d.name()
-                        .map(|n| n.trim() == device_name.trim())
-                        .unwrap_or(false)
-                })
-                .unwrap_or(default_device),
-            None => default_device,
-        };
+        let device = self.find_device(device_name.as_deref())?;
+        *self.current_device.write().expect("lock poisoned") = device_name;
         info!("Using device: {:?}", device.name());
         info!("Device config: {config:?}");
 
@@ -502,10 +910,104 @@ impl<H: Host> OutputBuilder<H> {
             self.on_configuration_changed.clone(),
             self.on_error.clone(),
             self.settings.clone(),
+            OUTPUT_BUFFER_DURATION,
+        ))
+    }
+
+    /// Opens an output for a [`MirrorOutput`]. Losing the device only flags the output, so it
+    /// doesn't trigger `on_configuration_changed` for the main output.
+    pub(crate) fn new_mirror_output<T: DecalSample + Default + 'static>(
+        &self,
+        device_name: &str,
+        config: SupportedStreamConfig,
+        buffer_duration: Duration,
+    ) -> Result<AudioOutput<T, H>, AudioOutputError> {
+        let device = self.find_device(Some(device_name))?;
+        info!("Using mirror device: {:?}", device.name());
+
+        Ok(AudioOutput::<T, H>::new(
+            device,
+            config,
+            Arc::new(Box::new(|| {})),
+            self.on_error.clone(),
+            self.settings.clone(),
+            buffer_duration,
         ))
     }
 }
 
+fn build_device_info<D: Device>(
+    device: &D,
+    default_id: Option<&DeviceId>,
+) -> Result<DeviceInfo, AudioOutputError> {
+    let id = device.id()?;
+    let max_channels = device
+        .supported_output_configs()?
+        .map(|c| c.channels)
+        .max()
+        .map(Ok)
+        .unwrap_or_else(|| device.default_output_config().map(|c| c.channels))?;
+
+    Ok(DeviceInfo {
+        name: device.name()?,
+        vendor: device.vendor(),
+        is_default: default_id == Some(&id),
+        max_channels,
+        sample_rates: device.supported_sample_rates()?,
+        id,
+    })
+}
+
+pub(crate) fn stream_error_handler(
+    on_configuration_changed: Arc<Box<dyn Fn() + Send + Sync>>,
+    on_error: Arc<Box<dyn Fn(BackendSpecificError) + Send + Sync>>,
+) -> impl FnMut(StreamError) + Clone + Send + Sync + 'static {
+    move |err| match err {
+        StreamError::DeviceNotAvailable | StreamError::StreamInvalidated => {
+            info!("Stream resetting due to error or configuration change...");
+            on_configuration_changed();
+        }
+        StreamError::BackendSpecific(err) => {
+            on_error(err);
+        }
+        StreamError::InputOverflow => {
+            warn!("input overflow")
+        }
+        StreamError::BufferUnderrun => {
+            warn!("buffer underrun");
+        }
+        StreamError::InvalidConfiguration(err) => {
+            error!("invalid configuration: {err}")
+        }
+        e => {
+            warn!("unknown error: {e:?}")
+        }
+    }
+}
+
+/// Flags the output as lost before passing the error on.
+fn lost_stream_handler(
+    device_lost: Arc<AtomicBool>,
+    mut handler: impl FnMut(StreamError) + Clone + Send + Sync + 'static,
+) -> impl FnMut(StreamError) + Clone + Send + Sync + 'static {
+    move |err| {
+        if matches!(
+            err,
+            StreamError::DeviceNotAvailable | StreamError::StreamInvalidated
+        ) {
+            device_lost.store(true, Ordering::SeqCst);
+        }
+        handler(err)
+    }
+}
+
+/// How much audio an output buffers ahead of the device.
+pub(crate) const OUTPUT_BUFFER_DURATION: Duration = Duration::from_millis(200);
+/// Default for [`OutputSettings::input_buffer_duration`].
+pub(crate) const INPUT_BUFFER_DURATION: Duration = Duration::from_millis(200);
+// How often a device that waits for data checks for more
+const DATA_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
 pub struct AudioOutput<T, H: Host> {
     ring_buf_producer: rb::Producer<T>,
     ring_buf: SpscRb<T>,
@@ -515,6 +1017,9 @@ pub struct AudioOutput<T, H: Host> {
     device: H::Device,
     config: SupportedStreamConfig,
     settings: OutputSettings,
+    device_lost: Arc<AtomicBool>,
+    // Cleared when the stream is paused or stopped so a callback waiting for data gives up
+    playing: Arc<AtomicBool>,
 }
 
 impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
@@ -524,8 +1029,8 @@ impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
         on_configuration_changed: Arc<Box<dyn Fn() + Send + Sync>>,
         on_error: Arc<Box<dyn Fn(BackendSpecificError) + Send + Sync>>,
         settings: OutputSettings,
+        buffer_duration: Duration,
     ) -> Self {
-        let buffer_duration = Duration::from_millis(200);
         let buffer_ms: usize = buffer_duration.as_millis().try_into().unwrap();
         let ring_buf = SpscRb::<T>::new(
             ((buffer_ms * config.sample_rate.0 as usize) / 1000) * config.channels.0 as usize,
@@ -540,32 +1045,43 @@ impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
             on_configuration_changed,
             on_error,
             settings,
+            device_lost: Default::default(),
+            playing: Default::default(),
         }
     }
 
     pub fn start(&mut self) -> Result<(), AudioOutputError> {
+        self.playing.store(true, Ordering::SeqCst);
         if self.stream.is_some() {
             return Ok(());
         }
 
         let mut stream = self.create_stream(self.ring_buf.consumer())?;
-        stream.play().unwrap();
+        stream.play()?;
         self.stream = Some(stream);
 
         Ok(())
     }
 
-    pub fn stop(&mut self) {
-        if let Some(stream) = self.stream.as_mut() {
-            stream.stop().unwrap()
+    pub fn stop(&mut self) -> Result<(), AudioOutputError> {
+        self.playing.store(false, Ordering::SeqCst);
+        // Drop the stream even if stopping fails so a dead device doesn't stick around
+        if let Some(mut stream) = self.stream.take() {
+            stream.stop().map_err(AudioOutputError::StopStreamError)?;
         }
-        self.stream = None;
+        Ok(())
     }
 
-    pub fn pause(&mut self) {
+    pub fn pause(&mut self) -> Result<(), AudioOutputError> {
+        self.playing.store(false, Ordering::SeqCst);
         if let Some(stream) = self.stream.as_mut() {
-            stream.pause().unwrap();
+            stream.pause().map_err(AudioOutputError::PauseStreamError)?;
         }
+        Ok(())
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.stream.is_some()
     }
 
     pub fn is_buffer_full(&self) -> bool {
@@ -596,6 +1112,21 @@ impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
         &self.device
     }
 
+    /// Whether the stream reported that its device went away or the stream became invalid.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::SeqCst)
+    }
+
+    /// Removes and returns everything that hasn't been played yet. The stream must be stopped
+    /// first, otherwise this races with the audio thread.
+    pub fn take_buffered(&mut self) -> Vec<T> {
+        debug_assert!(self.stream.is_none(), "stream must be stopped");
+        let mut buffered = vec![T::EQUILIBRIUM; self.ring_buf.count()];
+        let read = self.ring_buf.consumer().read(&mut buffered).unwrap_or(0);
+        buffered.truncate(read);
+        buffered
+    }
+
     pub fn write_blocking(&self, mut samples: &[T]) -> Result<(), WriteBlockingError> {
         let timeout = self.settings.buffer_duration;
         loop {
@@ -618,6 +1149,18 @@ impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
         Ok(())
     }
 
+    /// Waits for the device to play everything that's been written.
+    pub fn drain(&self) {
+        if self.stream.is_some() && self.device.waits_for_data() {
+            // The device reads as fast as it can, so it's done once the buffer is empty
+            while !self.ring_buf.is_empty() && self.playing.load(Ordering::SeqCst) {
+                thread::sleep(DATA_POLL_INTERVAL);
+            }
+            return;
+        }
+        thread::sleep(self.settings.buffer_duration);
+    }
+
     fn create_stream(
         &mut self,
         ring_buf_consumer: rb::Consumer<T>,
@@ -633,8 +1176,8 @@ impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
         info!("Output sample rate = {}", self.config.sample_rate.0);
 
         let filler = T::EQUILIBRIUM;
-        let on_error = self.on_error.clone();
-        let on_configuration_changed = self.on_configuration_changed.clone();
+        let waits_for_data = self.device.waits_for_data();
+        let playing = self.playing.clone();
         let mut stream = self
             .device
             .build_output_stream(
@@ -642,34 +1185,24 @@ impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
                 move |data: &mut [T]| {
                     // Write out as many samples as possible from the ring buffer to the audio
                     // output.
-                    let written = ring_buf_consumer.read(data).unwrap_or(0);
+                    let mut written = ring_buf_consumer.read(data).unwrap_or(0);
+                    while waits_for_data && written < data.len() && playing.load(Ordering::SeqCst) {
+                        thread::sleep(DATA_POLL_INTERVAL);
+                        written += ring_buf_consumer.read(&mut data[written..]).unwrap_or(0);
+                    }
                     // Mute any remaining samples.
                     if data.len() > written {
                         warn!("Output buffer not full, muting remaining",);
                         data[written..].iter_mut().for_each(|s| *s = filler);
                     }
                 },
-                move |err| match err {
-                    StreamError::DeviceNotAvailable | StreamError::StreamInvalidated => {
-                        info!("Stream resetting due to error or configuration change...");
-                        on_configuration_changed();
-                    }
-                    StreamError::BackendSpecific(err) => {
-                        on_error(err);
-                    }
-                    StreamError::InputOverflow => {
-                        warn!("input overflow")
-                    }
-                    StreamError::BufferUnderrun => {
-                        warn!("buffer underrun");
-                    }
-                    StreamError::InvalidConfiguration(err) => {
-                        error!("invalid configuration: {err}")
-                    }
-                    e => {
-                        warn!("unknown error: {e:?}")
-                    }
-                },
+                lost_stream_handler(
+                    self.device_lost.clone(),
+                    stream_error_handler(
+                        self.on_configuration_changed.clone(),
+                        self.on_error.clone(),
+                    ),
+                ),
             )
             .map_err(AudioOutputError::OpenStreamError)?;
 
@@ -680,10 +1213,65 @@ impl<T: DecalSample + Default + 'static, H: Host> AudioOutput<T, H> {
     }
 }
 
+impl<T, H: Host> Drop for AudioOutput<T, H> {
+    fn drop(&mut self) {
+        // Let a callback that's waiting for data return so the stream can shut down
+        self.playing.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+#[path = "./any_host_test.rs"]
+mod any_host_test;
+
+#[cfg(test)]
+#[path = "./crossfeed_test.rs"]
+mod crossfeed_test;
+
+#[cfg(test)]
+#[path = "./device_preference_test.rs"]
+mod device_preference_test;
+
+#[cfg(test)]
+#[path = "./device_watcher_test.rs"]
+mod device_watcher_test;
+
+#[cfg(test)]
+#[path = "./file_test.rs"]
+mod file_test;
+
+#[cfg(test)]
+#[path = "./input_test.rs"]
+mod input_test;
+
+#[cfg(test)]
+#[path = "./mirror_test.rs"]
+mod mirror_test;
+
+#[cfg(test)]
+#[path = "./mock_test.rs"]
+mod mock_test;
+
+#[cfg(test)]
+#[path = "./null_test.rs"]
+mod null_test;
+
+#[cfg(test)]
+#[path = "./offline_test.rs"]
+mod offline_test;
+
 #[cfg(test)]
 #[path = "./output_config_test.rs"]
 mod output_config_test;
 
+#[cfg(test)]
+#[path = "./pipe_test.rs"]
+mod pipe_test;
+
+#[cfg(test)]
+#[path = "./tcp_test.rs"]
+mod tcp_test;
+
 #[cfg(test)]
 #[path = "./write_output_test.rs"]
 mod write_output_test;
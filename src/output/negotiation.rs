@@ -0,0 +1,204 @@
+use std::cmp::Reverse;
+
+use super::{
+    RequestedOutputConfig, SampleFormat, SupportedStreamConfig, SupportedStreamConfigRange,
+};
+use crate::{ChannelCount, SampleRate};
+
+/// Explains where a candidate landed in the ranking.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CandidateReason {
+    DeviceDefault,
+    ExactSampleRate,
+    /// The requested rate multiplied by the given factor is supported.
+    SampleRateMultiple(u32),
+    /// The requested rate isn't supported so the highest available one was used.
+    HighestSampleRate,
+    ExactChannels,
+    NearestChannels,
+    ExactSampleFormat,
+    /// The requested format isn't supported. Higher bit depths are preferred.
+    FallbackSampleFormat,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RejectionReason {
+    NoChannels,
+    EmptySampleRateRange,
+    /// An equivalent config was already ranked higher.
+    Duplicate,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ConfigCandidate {
+    pub config: SupportedStreamConfig,
+    pub reasons: Vec<CandidateReason>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RejectedConfig {
+    pub config: SupportedStreamConfig,
+    pub reason: RejectionReason,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RankedConfigs {
+    /// Usable configs, best match first.
+    pub candidates: Vec<ConfigCandidate>,
+    pub rejected: Vec<RejectedConfig>,
+}
+
+impl RankedConfigs {
+    pub fn best(&self) -> Option<&SupportedStreamConfig> {
+        self.candidates.first().map(|c| &c.config)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+enum RateRank {
+    Exact,
+    Multiple(u32),
+    Highest(Reverse<SampleRate>),
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+struct Score {
+    channel_distance: u16,
+    // Prefer more channels over fewer when the distance is the same since downmixing loses less
+    fewer_channels: bool,
+    // Resampling costs more than converting the format, so the rate comes before bit depth
+    rate: RateRank,
+    format_mismatch: bool,
+    bits_per_sample: Reverse<u32>,
+}
+
+fn rate_rank(range: &SupportedStreamConfigRange, requested: SampleRate) -> RateRank {
+    if range.contains_sample_rate(requested) {
+        return RateRank::Exact;
+    }
+    if requested.0 > 0 {
+        let min_multiple = range.min_sample_rate.0.div_ceil(requested.0).max(2);
+        let max_multiple = range.max_sample_rate.0 / requested.0;
+        if min_multiple <= max_multiple {
+            return RateRank::Multiple(min_multiple);
+        }
+    }
+    RateRank::Highest(Reverse(range.max_sample_rate))
+}
+
+fn score(
+    config: &SupportedStreamConfig,
+    rate: RateRank,
+    channels: ChannelCount,
+    sample_format: SampleFormat,
+) -> Score {
+    Score {
+        channel_distance: config.channels.0.abs_diff(channels.0),
+        fewer_channels: config.channels < channels,
+        rate,
+        format_mismatch: config.sample_format != sample_format,
+        bits_per_sample: Reverse(config.sample_format.bits_per_sample()),
+    }
+}
+
+fn reasons(score: &Score, is_default: bool) -> Vec<CandidateReason> {
+    let mut reasons = vec![];
+    if is_default {
+        reasons.push(CandidateReason::DeviceDefault);
+    }
+    reasons.push(match score.rate {
+        RateRank::Exact => CandidateReason::ExactSampleRate,
+        RateRank::Multiple(factor) => CandidateReason::SampleRateMultiple(factor),
+        RateRank::Highest(_) => CandidateReason::HighestSampleRate,
+    });
+    reasons.push(if score.channel_distance == 0 {
+        CandidateReason::ExactChannels
+    } else {
+        CandidateReason::NearestChannels
+    });
+    reasons.push(if score.format_mismatch {
+        CandidateReason::FallbackSampleFormat
+    } else {
+        CandidateReason::ExactSampleFormat
+    });
+    reasons
+}
+
+/// Ranks the supported configs against the request. After the channel count, sample rates are
+/// ranked by an exact match, then integer multiples of the requested rate, then the highest rate
+/// available. Formats only break ties between equally good rates, preferring the requested format
+/// and then higher bit depths. Any fields missing from the request are filled in from the default
+/// config.
+pub fn rank_configs<I>(
+    default_config: &SupportedStreamConfig,
+    supported_configs: I,
+    requested: &RequestedOutputConfig,
+) -> RankedConfigs
+where
+    I: IntoIterator<Item = SupportedStreamConfigRange>,
+{
+    let channels = requested.channels.unwrap_or(default_config.channels);
+    let sample_rate = requested.sample_rate.unwrap_or(default_config.sample_rate);
+    let sample_format = requested
+        .sample_format
+        .unwrap_or(default_config.sample_format);
+
+    let mut rejected = vec![];
+    let default_range = SupportedStreamConfigRange {
+        channels: default_config.channels,
+        min_sample_rate: default_config.sample_rate,
+        max_sample_rate: default_config.sample_rate,
+        buffer_size: default_config.buffer_size.clone(),
+        sample_format: default_config.sample_format,
+    };
+    let mut scored: Vec<_> = [(default_range, true)]
+        .into_iter()
+        .chain(supported_configs.into_iter().map(|c| (c, false)))
+        .filter_map(|(range, is_default)| {
+            let rate = rate_rank(&range, sample_rate);
+            let config = range.clone().with_sample_rate(match rate {
+                RateRank::Exact => sample_rate,
+                RateRank::Multiple(factor) => SampleRate(sample_rate.0 * factor),
+                RateRank::Highest(Reverse(max)) => max,
+            });
+            if range.channels.0 == 0 {
+                rejected.push(RejectedConfig {
+                    config,
+                    reason: RejectionReason::NoChannels,
+                });
+                return None;
+            }
+            if range.min_sample_rate > range.max_sample_rate {
+                rejected.push(RejectedConfig {
+                    config,
+                    reason: RejectionReason::EmptySampleRateRange,
+                });
+                return None;
+            }
+            let score = score(&config, rate, channels, sample_format);
+            Some((score, config, is_default))
+        })
+        .collect();
+    // Stable sort so the default config wins ties
+    scored.sort_by_key(|(score, _, _)| *score);
+
+    let mut candidates: Vec<ConfigCandidate> = vec![];
+    for (score, config, is_default) in scored {
+        if candidates.iter().any(|c| c.config == config) {
+            rejected.push(RejectedConfig {
+                config,
+                reason: RejectionReason::Duplicate,
+            });
+            continue;
+        }
+        candidates.push(ConfigCandidate {
+            config,
+            reasons: reasons(&score, is_default),
+        });
+    }
+
+    RankedConfigs {
+        candidates,
+        rejected,
+    }
+}
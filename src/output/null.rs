@@ -0,0 +1,190 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{
+    BuildStreamError, DecalSample, DefaultStreamConfigError, Device, DeviceId, DeviceIdError,
+    DeviceNameError, DevicesError, Host, HostUnavailableError, Pacing, RenderSink, RenderStream,
+    Stream, StreamConfig, StreamError, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError, VirtualClock, virtual_config_ranges, virtual_default_config,
+};
+
+#[derive(Clone, Debug)]
+pub struct NullOutputSettings {
+    /// The config reported as the device's default.
+    pub default_config: SupportedStreamConfig,
+    pub clock: VirtualClock,
+}
+
+impl Default for NullOutputSettings {
+    fn default() -> Self {
+        Self {
+            default_config: virtual_default_config(),
+            clock: VirtualClock::new(),
+        }
+    }
+}
+
+struct NullSink;
+
+impl<T: DecalSample> RenderSink<T> for NullSink {
+    fn write(&mut self, _samples: &[T]) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+/// A device that requests audio at the same rate as a sound card and throws it away. Input
+/// streams capture silence.
+#[derive(Clone, Debug)]
+pub struct NullDevice {
+    settings: NullOutputSettings,
+    position: Arc<AtomicU64>,
+}
+
+impl NullDevice {
+    pub fn clock(&self) -> &VirtualClock {
+        &self.settings.clock
+    }
+
+    /// Number of frames requested by the most recently built stream.
+    pub fn position(&self) -> u64 {
+        self.position.load(Ordering::SeqCst)
+    }
+}
+
+impl Device for NullDevice {
+    type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(self.settings.default_config.clone())
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(self.settings.default_config.clone())
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok("Null Device".to_owned())
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(DeviceId("null".to_owned()))
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(virtual_config_ranges(self.settings.default_config.buffer_size.clone()).into_iter())
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        Ok(self.supported_output_configs()?.collect())
+    }
+
+    fn build_output_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&mut [T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        Ok(Box::new(RenderStream::new(
+            config,
+            Pacing::Clock(self.settings.clock.clone()),
+            self.position.clone(),
+            data_callback,
+            error_callback,
+            NullSink,
+        )?))
+    }
+
+    fn build_input_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&[T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        // Captures silence at the same rate as playback
+        Ok(Box::new(RenderStream::new(
+            config,
+            Pacing::Clock(self.settings.clock.clone()),
+            self.position.clone(),
+            move |data: &mut [T]| {
+                data.fill(T::EQUILIBRIUM);
+                data_callback(data);
+            },
+            error_callback,
+            NullSink,
+        )?))
+    }
+}
+
+/// A host for machines without a sound card. Its only device is paced by a [`VirtualClock`]
+/// that can be controlled through [`NullHost::clock`].
+#[derive(Clone, Debug)]
+pub struct NullHost {
+    device: NullDevice,
+}
+
+impl Default for NullHost {
+    fn default() -> Self {
+        Self::new(NullOutputSettings::default())
+    }
+}
+
+impl NullHost {
+    pub fn new(settings: NullOutputSettings) -> Self {
+        Self {
+            device: NullDevice {
+                settings,
+                position: Default::default(),
+            },
+        }
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        self.device.clock()
+    }
+}
+
+impl Host for NullHost {
+    type Device = NullDevice;
+    type Id = ();
+    type Devices = std::option::IntoIter<NullDevice>;
+
+    fn from_id(_id: Self::Id) -> Result<Self, HostUnavailableError> {
+        Ok(Self::default())
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        Some(self.device.clone())
+    }
+
+    fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(Some(self.device.clone()).into_iter())
+    }
+
+    fn id(&self) -> Self::Id {}
+
+    fn devices_can_change(&self) -> bool {
+        false
+    }
+
+    fn default_input_device(&self) -> Option<Self::Device> {
+        Some(self.device.clone())
+    }
+
+    fn input_devices(&self) -> Result<Vec<Self::Device>, DevicesError> {
+        Ok(vec![self.device.clone()])
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use super::{
+    BufferSize, Device, Host, NullHost, OutputBuilder, OutputSettings, StreamConfig, StreamError,
+    VirtualClock, WriteBlockingError,
+};
+use crate::{ChannelCount, SampleRate};
+
+// 10 frames at 1kHz gives a 10ms period
+const CONFIG: StreamConfig = StreamConfig {
+    channels: ChannelCount(2),
+    sample_rate: SampleRate(1000),
+    buffer_size: BufferSize::Fixed(10),
+};
+
+fn wait_for(condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn virtual_clock_controls() {
+    let clock = VirtualClock::new();
+    clock.pause();
+    let now = clock.now();
+    std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(now, clock.now());
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(now + Duration::from_secs(1), clock.now());
+
+    clock.set_speed(1000.0);
+    clock.resume();
+    std::thread::sleep(Duration::from_millis(5));
+    assert!(clock.now() >= now + Duration::from_secs(6));
+}
+
+#[test]
+fn null_device_follows_clock() {
+    let host = NullHost::default();
+    host.clock().pause();
+    let mut device = host.default_output_device().unwrap();
+    let (error_tx, error_rx) = mpsc::channel();
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(&CONFIG, |_| {}, move |e| error_tx.send(e).unwrap())
+        .unwrap();
+    stream.play().unwrap();
+
+    // The first buffer is requested immediately, then the device waits for the clock
+    wait_for(|| device.position() == 10);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(10, device.position());
+
+    // Missed periods are caught up on
+    host.clock().advance(Duration::from_millis(30));
+    wait_for(|| device.position() == 40);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(40, device.position());
+
+    stream.stop().unwrap();
+    assert!(error_rx.try_recv().is_err());
+}
+
+#[test]
+fn clock_jumps_during_callback_arent_underruns() {
+    let host = NullHost::default();
+    host.clock().pause();
+    let mut device = host.default_output_device().unwrap();
+    let (error_tx, error_rx) = mpsc::channel();
+    let clock = host.clock().clone();
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(
+            &CONFIG,
+            move |_| clock.advance(Duration::from_millis(50)),
+            move |e| {
+                error_tx.send(e).ok();
+            },
+        )
+        .unwrap();
+    stream.play().unwrap();
+
+    wait_for(|| device.position() >= 100);
+    stream.stop().unwrap();
+    assert!(error_rx.try_recv().is_err());
+}
+
+#[test]
+fn null_device_underrun() {
+    let host = NullHost::default();
+    let mut device = host.default_output_device().unwrap();
+    let (error_tx, error_rx) = mpsc::channel();
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(
+            &CONFIG,
+            |_| std::thread::sleep(Duration::from_millis(15)),
+            move |e| {
+                error_tx.send(e).ok();
+            },
+        )
+        .unwrap();
+    stream.play().unwrap();
+
+    let error = error_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(error, StreamError::BufferUnderrun));
+    stream.stop().unwrap();
+}
+
+#[test]
+fn null_output_stalls_with_paused_clock() {
+    let host = NullHost::default();
+    host.clock().pause();
+    let output_builder = OutputBuilder::new(
+        host,
+        OutputSettings {
+            buffer_duration: Duration::from_millis(20),
+            ..Default::default()
+        },
+        || {},
+        |_| {},
+    );
+    let mut output = output_builder
+        .new_output::<f32>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+    output.start().unwrap();
+
+    let samples = vec![0.0; output.buffer_capacity() * 2];
+    assert!(matches!(
+        output.write_blocking(&samples),
+        Err(WriteBlockingError::OutputStalled)
+    ));
+}
@@ -0,0 +1,280 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rb::{RbInspector, RbProducer};
+use tracing::warn;
+
+use super::{
+    AudioOutput, BuildStreamError, DecalSample, DefaultStreamConfigError, Device, DeviceId,
+    DeviceIdError, DeviceNameError, DevicesError, Host, HostUnavailableError, PlayStreamError,
+    Stream, StreamConfig, StreamError, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError, WriteBlockingError, virtual_config_ranges, virtual_default_config,
+};
+
+#[derive(Clone, Debug)]
+pub struct OfflineOutputSettings {
+    /// The config reported as the device's default.
+    pub default_config: SupportedStreamConfig,
+}
+
+impl Default for OfflineOutputSettings {
+    fn default() -> Self {
+        Self {
+            default_config: virtual_default_config(),
+        }
+    }
+}
+
+// Returns false if the stream isn't playing
+type RenderCallback = Box<dyn FnMut(usize) -> bool + Send>;
+
+#[derive(Default)]
+struct OfflineState {
+    render: Option<RenderCallback>,
+    // An `Arc<Mutex<Vec<T>>>` shared with the render callback
+    rendered: Option<Box<dyn Any + Send>>,
+    position: u64,
+    stream_id: u64,
+}
+
+struct OfflineStream {
+    id: u64,
+    playing: Arc<AtomicBool>,
+    state: Arc<Mutex<OfflineState>>,
+}
+
+impl OfflineStream {
+    fn release(&self) {
+        self.playing.store(false, Ordering::SeqCst);
+        let mut state = self.state.lock().expect("lock poisoned");
+        // A newer stream may have replaced this one already
+        if state.stream_id == self.id {
+            state.render = None;
+        }
+    }
+}
+
+impl Stream for OfflineStream {
+    fn play(&mut self) -> Result<(), PlayStreamError> {
+        self.playing.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), PlayStreamError> {
+        self.playing.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), PlayStreamError> {
+        self.release();
+        Ok(())
+    }
+}
+
+impl Drop for OfflineStream {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// A device without an audio thread. Audio is only requested when the output's buffer is full
+/// or drained, so everything written to it is rendered as fast as it's produced and in the same
+/// chunks every time.
+///
+/// The rendered audio is kept until it's collected with [`take_rendered`](Self::take_rendered),
+/// including across streams with the same sample type.
+#[derive(Clone)]
+pub struct OfflineDevice {
+    settings: OfflineOutputSettings,
+    state: Arc<Mutex<OfflineState>>,
+}
+
+impl OfflineDevice {
+    /// Number of frames rendered by the most recently built stream.
+    pub fn position(&self) -> u64 {
+        self.state.lock().expect("lock poisoned").position
+    }
+
+    /// Returns everything rendered since the last call. Returns nothing if the stream was built
+    /// with a different sample type.
+    pub fn take_rendered<T: DecalSample>(&self) -> Vec<T> {
+        let state = self.state.lock().expect("lock poisoned");
+        state
+            .rendered
+            .as_ref()
+            .and_then(|rendered| rendered.downcast_ref::<Arc<Mutex<Vec<T>>>>())
+            .map(|rendered| std::mem::take(&mut *rendered.lock().expect("lock poisoned")))
+            .unwrap_or_default()
+    }
+
+    // Synchronously requests frames from the stream. Returns false if it isn't playing.
+    fn render(&self, frames: usize) -> bool {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let Some(render) = state.render.as_mut() else {
+            return false;
+        };
+        if !render(frames) {
+            return false;
+        }
+        state.position += frames as u64;
+        true
+    }
+}
+
+impl Device for OfflineDevice {
+    type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(self.settings.default_config.clone())
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok("Offline Device".to_owned())
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(DeviceId("offline".to_owned()))
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(virtual_config_ranges(self.settings.default_config.buffer_size.clone()).into_iter())
+    }
+
+    fn build_output_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        _error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&mut [T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        if config.channels.0 == 0 || config.sample_rate.0 == 0 {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
+        let channels = config.channels.0 as usize;
+        let playing = Arc::new(AtomicBool::new(false));
+        let mut state = self.state.lock().expect("lock poisoned");
+        let rendered = state
+            .rendered
+            .as_ref()
+            .and_then(|rendered| rendered.downcast_ref::<Arc<Mutex<Vec<T>>>>())
+            .cloned()
+            .unwrap_or_default();
+        state.rendered = Some(Box::new(rendered.clone()));
+        state.position = 0;
+        state.stream_id += 1;
+        let id = state.stream_id;
+
+        let callback_playing = playing.clone();
+        let mut buf = Vec::new();
+        state.render = Some(Box::new(move |frames| {
+            if !callback_playing.load(Ordering::SeqCst) {
+                return false;
+            }
+            buf.clear();
+            buf.resize(frames * channels, T::EQUILIBRIUM);
+            data_callback(&mut buf);
+            rendered
+                .lock()
+                .expect("lock poisoned")
+                .extend_from_slice(&buf);
+            true
+        }));
+
+        Ok(Box::new(OfflineStream {
+            id,
+            playing,
+            state: self.state.clone(),
+        }))
+    }
+}
+
+/// A host for rendering audio without a sound card. Clones share the same device, so a clone
+/// can be kept to collect the output.
+#[derive(Clone)]
+pub struct OfflineHost {
+    device: OfflineDevice,
+}
+
+impl Default for OfflineHost {
+    fn default() -> Self {
+        Self::new(OfflineOutputSettings::default())
+    }
+}
+
+impl OfflineHost {
+    pub fn new(settings: OfflineOutputSettings) -> Self {
+        Self {
+            device: OfflineDevice {
+                settings,
+                state: Default::default(),
+            },
+        }
+    }
+
+    pub fn device(&self) -> &OfflineDevice {
+        &self.device
+    }
+
+    /// Returns everything rendered since the last call.
+    pub fn take_rendered<T: DecalSample>(&self) -> Vec<T> {
+        self.device.take_rendered()
+    }
+}
+
+impl Host for OfflineHost {
+    type Device = OfflineDevice;
+    type Id = ();
+    type Devices = std::option::IntoIter<OfflineDevice>;
+
+    fn from_id(_id: Self::Id) -> Result<Self, HostUnavailableError> {
+        Ok(Self::default())
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        Some(self.device.clone())
+    }
+
+    fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(Some(self.device.clone()).into_iter())
+    }
+
+    fn id(&self) -> Self::Id {}
+
+    fn devices_can_change(&self) -> bool {
+        false
+    }
+}
+
+impl<T: DecalSample + Default + 'static> AudioOutput<T, OfflineHost> {
+    /// Like [`write_blocking`](Self::write_blocking), but nothing else consumes the buffer, so it's
+    /// rendered whenever it fills up instead of waiting for it to play.
+    pub fn render_blocking(&self, mut samples: &[T]) -> Result<(), WriteBlockingError> {
+        while !samples.is_empty() {
+            let written = self.ring_buf_producer.write(samples).unwrap_or(0);
+            samples = &samples[written..];
+            if !samples.is_empty() && !self.render_buffered() {
+                warn!("Offline stream isn't playing. Cancelling write.");
+                return Err(WriteBlockingError::OutputStalled);
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders everything that's been written, instead of waiting like [`drain`](Self::drain).
+    pub fn render_remaining(&self) {
+        while !self.ring_buf.is_empty() && self.render_buffered() {}
+    }
+
+    // Returns false if nothing could be rendered
+    fn render_buffered(&self) -> bool {
+        let buffered = self.ring_buf.count();
+        let frames = buffered / self.config.channels.0.max(1) as usize;
+        frames > 0 && self.device.render(frames) && self.ring_buf.count() < buffered
+    }
+}
@@ -0,0 +1,45 @@
+use super::{OfflineHost, OutputBuilder, WriteBlockingError};
+
+fn output_builder(host: &OfflineHost) -> OutputBuilder<OfflineHost> {
+    OutputBuilder::new(host.clone(), Default::default(), || {}, |_| {})
+}
+
+#[test]
+fn offline_output_renders_everything_written() {
+    let host = OfflineHost::default();
+    let output_builder = output_builder(&host);
+    let mut output = output_builder
+        .new_output::<f32>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+    output.start().unwrap();
+
+    // More than the buffer can hold, so the device has to render while writing
+    let samples: Vec<f32> = (0..output.buffer_capacity() * 3)
+        .map(|i| (i % 100) as f32 / 100.0)
+        .collect();
+    output.render_blocking(&samples).unwrap();
+    assert!(host.device().position() > 0);
+    output.render_remaining();
+
+    assert_eq!(0, output.buffer_size());
+    assert_eq!(samples, host.take_rendered::<f32>());
+    assert!(host.take_rendered::<f32>().is_empty());
+}
+
+#[test]
+fn offline_output_stalls_when_not_playing() {
+    let host = OfflineHost::default();
+    let output_builder = output_builder(&host);
+    let output = output_builder
+        .new_output::<f32>(None, output_builder.default_output_config().unwrap())
+        .unwrap();
+
+    let samples = vec![0.0f32; output.buffer_capacity() + 2];
+    assert!(matches!(
+        output.render_blocking(&samples),
+        Err(WriteBlockingError::OutputStalled)
+    ));
+    // Rendering the rest of a stopped output doesn't block either
+    output.render_remaining();
+    assert!(host.take_rendered::<f32>().is_empty());
+}
@@ -1,6 +1,9 @@
 use std::vec;
 
-use super::{MockDevice, MockHost, OutputBuilder, RequestedOutputConfig};
+use super::{
+    AudioOutputError, CandidateReason, DeviceId, DeviceInfo, MockDevice, MockHost, OutputBuilder,
+    RejectionReason, RequestedOutputConfig, SupportedStreamConfigRange, rank_configs,
+};
 use crate::{
     ChannelCount,
     output::{SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfig},
@@ -9,8 +12,8 @@ use crate::{
 #[test]
 fn find_closest_config_default() {
     let output_builder = OutputBuilder::new(
-        MockHost {
-            default_device: MockDevice::new(
+        MockHost::new(
+            MockDevice::new(
                 "test-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -22,8 +25,8 @@ fn find_closest_config_default() {
                 SampleRate(192000),
                 vec![],
             ),
-            additional_devices: vec![],
-        },
+            vec![],
+        ),
         Default::default(),
         move || {},
         |_| {},
@@ -51,8 +54,8 @@ fn find_closest_config_default() {
 #[test]
 fn find_closest_config_sample_rate() {
     let output_builder = OutputBuilder::new(
-        MockHost {
-            default_device: MockDevice::new(
+        MockHost::new(
+            MockDevice::new(
                 "test-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -64,8 +67,8 @@ fn find_closest_config_sample_rate() {
                 SampleRate(192000),
                 vec![],
             ),
-            additional_devices: vec![],
-        },
+            vec![],
+        ),
         Default::default(),
         move || {},
         |_| {},
@@ -93,8 +96,8 @@ fn find_closest_config_sample_rate() {
 #[test]
 fn find_closest_config_channel_mismatch() {
     let output_builder = OutputBuilder::new(
-        MockHost {
-            default_device: MockDevice::new(
+        MockHost::new(
+            MockDevice::new(
                 "test-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -106,7 +109,7 @@ fn find_closest_config_channel_mismatch() {
                 SampleRate(192000),
                 vec![],
             ),
-            additional_devices: vec![MockDevice::new(
+            vec![MockDevice::new(
                 "second-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(1),
@@ -118,7 +121,7 @@ fn find_closest_config_channel_mismatch() {
                 SampleRate(192000),
                 vec![],
             )],
-        },
+        ),
         Default::default(),
         move || {},
         |_| {},
@@ -146,8 +149,8 @@ fn find_closest_config_channel_mismatch() {
 #[test]
 fn find_closest_config_sample_rate_mismatch() {
     let output_builder = OutputBuilder::new(
-        MockHost {
-            default_device: MockDevice::new(
+        MockHost::new(
+            MockDevice::new(
                 "test-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -159,7 +162,7 @@ fn find_closest_config_sample_rate_mismatch() {
                 SampleRate(44100),
                 vec![],
             ),
-            additional_devices: vec![MockDevice::new(
+            vec![MockDevice::new(
                 "second-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(1),
@@ -171,7 +174,7 @@ fn find_closest_config_sample_rate_mismatch() {
                 SampleRate(192000),
                 vec![],
             )],
-        },
+        ),
         Default::default(),
         move || {},
         |_| {},
@@ -199,8 +202,8 @@ fn find_closest_config_sample_rate_mismatch() {
 #[test]
 fn find_closest_config_non_default_device() {
     let output_builder = OutputBuilder::new(
-        MockHost {
-            default_device: MockDevice::new(
+        MockHost::new(
+            MockDevice::new(
                 "test-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -212,7 +215,7 @@ fn find_closest_config_non_default_device() {
                 SampleRate(192000),
                 vec![],
             ),
-            additional_devices: vec![MockDevice::new(
+            vec![MockDevice::new(
                 "second-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -224,7 +227,7 @@ fn find_closest_config_non_default_device() {
                 SampleRate(192000),
                 vec![],
             )],
-        },
+        ),
         Default::default(),
         move || {},
         |_| {},
@@ -248,3 +251,215 @@ fn find_closest_config_non_default_device() {
     assert_eq!(SampleFormat::F32, config.sample_format);
     assert_eq!(SampleRate(48000), config.sample_rate);
 }
+
+#[test]
+fn find_closest_config_missing_device() {
+    let output_builder =
+        OutputBuilder::new(MockHost::default(), Default::default(), move || {}, |_| {});
+    let res = output_builder.find_closest_config(
+        Some("missing-device"),
+        RequestedOutputConfig {
+            sample_rate: None,
+            channels: None,
+            sample_format: None,
+        },
+    );
+
+    assert!(matches!(res, Err(AudioOutputError::DeviceNotFound(name)) if name == "missing-device"));
+}
+
+#[test]
+fn find_device_by_id() {
+    let mut first = MockDevice::new(
+        "usb-dac".to_owned(),
+        SupportedStreamConfig {
+            channels: ChannelCount(2),
+            sample_rate: SampleRate(44100),
+            buffer_size: SupportedBufferSize::Range { min: 0, max: 9999 },
+            sample_format: SampleFormat::F32,
+        },
+        SampleRate(44100),
+        SampleRate(48000),
+        vec![],
+    );
+    first.id = DeviceId("usb-dac-1".to_owned());
+    let mut second = first.clone();
+    second.id = DeviceId("usb-dac-2".to_owned());
+
+    let output_builder = OutputBuilder::new(
+        MockHost::new(first, vec![second]),
+        Default::default(),
+        move || {},
+        |_| {},
+    );
+
+    let device = output_builder.find_device(Some("usb-dac-2")).unwrap();
+    assert_eq!(DeviceId("usb-dac-2".to_owned()), device.id);
+
+    // Names are ambiguous so the first match wins
+    let device = output_builder.find_device(Some("usb-dac")).unwrap();
+    assert_eq!(DeviceId("usb-dac-1".to_owned()), device.id);
+
+    let info = output_builder.output_device_info().unwrap();
+    assert_eq!(
+        vec![
+            DeviceInfo {
+                id: DeviceId("usb-dac-1".to_owned()),
+                name: "usb-dac".to_owned(),
+                vendor: None,
+                is_default: true,
+                max_channels: ChannelCount(2),
+                sample_rates: vec![SampleRate(44100), SampleRate(48000)],
+            },
+            DeviceInfo {
+                id: DeviceId("usb-dac-2".to_owned()),
+                name: "usb-dac".to_owned(),
+                vendor: None,
+                is_default: false,
+                max_channels: ChannelCount(2),
+                sample_rates: vec![SampleRate(44100), SampleRate(48000)],
+            }
+        ],
+        info
+    );
+}
+
+fn range(
+    channels: u16,
+    min_sample_rate: u32,
+    max_sample_rate: u32,
+    sample_format: SampleFormat,
+) -> SupportedStreamConfigRange {
+    SupportedStreamConfigRange {
+        channels: ChannelCount(channels),
+        min_sample_rate: SampleRate(min_sample_rate),
+        max_sample_rate: SampleRate(max_sample_rate),
+        buffer_size: SupportedBufferSize::Unknown,
+        sample_format,
+    }
+}
+
+fn default_config() -> SupportedStreamConfig {
+    SupportedStreamConfig {
+        channels: ChannelCount(2),
+        sample_rate: SampleRate(48000),
+        buffer_size: SupportedBufferSize::Unknown,
+        sample_format: SampleFormat::F32,
+    }
+}
+
+#[test]
+fn rank_configs_sample_rate_multiple() {
+    let ranked = rank_configs(
+        &default_config(),
+        vec![
+            range(2, 48000, 48000, SampleFormat::F32),
+            range(2, 88200, 176400, SampleFormat::F32),
+        ],
+        &RequestedOutputConfig {
+            sample_rate: Some(SampleRate(44100)),
+            ..Default::default()
+        },
+    );
+
+    let best = &ranked.candidates[0];
+    assert_eq!(SampleRate(88200), best.config.sample_rate);
+    assert!(
+        best.reasons
+            .contains(&CandidateReason::SampleRateMultiple(2))
+    );
+    assert_eq!(SampleRate(48000), ranked.candidates[1].config.sample_rate);
+    assert!(
+        ranked.candidates[1]
+            .reasons
+            .contains(&CandidateReason::HighestSampleRate)
+    );
+}
+
+#[test]
+fn rank_configs_nearest_channels() {
+    let ranked = rank_configs(
+        &SupportedStreamConfig {
+            channels: ChannelCount(8),
+            ..default_config()
+        },
+        vec![
+            range(8, 48000, 48000, SampleFormat::F32),
+            range(1, 48000, 48000, SampleFormat::F32),
+            range(4, 48000, 48000, SampleFormat::F32),
+        ],
+        &RequestedOutputConfig {
+            channels: Some(ChannelCount(2)),
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(ChannelCount(1), ranked.candidates[0].config.channels);
+    assert!(
+        ranked.candidates[0]
+            .reasons
+            .contains(&CandidateReason::NearestChannels)
+    );
+    assert_eq!(ChannelCount(4), ranked.candidates[1].config.channels);
+}
+
+#[test]
+fn rank_configs_higher_bit_depth() {
+    let ranked = rank_configs(
+        &SupportedStreamConfig {
+            sample_format: SampleFormat::I16,
+            ..default_config()
+        },
+        vec![
+            range(2, 48000, 48000, SampleFormat::I16),
+            range(2, 48000, 48000, SampleFormat::I24),
+            range(2, 48000, 48000, SampleFormat::F32),
+            range(0, 48000, 48000, SampleFormat::F32),
+        ],
+        &RequestedOutputConfig {
+            sample_format: Some(SampleFormat::F64),
+            ..Default::default()
+        },
+    );
+
+    let formats: Vec<_> = ranked
+        .candidates
+        .iter()
+        .map(|c| c.config.sample_format)
+        .collect();
+    assert_eq!(
+        vec![SampleFormat::F32, SampleFormat::I24, SampleFormat::I16],
+        formats
+    );
+    let rejected: Vec<_> = ranked.rejected.iter().map(|r| r.reason).collect();
+    assert_eq!(
+        vec![RejectionReason::NoChannels, RejectionReason::Duplicate],
+        rejected
+    );
+}
+
+#[test]
+fn rank_configs_rate_before_bit_depth() {
+    let ranked = rank_configs(
+        &default_config(),
+        vec![
+            range(2, 48000, 48000, SampleFormat::F32),
+            range(2, 44100, 44100, SampleFormat::I24),
+        ],
+        &RequestedOutputConfig {
+            sample_rate: Some(SampleRate(44100)),
+            sample_format: Some(SampleFormat::F32),
+            ..Default::default()
+        },
+    );
+
+    // Converting to 24-bit is better than resampling to keep the requested format
+    let best = &ranked.candidates[0];
+    assert_eq!(SampleRate(44100), best.config.sample_rate);
+    assert_eq!(SampleFormat::I24, best.config.sample_format);
+    assert!(
+        best.reasons
+            .contains(&CandidateReason::FallbackSampleFormat)
+    );
+    assert_eq!(SampleRate(48000), ranked.candidates[1].config.sample_rate);
+}
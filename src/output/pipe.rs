@@ -0,0 +1,188 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use super::{
+    BackendSpecificError, BuildStreamError, DecalSample, DefaultStreamConfigError, Device,
+    DeviceId, DeviceIdError, DeviceNameError, DevicesError, Host, HostUnavailableError, Pacing,
+    RenderSink, RenderStream, SampleFormat, Stream, StreamConfig, StreamError,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
+    virtual_config_ranges, virtual_default_config,
+};
+use crate::encoder::{Endianness, PcmFormat};
+
+#[derive(Clone, Debug)]
+pub struct PipeOutputSettings {
+    /// The format samples are written in. Defaults to the stream's sample format.
+    pub sample_format: Option<SampleFormat>,
+    pub endianness: Endianness,
+    /// The config reported as the device's default.
+    pub default_config: SupportedStreamConfig,
+}
+
+impl Default for PipeOutputSettings {
+    fn default() -> Self {
+        Self {
+            sample_format: None,
+            endianness: Endianness::native(),
+            default_config: virtual_default_config(),
+        }
+    }
+}
+
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+fn pipe_error(e: io::Error) -> StreamError {
+    match e.kind() {
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::WriteZero => {
+            StreamError::DeviceNotAvailable
+        }
+        _ => StreamError::BackendSpecific(BackendSpecificError(e.to_string())),
+    }
+}
+
+struct PipeSink {
+    writer: SharedWriter,
+    format: PcmFormat,
+    buf: Vec<u8>,
+}
+
+impl<T: DecalSample> RenderSink<T> for PipeSink {
+    fn write(&mut self, samples: &[T]) -> Result<(), StreamError> {
+        self.buf.clear();
+        self.format.encode(samples, &mut self.buf);
+        let mut writer = self.writer.lock().expect("lock poisoned");
+        writer.write_all(&self.buf).map_err(pipe_error)?;
+        writer.flush().map_err(pipe_error)
+    }
+
+    fn finish(&mut self) -> Result<(), StreamError> {
+        self.writer
+            .lock()
+            .expect("lock poisoned")
+            .flush()
+            .map_err(pipe_error)
+    }
+}
+
+/// A device that writes interleaved PCM to a pipe. Blocking writes pace the data callback, so
+/// stopping the stream waits for a pending write to finish.
+#[derive(Clone)]
+pub struct PipeDevice {
+    settings: PipeOutputSettings,
+    writer: SharedWriter,
+}
+
+impl fmt::Debug for PipeDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipeDevice")
+            .field("settings", &self.settings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Device for PipeDevice {
+    type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(self.settings.default_config.clone())
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok("Pipe Output".to_owned())
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(DeviceId("pipe".to_owned()))
+    }
+
+    fn waits_for_data(&self) -> bool {
+        true
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(virtual_config_ranges(self.settings.default_config.buffer_size.clone()).into_iter())
+    }
+
+    fn build_output_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&mut [T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        let format = PcmFormat::new(
+            self.settings.sample_format.unwrap_or(T::FORMAT),
+            self.settings.endianness,
+        );
+        Ok(Box::new(RenderStream::new(
+            config,
+            Pacing::Unpaced,
+            Default::default(),
+            data_callback,
+            error_callback,
+            PipeSink {
+                writer: self.writer.clone(),
+                format,
+                buf: Vec::new(),
+            },
+        )?))
+    }
+}
+
+/// A host whose only device writes raw PCM to stdout or any other [`Write`], such as a named
+/// pipe or a child process's stdin.
+#[derive(Clone, Debug)]
+pub struct PipeHost {
+    device: PipeDevice,
+}
+
+impl Default for PipeHost {
+    fn default() -> Self {
+        Self::stdout(PipeOutputSettings::default())
+    }
+}
+
+impl PipeHost {
+    pub fn new<W: Write + Send + 'static>(settings: PipeOutputSettings, writer: W) -> Self {
+        Self {
+            device: PipeDevice {
+                settings,
+                writer: Arc::new(Mutex::new(Box::new(writer))),
+            },
+        }
+    }
+
+    pub fn stdout(settings: PipeOutputSettings) -> Self {
+        Self::new(settings, io::stdout())
+    }
+}
+
+impl Host for PipeHost {
+    type Device = PipeDevice;
+    type Id = ();
+    type Devices = std::option::IntoIter<PipeDevice>;
+
+    fn from_id(_id: Self::Id) -> Result<Self, HostUnavailableError> {
+        Ok(Self::default())
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        Some(self.device.clone())
+    }
+
+    fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(Some(self.device.clone()).into_iter())
+    }
+
+    fn id(&self) -> Self::Id {}
+
+    fn devices_can_change(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,52 @@
+use std::io::Read;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::{
+    BufferSize, Device, Host, PipeHost, PipeOutputSettings, SampleFormat, StreamConfig, StreamError,
+};
+use crate::encoder::Endianness;
+use crate::{ChannelCount, SampleRate};
+
+#[test]
+fn pipe_host_writes_pcm() {
+    let (mut reader, writer) = std::io::pipe().unwrap();
+    let host = PipeHost::new(
+        PipeOutputSettings {
+            sample_format: Some(SampleFormat::I16),
+            endianness: Endianness::Big,
+            ..Default::default()
+        },
+        writer,
+    );
+    let mut device = host.default_output_device().unwrap();
+    let (error_tx, error_rx) = mpsc::channel();
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(
+            &StreamConfig {
+                channels: ChannelCount(2),
+                sample_rate: SampleRate(48000),
+                buffer_size: BufferSize::Fixed(64),
+            },
+            |data: &mut [f32]| data.fill(0.5),
+            move |e| {
+                error_tx.send(e).ok();
+            },
+        )
+        .unwrap();
+    stream.play().unwrap();
+
+    let mut bytes = [0; 1024];
+    reader.read_exact(&mut bytes).unwrap();
+    assert!(
+        bytes
+            .chunks(2)
+            .all(|s| i16::from_be_bytes([s[0], s[1]]) == 16384)
+    );
+
+    // Closing the read end unblocks the writer
+    drop(reader);
+    let error = error_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(error, StreamError::DeviceNotAvailable));
+    stream.stop().unwrap();
+}
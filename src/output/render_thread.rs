@@ -0,0 +1,221 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tap::TapFallible;
+use tracing::warn;
+
+use super::{
+    BufferSize, BuildStreamError, DecalSample, FrameCount, PlayStreamError, Stream, StreamConfig,
+    StreamError, VirtualClock,
+};
+
+/// Period used by software devices when the stream config doesn't request a fixed buffer size.
+pub(crate) const DEFAULT_PERIOD_FRAMES: FrameCount = 512;
+// Upper bound on a single wait so changes to the clock are picked up quickly
+const MAX_WAIT: Duration = Duration::from_millis(5);
+
+/// Controls how often a software device requests audio from the data callback.
+#[derive(Debug, Clone, Default)]
+pub enum Pacing {
+    /// Request a buffer once per period, like a sound card would.
+    #[default]
+    RealTime,
+    /// Request a buffer once per period of the clock's virtual time.
+    Clock(VirtualClock),
+    /// Request buffers as fast as they can be consumed.
+    Unpaced,
+}
+
+/// Receives each buffer rendered by a [`RenderStream`].
+pub(crate) trait RenderSink<T>: Send + 'static {
+    fn write(&mut self, samples: &[T]) -> Result<(), StreamError>;
+
+    /// Called once after the stream stops.
+    fn finish(&mut self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum RenderState {
+    Paused,
+    Playing,
+    Stopped,
+}
+
+struct SharedState {
+    state: Mutex<RenderState>,
+    changed: Condvar,
+}
+
+impl SharedState {
+    fn set(&self, state: RenderState) {
+        *self.state.lock().expect("lock poisoned") = state;
+        self.changed.notify_all();
+    }
+
+    /// Waits until the clock reaches the deadline or the stream stops playing.
+    fn wait_until(&self, clock: &VirtualClock, deadline: Duration) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        while *state == RenderState::Playing {
+            let now = clock.now();
+            if now >= deadline {
+                return;
+            }
+            let timeout = clock
+                .real_duration(deadline - now)
+                .map_or(MAX_WAIT, |d| d.min(MAX_WAIT));
+            state = self
+                .changed
+                .wait_timeout(state, timeout)
+                .expect("lock poisoned")
+                .0;
+        }
+    }
+}
+
+/// Drives a data callback from a background thread for devices that don't have a native audio
+/// thread.
+///
+/// If the callback and sink take longer than a period to process a buffer, the stream reports
+/// [`StreamError::BufferUnderrun`] and skips ahead, like a sound card would. The stream stalls
+/// while the clock is paused.
+pub(crate) struct RenderStream {
+    shared: Arc<SharedState>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RenderStream {
+    /// `position` is reset and then incremented by the number of frames in each buffer.
+    pub(crate) fn new<T, D, E, S>(
+        config: &StreamConfig,
+        pacing: Pacing,
+        position: Arc<AtomicU64>,
+        mut data_callback: D,
+        mut error_callback: E,
+        mut sink: S,
+    ) -> Result<Self, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&mut [T]) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+        S: RenderSink<T>,
+    {
+        let frames = match config.buffer_size {
+            BufferSize::Default => DEFAULT_PERIOD_FRAMES,
+            BufferSize::Fixed(frames) => frames,
+        };
+        if frames == 0 || config.channels.0 == 0 || config.sample_rate.0 == 0 {
+            return Err(BuildStreamError::StreamConfigNotSupported);
+        }
+        let period = Duration::from_secs_f64(frames as f64 / config.sample_rate.0 as f64);
+        let clock = match pacing {
+            Pacing::RealTime => Some(VirtualClock::new()),
+            Pacing::Clock(clock) => Some(clock),
+            Pacing::Unpaced => None,
+        };
+        position.store(0, Ordering::SeqCst);
+        let buf_len = frames as usize * config.channels.0 as usize;
+
+        let shared = Arc::new(SharedState {
+            state: Mutex::new(RenderState::Paused),
+            changed: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let handle = thread::Builder::new()
+            .name("decal-render".to_owned())
+            .spawn(move || {
+                let shared = thread_shared;
+                let mut buf = vec![T::EQUILIBRIUM; buf_len];
+                let mut deadline = None;
+                loop {
+                    {
+                        let mut state = shared.state.lock().expect("lock poisoned");
+                        while *state == RenderState::Paused {
+                            deadline = None;
+                            state = shared.changed.wait(state).expect("lock poisoned");
+                        }
+                        if *state == RenderState::Stopped {
+                            break;
+                        }
+                    }
+
+                    let started = clock.as_ref().map(|c| c.now());
+                    let started_real = Instant::now();
+                    data_callback(&mut buf);
+                    if let Err(e) = sink.write(&buf) {
+                        error_callback(e);
+                        break;
+                    }
+
+                    if let (Some(clock), Some(started)) = (&clock, started) {
+                        // Falling behind because the clock jumped forward is fine since the
+                        // missed periods are caught up on, but a slow callback would've
+                        // starved a real device. The cost is measured in real time so changes
+                        // to the clock during the callback don't count against it.
+                        let slow = clock
+                            .real_duration(period)
+                            .is_some_and(|budget| started_real.elapsed() > budget);
+                        let next = if slow {
+                            error_callback(StreamError::BufferUnderrun);
+                            clock.now()
+                        } else {
+                            deadline.unwrap_or(started) + period
+                        };
+                        deadline = Some(next);
+                    }
+                    position.fetch_add(frames as u64, Ordering::SeqCst);
+                    if let (Some(clock), Some(deadline)) = (&clock, deadline) {
+                        shared.wait_until(clock, deadline);
+                    }
+                }
+                if let Err(e) = sink.finish() {
+                    error_callback(e);
+                }
+            })
+            .map_err(|e| BuildStreamError::Unknown(e.to_string()))?;
+
+        Ok(Self {
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.shared.set(RenderState::Stopped);
+            handle
+                .join()
+                .tap_err(|e| warn!("Render thread panicked: {e:?}"))
+                .ok();
+        }
+    }
+}
+
+impl Stream for RenderStream {
+    fn play(&mut self) -> Result<(), PlayStreamError> {
+        if self.handle.is_none() {
+            return Err(PlayStreamError::DeviceNotAvailable);
+        }
+        self.shared.set(RenderState::Playing);
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), PlayStreamError> {
+        self.shared.set(RenderState::Paused);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), PlayStreamError> {
+        self.shutdown();
+        Ok(())
+    }
+}
+
+impl Drop for RenderStream {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
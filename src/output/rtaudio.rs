@@ -5,10 +5,10 @@ use rtaudio::NativeFormats;
 use crate::{
     ChannelCount, SampleRate,
     output::{
-        BuildStreamError, DecalSample, DefaultStreamConfigError, Device, DeviceNameError,
-        DevicesError, Host, PlayStreamError, SampleFormat, Stream, StreamConfig, StreamError,
-        SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
-        SupportedStreamConfigsError,
+        BackendSpecificError, BuildStreamError, DecalSample, DefaultStreamConfigError, Device,
+        DeviceId, DeviceIdError, DeviceNameError, DevicesError, Host, HostUnavailableError,
+        PlayStreamError, SampleFormat, Stream, StreamConfig, StreamError, SupportedBufferSize,
+        SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
     },
 };
 
@@ -21,49 +21,134 @@ unsafe impl Sync for RtAudioHost {}
 
 pub struct RtAudioStream(Option<rtaudio::StreamHandle>);
 
-pub struct RtAudioDevice(rtaudio::DeviceInfo);
+pub struct RtAudioDevice {
+    info: rtaudio::DeviceInfo,
+    // Streams have to be opened with the API the device was listed by
+    api: rtaudio::Api,
+}
 
-impl Device for RtAudioDevice {
-    type SupportedOutputConfigs = Box<dyn Iterator<Item = SupportedStreamConfigRange>>;
+fn rtaudio_sample_format<T: DecalSample>() -> Result<rtaudio::SampleFormat, BuildStreamError> {
+    Ok(match <T as DecalSample>::FORMAT {
+        SampleFormat::I8 => rtaudio::SampleFormat::SInt8,
+        SampleFormat::I16 => rtaudio::SampleFormat::SInt16,
+        SampleFormat::I32 => rtaudio::SampleFormat::SInt32,
+        SampleFormat::F32 => rtaudio::SampleFormat::Float32,
+        SampleFormat::F64 => rtaudio::SampleFormat::Float64,
+        _ => return Err(BuildStreamError::StreamConfigNotSupported),
+    })
+}
 
-    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+fn cast_input<S: DecalSample, T: DecalSample>(input: &[S]) -> Option<&[T]> {
+    // SAFETY: S and T are the same type if their formats match
+    (<S as DecalSample>::FORMAT == <T as DecalSample>::FORMAT)
+        .then(|| unsafe { std::slice::from_raw_parts(input.as_ptr().cast::<T>(), input.len()) })
+}
+
+impl RtAudioDevice {
+    fn open_host(&self) -> Result<rtaudio::Host, BuildStreamError> {
+        rtaudio::Host::new(self.api)
+            .map_err(|e| BuildStreamError::BackendSpecific(BackendSpecificError(e.to_string())))
+    }
+
+    fn default_config(
+        &self,
+        channels: u32,
+    ) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        if channels == 0 {
+            return Err(DefaultStreamConfigError::StreamTypeNotSupported);
+        }
         Ok(SupportedStreamConfig {
-            channels: ChannelCount(self.0.output_channels as u16),
+            channels: ChannelCount(channels as u16),
             buffer_size: SupportedBufferSize::Unknown,
             sample_format: SampleFormat::F32,
-            sample_rate: SampleRate(self.0.preferred_sample_rate),
+            sample_rate: SampleRate(self.info.preferred_sample_rate),
         })
     }
 
-    fn name(&self) -> Result<String, DeviceNameError> {
-        Ok(self.0.name().to_string())
-    }
-
-    fn supported_output_configs(
+    fn config_ranges(
         &self,
-    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        channels: u32,
+    ) -> Result<Box<dyn Iterator<Item = SupportedStreamConfigRange>>, SupportedStreamConfigsError>
+    {
+        if channels == 0 {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        let (Some(min_sample_rate), Some(max_sample_rate)) = (
+            self.info.sample_rates.iter().min(),
+            self.info.sample_rates.iter().max(),
+        ) else {
+            return Err(SupportedStreamConfigsError::BackendSpecific(
+                BackendSpecificError("Device does not report any sample rates".to_string()),
+            ));
+        };
         let formats: Vec<_> = self
             .0
             .native_formats
             .iter()
-            .map(|f| SupportedStreamConfigRange {
-                channels: ChannelCount(self.0.output_channels as u16),
-                buffer_size: SupportedBufferSize::Unknown,
-                min_sample_rate: SampleRate(*self.0.sample_rates.iter().min().unwrap()),
-                max_sample_rate: SampleRate(*self.0.sample_rates.iter().max().unwrap()),
-                sample_format: match f {
-                    NativeFormats::SINT8 => SampleFormat::I8,
-                    NativeFormats::SINT16 => SampleFormat::I16,
-                    NativeFormats::SINT24 => SampleFormat::I24,
-                    NativeFormats::SINT32 => SampleFormat::I32,
-                    NativeFormats::FLOAT32 => SampleFormat::F32,
-                    NativeFormats::FLOAT64 => SampleFormat::F64,
-                    _ => unreachable!(),
-                },
+            .filter_map(|f| {
+                Some(SupportedStreamConfigRange {
+                    channels: ChannelCount(channels as u16),
+                    buffer_size: SupportedBufferSize::Unknown,
+                    min_sample_rate: SampleRate(*min_sample_rate),
+                    max_sample_rate: SampleRate(*max_sample_rate),
+                    sample_format: match f {
+                        NativeFormats::SINT8 => SampleFormat::I8,
+                        NativeFormats::SINT16 => SampleFormat::I16,
+                        NativeFormats::SINT24 => SampleFormat::I24,
+                        NativeFormats::SINT32 => SampleFormat::I32,
+                        NativeFormats::FLOAT32 => SampleFormat::F32,
+                        NativeFormats::FLOAT64 => SampleFormat::F64,
+                        _ => return None,
+                    },
+                })
             })
             .collect();
         Ok(Box::new(formats.into_iter()))
     }
+}
+
+impl Device for RtAudioDevice {
+    type SupportedOutputConfigs = Box<dyn Iterator<Item = SupportedStreamConfigRange>>;
+
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        self.default_config(self.info.output_channels)
+    }
+
+    fn default_input_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        self.default_config(self.info.input_channels)
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok(self.info.name().to_string())
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(DeviceId(self.info.id.0.to_string()))
+    }
+
+    fn supported_sample_rates(&self) -> Result<Vec<SampleRate>, SupportedStreamConfigsError> {
+        let mut sample_rates: Vec<_> = self
+            .info
+            .sample_rates
+            .iter()
+            .map(|r| SampleRate(*r))
+            .collect();
+        sample_rates.sort();
+        sample_rates.dedup();
+        Ok(sample_rates)
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        self.config_ranges(self.info.output_channels)
+    }
+
+    fn supported_input_configs(
+        &self,
+    ) -> Result<Vec<SupportedStreamConfigRange>, SupportedStreamConfigsError> {
+        Ok(self.config_ranges(self.info.input_channels)?.collect())
+    }
 
     fn build_output_stream<T, D, E>(
         &mut self,
@@ -76,25 +161,21 @@ impl Device for RtAudioDevice {
         D: FnMut(&mut [T]) + Send + Sync + 'static,
         E: FnMut(StreamError) + Clone + Send + Sync + 'static,
     {
-        let mut stream = rtaudio::Host::default()
+        let mut stream = self
+            .open_host()?
             .open_stream(&rtaudio::StreamConfig {
                 output_device: Some(rtaudio::DeviceParams {
-                    device_id: Some(self.0.id.clone()),
+                    device_id: Some(self.info.id.clone()),
                     num_channels: Some(config.channels.0 as u32),
                     ..Default::default()
                 }),
                 sample_rate: Some(config.sample_rate.0),
-                sample_format: match <T as DecalSample>::FORMAT {
-                    SampleFormat::I8 => rtaudio::SampleFormat::SInt8,
-                    SampleFormat::I16 => rtaudio::SampleFormat::SInt16,
-                    SampleFormat::I32 => rtaudio::SampleFormat::SInt32,
-                    SampleFormat::F32 => rtaudio::SampleFormat::Float32,
-                    SampleFormat::F64 => rtaudio::SampleFormat::Float64,
-                    _ => unreachable!(),
-                },
+                sample_format: rtaudio_sample_format::<T>()?,
                 ..Default::default()
             })
-            .unwrap();
+            .map_err(|(_, e)| {
+                BuildStreamError::BackendSpecific(BackendSpecificError(e.to_string()))
+            })?;
 
         stream
             .start(
@@ -159,7 +240,65 @@ impl Device for RtAudioDevice {
                     ));
                 },
             )
-            .unwrap();
+            .map_err(|e| BuildStreamError::BackendSpecific(BackendSpecificError(e.to_string())))?;
+
+        Ok(Box::new(RtAudioStream(Some(stream))))
+    }
+
+    fn build_input_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        mut error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&[T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        let mut stream = self
+            .open_host()?
+            .open_stream(&rtaudio::StreamConfig {
+                input_device: Some(rtaudio::DeviceParams {
+                    device_id: Some(self.info.id.clone()),
+                    num_channels: Some(config.channels.0 as u32),
+                    ..Default::default()
+                }),
+                sample_rate: Some(config.sample_rate.0),
+                sample_format: rtaudio_sample_format::<T>()?,
+                ..Default::default()
+            })
+            .map_err(|(_, e)| {
+                BuildStreamError::BackendSpecific(BackendSpecificError(e.to_string()))
+            })?;
+
+        stream
+            .start(
+                move |buffers: rtaudio::Buffers<'_>,
+                      _info: &rtaudio::StreamInfo,
+                      status: rtaudio::StreamStatus| {
+                    if status.intersects(rtaudio::StreamStatus::INPUT_OVERFLOW) {
+                        error_callback(StreamError::InputOverflow);
+                    }
+
+                    let input = match buffers {
+                        rtaudio::Buffers::SInt8 { input, .. } => cast_input::<i8, T>(input),
+                        rtaudio::Buffers::SInt16 { input, .. } => cast_input::<i16, T>(input),
+                        rtaudio::Buffers::SInt32 { input, .. } => cast_input::<i32, T>(input),
+                        rtaudio::Buffers::Float32 { input, .. } => cast_input::<f32, T>(input),
+                        rtaudio::Buffers::Float64 { input, .. } => cast_input::<f64, T>(input),
+                        // unsupported
+                        rtaudio::Buffers::SInt24 { .. } => None,
+                    };
+                    match input {
+                        Some(input) => data_callback(input),
+                        None => error_callback(StreamError::InvalidConfiguration(
+                            "Sample type does not match input buffer".to_string(),
+                        )),
+                    }
+                },
+            )
+            .map_err(|e| BuildStreamError::BackendSpecific(BackendSpecificError(e.to_string())))?;
 
         Ok(Box::new(RtAudioStream(Some(stream))))
     }
@@ -190,19 +329,30 @@ impl Drop for RtAudioStream {
     }
 }
 
+impl RtAudioHost {
+    fn device(&self, info: &rtaudio::DeviceInfo) -> RtAudioDevice {
+        RtAudioDevice {
+            info: info.clone(),
+            api: self.0.api(),
+        }
+    }
+}
+
 impl Host for RtAudioHost {
     type Device = RtAudioDevice;
     type Id = rtaudio::Api;
     type Devices = Box<dyn Iterator<Item = RtAudioDevice>>;
 
-    fn from_id(id: Self::Id) -> Result<Self, super::HostUnavailableError> {
-        Ok(RtAudioHost(rtaudio::Host::new(id).unwrap()))
+    fn from_id(id: Self::Id) -> Result<Self, HostUnavailableError> {
+        rtaudio::Host::new(id)
+            .map(RtAudioHost)
+            .map_err(|e| HostUnavailableError::BackendSpecific(BackendSpecificError(e.to_string())))
     }
 
     fn default_output_device(&self) -> Option<Self::Device> {
         self.0
             .default_output_device_index()
-            .map(|i| RtAudioDevice(self.0.devices()[i].clone()))
+            .map(|i| self.device(&self.0.devices()[i]))
     }
 
     fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
@@ -211,12 +361,33 @@ impl Host for RtAudioHost {
             .devices()
             .iter()
             .filter(|d| d.output_channels > 0)
-            .map(|d| RtAudioDevice(d.clone()))
+            .map(|d| self.device(d))
             .collect();
         Ok(Box::new(devices.into_iter()))
     }
 
+    fn default_input_device(&self) -> Option<Self::Device> {
+        self.0
+            .default_input_device_index()
+            .map(|i| self.device(&self.0.devices()[i]))
+    }
+
+    fn input_devices(&self) -> Result<Vec<Self::Device>, DevicesError> {
+        Ok(self
+            .0
+            .devices()
+            .iter()
+            .filter(|d| d.input_channels > 0)
+            .map(|d| self.device(d))
+            .collect())
+    }
+
     fn id(&self) -> Self::Id {
         self.0.api()
     }
+
+    // Streams are opened on a device index, so they never follow the default
+    fn needs_default_device_restart(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,438 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tap::TapFallible;
+use tracing::{info, warn};
+
+use super::{
+    BackendSpecificError, BuildStreamError, DecalSample, DefaultStreamConfigError, Device,
+    DeviceId, DeviceIdError, DeviceNameError, DevicesError, Host, HostUnavailableError, Pacing,
+    RenderSink, RenderStream, SampleFormat, Stream, StreamConfig, StreamError,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
+    virtual_config_ranges, virtual_default_config,
+};
+use crate::encoder::{Endianness, PcmFormat};
+use crate::{ChannelCount, SampleRate};
+
+const HEADER_MAGIC: [u8; 4] = *b"DCAL";
+const HEADER_VERSION: u8 = 1;
+pub const TCP_STREAM_HEADER_LEN: usize = 16;
+
+/// Describes the stream. Sent to each client when it connects, and again when a new stream with a
+/// different format starts, unless [`TcpOutputSettings::send_header`] is disabled.
+///
+/// The layout is the magic bytes `DCAL`, the version, the sample format, the endianness
+/// (`0` for little and `1` for big), the bits per sample, the channel count as a little-endian
+/// `u16`, two reserved bytes and the sample rate as a little-endian `u32`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TcpStreamHeader {
+    pub sample_rate: SampleRate,
+    pub channels: ChannelCount,
+    pub format: PcmFormat,
+}
+
+fn format_code(sample_format: SampleFormat) -> u8 {
+    match sample_format {
+        SampleFormat::I8 => 0,
+        SampleFormat::I16 => 1,
+        SampleFormat::I24 => 2,
+        SampleFormat::I32 => 3,
+        SampleFormat::I64 => 4,
+        SampleFormat::U8 => 5,
+        SampleFormat::U16 => 6,
+        SampleFormat::U24 => 7,
+        SampleFormat::U32 => 8,
+        SampleFormat::U64 => 9,
+        SampleFormat::F32 => 10,
+        SampleFormat::F64 => 11,
+    }
+}
+
+fn format_from_code(code: u8) -> Option<SampleFormat> {
+    Some(match code {
+        0 => SampleFormat::I8,
+        1 => SampleFormat::I16,
+        2 => SampleFormat::I24,
+        3 => SampleFormat::I32,
+        4 => SampleFormat::I64,
+        5 => SampleFormat::U8,
+        6 => SampleFormat::U16,
+        7 => SampleFormat::U24,
+        8 => SampleFormat::U32,
+        9 => SampleFormat::U64,
+        10 => SampleFormat::F32,
+        11 => SampleFormat::F64,
+        _ => return None,
+    })
+}
+
+impl TcpStreamHeader {
+    pub fn encode(&self) -> [u8; TCP_STREAM_HEADER_LEN] {
+        let mut header = [0; TCP_STREAM_HEADER_LEN];
+        header[0..4].copy_from_slice(&HEADER_MAGIC);
+        header[4] = HEADER_VERSION;
+        header[5] = format_code(self.format.sample_format);
+        header[6] = match self.format.endianness {
+            Endianness::Little => 0,
+            Endianness::Big => 1,
+        };
+        header[7] = self.format.sample_format.bits_per_sample() as u8;
+        header[8..10].copy_from_slice(&self.channels.0.to_le_bytes());
+        header[12..16].copy_from_slice(&self.sample_rate.0.to_le_bytes());
+        header
+    }
+
+    /// Returns `None` if the header is invalid or from an unsupported version.
+    pub fn decode(header: &[u8; TCP_STREAM_HEADER_LEN]) -> Option<Self> {
+        if header[0..4] != HEADER_MAGIC || header[4] != HEADER_VERSION {
+            return None;
+        }
+        let endianness = match header[6] {
+            0 => Endianness::Little,
+            1 => Endianness::Big,
+            _ => return None,
+        };
+        Some(Self {
+            sample_rate: SampleRate(u32::from_le_bytes([
+                header[12], header[13], header[14], header[15],
+            ])),
+            channels: ChannelCount(u16::from_le_bytes([header[8], header[9]])),
+            format: PcmFormat::new(format_from_code(header[5])?, endianness),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TcpOutputSettings {
+    /// Defaults to port 4953 on localhost. Use an unspecified address to accept clients from
+    /// other machines.
+    pub address: SocketAddr,
+    /// The format samples are sent in. Defaults to the stream's sample format.
+    pub sample_format: Option<SampleFormat>,
+    pub endianness: Endianness,
+    /// Send a [`TcpStreamHeader`] when a client connects. Disable this for clients that expect
+    /// raw PCM.
+    pub send_header: bool,
+    /// Clients that fall further behind than this are disconnected.
+    pub max_client_latency: Duration,
+    pub pacing: Pacing,
+    /// The config reported as the device's default.
+    pub default_config: SupportedStreamConfig,
+}
+
+impl Default for TcpOutputSettings {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, 4953)),
+            sample_format: None,
+            endianness: Endianness::Little,
+            send_header: true,
+            max_client_latency: Duration::from_millis(500),
+            pacing: Pacing::RealTime,
+            default_config: virtual_default_config(),
+        }
+    }
+}
+
+struct TcpClient {
+    stream: TcpStream,
+    address: SocketAddr,
+    pending: Vec<u8>,
+}
+
+impl TcpClient {
+    /// Queues the data and writes as much as the socket accepts without blocking.
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(data);
+        let mut written = 0;
+        while written < self.pending.len() {
+            match self.stream.write(&self.pending[written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.pending.drain(..written);
+        Ok(())
+    }
+}
+
+struct TcpSink {
+    server: Arc<TcpServer>,
+    header: TcpStreamHeader,
+    send_header: bool,
+    max_pending: usize,
+    buf: Vec<u8>,
+}
+
+impl<T: DecalSample> RenderSink<T> for TcpSink {
+    fn write(&mut self, samples: &[T]) -> Result<(), StreamError> {
+        let mut state = self.server.state.lock().expect("lock poisoned");
+        let Some(listener) = &state.listener else {
+            return Ok(());
+        };
+        let new_clients = accept_clients(listener);
+        let ServerState {
+            clients, header, ..
+        } = &mut *state;
+
+        // Clients that stayed connected from the previous stream need to know the format changed
+        if *header != Some(self.header) {
+            if self.send_header && header.is_some() {
+                let encoded = self.header.encode();
+                clients.retain_mut(|client| {
+                    client
+                        .send(&encoded)
+                        .tap_err(|e| info!("TCP client disconnected: {} {e:?}", client.address))
+                        .is_ok()
+                });
+            }
+            *header = Some(self.header);
+        }
+        for mut client in new_clients {
+            match self.init_client(&mut client) {
+                Ok(()) => clients.push(client),
+                Err(e) => warn!("Error setting up TCP client {}: {e:?}", client.address),
+            }
+        }
+        if clients.is_empty() {
+            return Ok(());
+        }
+
+        self.buf.clear();
+        self.header.format.encode(samples, &mut self.buf);
+        let max_pending = self.max_pending;
+        let buf = &self.buf;
+        clients.retain_mut(|client| match client.send(buf) {
+            Ok(()) if client.pending.len() <= max_pending => true,
+            Ok(()) => {
+                warn!("Dropping slow TCP client: {}", client.address);
+                false
+            }
+            Err(e) => {
+                info!("TCP client disconnected: {} {e:?}", client.address);
+                false
+            }
+        });
+        Ok(())
+    }
+}
+
+impl TcpSink {
+    fn init_client(&self, client: &mut TcpClient) -> io::Result<()> {
+        client.stream.set_nonblocking(true)?;
+        client.stream.set_nodelay(true)?;
+        if self.send_header {
+            client.send(&self.header.encode())?;
+        }
+        Ok(())
+    }
+}
+
+fn accept_clients(listener: &TcpListener) -> Vec<TcpClient> {
+    let mut clients = Vec::new();
+    loop {
+        match listener.accept() {
+            Ok((stream, address)) => {
+                info!("TCP client connected: {address}");
+                clients.push(TcpClient {
+                    stream,
+                    address,
+                    pending: Vec::new(),
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return clients,
+            Err(e) => {
+                warn!("Error accepting TCP client: {e:?}");
+                return clients;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ServerState {
+    listener: Option<TcpListener>,
+    // Kept between streams so clients stay connected when the output is rebuilt
+    clients: Vec<TcpClient>,
+    // The format the clients were last sent
+    header: Option<TcpStreamHeader>,
+}
+
+struct TcpServer {
+    settings: TcpOutputSettings,
+    state: Mutex<ServerState>,
+}
+
+impl fmt::Debug for TcpServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpServer")
+            .field("settings", &self.settings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TcpServer {
+    /// Binds the listener the first time it's needed. It's kept open for the lifetime of the
+    /// host so clients can reconnect between streams.
+    fn bind(&self) -> io::Result<SocketAddr> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if state.listener.is_none() {
+            let new_listener = TcpListener::bind(self.settings.address)?;
+            new_listener.set_nonblocking(true)?;
+            info!(
+                "Serving PCM on {:?}",
+                new_listener
+                    .local_addr()
+                    .tap_err(|e| warn!("Error getting local address: {e:?}"))
+            );
+            state.listener = Some(new_listener);
+        }
+        state
+            .listener
+            .as_ref()
+            .expect("listener bound")
+            .local_addr()
+    }
+}
+
+/// A device that streams interleaved PCM to every connected TCP client.
+#[derive(Clone, Debug)]
+pub struct TcpDevice {
+    server: Arc<TcpServer>,
+}
+
+impl TcpDevice {
+    /// The address the server is listening on. Binds the listener if the stream hasn't been
+    /// started yet.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.bind()
+    }
+}
+
+impl Device for TcpDevice {
+    type SupportedOutputConfigs = std::vec::IntoIter<SupportedStreamConfigRange>;
+    fn default_output_config(&self) -> Result<SupportedStreamConfig, DefaultStreamConfigError> {
+        Ok(self.server.settings.default_config.clone())
+    }
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok(format!("TCP Output ({})", self.server.settings.address))
+    }
+
+    fn id(&self) -> Result<DeviceId, DeviceIdError> {
+        Ok(DeviceId(format!("tcp:{}", self.server.settings.address)))
+    }
+
+    fn supported_output_configs(
+        &self,
+    ) -> Result<Self::SupportedOutputConfigs, SupportedStreamConfigsError> {
+        Ok(
+            virtual_config_ranges(self.server.settings.default_config.buffer_size.clone())
+                .into_iter(),
+        )
+    }
+
+    fn build_output_stream<T, D, E>(
+        &mut self,
+        config: &StreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Box<dyn Stream>, BuildStreamError>
+    where
+        T: DecalSample,
+        D: FnMut(&mut [T]) + Send + Sync + 'static,
+        E: FnMut(StreamError) + Clone + Send + Sync + 'static,
+    {
+        let settings = &self.server.settings;
+        self.server
+            .bind()
+            .map_err(|e| BuildStreamError::BackendSpecific(BackendSpecificError(e.to_string())))?;
+        let format = PcmFormat::new(
+            settings.sample_format.unwrap_or(T::FORMAT),
+            settings.endianness,
+        );
+        let bytes_per_second = config.sample_rate.0 as f64
+            * config.channels.0 as f64
+            * format.bytes_per_sample() as f64;
+
+        Ok(Box::new(RenderStream::new(
+            config,
+            settings.pacing.clone(),
+            Default::default(),
+            data_callback,
+            error_callback,
+            TcpSink {
+                server: self.server.clone(),
+                header: TcpStreamHeader {
+                    sample_rate: config.sample_rate,
+                    channels: config.channels,
+                    format,
+                },
+                send_header: settings.send_header,
+                max_pending: (settings.max_client_latency.as_secs_f64() * bytes_per_second)
+                    as usize,
+                buf: Vec::new(),
+            },
+        )?))
+    }
+}
+
+/// A host whose only device serves the stream to TCP clients, such as a Snapcast TCP source.
+/// Audio is rendered and discarded while no clients are connected.
+#[derive(Clone, Debug)]
+pub struct TcpHost {
+    device: TcpDevice,
+}
+
+impl Default for TcpHost {
+    fn default() -> Self {
+        Self::new(TcpOutputSettings::default())
+    }
+}
+
+impl TcpHost {
+    pub fn new(settings: TcpOutputSettings) -> Self {
+        Self {
+            device: TcpDevice {
+                server: Arc::new(TcpServer {
+                    settings,
+                    state: Mutex::default(),
+                }),
+            },
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.device.local_addr()
+    }
+}
+
+impl Host for TcpHost {
+    type Device = TcpDevice;
+    type Id = ();
+    type Devices = std::option::IntoIter<TcpDevice>;
+
+    fn from_id(_id: Self::Id) -> Result<Self, HostUnavailableError> {
+        Ok(Self::default())
+    }
+
+    fn default_output_device(&self) -> Option<Self::Device> {
+        Some(self.device.clone())
+    }
+
+    fn output_devices(&self) -> Result<Self::Devices, DevicesError> {
+        Ok(Some(self.device.clone()).into_iter())
+    }
+
+    fn id(&self) -> Self::Id {}
+
+    fn devices_can_change(&self) -> bool {
+        false
+    }
+}
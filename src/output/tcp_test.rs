@@ -0,0 +1,159 @@
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use super::{
+    BufferSize, Device, Host, Pacing, SampleFormat, StreamConfig, TCP_STREAM_HEADER_LEN, TcpHost,
+    TcpOutputSettings, TcpStreamHeader,
+};
+use crate::encoder::{Endianness, PcmFormat};
+use crate::{ChannelCount, SampleRate};
+
+const CONFIG: StreamConfig = StreamConfig {
+    channels: ChannelCount(2),
+    sample_rate: SampleRate(48000),
+    buffer_size: BufferSize::Fixed(64),
+};
+
+fn loopback_settings(pacing: Pacing) -> TcpOutputSettings {
+    TcpOutputSettings {
+        address: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        sample_format: Some(SampleFormat::I16),
+        max_client_latency: Duration::from_millis(10),
+        pacing,
+        ..Default::default()
+    }
+}
+
+fn connect(host: &TcpHost) -> TcpStream {
+    let client = TcpStream::connect(host.local_addr().unwrap()).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    client
+}
+
+fn read_header(client: &mut TcpStream) -> Option<TcpStreamHeader> {
+    let mut header = [0; TCP_STREAM_HEADER_LEN];
+    client.read_exact(&mut header).unwrap();
+    TcpStreamHeader::decode(&header)
+}
+
+#[test]
+fn tcp_header_round_trip() {
+    let header = TcpStreamHeader {
+        sample_rate: SampleRate(44100),
+        channels: ChannelCount(6),
+        format: PcmFormat::new(SampleFormat::I24, Endianness::Big),
+    };
+    assert_eq!(Some(header), TcpStreamHeader::decode(&header.encode()));
+    assert_eq!(None, TcpStreamHeader::decode(&[0; TCP_STREAM_HEADER_LEN]));
+}
+
+#[test]
+fn tcp_host_streams_to_clients() {
+    let host = TcpHost::new(loopback_settings(Pacing::RealTime));
+    let mut device = host.default_output_device().unwrap();
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(&CONFIG, |data: &mut [f32]| data.fill(0.5), |_| {})
+        .unwrap();
+    stream.play().unwrap();
+
+    for mut client in [connect(&host), connect(&host)] {
+        assert_eq!(
+            Some(TcpStreamHeader {
+                sample_rate: SampleRate(48000),
+                channels: ChannelCount(2),
+                format: PcmFormat::new(SampleFormat::I16, Endianness::Little),
+            }),
+            read_header(&mut client)
+        );
+
+        let mut data = [0; 1024];
+        client.read_exact(&mut data).unwrap();
+        assert!(
+            data.chunks(2)
+                .all(|s| i16::from_le_bytes([s[0], s[1]]) == 16384)
+        );
+    }
+    stream.stop().unwrap();
+}
+
+#[test]
+fn tcp_host_drops_slow_clients() {
+    let host = TcpHost::new(loopback_settings(Pacing::Unpaced));
+    let mut client = connect(&host);
+    let mut device = host.default_output_device().unwrap();
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(&CONFIG, |_: &mut [f32]| {}, |_| {})
+        .unwrap();
+    stream.play().unwrap();
+
+    // The client doesn't read anything so the server closes the connection once the socket
+    // buffers fill up
+    std::thread::sleep(Duration::from_millis(500));
+    let mut buf = vec![0; 65536];
+    loop {
+        match client.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => break,
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+    stream.stop().unwrap();
+}
+
+#[test]
+fn tcp_clients_stay_connected_between_streams() {
+    let host = TcpHost::new(loopback_settings(Pacing::RealTime));
+    let mut device = host.default_output_device().unwrap();
+    let mut client = connect(&host);
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(&CONFIG, |data: &mut [f32]| data.fill(0.5), |_| {})
+        .unwrap();
+    stream.play().unwrap();
+    assert_eq!(
+        SampleRate(48000),
+        read_header(&mut client).unwrap().sample_rate
+    );
+    let mut data = [0; 1024];
+    client.read_exact(&mut data).unwrap();
+    drop(stream);
+
+    // The new stream tells the same client about its format
+    let mut stream = device
+        .build_output_stream::<f32, _, _>(
+            &StreamConfig {
+                sample_rate: SampleRate(44100),
+                ..CONFIG
+            },
+            |data: &mut [f32]| data.fill(0.25),
+            |_| {},
+        )
+        .unwrap();
+    stream.play().unwrap();
+    let mut sample = [0; 2];
+    loop {
+        client.read_exact(&mut sample).unwrap();
+        if i16::from_le_bytes(sample) != 16384 {
+            break;
+        }
+    }
+    // The first two bytes of the magic were just read
+    let mut rest = [0; TCP_STREAM_HEADER_LEN - 2];
+    client.read_exact(&mut rest).unwrap();
+    let mut header = [0; TCP_STREAM_HEADER_LEN];
+    header[..2].copy_from_slice(&sample);
+    header[2..].copy_from_slice(&rest);
+    assert_eq!(
+        SampleRate(44100),
+        TcpStreamHeader::decode(&header).unwrap().sample_rate
+    );
+    client.read_exact(&mut data).unwrap();
+    assert!(
+        data.chunks(2)
+            .all(|s| i16::from_le_bytes([s[0], s[1]]) == 8192)
+    );
+    stream.stop().unwrap();
+}
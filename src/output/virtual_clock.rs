@@ -0,0 +1,106 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct ClockState {
+    // Virtual time at the anchor
+    elapsed: Duration,
+    anchor: Instant,
+    speed: f64,
+    paused: bool,
+}
+
+impl ClockState {
+    fn now(&self) -> Duration {
+        if self.paused {
+            self.elapsed
+        } else {
+            self.elapsed + self.anchor.elapsed().mul_f64(self.speed)
+        }
+    }
+
+    fn reanchor(&mut self) {
+        self.elapsed = self.now();
+        self.anchor = Instant::now();
+    }
+}
+
+/// Paces software devices. The clock follows real time by default, but it can be sped up,
+/// slowed down, paused or stepped manually so tests don't have to wait on real time.
+///
+/// Clones share the same clock.
+#[derive(Clone, Debug)]
+pub struct VirtualClock {
+    state: Arc<Mutex<ClockState>>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ClockState {
+                elapsed: Duration::ZERO,
+                anchor: Instant::now(),
+                speed: 1.0,
+                paused: false,
+            })),
+        }
+    }
+
+    /// Virtual time elapsed since the clock was created.
+    pub fn now(&self) -> Duration {
+        self.state.lock().expect("lock poisoned").now()
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.state.lock().expect("lock poisoned").speed
+    }
+
+    /// Sets how fast virtual time passes relative to real time, e.g. `2.0` runs twice as fast.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `speed` isn't a positive, finite number.
+    pub fn set_speed(&self, speed: f64) {
+        assert!(
+            speed.is_finite() && speed > 0.0,
+            "clock speed must be positive"
+        );
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.reanchor();
+        state.speed = speed;
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.reanchor();
+        state.paused = true;
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.reanchor();
+        state.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().expect("lock poisoned").paused
+    }
+
+    /// Moves the clock forward. This also works while the clock is paused.
+    pub fn advance(&self, duration: Duration) {
+        self.state.lock().expect("lock poisoned").elapsed += duration;
+    }
+
+    /// How long it will take in real time for the given amount of virtual time to pass, or
+    /// `None` if the clock is paused.
+    pub(crate) fn real_duration(&self, duration: Duration) -> Option<Duration> {
+        let state = self.state.lock().expect("lock poisoned");
+        (!state.paused).then(|| duration.div_f64(state.speed))
+    }
+}
@@ -9,8 +9,8 @@ use crate::{
 #[test]
 fn test_write_output() {
     let output_builder = OutputBuilder::new(
-        MockHost {
-            default_device: MockDevice::new(
+        MockHost::new(
+            MockDevice::new(
                 "test-device".to_owned(),
                 SupportedStreamConfig {
                     channels: ChannelCount(2),
@@ -22,8 +22,8 @@ fn test_write_output() {
                 SampleRate(192000),
                 vec![],
             ),
-            additional_devices: vec![],
-        },
+            vec![],
+        ),
         Default::default(),
         move || {},
         |_| {},
@@ -0,0 +1,51 @@
+use dasp::Sample;
+
+/// Converts a linear amplitude to decibels relative to full scale.
+pub fn amplitude_to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.max(f32::MIN_POSITIVE).log10()
+}
+
+/// Converts decibels relative to full scale to a linear amplitude.
+pub fn db_to_amplitude(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// The level of a single channel over a block of samples, as linear amplitudes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelLevel {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+impl ChannelLevel {
+    pub fn peak_db(&self) -> f32 {
+        amplitude_to_db(self.peak)
+    }
+
+    pub fn rms_db(&self) -> f32 {
+        amplitude_to_db(self.rms)
+    }
+}
+
+/// Measures the level of each channel in a block of interleaved samples.
+pub fn measure_levels<S: Sample>(samples: &[S], levels: &mut [ChannelLevel]) {
+    let channels = levels.len();
+    if channels == 0 {
+        return;
+    }
+    let mut sums = vec![0.0f64; channels];
+    levels.fill(ChannelLevel::default());
+    for frame in samples.chunks_exact(channels) {
+        for ((sample, level), sum) in frame.iter().zip(levels.iter_mut()).zip(sums.iter_mut()) {
+            let sample = sample.to_float_sample().to_sample::<f32>();
+            level.peak = level.peak.max(sample.abs());
+            *sum += sample as f64 * sample as f64;
+        }
+    }
+    let frames = samples.len() / channels;
+    if frames > 0 {
+        for (level, sum) in levels.iter_mut().zip(sums) {
+            level.rms = (sum / frames as f64).sqrt() as f32;
+        }
+    }
+}
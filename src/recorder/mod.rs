@@ -0,0 +1,423 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use dasp::Sample;
+use thiserror::Error;
+use tracing::info;
+
+use crate::encoder::{
+    AiffWriter, Endianness, FlacWriter, PcmFormat, WavWriter, aiff_sample_format, flac_supports,
+    wav_sample_format,
+};
+use crate::output::{
+    AudioInput, AudioOutputError, DecalSample, Host, ReadBlockingError, SampleFormat,
+};
+use crate::{ChannelCount, SampleRate};
+
+mod level;
+pub use level::*;
+mod output_tap;
+pub use output_tap::*;
+
+#[cfg(test)]
+#[path = "./recorder_test.rs"]
+mod recorder_test;
+
+#[cfg(test)]
+#[path = "./output_tap_test.rs"]
+mod output_tap_test;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RecordingContainer {
+    #[default]
+    Wav,
+    Aiff,
+    Flac,
+}
+
+/// When to continue a recording in a new file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum FileSplit {
+    #[default]
+    Never,
+    /// Split once a file reaches this many bytes of sample data. FLAC files are split by their
+    /// uncompressed size, so they end up smaller than the limit.
+    Size(u64),
+    Duration(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub struct SilenceTrigger {
+    /// Peak level in dBFS below which the input counts as silence.
+    pub threshold_db: f32,
+    /// Wait for the input to rise above the threshold before writing anything.
+    pub start_on_sound: bool,
+    /// End the recording once the input has been silent for this long. If `start_on_sound` is
+    /// set, the recorder goes back to waiting and the next sound starts a new file.
+    pub stop_after: Option<Duration>,
+}
+
+impl Default for SilenceTrigger {
+    fn default() -> Self {
+        Self {
+            threshold_db: -50.0,
+            start_on_sound: true,
+            stop_after: Some(Duration::from_secs(2)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecorderSettings {
+    /// Where the first file is written. Later files get a numbered suffix, e.g. `memo-2.wav`.
+    pub path: PathBuf,
+    pub container: RecordingContainer,
+    /// The format samples are stored in, which also sets the bit depth.
+    pub sample_format: SampleFormat,
+    pub silence_trigger: Option<SilenceTrigger>,
+    pub split: FileSplit,
+    /// How much audio is read from the input each time the recorder is processed.
+    pub chunk_duration: Duration,
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("recording.wav"),
+            container: RecordingContainer::Wav,
+            sample_format: SampleFormat::I16,
+            silence_trigger: None,
+            split: FileSplit::Never,
+            chunk_duration: Duration::from_millis(20),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecorderState {
+    Stopped,
+    WaitingForSound,
+    Recording,
+    Paused,
+}
+
+#[derive(Debug, Error)]
+pub enum RecorderError {
+    #[error("{0:?} samples can't be stored in a {1:?} file")]
+    UnsupportedFormat(SampleFormat, RecordingContainer),
+    #[error("Error writing recording: {0}")]
+    IoError(#[from] io::Error),
+    #[error(transparent)]
+    AudioOutputError(#[from] AudioOutputError),
+    #[error(transparent)]
+    ReadBlockingError(#[from] ReadBlockingError),
+}
+
+pub(crate) enum RecordingWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Aiff(AiffWriter<BufWriter<File>>),
+    Flac(FlacWriter<BufWriter<File>>),
+}
+
+impl RecordingWriter {
+    pub(crate) fn create(
+        path: &Path,
+        container: RecordingContainer,
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        sample_format: SampleFormat,
+    ) -> Result<Self, RecorderError> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match container {
+            RecordingContainer::Wav => {
+                Self::Wav(WavWriter::new(file, channels, sample_rate, sample_format)?)
+            }
+            RecordingContainer::Aiff => {
+                Self::Aiff(AiffWriter::new(file, channels, sample_rate, sample_format)?)
+            }
+            RecordingContainer::Flac => {
+                Self::Flac(FlacWriter::new(file, channels, sample_rate, sample_format)?)
+            }
+        })
+    }
+
+    /// The format samples are stored in, which may differ from the requested format.
+    pub(crate) fn sample_format(&self) -> SampleFormat {
+        match self {
+            Self::Wav(writer) => writer.sample_format(),
+            Self::Aiff(writer) => writer.sample_format(),
+            Self::Flac(writer) => writer.sample_format(),
+        }
+    }
+
+    pub(crate) fn write_samples<S: Sample>(&mut self, samples: &[S]) -> io::Result<()> {
+        match self {
+            Self::Wav(writer) => writer.write_samples(samples),
+            Self::Aiff(writer) => writer.write_samples(samples),
+            Self::Flac(writer) => writer.write_samples(samples),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> io::Result<()> {
+        match self {
+            Self::Wav(writer) => writer.finalize().map(drop),
+            Self::Aiff(writer) => writer.finalize().map(drop),
+            Self::Flac(writer) => writer.finalize().map(drop),
+        }
+    }
+}
+
+pub(crate) fn check_format(
+    container: RecordingContainer,
+    sample_format: SampleFormat,
+) -> Result<(), RecorderError> {
+    if container == RecordingContainer::Flac && !flac_supports(sample_format) {
+        return Err(RecorderError::UnsupportedFormat(sample_format, container));
+    }
+    Ok(())
+}
+
+pub(crate) fn numbered_path(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_owned();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = format!("{stem}-{}", index + 1);
+    if let Some(extension) = path.extension() {
+        file_name = format!("{file_name}.{}", extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
+}
+
+/// Writes the audio captured by an [`AudioInput`] to WAV or FLAC files.
+///
+/// Like [`AudioManager`](crate::AudioManager), the recorder is driven by the caller. Each call
+/// to [`process`](Self::process) blocks until the next chunk of audio has been captured. The
+/// input keeps being read while the recorder is paused so the levels stay up to date.
+pub struct Recorder<T, H: Host> {
+    input: AudioInput<T, H>,
+    settings: RecorderSettings,
+    state: RecorderState,
+    resume_state: RecorderState,
+    writer: Option<RecordingWriter>,
+    files: Vec<PathBuf>,
+    file_frames: u64,
+    silent_frames: u64,
+    levels: Vec<ChannelLevel>,
+    buf: Vec<T>,
+}
+
+impl<T: DecalSample, H: Host> Recorder<T, H> {
+    pub fn new(input: AudioInput<T, H>, settings: RecorderSettings) -> Result<Self, RecorderError> {
+        check_format(settings.container, settings.sample_format)?;
+        let config = input.config();
+        let channels = config.channels.0 as usize;
+        let chunk_frames =
+            ((settings.chunk_duration.as_secs_f64() * config.sample_rate.0 as f64) as usize).max(1);
+
+        Ok(Self {
+            levels: vec![ChannelLevel::default(); channels],
+            buf: vec![T::EQUILIBRIUM; chunk_frames * channels],
+            input,
+            settings,
+            state: RecorderState::Stopped,
+            resume_state: RecorderState::Recording,
+            writer: None,
+            files: Vec::new(),
+            file_frames: 0,
+            silent_frames: 0,
+        })
+    }
+
+    pub fn state(&self) -> RecorderState {
+        self.state
+    }
+
+    pub fn settings(&self) -> &RecorderSettings {
+        &self.settings
+    }
+
+    pub fn input(&self) -> &AudioInput<T, H> {
+        &self.input
+    }
+
+    /// Levels of each channel in the most recently processed chunk.
+    pub fn levels(&self) -> &[ChannelLevel] {
+        &self.levels
+    }
+
+    /// Every file the recorder has created, in order.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Starts a new recording, or resumes a paused one.
+    pub fn start(&mut self) -> Result<(), RecorderError> {
+        match self.state {
+            RecorderState::Stopped => {
+                self.input.start()?;
+                self.silent_frames = 0;
+                self.state = if self.waits_for_sound() {
+                    RecorderState::WaitingForSound
+                } else {
+                    RecorderState::Recording
+                };
+            }
+            RecorderState::Paused => {
+                self.state = self.resume_state;
+            }
+            RecorderState::WaitingForSound | RecorderState::Recording => {}
+        }
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        if matches!(
+            self.state,
+            RecorderState::WaitingForSound | RecorderState::Recording
+        ) {
+            self.resume_state = self.state;
+            self.state = RecorderState::Paused;
+        }
+    }
+
+    /// Finishes the current file and stops capturing.
+    pub fn stop(&mut self) -> Result<(), RecorderError> {
+        self.state = RecorderState::Stopped;
+        self.finish_file()?;
+        self.input.stop()?;
+        Ok(())
+    }
+
+    /// Reads the next chunk of input and writes it to the current file. Returns the state after
+    /// the chunk was processed, which changes when the silence trigger starts or ends a
+    /// recording.
+    pub fn process(&mut self) -> Result<RecorderState, RecorderError> {
+        if self.state == RecorderState::Stopped {
+            return Ok(self.state);
+        }
+        self.input.read_blocking(&mut self.buf)?;
+        measure_levels(&self.buf, &mut self.levels);
+
+        let peak = self.levels.iter().map(|l| l.peak).fold(0.0, f32::max);
+        let is_silent = self
+            .settings
+            .silence_trigger
+            .as_ref()
+            .is_some_and(|trigger| peak < db_to_amplitude(trigger.threshold_db));
+        match self.state {
+            RecorderState::WaitingForSound if !is_silent => {
+                info!("Sound detected, starting recording");
+                self.state = RecorderState::Recording;
+            }
+            RecorderState::Recording => {}
+            _ => return Ok(self.state),
+        }
+
+        let buf = std::mem::take(&mut self.buf);
+        let written = self.write_chunk(&buf);
+        self.buf = buf;
+        written?;
+
+        if is_silent {
+            self.silent_frames += (self.buf.len() / self.levels.len().max(1)) as u64;
+        } else {
+            self.silent_frames = 0;
+        }
+        let stop_after = self
+            .settings
+            .silence_trigger
+            .as_ref()
+            .and_then(|trigger| trigger.stop_after);
+        if let Some(stop_after) = stop_after
+            && self.silent_frames >= self.duration_frames(stop_after)
+        {
+            info!("Silence detected, ending recording");
+            self.silent_frames = 0;
+            self.finish_file()?;
+            if self.waits_for_sound() {
+                self.state = RecorderState::WaitingForSound;
+            } else {
+                self.stop()?;
+            }
+        }
+        Ok(self.state)
+    }
+
+    fn waits_for_sound(&self) -> bool {
+        self.settings
+            .silence_trigger
+            .as_ref()
+            .is_some_and(|trigger| trigger.start_on_sound)
+    }
+
+    fn duration_frames(&self, duration: Duration) -> u64 {
+        ((duration.as_secs_f64() * self.input.config().sample_rate.0 as f64) as u64).max(1)
+    }
+
+    /// Maximum number of frames in each file.
+    fn split_frames(&self) -> Option<u64> {
+        match self.settings.split {
+            FileSplit::Never => None,
+            FileSplit::Size(bytes) => {
+                let sample_format = match self.settings.container {
+                    RecordingContainer::Aiff => aiff_sample_format(self.settings.sample_format),
+                    _ => wav_sample_format(self.settings.sample_format),
+                };
+                let format = PcmFormat::new(sample_format, Endianness::Little);
+                let frame_bytes = (format.bytes_per_sample() * self.levels.len()).max(1) as u64;
+                Some((bytes / frame_bytes).max(1))
+            }
+            FileSplit::Duration(duration) => Some(self.duration_frames(duration)),
+        }
+    }
+
+    fn write_chunk(&mut self, samples: &[T]) -> Result<(), RecorderError> {
+        let channels = self.levels.len().max(1);
+        let split_frames = self.split_frames();
+        let mut samples = samples;
+        while !samples.is_empty() {
+            if split_frames.is_some_and(|max| self.file_frames >= max) {
+                self.finish_file()?;
+            }
+            let frames = (samples.len() / channels) as u64;
+            let frames = split_frames.map_or(frames, |max| frames.min(max - self.file_frames));
+            let (chunk, rest) = samples.split_at(frames as usize * channels);
+            if self.writer.is_none() {
+                self.writer = Some(self.open_file()?);
+            }
+            self.writer
+                .as_mut()
+                .expect("file opened")
+                .write_samples(chunk)?;
+            self.file_frames += frames;
+            samples = rest;
+        }
+        Ok(())
+    }
+
+    fn open_file(&mut self) -> Result<RecordingWriter, RecorderError> {
+        let path = numbered_path(&self.settings.path, self.files.len());
+        info!("Recording to {path:?}");
+        let config = self.input.config();
+        let writer = RecordingWriter::create(
+            &path,
+            self.settings.container,
+            config.channels,
+            config.sample_rate,
+            self.settings.sample_format,
+        )?;
+        self.files.push(path);
+        Ok(writer)
+    }
+
+    fn finish_file(&mut self) -> Result<(), RecorderError> {
+        self.file_frames = 0;
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+}
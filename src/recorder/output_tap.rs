@@ -0,0 +1,273 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tap::TapFallible;
+use tracing::{error, info, warn};
+
+use super::{RecorderError, RecordingContainer, RecordingWriter, check_format, numbered_path};
+use crate::output::{DecalSample, SampleFormat};
+use crate::{ChannelCount, SampleRate};
+
+/// The layout of the samples passing through an [`OutputTap`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TapFormat {
+    pub channels: ChannelCount,
+    pub sample_rate: SampleRate,
+}
+
+#[derive(Clone, Debug)]
+pub struct TapFileSettings {
+    /// Where the first file is written. If the output format changes, the tap continues in a
+    /// new file with a numbered suffix, e.g. `capture-2.wav`.
+    pub path: PathBuf,
+    pub container: RecordingContainer,
+    pub sample_format: SampleFormat,
+}
+
+impl Default for TapFileSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("capture.wav"),
+            container: RecordingContainer::Wav,
+            sample_format: SampleFormat::I16,
+        }
+    }
+}
+
+pub type TapCallback<T> = Box<dyn FnMut(&[T], TapFormat) + Send>;
+
+/// Where the samples copied by an [`OutputTap`] are sent.
+pub enum TapSink<T> {
+    File(TapFileSettings),
+    Callback(TapCallback<T>),
+}
+
+#[derive(Clone, Debug)]
+pub struct TapSettings {
+    /// How much audio can be queued for the sink before new samples are dropped.
+    pub queue_duration: Duration,
+}
+
+impl Default for TapSettings {
+    fn default() -> Self {
+        Self {
+            queue_duration: Duration::from_secs(2),
+        }
+    }
+}
+
+// Messages are assumed to hold at least this many frames when sizing the queue
+const MIN_CHUNK_FRAMES: usize = 64;
+
+// Each message carries its format, so a format change can't be dropped when the queue is full
+enum TapMessage<T> {
+    Samples(Vec<T>, TapFormat),
+}
+
+enum TapTarget<T> {
+    File(FileTap),
+    Callback(TapCallback<T>),
+}
+
+struct FileTap {
+    settings: TapFileSettings,
+    writer: Option<RecordingWriter>,
+    files: usize,
+}
+
+impl FileTap {
+    fn open(&mut self, format: TapFormat) -> Result<(), RecorderError> {
+        self.finish()?;
+        let path = numbered_path(&self.settings.path, self.files);
+        info!("Capturing output to {path:?}");
+        self.writer = Some(RecordingWriter::create(
+            &path,
+            self.settings.container,
+            format.channels,
+            format.sample_rate,
+            self.settings.sample_format,
+        )?);
+        self.files += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), RecorderError> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies samples to a file or callback from a background thread so the playback path never
+/// waits on the sink. Samples that don't fit in the queue are dropped and counted.
+pub struct OutputTap<T> {
+    message_tx: Option<mpsc::SyncSender<TapMessage<T>>>,
+    handle: Option<JoinHandle<Result<(), RecorderError>>>,
+    format: TapFormat,
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+    failed: Arc<AtomicBool>,
+    dropped_frames: AtomicU64,
+}
+
+impl<T: DecalSample> OutputTap<T> {
+    /// Creates the tap. The first file is created immediately so errors opening it are returned
+    /// here rather than from the background thread.
+    pub fn new(
+        sink: TapSink<T>,
+        format: TapFormat,
+        settings: TapSettings,
+    ) -> Result<Self, RecorderError> {
+        let mut target = match sink {
+            TapSink::File(settings) => {
+                check_format(settings.container, settings.sample_format)?;
+                let mut file = FileTap {
+                    settings,
+                    writer: None,
+                    files: 0,
+                };
+                file.open(format)?;
+                TapTarget::File(file)
+            }
+            TapSink::Callback(callback) => TapTarget::Callback(callback),
+        };
+
+        let max_frames =
+            (settings.queue_duration.as_secs_f64() * format.sample_rate.0 as f64) as usize;
+        let (message_tx, message_rx) =
+            mpsc::sync_channel((max_frames / MIN_CHUNK_FRAMES).max(1) + 1);
+        let queued = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicBool::new(false));
+        let handle = thread::Builder::new().name("decal-tap".to_owned()).spawn({
+            let queued = queued.clone();
+            let failed = failed.clone();
+            let mut format = format;
+            move || {
+                let result: Result<(), RecorderError> = message_rx.iter().try_for_each(|message| {
+                    let TapMessage::Samples(samples, new_format) = message;
+                    queued.fetch_sub(samples.len(), Ordering::SeqCst);
+                    match &mut target {
+                        TapTarget::File(file) => {
+                            if new_format != format {
+                                file.open(new_format)?;
+                            }
+                            file.writer
+                                .as_mut()
+                                .expect("file opened")
+                                .write_samples(&samples)?;
+                        }
+                        TapTarget::Callback(callback) => callback(&samples, new_format),
+                    }
+                    format = new_format;
+                    Ok(())
+                });
+                // Stop taking samples right away rather than when the tap is finished
+                if let Err(e) = &result {
+                    error!("Output tap failed, dropping samples from now on: {e}");
+                    failed.store(true, Ordering::SeqCst);
+                }
+                if let TapTarget::File(file) = &mut target {
+                    file.finish()?;
+                }
+                result
+            }
+        })?;
+
+        Ok(Self {
+            message_tx: Some(message_tx),
+            handle: Some(handle),
+            format,
+            queued,
+            max_queued: max_frames * format.channels.0 as usize,
+            failed,
+            dropped_frames: AtomicU64::new(0),
+        })
+    }
+
+    pub fn format(&self) -> TapFormat {
+        self.format
+    }
+
+    /// Whether the sink stopped with an error. The error is returned by [`finish`](Self::finish).
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Number of frames that were dropped because the sink couldn't keep up or stopped with an
+    /// error.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::SeqCst)
+    }
+
+    /// Queues a copy of the samples without blocking.
+    pub fn push(&self, samples: &[T]) {
+        if samples.is_empty() {
+            return;
+        }
+        if !self.failed() && self.reserve(samples.len()) {
+            let sent = self.message_tx.as_ref().is_some_and(|tx| {
+                tx.try_send(TapMessage::Samples(samples.to_vec(), self.format))
+                    .is_ok()
+            });
+            if sent {
+                return;
+            }
+            self.queued.fetch_sub(samples.len(), Ordering::SeqCst);
+        }
+        let frames = samples.len() / self.format.channels.0.max(1) as usize;
+        if self
+            .dropped_frames
+            .fetch_add(frames as u64, Ordering::SeqCst)
+            == 0
+        {
+            warn!("Output tap can't keep up, dropping samples");
+        }
+    }
+
+    // Claims room in the queue, so pushes from several threads can't go over the limit together
+    fn reserve(&self, len: usize) -> bool {
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued + len <= self.max_queued).then_some(queued + len)
+            })
+            .is_ok()
+    }
+
+    /// Changes the format of the samples that are pushed from now on. File sinks continue in a
+    /// new file once samples in the new format arrive.
+    pub fn set_format(&mut self, format: TapFormat) {
+        self.format = format;
+    }
+
+    /// Waits for the queued samples to be written and closes the sink.
+    pub fn finish(mut self) -> Result<(), RecorderError> {
+        self.shutdown()
+    }
+}
+
+impl<T> OutputTap<T> {
+    fn shutdown(&mut self) -> Result<(), RecorderError> {
+        self.message_tx = None;
+        match self.handle.take().map(|handle| handle.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => {
+                error!("Output tap thread panicked: {e:?}");
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T> Drop for OutputTap<T> {
+    fn drop(&mut self) {
+        self.shutdown()
+            .tap_err(|e| error!("Error finishing output tap: {e}"))
+            .ok();
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::{OutputTap, TapFileSettings, TapFormat, TapSettings, TapSink};
+use crate::{ChannelCount, SampleRate};
+
+const FORMAT: TapFormat = TapFormat {
+    channels: ChannelCount(1),
+    sample_rate: SampleRate(1000),
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("decal-{}-{name}", std::process::id()))
+}
+
+#[test]
+fn tap_sends_samples_to_callback() {
+    let (samples_tx, samples_rx) = mpsc::channel();
+    let mut tap = OutputTap::new(
+        TapSink::Callback(Box::new(move |samples: &[i16], format: TapFormat| {
+            samples_tx.send((samples.to_vec(), format)).unwrap();
+        })),
+        FORMAT,
+        TapSettings::default(),
+    )
+    .unwrap();
+    tap.push(&[1, 2, 3]);
+    let stereo = TapFormat {
+        channels: ChannelCount(2),
+        ..FORMAT
+    };
+    tap.set_format(stereo);
+    tap.push(&[4, 5]);
+    tap.finish().unwrap();
+
+    assert_eq!(
+        vec![(vec![1, 2, 3], FORMAT), (vec![4, 5], stereo)],
+        samples_rx.iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn tap_drops_samples_when_full() {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let tap = OutputTap::new(
+        TapSink::Callback(Box::new(move |_: &[f32], _: TapFormat| {
+            started_tx.send(()).ok();
+            release_rx.recv().ok();
+        })),
+        FORMAT,
+        // 10 samples at 1kHz
+        TapSettings {
+            queue_duration: Duration::from_millis(10),
+        },
+    )
+    .unwrap();
+
+    // The first buffer is taken off the queue before the callback blocks
+    tap.push(&[0.0; 10]);
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    tap.push(&[0.0; 10]);
+    tap.push(&[0.0; 5]);
+    assert_eq!(5, tap.dropped_frames());
+
+    drop(release_tx);
+    tap.finish().unwrap();
+}
+
+#[test]
+fn tap_writes_files() {
+    let path = temp_path("tap.wav");
+    let mut tap = OutputTap::new(
+        TapSink::File(TapFileSettings {
+            path: path.clone(),
+            ..Default::default()
+        }),
+        FORMAT,
+        TapSettings::default(),
+    )
+    .unwrap();
+    tap.push(&[0.5f32; 4]);
+    // Changing the format continues in a new file
+    tap.set_format(TapFormat {
+        sample_rate: SampleRate(2000),
+        ..FORMAT
+    });
+    tap.push(&[0.5f32; 6]);
+    tap.finish().unwrap();
+
+    let first = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let second_path = temp_path("tap-2.wav");
+    let second = std::fs::read(&second_path).unwrap();
+    std::fs::remove_file(&second_path).unwrap();
+
+    assert_eq!(8u32.to_le_bytes(), first[76..80]);
+    assert_eq!(1000u32.to_le_bytes(), first[60..64]);
+    assert_eq!(12u32.to_le_bytes(), second[76..80]);
+    assert_eq!(2000u32.to_le_bytes(), second[60..64]);
+}
@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{
+    FileSplit, Recorder, RecorderSettings, RecorderState, RecordingContainer, SilenceTrigger,
+};
+use crate::output::{MockHost, OutputBuilder, SampleFormat};
+
+// 20ms chunks of 44.1kHz stereo audio from the mock's default config
+const CHUNK_SAMPLES: usize = 882 * 2;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("decal-{}-{name}", std::process::id()))
+}
+
+fn recorder(settings: RecorderSettings) -> Recorder<f32, MockHost> {
+    let output_builder = OutputBuilder::new(MockHost::default(), Default::default(), || {}, |_| {});
+    let input = output_builder
+        .new_input::<f32>(None, output_builder.default_input_config().unwrap())
+        .unwrap();
+    Recorder::new(input, settings).unwrap()
+}
+
+fn process(recorder: &mut Recorder<f32, MockHost>, sample: f32) -> RecorderState {
+    recorder
+        .input()
+        .device()
+        .send_input(vec![sample; CHUNK_SAMPLES]);
+    recorder.process().unwrap()
+}
+
+fn read_and_remove(path: &PathBuf) -> Vec<u8> {
+    let bytes = std::fs::read(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    bytes
+}
+
+fn wav_data_len(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[76..80].try_into().unwrap())
+}
+
+#[test]
+fn recorder_writes_wav() {
+    let mut recorder = recorder(RecorderSettings {
+        path: temp_path("recorder.wav"),
+        ..Default::default()
+    });
+    recorder.start().unwrap();
+    assert_eq!(RecorderState::Recording, process(&mut recorder, 0.5));
+    assert_eq!(0.5, recorder.levels()[0].peak);
+    assert_eq!(0.5, recorder.levels()[1].rms);
+
+    // Paused audio is metered but not written
+    recorder.pause();
+    assert_eq!(RecorderState::Paused, process(&mut recorder, 0.25));
+    assert_eq!(0.25, recorder.levels()[0].peak);
+    recorder.start().unwrap();
+    assert_eq!(RecorderState::Recording, process(&mut recorder, -0.5));
+    recorder.stop().unwrap();
+
+    assert_eq!(1, recorder.files().len());
+    let bytes = read_and_remove(&recorder.files()[0]);
+    assert_eq!(CHUNK_SAMPLES as u32 * 2 * 2, wav_data_len(&bytes));
+    assert_eq!(16384i16.to_le_bytes(), bytes[80..82]);
+    assert_eq!((-16384i16).to_le_bytes(), bytes[bytes.len() - 2..]);
+}
+
+#[test]
+fn recorder_silence_trigger() {
+    let mut recorder = recorder(RecorderSettings {
+        path: temp_path("trigger.wav"),
+        silence_trigger: Some(SilenceTrigger {
+            threshold_db: -40.0,
+            start_on_sound: true,
+            stop_after: Some(Duration::from_millis(40)),
+        }),
+        ..Default::default()
+    });
+    recorder.start().unwrap();
+    assert_eq!(
+        RecorderState::WaitingForSound,
+        process(&mut recorder, 0.001)
+    );
+    assert!(recorder.files().is_empty());
+
+    assert_eq!(RecorderState::Recording, process(&mut recorder, 0.5));
+    assert_eq!(RecorderState::Recording, process(&mut recorder, 0.001));
+    assert_eq!(
+        RecorderState::WaitingForSound,
+        process(&mut recorder, 0.001)
+    );
+    assert_eq!(
+        RecorderState::WaitingForSound,
+        process(&mut recorder, 0.001)
+    );
+    assert_eq!(RecorderState::Recording, process(&mut recorder, 0.5));
+    recorder.stop().unwrap();
+
+    // Each sound starts a new file, which includes the silence before the recording ended
+    let files = recorder.files().to_vec();
+    assert_eq!(
+        vec![temp_path("trigger.wav"), temp_path("trigger-2.wav")],
+        files
+    );
+    assert_eq!(
+        CHUNK_SAMPLES as u32 * 3 * 2,
+        wav_data_len(&read_and_remove(&files[0]))
+    );
+    assert_eq!(
+        CHUNK_SAMPLES as u32 * 2,
+        wav_data_len(&read_and_remove(&files[1]))
+    );
+}
+
+#[test]
+fn recorder_splits_files() {
+    let mut recorder = recorder(RecorderSettings {
+        path: temp_path("split.wav"),
+        split: FileSplit::Duration(Duration::from_millis(30)),
+        ..Default::default()
+    });
+    recorder.start().unwrap();
+    for _ in 0..4 {
+        process(&mut recorder, 0.5);
+    }
+    recorder.stop().unwrap();
+
+    // 80ms of audio split into 30ms files
+    let lengths: Vec<_> = recorder
+        .files()
+        .iter()
+        .map(|path| wav_data_len(&read_and_remove(path)))
+        .collect();
+    assert_eq!(vec![1323 * 4, 1323 * 4, 882 * 4], lengths);
+}
+
+#[test]
+fn recorder_writes_flac() {
+    let mut recorder = recorder(RecorderSettings {
+        path: temp_path("recorder.flac"),
+        container: RecordingContainer::Flac,
+        sample_format: SampleFormat::I24,
+        ..Default::default()
+    });
+    recorder.start().unwrap();
+    process(&mut recorder, 0.5);
+    recorder.stop().unwrap();
+
+    let bytes = read_and_remove(&recorder.files()[0]);
+    assert_eq!(b"fLaC", &bytes[0..4]);
+    // 24 bits per sample and 882 frames
+    let packed = u64::from_be_bytes(bytes[18..26].try_into().unwrap());
+    assert_eq!(24, ((packed >> 36) & 0x1F) + 1);
+    assert_eq!(882, packed & ((1 << 36) - 1));
+}
+
+#[test]
+fn recorder_rejects_float_flac() {
+    let output_builder = OutputBuilder::new(MockHost::default(), Default::default(), || {}, |_| {});
+    let input = output_builder
+        .new_input::<f32>(None, output_builder.default_input_config().unwrap())
+        .unwrap();
+    assert!(
+        Recorder::new(
+            input,
+            RecorderSettings {
+                container: RecordingContainer::Flac,
+                sample_format: SampleFormat::F32,
+                ..Default::default()
+            },
+        )
+        .is_err()
+    );
+}